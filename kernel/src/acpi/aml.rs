@@ -0,0 +1,122 @@
+//! Minimal AML scanning
+//!
+//! There is no interpreter: the only thing the kernel needs from the DSDT
+//! is the `\_S5` package, which firmware defines as a plain named package of
+//! integer constants. Its first two elements are the SLP_TYP values to write
+//! to PM1a and PM1b control when entering soft-off.
+
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const ROOT_CHAR: u8 = b'\\';
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ONES_OP: u8 = 0xFF;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const DWORD_PREFIX: u8 = 0x0C;
+const QWORD_PREFIX: u8 = 0x0E;
+
+/// Decode a PkgLength at the start of `data`, returning the number of bytes it uses
+fn pkg_length_size(data: &[u8]) -> Option<usize> {
+    let lead = *data.first()?;
+    Some(1 + (lead >> 6) as usize)
+}
+
+/// Decode an integer constant, returning its value and encoded size
+fn integer(data: &[u8]) -> Option<(u64, usize)> {
+    let le = |n: usize| -> Option<u64> {
+        let bytes = data.get(1..1 + n)?;
+        Some(bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+    };
+    match *data.first()? {
+        ZERO_OP => Some((0, 1)),
+        ONE_OP => Some((1, 1)),
+        ONES_OP => Some((u64::MAX, 1)),
+        BYTE_PREFIX => Some((le(1)?, 2)),
+        WORD_PREFIX => Some((le(2)?, 3)),
+        DWORD_PREFIX => Some((le(4)?, 5)),
+        QWORD_PREFIX => Some((le(8)?, 9)),
+        _ => None,
+    }
+}
+
+/// Find `Name(\_S5, Package() { a, b, ... })` and return (SLP_TYPa, SLP_TYPb)
+pub fn find_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    let mut start = 0;
+    while let Some(pos) = aml[start..].windows(4).position(|w| w == b"_S5_") {
+        let at = start + pos;
+        start = at + 1;
+
+        // The name must be the operand of a NameOp, optionally rooted
+        let named = match at {
+            0 => false,
+            1 => aml[0] == NAME_OP,
+            _ => aml[at - 1] == NAME_OP || (aml[at - 1] == ROOT_CHAR && aml[at - 2] == NAME_OP),
+        };
+        if !named || aml.get(at + 4) != Some(&PACKAGE_OP) {
+            continue;
+        }
+
+        // A malformed package is no definition; keep looking
+        if let Some(values) = s5_values(&aml[at + 5..]) {
+            return Some(values);
+        }
+    }
+    None
+}
+
+/// The first two integers of a package, given the bytes after its PackageOp
+fn s5_values(package: &[u8]) -> Option<(u8, u8)> {
+    // PkgLength NumElements PackageElementList
+    let mut offset = pkg_length_size(package)?;
+    let count = *package.get(offset)?;
+    offset += 1;
+    if count < 2 {
+        return None;
+    }
+    let (a, size) = integer(&package[offset..])?;
+    offset += size;
+    let (b, _) = integer(&package[offset..])?;
+    Some((a as u8, b as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_s5_rooted_with_byte_prefixes() {
+        // Name (\_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })
+        let aml = [
+            0x10, 0x08, NAME_OP, b'\\', b'_', b'S', b'5', b'_', PACKAGE_OP, 0x0A, 0x04, BYTE_PREFIX, 0x05, BYTE_PREFIX,
+            0x05, ZERO_OP, ZERO_OP,
+        ];
+        assert_eq!(find_s5(&aml), Some((5, 5)));
+    }
+
+    #[test]
+    fn test_find_s5_with_constant_ops() {
+        // Name (_S5, Package (0x02) { Zero, One }) as emitted by some compilers
+        let aml = [NAME_OP, b'_', b'S', b'5', b'_', PACKAGE_OP, 0x04, 0x02, ZERO_OP, ONE_OP];
+        assert_eq!(find_s5(&aml), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_find_s5_skips_references() {
+        // A method body mentioning _S5_ must not be taken for the definition
+        let aml = [
+            0x70, b'_', b'S', b'5', b'_', 0x60, NAME_OP, b'_', b'S', b'5', b'_', PACKAGE_OP, 0x06, 0x02, BYTE_PREFIX,
+            0x07, BYTE_PREFIX, 0x00,
+        ];
+        assert_eq!(find_s5(&aml), Some((7, 0)));
+
+        // A malformed package is passed over for a later definition
+        let aml = [
+            NAME_OP, b'_', b'S', b'5', b'_', PACKAGE_OP, 0x03, 0x02, 0x70, NAME_OP, b'_', b'S', b'5', b'_', PACKAGE_OP,
+            0x04, 0x02, ONE_OP, ONE_OP,
+        ];
+        assert_eq!(find_s5(&aml), Some((1, 1)));
+        assert_eq!(find_s5(&[NAME_OP, b'_', b'S', b'4', b'_']), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Fixed ACPI Description Table
+//!
+//! Holds the fixed-hardware power management registers, the reset register
+//! and the location of the DSDT. Fields added by later revisions are only
+//! read if the table is long enough to contain them.
+
+use super::{GenericAddress, read_u8, read_u16, read_u32, read_u64};
+
+/// Boot architecture flags
+pub const BOOT_ARCH_8042: u16 = 1 << 1;
+
+/// Feature flags
+pub const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+/// The parsed FADT
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub revision: u8,
+    /// Physical address of the DSDT
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    /// Port written with `acpi_enable` to hand control to the OS
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub pm_timer: u32,
+    /// CMOS index of the century register, 0 if absent
+    pub century: u8,
+    pub boot_arch: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    /// Whether the reset register may be used
+    pub fn supports_reset(&self) -> bool {
+        self.flags & FLAG_RESET_REG_SUP != 0 && self.reset_register.is_some()
+    }
+}
+
+/// Parse the table contents, header included
+pub fn parse(data: &[u8]) -> Option<Fadt> {
+    // ACPI 1.0 tables end after the flags
+    if data.len() < 116 {
+        return None;
+    }
+    let has = |end: usize| data.len() >= end;
+
+    let mut fadt = Fadt {
+        revision: read_u8(data, 8),
+        dsdt: read_u32(data, 40) as u64,
+        sci_interrupt: read_u16(data, 46),
+        smi_command: read_u32(data, 48),
+        acpi_enable: read_u8(data, 52),
+        pm1a_control: read_u32(data, 64),
+        pm1b_control: read_u32(data, 68),
+        pm_timer: read_u32(data, 76),
+        century: read_u8(data, 108),
+        boot_arch: read_u16(data, 109),
+        flags: read_u32(data, 112),
+        reset_register: None,
+        reset_value: 0,
+    };
+
+    if has(129) {
+        let reset = GenericAddress::parse(data, 116);
+        if reset.address != 0 {
+            fadt.reset_register = Some(reset);
+        }
+        fadt.reset_value = read_u8(data, 128);
+    }
+    // 64-bit addresses take precedence over the 32-bit ones when present
+    if has(148) && read_u64(data, 140) != 0 {
+        fadt.dsdt = read_u64(data, 140);
+    }
+    for (offset, field) in [
+        (172, &mut fadt.pm1a_control),
+        (184, &mut fadt.pm1b_control),
+        (208, &mut fadt.pm_timer),
+    ] {
+        if has(offset + 12) {
+            let gas = GenericAddress::parse(data, offset);
+            if gas.space == GenericAddress::SPACE_IO && gas.address != 0 {
+                *field = gas.address as u32;
+            }
+        }
+    }
+    Some(fadt)
+}
@@ -0,0 +1,36 @@
+//! High Precision Event Timer description table
+
+use super::{GenericAddress, HEADER_SIZE, read_u8, read_u16, read_u32};
+
+/// The parsed HPET table
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// Location of the timer block, normally in system memory
+    pub address: GenericAddress,
+    pub number: u8,
+    /// Minimum periodic tick in main counter ticks
+    pub minimum_tick: u16,
+}
+
+/// Parse the table contents, header included
+pub fn parse(data: &[u8]) -> Option<Hpet> {
+    if data.len() < HEADER_SIZE + 20 {
+        return None;
+    }
+    let id = read_u32(data, HEADER_SIZE);
+    Some(Hpet {
+        hardware_revision: id as u8,
+        comparators: ((id >> 8) & 0x1F) as u8 + 1,
+        counter_64bit: id & (1 << 13) != 0,
+        legacy_replacement: id & (1 << 15) != 0,
+        pci_vendor_id: (id >> 16) as u16,
+        address: GenericAddress::parse(data, HEADER_SIZE + 4),
+        number: read_u8(data, HEADER_SIZE + 16),
+        minimum_tick: read_u16(data, HEADER_SIZE + 17),
+    })
+}
@@ -0,0 +1,153 @@
+//! Multiple APIC Description Table
+//!
+//! Lists the processors' local APICs, the I/O APICs and how legacy ISA
+//! interrupts are wired to global system interrupts.
+
+use alloc::vec::Vec;
+
+use super::{HEADER_SIZE, read_u8, read_u16, read_u32, read_u64};
+
+/// MADT flag: the system also has dual 8259 PICs
+pub const PCAT_COMPAT: u32 = 1 << 0;
+
+/// Interrupt override and NMI flags
+pub const POLARITY_MASK: u16 = 0x3;
+pub const POLARITY_ACTIVE_LOW: u16 = 0x3;
+pub const TRIGGER_MASK: u16 = 0xC;
+pub const TRIGGER_LEVEL: u16 = 0xC;
+
+/// One interrupt controller structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        /// Usable now, or could be brought online
+        enabled: bool,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    /// An ISA IRQ wired to a different global system interrupt
+    InterruptOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    NmiSource {
+        flags: u16,
+        gsi: u32,
+    },
+    /// Local APIC LINT pin wired to NMI; processor 0xFF means all
+    LocalApicNmi {
+        processor_id: u8,
+        flags: u16,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        processor_uid: u32,
+        enabled: bool,
+    },
+    Other {
+        kind: u8,
+    },
+}
+
+/// The parsed MADT
+#[derive(Debug, Clone)]
+pub struct Madt {
+    /// Physical address of the local APIC, after any 64-bit override
+    pub local_apic_address: u64,
+    pub flags: u32,
+    pub entries: Vec<MadtEntry>,
+}
+
+impl Madt {
+    /// APIC IDs of processors that are enabled or can be
+    pub fn processors(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entries.iter().filter_map(|entry| match *entry {
+            MadtEntry::LocalApic { apic_id, enabled: true, .. } => Some(apic_id as u32),
+            MadtEntry::LocalX2Apic { x2apic_id, enabled: true, .. } => Some(x2apic_id),
+            _ => None,
+        })
+    }
+
+    /// (ID, physical address, first GSI) of each I/O APIC
+    pub fn io_apics(&self) -> impl Iterator<Item = (u8, u32, u32)> + '_ {
+        self.entries.iter().filter_map(|entry| match *entry {
+            MadtEntry::IoApic { id, address, gsi_base } => Some((id, address, gsi_base)),
+            _ => None,
+        })
+    }
+}
+
+/// Parse the table contents, header included
+pub fn parse(data: &[u8]) -> Option<Madt> {
+    if data.len() < HEADER_SIZE + 8 {
+        return None;
+    }
+    let mut madt = Madt {
+        local_apic_address: read_u32(data, HEADER_SIZE) as u64,
+        flags: read_u32(data, HEADER_SIZE + 4),
+        entries: Vec::new(),
+    };
+
+    let mut offset = HEADER_SIZE + 8;
+    while offset + 2 <= data.len() {
+        let kind = read_u8(data, offset);
+        let len = read_u8(data, offset + 1) as usize;
+        if len < 2 || offset + len > data.len() {
+            break;
+        }
+        let entry = &data[offset..offset + len];
+        let enabled = |flags: u32| flags & 0b11 != 0; // enabled or online capable
+        let parsed = match (kind, len) {
+            (0, 8..) => MadtEntry::LocalApic {
+                processor_id: entry[2],
+                apic_id: entry[3],
+                enabled: enabled(read_u32(entry, 4)),
+            },
+            (1, 12..) => MadtEntry::IoApic {
+                id: entry[2],
+                address: read_u32(entry, 4),
+                gsi_base: read_u32(entry, 8),
+            },
+            (2, 10..) => MadtEntry::InterruptOverride {
+                bus: entry[2],
+                source: entry[3],
+                gsi: read_u32(entry, 4),
+                flags: read_u16(entry, 8),
+            },
+            (3, 8..) => MadtEntry::NmiSource {
+                flags: read_u16(entry, 2),
+                gsi: read_u32(entry, 4),
+            },
+            (4, 6..) => MadtEntry::LocalApicNmi {
+                processor_id: entry[2],
+                flags: read_u16(entry, 3),
+                lint: entry[5],
+            },
+            (5, 12..) => {
+                let address = read_u64(entry, 4);
+                madt.local_apic_address = address;
+                MadtEntry::LocalApicAddressOverride { address }
+            }
+            (9, 16..) => MadtEntry::LocalX2Apic {
+                x2apic_id: read_u32(entry, 4),
+                enabled: enabled(read_u32(entry, 8)),
+                processor_uid: read_u32(entry, 12),
+            },
+            _ => MadtEntry::Other { kind },
+        };
+        madt.entries.push(parsed);
+        offset += len;
+    }
+    Some(madt)
+}
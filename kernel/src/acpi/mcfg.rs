@@ -0,0 +1,32 @@
+//! PCI Express memory-mapped configuration table
+
+use alloc::vec::Vec;
+
+use super::{HEADER_SIZE, read_u8, read_u16, read_u64};
+
+/// One ECAM region
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    /// Physical address of bus 0's configuration space in this segment
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Parse the table contents, header included
+pub fn parse(data: &[u8]) -> Vec<McfgEntry> {
+    // 8 reserved bytes follow the header, then 16-byte allocation entries
+    let mut entries = Vec::new();
+    let mut offset = HEADER_SIZE + 8;
+    while offset + 16 <= data.len() {
+        entries.push(McfgEntry {
+            base: read_u64(data, offset),
+            segment: read_u16(data, offset + 8),
+            start_bus: read_u8(data, offset + 10),
+            end_bus: read_u8(data, offset + 11),
+        });
+        offset += 16;
+    }
+    entries
+}
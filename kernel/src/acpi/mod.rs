@@ -0,0 +1,292 @@
+//! ACPI table discovery
+//!
+//! Limine hands over the physical address of the RSDP; from there the RSDT
+//! or XSDT lists every system description table. Tables live outside the
+//! HHDM, so each one is mapped into the kernel's device window. Tables whose
+//! checksum does not add up are listed but never handed to parsers.
+
+pub mod aml;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+use alloc::vec::Vec;
+use limine::request::RsdpRequest;
+use spin::Mutex;
+use x86_64::PhysAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::{Madt, MadtEntry};
+pub use mcfg::McfgEntry;
+
+/// Request the RSDP address from Limine
+#[used]
+#[unsafe(link_section = ".limine_requests")]
+static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
+/// Size of the common table header
+pub const HEADER_SIZE: usize = 36;
+
+/// Header fields of a table found at boot
+#[derive(Debug, Clone, Copy)]
+pub struct TableInfo {
+    pub signature: [u8; 4],
+    pub phys: u64,
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    /// Whether the bytes of the table sum to zero
+    pub valid: bool,
+}
+
+/// The root pointer's revision and OEM
+#[derive(Debug, Clone, Copy)]
+pub struct RsdpInfo {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    /// Physical address of the XSDT, or the RSDT if there is none
+    pub root: u64,
+    pub xsdt: bool,
+}
+
+static RSDP: Mutex<Option<RsdpInfo>> = Mutex::new(None);
+
+/// Tables found at boot with their full contents, header included
+static TABLES: Mutex<Vec<(TableInfo, &'static [u8])>> = Mutex::new(Vec::new());
+
+/// An ACPI Generic Address Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    /// 0 for system memory, 1 for system I/O
+    pub space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SPACE_MEMORY: u8 = 0;
+    pub const SPACE_IO: u8 = 1;
+
+    /// Decode the 12-byte structure at `offset`
+    pub fn parse(data: &[u8], offset: usize) -> Self {
+        Self {
+            space: read_u8(data, offset),
+            bit_width: read_u8(data, offset + 1),
+            bit_offset: read_u8(data, offset + 2),
+            access_size: read_u8(data, offset + 3),
+            address: read_u64(data, offset + 4),
+        }
+    }
+
+    pub fn space_name(&self) -> &'static str {
+        match self.space {
+            Self::SPACE_MEMORY => "memory",
+            Self::SPACE_IO => "I/O",
+            2 => "PCI config",
+            _ => "other",
+        }
+    }
+}
+
+/// Map `len` bytes of firmware memory at `phys`, read-only
+fn map(phys: u64, len: usize) -> Option<&'static [u8]> {
+    let virt = crate::memory::paging::map_physical(PhysAddr::new(phys), len as u64).ok()?;
+    // Firmware tables are never written
+    let _ = crate::memory::paging::protect(virt, len as u64, PageTableFlags::PRESENT);
+    Some(unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), len) })
+}
+
+/// Map a whole table given the physical address of its header
+fn map_table(phys: u64) -> Option<&'static [u8]> {
+    let header = map(phys, HEADER_SIZE)?;
+    let len = read_u32(header, 4) as usize;
+    // The header was only needed for the length
+    let _ = crate::memory::paging::unmap(x86_64::VirtAddr::from_ptr(header.as_ptr()), HEADER_SIZE as u64);
+    if len < HEADER_SIZE {
+        return None;
+    }
+    map(phys, len)
+}
+
+/// Whether the bytes sum to zero modulo 256
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Little-endian field readers for table parsing
+pub fn read_u8(data: &[u8], offset: usize) -> u8 {
+    data[offset]
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Printable form of a signature or OEM ID
+pub fn text(bytes: &[u8]) -> &str {
+    core::str::from_utf8(bytes).unwrap_or("?").trim_end_matches(['\0', ' '])
+}
+
+/// Find a valid table by signature, e.g. `b"MCFG"`
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    TABLES
+        .lock()
+        .iter()
+        .find(|(info, _)| info.valid && &info.signature == signature)
+        .map(|(_, data)| *data)
+}
+
+/// Every table found, valid or not
+pub fn tables() -> Vec<TableInfo> {
+    TABLES.lock().iter().map(|(info, _)| *info).collect()
+}
+
+pub fn rsdp() -> Option<RsdpInfo> {
+    *RSDP.lock()
+}
+
+/// The Multiple APIC Description Table
+pub fn madt() -> Option<Madt> {
+    madt::parse(find_table(b"APIC")?)
+}
+
+/// The Fixed ACPI Description Table
+pub fn fadt() -> Option<Fadt> {
+    fadt::parse(find_table(b"FACP")?)
+}
+
+/// The HPET description table
+pub fn hpet() -> Option<Hpet> {
+    hpet::parse(find_table(b"HPET")?)
+}
+
+/// PCI Express ECAM regions from the MCFG table
+pub fn mcfg() -> Vec<McfgEntry> {
+    find_table(b"MCFG").map(mcfg::parse).unwrap_or_default()
+}
+
+/// SLP_TYP values for soft-off, from `\_S5` in the DSDT or an SSDT
+pub fn s5_sleep_type() -> Option<(u8, u8)> {
+    let tables = TABLES.lock();
+    // Search the DSDT before any SSDT
+    [b"DSDT", b"SSDT"]
+        .iter()
+        .flat_map(|signature| tables.iter().filter(move |(info, _)| info.valid && &info.signature == *signature))
+        .find_map(|(_, data)| aml::find_s5(&data[HEADER_SIZE..]))
+}
+
+/// Map a table and record it, checking its checksum
+fn add_table(tables: &mut Vec<(TableInfo, &'static [u8])>, phys: u64) {
+    let Some(table) = map_table(phys) else {
+        return;
+    };
+    let info = TableInfo {
+        signature: table[0..4].try_into().unwrap(),
+        phys,
+        length: table.len() as u32,
+        revision: read_u8(table, 8),
+        oem_id: table[10..16].try_into().unwrap(),
+        valid: checksum_ok(table),
+    };
+
+    crate::serial::print("ACPI: found ");
+    crate::serial::print(text(&info.signature));
+    if !info.valid {
+        crate::serial::print(" (bad checksum, ignored)");
+    }
+    crate::serial::print("\n");
+    tables.push((info, table));
+}
+
+/// Locate the RSDP and map every table listed in the RSDT/XSDT
+pub fn init() {
+    crate::serial::print("Initializing ACPI...\n");
+
+    let Some(response) = RSDP_REQUEST.get_response() else {
+        crate::serial::print("ACPI: no RSDP from bootloader\n");
+        return;
+    };
+    // Older base revisions report a HHDM pointer rather than a physical address
+    let mut rsdp_phys = response.address() as u64;
+    let hhdm = crate::memory::paging::hhdm_offset().as_u64();
+    if rsdp_phys >= hhdm {
+        rsdp_phys -= hhdm;
+    }
+
+    // Revision 2+ RSDPs are 36 bytes and carry the 64-bit XSDT address
+    let Some(rsdp) = map(rsdp_phys, 36) else {
+        return;
+    };
+    if &rsdp[0..8] != b"RSD PTR " || !checksum_ok(&rsdp[0..20]) {
+        crate::serial::print("ACPI: bad RSDP\n");
+        return;
+    }
+    let revision = read_u8(rsdp, 15);
+    let xsdt = revision >= 2 && checksum_ok(&rsdp[0..36]) && read_u64(rsdp, 24) != 0;
+    let (root_phys, entry_size) = if xsdt {
+        (read_u64(rsdp, 24), 8)
+    } else {
+        (read_u32(rsdp, 16) as u64, 4)
+    };
+    *RSDP.lock() = Some(RsdpInfo {
+        revision,
+        oem_id: rsdp[9..15].try_into().unwrap(),
+        root: root_phys,
+        xsdt,
+    });
+
+    let Some(root) = map_table(root_phys) else {
+        crate::serial::print("ACPI: cannot map root table\n");
+        return;
+    };
+    if !checksum_ok(root) {
+        crate::serial::print("ACPI: bad root table checksum\n");
+        return;
+    }
+
+    let mut tables = TABLES.lock();
+    for offset in (HEADER_SIZE..root.len()).step_by(entry_size) {
+        if offset + entry_size > root.len() {
+            break;
+        }
+        let phys = if entry_size == 8 {
+            read_u64(root, offset)
+        } else {
+            read_u32(root, offset) as u64
+        };
+        add_table(&mut tables, phys);
+    }
+
+    // The DSDT is not listed in the root table; the FADT points to it
+    let dsdt = tables
+        .iter()
+        .find(|(info, _)| info.valid && &info.signature == b"FACP")
+        .and_then(|(_, data)| fadt::parse(data))
+        .map(|fadt| fadt.dsdt)
+        .filter(|&phys| phys != 0);
+    if let Some(phys) = dsdt {
+        add_table(&mut tables, phys);
+    }
+    drop(tables);
+
+    if let Some(madt) = madt() {
+        crate::serial::print("ACPI: ");
+        crate::memory::print_decimal(madt.processors().count() as u64);
+        crate::serial::print(" processors, ");
+        crate::memory::print_decimal(madt.io_apics().count() as u64);
+        crate::serial::print(" I/O APICs\n");
+    }
+}
@@ -0,0 +1,233 @@
+//! Escape sequence parser
+//!
+//! Splits a byte stream into printable bytes, control characters and
+//! escape sequences, following the outline of the DEC VT500 state
+//! machine. CSI sequences collect up to `MAX_PARAMS` numeric parameters,
+//! OSC strings are skipped, and CAN or SUB abandon a sequence.
+
+/// Parameters kept per CSI sequence; sequences with more are dropped
+pub const MAX_PARAMS: usize = 16;
+
+const ESC: u8 = 0x1B;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1A;
+const BEL: u8 = 0x07;
+const DEL: u8 = 0x7F;
+
+/// What a byte, or the sequence it completes, asks the terminal to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Print(u8),
+    /// A C0 control character such as LF or BS
+    Control(u8),
+    /// ESC and a final byte, with an intermediate byte such as `(`
+    Escape { intermediate: Option<u8>, final_byte: u8 },
+    Csi(Csi),
+}
+
+/// A control sequence: ESC `[`, parameters, and a final byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Marker before the parameters, such as `?` for DEC private modes
+    pub private: Option<u8>,
+    pub intermediate: Option<u8>,
+    pub final_byte: u8,
+}
+
+impl Csi {
+    const EMPTY: Csi = Csi {
+        params: [0; MAX_PARAMS],
+        len: 0,
+        private: None,
+        intermediate: None,
+        final_byte: 0,
+    };
+
+    /// The parameters given; omitted ones read as 0
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Parameter `index`, or `default` if it is missing or 0
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    /// Inside a CSI sequence that will be dropped
+    CsiIgnore,
+    Osc,
+    /// ESC inside an OSC string, which ends it if followed by `\`
+    OscEscape,
+}
+
+/// Escape sequence state machine
+pub struct Parser {
+    state: State,
+    csi: Csi,
+    intermediate: Option<u8>,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi::EMPTY,
+            intermediate: None,
+        }
+    }
+
+    /// Feed one byte, returning an action once one is complete
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match (self.state, byte) {
+            (State::Osc, ESC) => {
+                self.state = State::OscEscape;
+                return None;
+            }
+            (State::Osc | State::OscEscape, _) => {
+                if byte == BEL || self.state == State::OscEscape {
+                    self.state = State::Ground;
+                }
+                return None;
+            }
+            (_, CAN | SUB) => {
+                self.state = State::Ground;
+                return None;
+            }
+            (_, ESC) => {
+                self.state = State::Escape;
+                self.intermediate = None;
+                return None;
+            }
+            // Control characters act even in the middle of a sequence
+            (_, 0x00..=0x1F) => return Some(Action::Control(byte)),
+            (_, DEL) => return None,
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => Some(Action::Print(byte)),
+            State::Escape => match byte {
+                b'[' => {
+                    self.state = State::Csi;
+                    self.csi = Csi::EMPTY;
+                    None
+                }
+                b']' => {
+                    self.state = State::Osc;
+                    None
+                }
+                0x20..=0x2F => {
+                    self.intermediate = Some(byte);
+                    None
+                }
+                _ => {
+                    self.state = State::Ground;
+                    Some(Action::Escape {
+                        intermediate: self.intermediate,
+                        final_byte: byte,
+                    })
+                }
+            },
+            State::Csi => self.csi_byte(byte),
+            State::CsiIgnore => {
+                if (0x40..=0x7E).contains(&byte) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Osc | State::OscEscape => None,
+        }
+    }
+
+    fn csi_byte(&mut self, byte: u8) -> Option<Action> {
+        let csi = &mut self.csi;
+        match byte {
+            b'0'..=b'9' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                let param = &mut csi.params[csi.len - 1];
+                *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+            }
+            b';' | b':' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if csi.len == MAX_PARAMS {
+                    self.state = State::CsiIgnore;
+                } else {
+                    csi.len += 1;
+                }
+            }
+            b'<'..=b'?' if csi.len == 0 && csi.private.is_none() => csi.private = Some(byte),
+            0x20..=0x2F => csi.intermediate = Some(byte),
+            0x40..=0x7E => {
+                csi.final_byte = byte;
+                self.state = State::Ground;
+                return Some(Action::Csi(*csi));
+            }
+            _ => self.state = State::CsiIgnore,
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn parse(bytes: &[u8]) -> Vec<Action> {
+        let mut parser = Parser::new();
+        bytes.iter().filter_map(|&b| parser.advance(b)).collect()
+    }
+
+    #[test]
+    fn test_sgr_parameters() {
+        let actions = parse(b"\x1b[1;38;5;208mA");
+        let Action::Csi(csi) = actions[0] else {
+            panic!("expected CSI, got {:?}", actions[0]);
+        };
+        assert_eq!(csi.final_byte, b'm');
+        assert_eq!(csi.params(), &[1, 38, 5, 208]);
+        assert_eq!(actions[1], Action::Print(b'A'));
+    }
+
+    #[test]
+    fn test_defaults_and_private_marker() {
+        let actions = parse(b"\x1b[;5H\x1b[?25l");
+        let (Action::Csi(home), Action::Csi(hide)) = (actions[0], actions[1]) else {
+            panic!("expected two CSI sequences, got {:?}", actions);
+        };
+        assert_eq!(home.param(0, 1), 1);
+        assert_eq!(home.param(1, 1), 5);
+        assert_eq!(hide.private, Some(b'?'));
+        assert_eq!(hide.params(), &[25]);
+    }
+
+    #[test]
+    fn test_controls_escapes_and_osc() {
+        assert_eq!(
+            parse(b"a\x1b]0;title\x07\r\x1b7\x1b(Bb\x1b]2;x\x1b\\c"),
+            vec![
+                Action::Print(b'a'),
+                Action::Control(b'\r'),
+                Action::Escape { intermediate: None, final_byte: b'7' },
+                Action::Escape { intermediate: Some(b'('), final_byte: b'B' },
+                Action::Print(b'b'),
+                Action::Print(b'c'),
+            ]
+        );
+    }
+}
@@ -1,5 +1,9 @@
 //! Simple bitmap font for console text rendering
 
+use alloc::collections::BTreeMap;
+
+use super::psf::{self, Font};
+
 /// 8x16 bitmap font data (simplified ASCII)
 /// Each character is 8 pixels wide, 16 pixels tall
 /// Each byte represents one row of 8 pixels
@@ -165,13 +169,18 @@ pub const FONT_DATA: &[u8] = &[
     0x60, 0x60, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
 
-/// Get font data for a character
-pub fn get_char_data(c: u8) -> &'static [u8] {
-    if c >= 32 && c <= 70 {
-        let index = (c - 32) as usize * FONT_HEIGHT;
-        &FONT_DATA[index..index + FONT_HEIGHT]
-    } else {
-        // Return space character for unsupported chars
-        &FONT_DATA[0..FONT_HEIGHT]
-    }
+/// Hollow box shown for characters the built-in font lacks
+const REPLACEMENT_GLYPH: [u8; FONT_HEIGHT] = [
+    0x00, 0x00, 0x7E, 0x42, 0x42, 0x42, 0x42, 0x42,
+    0x42, 0x42, 0x42, 0x42, 0x7E, 0x00, 0x00, 0x00,
+];
+
+/// The font compiled into the kernel, used until another is loaded
+pub fn builtin() -> Font {
+    let count = FONT_DATA.len() / FONT_HEIGHT;
+    let mut glyphs = FONT_DATA.to_vec();
+    glyphs.extend_from_slice(&REPLACEMENT_GLYPH);
+    let mut unicode: BTreeMap<char, usize> = (0..count).map(|i| ((32 + i as u8) as char, i)).collect();
+    unicode.insert(psf::REPLACEMENT, count);
+    Font::new(FONT_WIDTH, FONT_HEIGHT, glyphs, unicode).expect("built-in font is valid")
 }
@@ -1,18 +1,82 @@
 //! Console and terminal emulation
+//!
+//! The screen is shared by `VT_COUNT` virtual consoles, switched with
+//! Alt+F1 onward. Each has its own terminal, with its cells, cursor and
+//! scrollback, and its own TTY; all but the last run a shell, and the last
+//! shows the kernel log. Only the foreground console has the framebuffer,
+//! and it redraws from its cells when brought forward.
+//!
+//! Printing goes to the current console: the one whose shell is handling
+//! input, or else the foreground one. The first console is also mirrored
+//! to COM1, which serves as its serial terminal.
 
+pub mod ansi;
 pub mod font;
+pub mod psf;
+pub mod selection;
 pub mod terminal;
 
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use limine::framebuffer::Framebuffer;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
 
-/// Global console instance
-static mut CONSOLE: Option<terminal::Terminal> = None;
+use crate::drivers::device::{Device, DeviceError};
+use crate::drivers::framebuffer::FramebufferDevice;
+use terminal::Terminal;
+
+/// Number of virtual consoles
+pub const VT_COUNT: usize = 6;
+
+/// The console showing the kernel log, with no shell
+pub const LOG_VT: usize = VT_COUNT - 1;
+
+/// Marks that no shell is directing output
+const NO_VT: usize = usize::MAX;
+
+/// Terminals of the virtual consoles, each behind its own lock
+///
+/// The locks are taken with interrupts disabled, since interrupt handlers
+/// print and switch consoles, and never while a TTY lock is held or
+/// another console's is. Nothing is called back with one held either:
+/// answers to status requests are fed to the TTY after unlocking.
+static CONSOLES: [Mutex<Option<Terminal>>; VT_COUNT] = [const { Mutex::new(None) }; VT_COUNT];
+
+/// The console on screen
+static FOREGROUND: AtomicUsize = AtomicUsize::new(0);
+
+/// The console whose shell is running, if any
+static OUTPUT: AtomicUsize = AtomicUsize::new(NO_VT);
+
+/// Run `f` on the terminal of console `vt`, if the console is up
+fn with_terminal<R>(vt: usize, f: impl FnOnce(&mut Terminal) -> R) -> Option<R> {
+    without_interrupts(|| CONSOLES.get(vt)?.lock().as_mut().map(f))
+}
 
 /// Initialize the console system
 pub fn init(framebuffer: &'static Framebuffer<'static>) {
-    unsafe {
-        CONSOLE = Some(terminal::Terminal::new(framebuffer));
+    crate::drivers::device::register("console", Arc::new(ConsoleDevice));
+
+    let Some(framebuffer) = FramebufferDevice::with_shadow(framebuffer) else {
+        crate::serial::print("Console: no memory for a shadow framebuffer\n");
+        return;
+    };
+    let (width, height) = framebuffer.dimensions();
+    let font = Arc::new(boot_font());
+    let mut framebuffer = Some(framebuffer);
+    for (vt, console) in CONSOLES.iter().enumerate() {
+        let mut terminal = Terminal::new(width, height, font.clone());
+        if vt == foreground()
+            && let Some(framebuffer) = framebuffer.take()
+        {
+            terminal.attach(framebuffer);
+        }
+        without_interrupts(|| *console.lock() = Some(terminal));
     }
+
+    crate::drivers::mouse::add_listener(handle_mouse);
     
     // Clear screen and show welcome message
     clear();
@@ -25,46 +89,220 @@ pub fn init(framebuffer: &'static Framebuffer<'static>) {
     // println("");
 }
 
-/// Print a string to the console
+/// The font given as a boot module, or the built-in one
+fn boot_font() -> psf::Font {
+    let Some(data) = crate::drivers::block::ramdisk::find_module(crate::drivers::block::ramdisk::FONT_MODULE) else {
+        return font::builtin();
+    };
+    match psf::parse(data) {
+        Ok(font) => font,
+        Err(e) => {
+            crate::serial::print("Console: bad font module: ");
+            crate::serial::print(e.as_str());
+            crate::serial::print("\n");
+            font::builtin()
+        }
+    }
+}
+
+/// The console on screen
+pub fn foreground() -> usize {
+    FOREGROUND.load(Ordering::Relaxed)
+}
+
+/// The console printing goes to
+pub fn current() -> usize {
+    match OUTPUT.load(Ordering::Relaxed) {
+        NO_VT => foreground(),
+        vt => vt,
+    }
+}
+
+/// Run `f` with printing going to console `vt`
+pub fn with_output<R>(vt: usize, f: impl FnOnce() -> R) -> R {
+    let previous = OUTPUT.swap(vt, Ordering::Relaxed);
+    let result = f();
+    OUTPUT.store(previous, Ordering::Relaxed);
+    result
+}
+
+/// Bring console `vt` on screen
+pub fn switch(vt: usize) {
+    let old = foreground();
+    if vt >= VT_COUNT || vt == old {
+        return;
+    }
+    // One console lock at a time: the framebuffer passes from one to the other
+    let Some(framebuffer) = with_terminal(old, |console| {
+        selection::hide(console);
+        console.detach()
+    }) else {
+        return;
+    };
+    FOREGROUND.store(vt, Ordering::Relaxed);
+    if let Some(framebuffer) = framebuffer {
+        with_terminal(vt, |console| console.attach(framebuffer));
+    }
+}
+
+/// Print a string to the current console
 pub fn print(s: &str) {
-    unsafe {
-        if let Some(ref mut console) = CONSOLE {
-            console.write_str(s);
+    print_to(current(), s);
+}
+
+/// Print a string to console `VT`, as the output of its TTY
+pub fn print_vt<const VT: usize>(s: &str) {
+    print_to(VT, s);
+}
+
+/// Print a string to console `vt`, and to COM1 for the first console
+///
+/// Answers to status requests in the output wait in the console until
+/// `deliver_replies` types them into its TTY.
+pub fn print_to(vt: usize, s: &str) {
+    if vt == 0 {
+        crate::drivers::uart::com1().write(s.as_bytes());
+    }
+    with_terminal(vt, |console| {
+        if vt == foreground() {
+            selection::hide(console);
+        }
+        console.write_str(s);
+    });
+}
+
+/// Whether any console has answers to status requests waiting
+pub fn replies_pending() -> bool {
+    (0..VT_COUNT).any(|vt| with_terminal(vt, |console| console.has_replies()) == Some(true))
+}
+
+/// Type the answers to status requests into their consoles' TTYs
+///
+/// The TTY's reader is usually what printed the request, so this runs from
+/// the idle loop rather than from inside `print_to`, where it would reenter
+/// the reader.
+pub fn deliver_replies() {
+    for vt in 0..VT_COUNT {
+        let replies = with_terminal(vt, |console| console.take_replies());
+        for byte in replies.unwrap_or_default() {
+            crate::tty::input(crate::tty::vt(vt), byte as char);
         }
     }
 }
 
+/// Append kernel log output to the log console
+///
+/// Logging happens from anywhere, including code that already holds the
+/// log console's lock, so it never waits for it: output that finds the
+/// console busy only reaches COM1.
+pub fn log(s: &str) {
+    without_interrupts(|| {
+        if let Some(mut console) = CONSOLES[LOG_VT].try_lock()
+            && let Some(console) = console.as_mut()
+        {
+            if LOG_VT == foreground() {
+                selection::hide(console);
+            }
+            console.write_str(s);
+        }
+    });
+}
+
 /// Print a string with newline
 pub fn println(s: &str) {
     print(s);
     print("\n");
 }
 
-/// Clear the console screen
+/// Clear the current console
 pub fn clear() {
-    unsafe {
-        if let Some(ref mut console) = CONSOLE {
-            console.clear();
+    let vt = current();
+    if vt == 0 {
+        // Clear the serial terminal too and home its cursor
+        crate::drivers::uart::com1().write(b"\x1b[2J\x1b[H");
+    }
+    with_terminal(vt, |console| {
+        if vt == foreground() {
+            selection::hide(console);
         }
+        console.clear();
+    });
+}
+
+/// Scroll the view of the console on screen through history, half a
+/// screen per step; negative steps move toward the newest output
+pub fn scroll_view(steps: isize) {
+    with_terminal(foreground(), |console| {
+        selection::hide(console);
+        let half = (console.dimensions().1 / 2).max(1) as isize;
+        console.scroll_view(steps * half);
+    });
+}
+
+/// Lines of scrollback the current console keeps
+pub fn scrollback_limit() -> usize {
+    with_terminal(current(), |console| console.scrollback_limit()).unwrap_or(0)
+}
+
+/// Change how many lines of scrollback every console keeps
+pub fn set_scrollback_limit(lines: usize) {
+    for vt in 0..VT_COUNT {
+        with_terminal(vt, |console| console.set_scrollback_limit(lines));
     }
 }
 
-/// Get console dimensions (width, height in characters)
-pub fn dimensions() -> (usize, usize) {
-    unsafe {
-        if let Some(ref console) = CONSOLE {
-            console.dimensions()
-        } else {
-            (80, 25) // Default
+/// The consoles' font
+pub fn font() -> Option<Arc<psf::Font>> {
+    with_terminal(foreground(), |console| console.font().clone())
+}
+
+/// Change the font of every console
+///
+/// The consoles share one screen, so a font that does not fit is refused by
+/// the first and none of them change.
+pub fn set_font(font: psf::Font) -> Result<(), &'static str> {
+    let font = Arc::new(font);
+    for vt in 0..VT_COUNT {
+        let result = with_terminal(vt, |console| {
+            if vt == foreground() {
+                selection::hide(console);
+            }
+            console.set_font(font.clone())
+        });
+        if let Some(Err(e)) = result {
+            return Err(e);
         }
     }
+    Ok(())
+}
+
+/// Move the pointer and select text with the mouse
+fn handle_mouse(event: &crate::drivers::mouse::MouseEvent) {
+    if with_terminal(foreground(), |console| selection::handle_mouse(console, event)) == Some(true) {
+        selection::paste();
+    }
 }
 
-/// Set cursor position
+/// Get the current console's dimensions (width, height in characters)
+pub fn dimensions() -> (usize, usize) {
+    with_terminal(current(), |console| console.dimensions()).unwrap_or((80, 25)) // Default
+}
+
+/// Set the current console's cursor position
 pub fn set_cursor(x: usize, y: usize) {
-    unsafe {
-        if let Some(ref mut console) = CONSOLE {
-            console.set_cursor(x, y);
-        }
+    with_terminal(current(), |console| console.set_cursor(x, y));
+}
+
+/// `/dev/console`: writes go to the screen, reads return typed characters
+pub struct ConsoleDevice;
+
+impl Device for ConsoleDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        Ok(crate::drivers::keyboard::read_chars(buf))
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        print(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
     }
 }
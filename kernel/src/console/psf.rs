@@ -0,0 +1,250 @@
+//! PC Screen Font loading
+//!
+//! Reads the PSF1 and PSF2 bitmap font formats used by the Linux console.
+//! Glyph rows are padded to whole bytes with the leftmost pixel in the high
+//! bit. A font's Unicode table maps characters to glyphs; multi-character
+//! sequences in it are skipped. Fonts without a table are indexed by code
+//! point.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_MODEHASSEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_STARTSEQ: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_STARTSEQ: u8 = 0xFE;
+
+/// Size of the PSF2 header as far as we read it
+const PSF2_HEADER_SIZE: usize = 32;
+
+/// Shown for characters the font has no glyph for
+pub const REPLACEMENT: char = '\u{FFFD}';
+
+/// Errors from parsing a font
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// Neither the PSF1 nor the PSF2 magic number
+    BadMagic,
+    /// The header describes glyphs that do not fit in the data
+    Truncated,
+    /// Glyphs of zero size, or wider than we draw
+    BadSize,
+}
+
+impl FontError {
+    /// Human-readable description of the error
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FontError::BadMagic => "not a PSF font",
+            FontError::Truncated => "font file is truncated",
+            FontError::BadSize => "unsupported glyph size",
+        }
+    }
+}
+
+/// A bitmap font
+pub struct Font {
+    width: usize,
+    height: usize,
+    /// Bytes per glyph, `height` rows of whole bytes
+    glyph_size: usize,
+    glyphs: Vec<u8>,
+    /// Glyph for each character; empty to index glyphs by code point
+    unicode: BTreeMap<char, usize>,
+    /// Glyph drawn for characters without one
+    replacement: usize,
+}
+
+impl Font {
+    /// Build a font from bitmaps and the characters each glyph shows
+    pub fn new(width: usize, height: usize, glyphs: Vec<u8>, unicode: BTreeMap<char, usize>) -> Result<Self, FontError> {
+        if width == 0 || height == 0 || width > 32 {
+            return Err(FontError::BadSize);
+        }
+        let glyph_size = width.div_ceil(8) * height;
+        let count = glyphs.len() / glyph_size;
+        if count == 0 {
+            return Err(FontError::Truncated);
+        }
+        let mut font = Font {
+            width,
+            height,
+            glyph_size,
+            glyphs,
+            unicode,
+            replacement: 0,
+        };
+        // Glyph 0 is the usual fallback when the font has neither
+        font.replacement = font.index(REPLACEMENT).or(font.index('?')).unwrap_or(0);
+        Ok(font)
+    }
+
+    /// Glyph width and height in pixels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Number of glyphs
+    pub fn glyph_count(&self) -> usize {
+        self.glyphs.len() / self.glyph_size
+    }
+
+    /// Whether the font has a Unicode table
+    pub fn has_unicode_table(&self) -> bool {
+        !self.unicode.is_empty()
+    }
+
+    fn index(&self, c: char) -> Option<usize> {
+        let index = if self.unicode.is_empty() {
+            c as usize
+        } else {
+            *self.unicode.get(&c)?
+        };
+        (index < self.glyph_count()).then_some(index)
+    }
+
+    /// The bitmap for `c`, or the replacement glyph
+    pub fn glyph(&self, c: char) -> &[u8] {
+        let index = self.index(c).unwrap_or(self.replacement);
+        &self.glyphs[index * self.glyph_size..(index + 1) * self.glyph_size]
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Parse a PSF1 or PSF2 font
+pub fn parse(data: &[u8]) -> Result<Font, FontError> {
+    if data.starts_with(&PSF2_MAGIC) {
+        parse_psf2(data)
+    } else if data.starts_with(&PSF1_MAGIC) {
+        parse_psf1(data)
+    } else {
+        Err(FontError::BadMagic)
+    }
+}
+
+fn parse_psf1(data: &[u8]) -> Result<Font, FontError> {
+    let mode = *data.get(2).ok_or(FontError::Truncated)?;
+    let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+    let count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+    let end = 4 + count * height;
+    let glyphs = data.get(4..end).ok_or(FontError::Truncated)?;
+
+    let mut unicode = BTreeMap::new();
+    if mode & (PSF1_MODEHASTAB | PSF1_MODEHASSEQ) != 0 {
+        // Per glyph: 16-bit characters, then sequences, then a separator
+        let mut entries = data[end..].chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+        for index in 0..count {
+            let mut in_sequence = false;
+            for entry in entries.by_ref() {
+                match entry {
+                    PSF1_SEPARATOR => break,
+                    PSF1_STARTSEQ => in_sequence = true,
+                    _ if in_sequence => {}
+                    _ => {
+                        if let Some(c) = char::from_u32(entry as u32) {
+                            unicode.entry(c).or_insert(index);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Font::new(8, height, glyphs.to_vec(), unicode)
+}
+
+fn parse_psf2(data: &[u8]) -> Result<Font, FontError> {
+    if data.len() < PSF2_HEADER_SIZE {
+        return Err(FontError::Truncated);
+    }
+    let header_size = read_u32(data, 8) as usize;
+    let flags = read_u32(data, 12);
+    let count = read_u32(data, 16) as usize;
+    let glyph_size = read_u32(data, 20) as usize;
+    let height = read_u32(data, 24) as usize;
+    let width = read_u32(data, 28) as usize;
+    if glyph_size != width.div_ceil(8) * height {
+        return Err(FontError::BadSize);
+    }
+    let end = count
+        .checked_mul(glyph_size)
+        .and_then(|size| size.checked_add(header_size))
+        .ok_or(FontError::Truncated)?;
+    let glyphs = data.get(header_size..end).ok_or(FontError::Truncated)?;
+
+    let mut unicode = BTreeMap::new();
+    if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+        // Per glyph: UTF-8 characters, then sequences, then a separator
+        let mut table = &data[end..];
+        for index in 0..count {
+            let len = table.iter().position(|&b| b == PSF2_SEPARATOR).unwrap_or(table.len());
+            let entry = &table[..len];
+            let singles = &entry[..entry.iter().position(|&b| b == PSF2_STARTSEQ).unwrap_or(len)];
+            for c in core::str::from_utf8(singles).unwrap_or("").chars() {
+                unicode.entry(c).or_insert(index);
+            }
+            table = table.get(len + 1..).unwrap_or(&[]);
+        }
+    }
+    Font::new(width, height, glyphs.to_vec(), unicode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_psf1_unicode_table() {
+        // 256 one-row glyphs; glyph 1 shows 'A' and 'Á' and, in a
+        // sequence, 'A' with a combining acute
+        let mut data = vec![0x36, 0x04, PSF1_MODEHASTAB, 1];
+        data.extend((0..256).map(|i| i as u8));
+        for glyph in 0..256u16 {
+            let entries: &[u16] = match glyph {
+                1 => &[0x41, 0xC1, PSF1_STARTSEQ, 0x41, 0x301],
+                _ => &[],
+            };
+            for entry in entries.iter().chain(&[PSF1_SEPARATOR]) {
+                data.extend(entry.to_le_bytes());
+            }
+        }
+
+        let font = parse(&data).unwrap();
+        assert_eq!(font.size(), (8, 1));
+        assert_eq!(font.glyph('A'), &[1]);
+        assert_eq!(font.glyph('Á'), &[1]);
+        // No replacement or '?' glyph: glyph 0 stands in
+        assert_eq!(font.glyph('\u{301}'), &[0]);
+        assert_eq!(font.glyph('€'), &[0]);
+    }
+
+    #[test]
+    fn test_psf2_wide_glyphs() {
+        // Three 10x2 glyphs, two bytes per row; glyph 2 shows U+FFFD
+        let mut data = Vec::new();
+        data.extend(PSF2_MAGIC);
+        for field in [0, 32, PSF2_HAS_UNICODE_TABLE, 3, 4, 2, 10] {
+            data.extend((field as u32).to_le_bytes());
+        }
+        data.extend([0u8; 4]);
+        data.extend([0xAAu8; 4]);
+        data.extend([0xFFu8, 0xC0, 0xFF, 0xC0]);
+        data.extend(b"a\xFFb\xFE\x62\x62\xFF\xEF\xBF\xBD\xFF");
+
+        let font = parse(&data).unwrap();
+        assert_eq!(font.size(), (10, 2));
+        assert_eq!(font.glyph_count(), 3);
+        assert_eq!(font.glyph('b'), &[0xAA; 4]);
+        assert_eq!(font.glyph('z'), &[0xFF, 0xC0, 0xFF, 0xC0]);
+        assert_eq!(parse(&data[..40]).err(), Some(FontError::Truncated));
+    }
+}
@@ -0,0 +1,175 @@
+//! Mouse pointer and text selection
+//!
+//! The pointer is shown by inverting the cell under it. Dragging with the
+//! left button selects cells in reading order, and releasing it copies
+//! them to the clipboard; the middle button types the clipboard into the
+//! console terminal. Console output hides the pointer and drops the
+//! selection, since the text it covered may have moved.
+
+use alloc::string::String;
+use spin::Mutex;
+
+use super::terminal::Terminal;
+use crate::drivers::mouse::{Buttons, MouseEvent};
+
+/// Pointer and selection state
+struct Selection {
+    /// Pointer position in pixels
+    x: i32,
+    y: i32,
+    /// Cell where the selection started, if any
+    anchor: Option<usize>,
+    /// Cell where the selection ends
+    end: usize,
+    buttons: Buttons,
+    /// Whether the pointer and selection are drawn
+    shown: bool,
+}
+
+impl Selection {
+    /// Inclusive range of selected cells
+    fn range(&self) -> Option<(usize, usize)> {
+        self.anchor.map(|anchor| (anchor.min(self.end), anchor.max(self.end)))
+    }
+
+    /// Cell under the pointer
+    fn pointer(&self, terminal: &Terminal) -> usize {
+        let (cell_width, cell_height) = terminal.cell_size();
+        (self.y as usize / cell_height) * terminal.dimensions().0 + self.x as usize / cell_width
+    }
+
+    /// What is drawn inverted: the selected range and the pointer cell
+    fn highlighted(&self, terminal: &Terminal) -> Highlight {
+        if !self.shown {
+            return Highlight { range: None, pointer: None };
+        }
+        Highlight {
+            range: self.range(),
+            pointer: Some(self.pointer(terminal)),
+        }
+    }
+}
+
+/// Cells drawn inverted at one moment
+#[derive(Clone, Copy)]
+struct Highlight {
+    range: Option<(usize, usize)>,
+    pointer: Option<usize>,
+}
+
+impl Highlight {
+    fn contains(&self, cell: usize) -> bool {
+        self.pointer == Some(cell) || self.range.is_some_and(|(start, end)| (start..=end).contains(&cell))
+    }
+
+    fn cells(&self) -> impl Iterator<Item = usize> {
+        self.range.map(|(start, end)| start..=end).into_iter().flatten().chain(self.pointer)
+    }
+}
+
+static SELECTION: Mutex<Selection> = Mutex::new(Selection {
+    x: 0,
+    y: 0,
+    anchor: None,
+    end: 0,
+    buttons: Buttons::empty(),
+    shown: false,
+});
+
+/// Text copied by the last selection
+static CLIPBOARD: Mutex<String> = Mutex::new(String::new());
+
+/// Redraw the cells whose highlighting changed from `old` to `new`
+fn redraw(terminal: &mut Terminal, old: Highlight, new: Highlight) {
+    let width = terminal.dimensions().0;
+    for cell in old.cells().chain(new.cells()) {
+        if old.contains(cell) != new.contains(cell) {
+            terminal.highlight(cell % width, cell / width, new.contains(cell));
+        }
+    }
+}
+
+/// Copy the selected cells, one line per row without trailing spaces
+fn copy(terminal: &Terminal, start: usize, end: usize) -> String {
+    let width = terminal.dimensions().0;
+    let mut text = String::new();
+    for row in start / width..=end / width {
+        let first = if row == start / width { start % width } else { 0 };
+        let last = if row == end / width { end % width } else { width - 1 };
+        let line: String = (first..=last).map(|x| terminal.char_at(x, row)).collect();
+        text.push_str(line.trim_end());
+        if row != end / width {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Move the pointer and update the selection for a mouse event
+///
+/// Returns whether the middle button was pressed; the caller pastes with
+/// `paste` once it has let go of the terminal.
+pub fn handle_mouse(terminal: &mut Terminal, event: &MouseEvent) -> bool {
+    let mut selection = SELECTION.lock();
+    let (width, height) = terminal.dimensions();
+    let (cell_width, cell_height) = terminal.cell_size();
+    let old = selection.highlighted(terminal);
+
+    if !selection.shown {
+        // Reappear in the middle of the screen the first time
+        if selection.x == 0 && selection.y == 0 {
+            selection.x = (width * cell_width / 2) as i32;
+            selection.y = (height * cell_height / 2) as i32;
+        }
+        selection.shown = true;
+    }
+    selection.x = (selection.x + event.dx as i32).clamp(0, (width * cell_width) as i32 - 1);
+    selection.y = (selection.y + event.dy as i32).clamp(0, (height * cell_height) as i32 - 1);
+    let pointer = selection.pointer(terminal);
+
+    let pressed = event.buttons.difference(selection.buttons);
+    let released = selection.buttons.difference(event.buttons);
+    selection.buttons = event.buttons;
+
+    if pressed.contains(Buttons::LEFT) {
+        selection.anchor = Some(pointer);
+        selection.end = pointer;
+    } else if event.buttons.contains(Buttons::LEFT) {
+        selection.end = pointer;
+    }
+    if released.contains(Buttons::LEFT)
+        && let Some((start, end)) = selection.range()
+    {
+        *CLIPBOARD.lock() = copy(terminal, start, end);
+    }
+
+    let new = selection.highlighted(terminal);
+    redraw(terminal, old, new);
+    pressed.contains(Buttons::MIDDLE)
+}
+
+/// Type the clipboard into the terminal on screen
+pub fn paste() {
+    let Some(tty) = crate::tty::foreground() else {
+        return;
+    };
+    let text = x86_64::instructions::interrupts::without_interrupts(|| CLIPBOARD.lock().clone());
+    for c in text.chars() {
+        crate::tty::input(tty, c);
+    }
+}
+
+/// Remove the pointer and selection from the screen before output
+pub fn hide(terminal: &mut Terminal) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut selection = SELECTION.lock();
+        if !selection.shown {
+            return;
+        }
+        let old = selection.highlighted(terminal);
+        selection.shown = false;
+        selection.anchor = None;
+        let new = selection.highlighted(terminal);
+        redraw(terminal, old, new);
+    });
+}
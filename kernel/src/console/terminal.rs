@@ -1,7 +1,26 @@
 //! Terminal emulator with text rendering
+//!
+//! Output is interpreted as a VT100/xterm-style stream. Control characters
+//! and escape sequences, split out by `ansi::Parser`, move the cursor,
+//! erase, insert and delete, scroll within the scroll region and select
+//! colors: the 16 ANSI colors, the xterm 256-color palette or 24-bit RGB.
+//! Text is UTF-8; characters the font lacks show its replacement glyph.
+//!
+//! The screen is kept as a grid of cells, each a character and its
+//! colors, and drawn from it while the terminal has the framebuffer;
+//! without it, output only updates the cells. Lines scrolled off the top
+//! of the screen go into a scrollback ring, which the view can be moved
+//! back through; new output returns the view to the bottom. Full-screen
+//! programs can switch to an alternate screen, which has no scrollback and
+//! gives the main screen back unchanged when they leave it.
 
-use limine::framebuffer::Framebuffer;
-use super::font::{self, FONT_WIDTH, FONT_HEIGHT};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use super::ansi::{Action, Csi, Parser};
+use super::psf::{Font, REPLACEMENT};
+use crate::drivers::framebuffer::{FramebufferDevice, Rect};
 
 /// Terminal colors
 pub const BLACK: u32 = 0x000000;
@@ -10,193 +29,1103 @@ pub const GREEN: u32 = 0x00FF00;
 pub const BLUE: u32 = 0x0000FF;
 pub const RED: u32 = 0xFF0000;
 
+/// Columns between tab stops
+const TAB_WIDTH: usize = 4;
+
+/// Lines of scrollback kept unless changed
+pub const DEFAULT_SCROLLBACK: usize = 500;
+
+/// DEC private modes set with `CSI ? n h` and reset with `CSI ? n l`
+const MODE_AUTOWRAP: u16 = 7;
+const MODE_CURSOR_VISIBLE: u16 = 25;
+const MODE_ALTERNATE_SCREEN: u16 = 47;
+const MODE_ALTERNATE_SCREEN_CLEAR: u16 = 1047;
+const MODE_ALTERNATE_SCREEN_SAVE_CURSOR: u16 = 1049;
+
+/// The 16 ANSI colors, normal then bright
+const ANSI_COLORS: [u32; 16] = [
+    0x000000, 0xAA0000, 0x00AA00, 0xAA5500, 0x0000AA, 0xAA00AA, 0x00AAAA, 0xAAAAAA,
+    0x555555, 0xFF5555, 0x55FF55, 0xFFFF55, 0x5555FF, 0xFF55FF, 0x55FFFF, 0xFFFFFF,
+];
+
+/// Color of an entry in the xterm 256-color palette
+fn palette(index: u8) -> u32 {
+    match index {
+        0..=15 => ANSI_COLORS[index as usize],
+        16..=231 => {
+            // 6x6x6 color cube
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v as u32 * 40 };
+            let i = index - 16;
+            level(i / 36) << 16 | level(i / 6 % 6) << 8 | level(i % 6)
+        }
+        232..=255 => {
+            // Grayscale ramp
+            let gray = 8 + (index - 232) as u32 * 10;
+            gray << 16 | gray << 8 | gray
+        }
+    }
+}
+
+/// A color chosen with SGR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    /// The terminal's default foreground or background
+    Default,
+    Indexed(u8),
+    Rgb(u32),
+}
+
+/// Graphic rendition set with SGR
+#[derive(Debug, Clone, Copy)]
+struct Pen {
+    fg: Color,
+    bg: Color,
+    /// Shows the first 8 colors in their bright variant
+    bold: bool,
+    reverse: bool,
+}
+
+impl Pen {
+    const DEFAULT: Pen = Pen {
+        fg: Color::Default,
+        bg: Color::Default,
+        bold: false,
+        reverse: false,
+    };
+}
+
+/// A character cell of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub fg: u32,
+    pub bg: u32,
+}
+
+impl Cell {
+    fn blank(bg: u32) -> Self {
+        Self { c: ' ', fg: bg, bg }
+    }
+}
+
+/// Assembles UTF-8 sequences from bytes, for terminal output and serial
+/// input
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    code: u32,
+    /// Continuation bytes still expected
+    remaining: u8,
+    /// Smallest code point the sequence may encode without being overlong
+    min: u32,
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Self { code: 0, remaining: 0, min: 0 }
+    }
+
+    /// Feed a byte of 0x80 or above, returning a character once one is
+    /// complete; malformed input decodes to U+FFFD
+    pub fn feed(&mut self, byte: u8) -> Option<char> {
+        if (0x80..=0xBF).contains(&byte) {
+            if self.remaining == 0 {
+                return Some(REPLACEMENT);
+            }
+            self.code = self.code << 6 | (byte & 0x3F) as u32;
+            self.remaining -= 1;
+            if self.remaining > 0 {
+                return None;
+            }
+            let c = char::from_u32(self.code).filter(|&c| c as u32 >= self.min);
+            return Some(c.unwrap_or(REPLACEMENT));
+        }
+
+        // A new lead byte cuts short any sequence in progress; a byte that
+        // cannot start one ends it too, with a single U+FFFD for both
+        let cut_short = self.remaining > 0;
+        (self.code, self.remaining, self.min) = match byte {
+            0xC2..=0xDF => ((byte & 0x1F) as u32, 1, 0x80),
+            0xE0..=0xEF => ((byte & 0x0F) as u32, 2, 0x800),
+            0xF0..=0xF4 => ((byte & 0x07) as u32, 3, 0x10000),
+            _ => {
+                self.remaining = 0;
+                return Some(REPLACEMENT);
+            }
+        };
+        cut_short.then_some(REPLACEMENT)
+    }
+
+    /// Drop a sequence in progress, returning whether there was one
+    pub fn abandon(&mut self) -> bool {
+        core::mem::take(&mut self.remaining) > 0
+    }
+}
+
+/// State kept by save cursor
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    x: usize,
+    y: usize,
+    pen: Pen,
+}
+
 /// Terminal state
 pub struct Terminal {
-    framebuffer: &'static Framebuffer<'static>,
+    /// Where the terminal is drawn, while it is on screen
+    framebuffer: Option<FramebufferDevice>,
+    /// Size of the screen in pixels
+    screen_width: usize,
+    screen_height: usize,
     width_chars: usize,
     height_chars: usize,
     cursor_x: usize,
     cursor_y: usize,
+    /// A character went into the last column; the next one wraps first
+    wrap_pending: bool,
+    /// Printing past the last column wraps to the next line, rather than
+    /// overwriting the last column
+    autowrap: bool,
+    /// Colors the default foreground and background stand for
     fg_color: u32,
     bg_color: u32,
+    pen: Pen,
+    saved: SavedCursor,
+    /// First and last rows of the scroll region
+    scroll_top: usize,
+    scroll_bottom: usize,
+    parser: Parser,
+    utf8: Utf8Decoder,
+    font: Arc<Font>,
+    /// Answers to status requests, to be fed back as input
+    replies: Vec<u8>,
+    /// The screen, row by row
+    cells: Vec<Cell>,
+    /// The main screen's cells while the alternate screen is shown
+    main_screen: Option<Vec<Cell>>,
+    /// Lines scrolled off the screen, oldest first
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_limit: usize,
+    /// How many lines the view is scrolled back into history
+    view_offset: usize,
 }
 
 impl Terminal {
-    /// Create a new terminal
-    pub fn new(framebuffer: &'static Framebuffer<'static>) -> Self {
-        let width_chars = framebuffer.width() as usize / FONT_WIDTH;
-        let height_chars = framebuffer.height() as usize / FONT_HEIGHT;
-        
+    /// Create a terminal for a screen of `width` by `height` pixels, not
+    /// yet shown
+    ///
+    /// A screen smaller than one cell still gets a grid of one, which is
+    /// clipped when drawn.
+    pub fn new(width: usize, height: usize, font: Arc<Font>) -> Self {
+        let (font_width, font_height) = font.size();
+        let width_chars = (width / font_width).max(1);
+        let height_chars = (height / font_height).max(1);
+
         Self {
-            framebuffer,
+            framebuffer: None,
+            screen_width: width,
+            screen_height: height,
             width_chars,
             height_chars,
             cursor_x: 0,
             cursor_y: 0,
+            wrap_pending: false,
+            autowrap: true,
             fg_color: WHITE,
             bg_color: BLACK,
+            pen: Pen::DEFAULT,
+            saved: SavedCursor { x: 0, y: 0, pen: Pen::DEFAULT },
+            scroll_top: 0,
+            scroll_bottom: height_chars - 1,
+            parser: Parser::new(),
+            utf8: Utf8Decoder::default(),
+            font,
+            replies: Vec::new(),
+            cells: vec![Cell::blank(BLACK); width_chars * height_chars],
+            main_screen: None,
+            scrollback: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK,
+            view_offset: 0,
         }
     }
-    
+
     /// Get terminal dimensions in characters
     pub fn dimensions(&self) -> (usize, usize) {
         (self.width_chars, self.height_chars)
     }
-    
+
+    /// Size of a character cell in pixels
+    pub fn cell_size(&self) -> (usize, usize) {
+        self.font.size()
+    }
+
+    pub fn font(&self) -> &Arc<Font> {
+        &self.font
+    }
+
+    /// Show the terminal on a framebuffer, drawing it in full
+    pub fn attach(&mut self, framebuffer: FramebufferDevice) {
+        self.framebuffer = Some(framebuffer);
+        self.fill_screen(self.bg_color);
+        self.redraw();
+        self.flush();
+    }
+
+    /// Take the terminal off screen, giving back its framebuffer
+    pub fn detach(&mut self) -> Option<FramebufferDevice> {
+        self.framebuffer.take()
+    }
+
+    /// Switch to another font, resizing the grid to fit the screen
+    ///
+    /// Text keeps its place from the top left; if the cursor's row no
+    /// longer fits, the rows above it move up into the scrollback. The
+    /// alternate screen is left first, as its program cannot redraw it.
+    /// A font whose glyphs are larger than the screen is refused.
+    pub fn set_font(&mut self, font: Arc<Font>) -> Result<(), &'static str> {
+        let (font_width, font_height) = font.size();
+        let (new_width, new_height) = (self.screen_width / font_width, self.screen_height / font_height);
+        if new_width == 0 || new_height == 0 {
+            return Err("font is larger than the screen");
+        }
+        self.leave_alternate_screen();
+
+        let dropped = (self.cursor_y + 1).saturating_sub(new_height);
+        self.push_scrollback(dropped);
+        let mut cells = vec![Cell::blank(self.bg_color); new_width * new_height];
+        let columns = self.width_chars.min(new_width);
+        for (y, row) in self.cells.chunks(self.width_chars).skip(dropped).take(new_height).enumerate() {
+            cells[y * new_width..y * new_width + columns].copy_from_slice(&row[..columns]);
+        }
+
+        self.font = font;
+        self.cells = cells;
+        self.width_chars = new_width;
+        self.height_chars = new_height;
+        self.cursor_x = self.cursor_x.min(new_width - 1);
+        self.cursor_y -= dropped;
+        self.wrap_pending = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = new_height - 1;
+        self.view_offset = 0;
+
+        self.fill_screen(self.bg_color);
+        self.redraw();
+        self.flush();
+        Ok(())
+    }
+
     /// Set cursor position
     pub fn set_cursor(&mut self, x: usize, y: usize) {
         if x < self.width_chars && y < self.height_chars {
             self.cursor_x = x;
             self.cursor_y = y;
+            self.wrap_pending = false;
         }
     }
-    
+
     /// Clear the screen
     pub fn clear(&mut self) {
-        let fb_ptr = self.framebuffer.addr() as *mut u32;
-        let width = self.framebuffer.width() as usize;
-        let height = self.framebuffer.height() as usize;
-        let pitch = self.framebuffer.pitch() as usize / 4; // Convert to u32 pitch
-        
-        unsafe {
-            for y in 0..height {
-                for x in 0..width {
-                    let offset = y * pitch + x;
-                    *fb_ptr.add(offset) = self.bg_color;
-                }
-            }
-        }
-        
+        let (_, bg_color) = self.colors();
+        self.fill_screen(bg_color);
+        self.flush();
+
+        self.cells.fill(Cell::blank(bg_color));
+        self.view_offset = 0;
         self.cursor_x = 0;
         self.cursor_y = 0;
+        self.wrap_pending = false;
     }
-    
+
     /// Write a string to the terminal
     pub fn write_str(&mut self, s: &str) {
         for byte in s.bytes() {
             self.write_char(byte);
         }
+        self.flush();
     }
-    
-    /// Write a single character
+
+    /// Write a single byte of output; it shows once the terminal is flushed
     pub fn write_char(&mut self, c: u8) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw();
+        }
+        let action = self.parser.advance(c);
+        if !matches!(action, Some(Action::Print(0x80..)) | None) && self.utf8.abandon() {
+            self.print(REPLACEMENT);
+        }
+        match action {
+            Some(Action::Print(c @ 0x80..)) => {
+                if let Some(c) = self.utf8.feed(c) {
+                    self.print(c);
+                }
+            }
+            Some(Action::Print(c)) => self.print(c as char),
+            Some(Action::Control(c)) => self.control(c),
+            Some(Action::Escape { intermediate: None, final_byte }) => self.escape(final_byte),
+            Some(Action::Csi(csi)) => self.csi(&csi),
+            Some(Action::Escape { .. }) | None => {}
+        }
+    }
+
+    /// Copy what was drawn since the last flush to the screen
+    pub fn flush(&mut self) {
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.flush();
+        }
+    }
+
+    /// Lines of scrollback kept
+    pub fn scrollback_limit(&self) -> usize {
+        self.scrollback_limit
+    }
+
+    /// Change how many lines of scrollback are kept, dropping the oldest
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scrollback_limit = lines;
+        let excess = self.scrollback.len().saturating_sub(lines);
+        self.scrollback.drain(..excess);
+        if self.view_offset > self.scrollback.len() {
+            self.view_offset = self.scrollback.len();
+            self.redraw();
+            self.flush();
+        }
+    }
+
+    /// Move the view `lines` back into history, or forward if negative
+    pub fn scroll_view(&mut self, lines: isize) {
+        let offset = self.view_offset.saturating_add_signed(lines).min(self.scrollback.len());
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+            self.flush();
+        }
+    }
+
+    /// Whether answers to status requests are waiting to be taken
+    pub fn has_replies(&self) -> bool {
+        !self.replies.is_empty()
+    }
+
+    /// Take the answers to status requests written so far
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.replies)
+    }
+
+    /// Current foreground and background colors
+    fn colors(&self) -> (u32, u32) {
+        let pen = &self.pen;
+        let fg = match pen.fg {
+            Color::Default => self.fg_color,
+            Color::Indexed(i) if pen.bold && i < 8 => palette(i + 8),
+            Color::Indexed(i) => palette(i),
+            Color::Rgb(rgb) => rgb,
+        };
+        let bg = match pen.bg {
+            Color::Default => self.bg_color,
+            Color::Indexed(i) => palette(i),
+            Color::Rgb(rgb) => rgb,
+        };
+        if pen.reverse { (bg, fg) } else { (fg, bg) }
+    }
+
+    /// Draw a printable character at the cursor and advance it
+    fn print(&mut self, c: char) {
+        if self.wrap_pending {
+            self.cursor_x = 0;
+            self.linefeed();
+        }
+        self.draw_char_at(self.cursor_x, self.cursor_y, c);
+        if self.cursor_x + 1 < self.width_chars {
+            self.cursor_x += 1;
+        } else {
+            self.wrap_pending = self.autowrap;
+        }
+    }
+
+    fn control(&mut self, c: u8) {
         match c {
             b'\n' => {
+                // Newline also returns the carriage, as with ONLCR
                 self.cursor_x = 0;
-                self.cursor_y += 1;
-                if self.cursor_y >= self.height_chars {
-                    self.scroll_up();
-                    self.cursor_y = self.height_chars - 1;
-                }
+                self.linefeed();
             }
+            0x0B | 0x0C => self.linefeed(),
             b'\r' => {
                 self.cursor_x = 0;
+                self.wrap_pending = false;
             }
             b'\t' => {
                 // Tab to next 4-character boundary
-                self.cursor_x = (self.cursor_x + 4) & !3;
-                if self.cursor_x >= self.width_chars {
-                    self.cursor_x = 0;
-                    self.cursor_y += 1;
-                    if self.cursor_y >= self.height_chars {
-                        self.scroll_up();
-                        self.cursor_y = self.height_chars - 1;
+                let x = (self.cursor_x / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.set_cursor(x.min(self.width_chars - 1), self.cursor_y);
+            }
+            0x08 => {
+                // Backspace moves left without erasing
+                self.set_cursor(self.cursor_x.saturating_sub(1), self.cursor_y);
+            }
+            _ => {
+                // Ignore other control characters
+            }
+        }
+    }
+
+    /// Move down a line, scrolling if the cursor is on the region's last row
+    fn linefeed(&mut self) {
+        if self.cursor_y == self.scroll_bottom {
+            self.scroll_up(self.scroll_top, self.scroll_bottom, 1);
+        } else if self.cursor_y + 1 < self.height_chars {
+            self.cursor_y += 1;
+        }
+        self.wrap_pending = false;
+    }
+
+    /// Move up a line, scrolling if the cursor is on the region's first row
+    fn reverse_index(&mut self) {
+        if self.cursor_y == self.scroll_top {
+            self.scroll_down(self.scroll_top, self.scroll_bottom, 1);
+        } else if self.cursor_y > 0 {
+            self.cursor_y -= 1;
+        }
+        self.wrap_pending = false;
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = SavedCursor {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            pen: self.pen,
+        };
+    }
+
+    fn restore_cursor(&mut self) {
+        self.pen = self.saved.pen;
+        self.set_cursor(self.saved.x, self.saved.y);
+    }
+
+    /// Handle ESC followed by `final_byte`
+    fn escape(&mut self, final_byte: u8) {
+        match final_byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.cursor_x = 0;
+                self.linefeed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => {
+                // Full reset
+                self.leave_alternate_screen();
+                self.autowrap = true;
+                self.pen = Pen::DEFAULT;
+                self.saved = SavedCursor { x: 0, y: 0, pen: Pen::DEFAULT };
+                self.scroll_top = 0;
+                self.scroll_bottom = self.height_chars - 1;
+                self.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Set or reset DEC private modes
+    fn private_modes(&mut self, csi: &Csi, set: bool) {
+        for &mode in csi.params() {
+            match mode {
+                MODE_AUTOWRAP => {
+                    self.autowrap = set;
+                    self.wrap_pending &= set;
+                }
+                // No cursor is drawn, so there is nothing to show or hide
+                MODE_CURSOR_VISIBLE => {}
+                MODE_ALTERNATE_SCREEN | MODE_ALTERNATE_SCREEN_CLEAR | MODE_ALTERNATE_SCREEN_SAVE_CURSOR => {
+                    let save_cursor = mode == MODE_ALTERNATE_SCREEN_SAVE_CURSOR;
+                    if set {
+                        if save_cursor {
+                            self.save_cursor();
+                        }
+                        self.enter_alternate_screen();
+                    } else {
+                        self.leave_alternate_screen();
+                        if save_cursor {
+                            self.restore_cursor();
+                        }
                     }
                 }
+                _ => {}
             }
-            b'\x08' => {
-                // Backspace
-                if self.cursor_x > 0 {
-                    self.cursor_x -= 1;
-                    self.draw_char_at(self.cursor_x, self.cursor_y, b' ');
-                }
+        }
+    }
+
+    /// Show a blank alternate screen in place of the main one
+    fn enter_alternate_screen(&mut self) {
+        if self.main_screen.is_none() {
+            self.main_screen = Some(self.cells.clone());
+            self.erase_rows(0, self.height_chars);
+        }
+    }
+
+    /// Bring back the main screen as it was before the alternate one
+    fn leave_alternate_screen(&mut self) {
+        if let Some(cells) = self.main_screen.take() {
+            self.cells = cells;
+            self.redraw();
+        }
+    }
+
+    /// Handle a control sequence
+    fn csi(&mut self, csi: &Csi) {
+        if csi.private == Some(b'?') && csi.intermediate.is_none() {
+            match csi.final_byte {
+                b'h' => self.private_modes(csi, true),
+                b'l' => self.private_modes(csi, false),
+                _ => {}
             }
-            c if c >= 32 && c <= 126 => {
-                // Printable character
-                self.draw_char_at(self.cursor_x, self.cursor_y, c);
-                self.cursor_x += 1;
-                if self.cursor_x >= self.width_chars {
-                    self.cursor_x = 0;
-                    self.cursor_y += 1;
-                    if self.cursor_y >= self.height_chars {
-                        self.scroll_up();
-                        self.cursor_y = self.height_chars - 1;
-                    }
+            return;
+        }
+        // Other private and intermediate forms are not supported
+        if csi.private.is_some() || csi.intermediate.is_some() {
+            return;
+        }
+        let n = csi.param(0, 1) as usize;
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        let (width, height) = (self.width_chars, self.height_chars);
+        // Vertical movement stops at the scroll region's margins from inside it
+        let top = if y >= self.scroll_top { self.scroll_top } else { 0 };
+        let bottom = if y <= self.scroll_bottom { self.scroll_bottom } else { height - 1 };
+
+        match csi.final_byte {
+            b'A' => self.set_cursor(x, y.saturating_sub(n).max(top)),
+            b'B' => self.set_cursor(x, (y + n).min(bottom)),
+            b'C' => self.set_cursor((x + n).min(width - 1), y),
+            b'D' => self.set_cursor(x.saturating_sub(n), y),
+            b'E' => self.set_cursor(0, (y + n).min(bottom)),
+            b'F' => self.set_cursor(0, y.saturating_sub(n).max(top)),
+            b'G' | b'`' => self.set_cursor((n - 1).min(width - 1), y),
+            b'd' => self.set_cursor(x, (n - 1).min(height - 1)),
+            b'H' | b'f' => {
+                let row = csi.param(0, 1) as usize - 1;
+                let column = csi.param(1, 1) as usize - 1;
+                self.set_cursor(column.min(width - 1), row.min(height - 1));
+            }
+            b'J' => match csi.param(0, 0) {
+                0 => {
+                    self.erase_cells(x, width, y);
+                    self.erase_rows(y + 1, height);
+                }
+                1 => {
+                    self.erase_rows(0, y);
+                    self.erase_cells(0, x + 1, y);
                 }
+                2 | 3 => self.erase_rows(0, height),
+                _ => {}
+            },
+            b'K' => match csi.param(0, 0) {
+                0 => self.erase_cells(x, width, y),
+                1 => self.erase_cells(0, x + 1, y),
+                2 => self.erase_cells(0, width, y),
+                _ => {}
+            },
+            b'L' if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                self.scroll_down(y, self.scroll_bottom, n);
+                self.cursor_x = 0;
             }
-            _ => {
-                // Ignore other control characters
+            b'M' if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                self.scroll_up(y, self.scroll_bottom, n);
+                self.cursor_x = 0;
+            }
+            b'@' => {
+                let n = n.min(width - x);
+                self.move_cells(y, x, x + n, width - x - n);
+                self.erase_cells(x, x + n, y);
+            }
+            b'P' => {
+                let n = n.min(width - x);
+                self.move_cells(y, x + n, x, width - x - n);
+                self.erase_cells(width - n, width, y);
             }
+            b'X' => self.erase_cells(x, (x + n).min(width), y),
+            b'S' => self.scroll_up(self.scroll_top, self.scroll_bottom, n),
+            b'T' => self.scroll_down(self.scroll_top, self.scroll_bottom, n),
+            b'm' => self.sgr(csi),
+            b'r' => {
+                let new_top = csi.param(0, 1) as usize - 1;
+                let new_bottom = (csi.param(1, height as u16) as usize - 1).min(height - 1);
+                if new_top < new_bottom {
+                    self.scroll_top = new_top;
+                    self.scroll_bottom = new_bottom;
+                    self.set_cursor(0, 0);
+                }
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            b'n' => match csi.param(0, 0) {
+                // Device status: report OK
+                5 => self.replies.extend_from_slice(b"\x1b[0n"),
+                // Cursor position report
+                6 => {
+                    let report = alloc::format!("\x1b[{};{}R", y + 1, x + 1);
+                    self.replies.extend_from_slice(report.as_bytes());
+                }
+                _ => {}
+            },
+            _ => {}
         }
     }
-    
-    /// Draw a character at specific position
-    fn draw_char_at(&mut self, x: usize, y: usize, c: u8) {
-        if x >= self.width_chars || y >= self.height_chars {
+
+    /// Select graphic rendition
+    fn sgr(&mut self, csi: &Csi) {
+        let params = csi.params();
+        if params.is_empty() {
+            self.pen = Pen::DEFAULT;
             return;
         }
-        
-        let char_data = font::get_char_data(c);
-        let fb_ptr = self.framebuffer.addr() as *mut u32;
-        let pitch = self.framebuffer.pitch() as usize / 4; // Convert to u32 pitch
-        
-        let start_x = x * FONT_WIDTH;
-        let start_y = y * FONT_HEIGHT;
-        
-        unsafe {
-            for row in 0..FONT_HEIGHT {
-                let font_row = char_data[row];
-                for col in 0..FONT_WIDTH {
-                    let pixel_x = start_x + col;
-                    let pixel_y = start_y + row;
-                    let offset = pixel_y * pitch + pixel_x;
-                    
-                    let color = if (font_row & (0x80 >> col)) != 0 {
-                        self.fg_color
-                    } else {
-                        self.bg_color
+
+        let mut i = 0;
+        while i < params.len() {
+            let pen = &mut self.pen;
+            match params[i] {
+                0 => *pen = Pen::DEFAULT,
+                1 => pen.bold = true,
+                22 => pen.bold = false,
+                7 => pen.reverse = true,
+                27 => pen.reverse = false,
+                p @ 30..=37 => pen.fg = Color::Indexed(p as u8 - 30),
+                39 => pen.fg = Color::Default,
+                p @ 40..=47 => pen.bg = Color::Indexed(p as u8 - 40),
+                49 => pen.bg = Color::Default,
+                p @ 90..=97 => pen.fg = Color::Indexed(p as u8 - 90 + 8),
+                p @ 100..=107 => pen.bg = Color::Indexed(p as u8 - 100 + 8),
+                p @ (38 | 48) => {
+                    // 5;INDEX or 2;R;G;B
+                    let (color, used) = match params[i + 1..] {
+                        [5, index, ..] => (Some(Color::Indexed(index as u8)), 2),
+                        [2, r, g, b, ..] => {
+                            let rgb = (r as u32 & 0xFF) << 16 | (g as u32 & 0xFF) << 8 | (b as u32 & 0xFF);
+                            (Some(Color::Rgb(rgb)), 4)
+                        }
+                        _ => (None, params.len()),
                     };
-                    
-                    *fb_ptr.add(offset) = color;
+                    if let Some(color) = color {
+                        if p == 38 {
+                            pen.fg = color;
+                        } else {
+                            pen.bg = color;
+                        }
+                    }
+                    i += used;
                 }
+                _ => {}
             }
+            i += 1;
         }
     }
-    
-    /// Scroll the screen up by one line
-    fn scroll_up(&mut self) {
-        let fb_ptr = self.framebuffer.addr() as *mut u32;
-        let pitch = self.framebuffer.pitch() as usize / 4;
-        let width = self.framebuffer.width() as usize;
-        
-        unsafe {
-            // Move all lines up by FONT_HEIGHT pixels
-            for y in FONT_HEIGHT..(self.height_chars * FONT_HEIGHT) {
-                for x in 0..width {
-                    let src_offset = y * pitch + x;
-                    let dst_offset = (y - FONT_HEIGHT) * pitch + x;
-                    *fb_ptr.add(dst_offset) = *fb_ptr.add(src_offset);
-                }
+
+    /// Draw a character at specific position
+    fn draw_char_at(&mut self, x: usize, y: usize, c: char) {
+        if x >= self.width_chars || y >= self.height_chars {
+            return;
+        }
+        let (fg, bg) = self.colors();
+        let cell = Cell { c, fg, bg };
+        self.cells[y * self.width_chars + x] = cell;
+        self.render_cell(x, y, cell);
+    }
+
+    /// The cell shown at a position, from history if the view is scrolled back
+    fn visible(&self, x: usize, y: usize) -> Cell {
+        let line = self.scrollback.len() - self.view_offset + y;
+        let cell = match self.scrollback.get(line) {
+            Some(row) => row.get(x),
+            None => self.cells.get((line - self.scrollback.len()) * self.width_chars + x),
+        };
+        cell.copied().unwrap_or(Cell::blank(self.bg_color))
+    }
+
+    /// The character shown at a cell
+    pub fn char_at(&self, x: usize, y: usize) -> char {
+        self.visible(x, y).c
+    }
+
+    /// Redraw a cell, with its colors swapped if `inverted`
+    pub fn highlight(&mut self, x: usize, y: usize, inverted: bool) {
+        if x >= self.width_chars || y >= self.height_chars {
+            return;
+        }
+        let cell = self.visible(x, y);
+        if inverted {
+            self.render_cell(x, y, Cell { fg: cell.bg, bg: cell.fg, ..cell });
+        } else {
+            self.render_cell(x, y, cell);
+        }
+        self.flush();
+    }
+
+    /// Draw every cell of the view
+    fn redraw(&mut self) {
+        if self.framebuffer.is_none() {
+            return;
+        }
+        for y in 0..self.height_chars {
+            for x in 0..self.width_chars {
+                self.render_cell(x, y, self.visible(x, y));
             }
-            
-            // Clear the last line
-            let last_line_start = (self.height_chars - 1) * FONT_HEIGHT;
-            for y in last_line_start..(self.height_chars * FONT_HEIGHT) {
-                for x in 0..width {
-                    let offset = y * pitch + x;
-                    *fb_ptr.add(offset) = self.bg_color;
-                }
+        }
+    }
+
+    /// Draw a cell's glyph in its colors
+    fn render_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        let (width, height) = self.font.size();
+        if let Some(framebuffer) = &mut self.framebuffer {
+            let glyph = self.font.glyph(cell.c);
+            framebuffer.draw_bitmap(x * width, y * height, width, glyph, cell.fg, cell.bg);
+        }
+    }
+
+    /// Fill the whole screen, margins included, with a color
+    fn fill_screen(&mut self, color: u32) {
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.fill_rect(Rect::new(0, 0, self.screen_width, self.screen_height), color);
+        }
+    }
+
+    /// Fill the cells in columns `x0..x1` of rows `y0..y1` with a color
+    fn fill_cells(&mut self, x0: usize, x1: usize, y0: usize, y1: usize, color: u32) {
+        let (width, height) = self.font.size();
+        let rect = Rect::new(x0 * width, y0 * height, (x1 - x0) * width, (y1 - y0) * height);
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.fill_rect(rect, color);
+        }
+    }
+
+    /// Blank columns `x0..x1` of row `y` in the current background
+    fn erase_cells(&mut self, x0: usize, x1: usize, y: usize) {
+        if x0 >= x1 {
+            return;
+        }
+        let bg = self.colors().1;
+        self.fill_cells(x0, x1, y, y + 1, bg);
+        let row = y * self.width_chars;
+        self.cells[row + x0..row + x1].fill(Cell::blank(bg));
+    }
+
+    /// Blank rows `y0..y1` in the current background
+    fn erase_rows(&mut self, y0: usize, y1: usize) {
+        if y0 >= y1 {
+            return;
+        }
+        let bg = self.colors().1;
+        self.fill_cells(0, self.width_chars, y0, y1, bg);
+        self.cells[y0 * self.width_chars..y1 * self.width_chars].fill(Cell::blank(bg));
+    }
+
+    /// Copy `count` character rows starting at row `src` to row `dst`
+    fn move_rows(&mut self, src: usize, dst: usize, count: usize) {
+        // Whole scanlines, so the framebuffer can move them in one piece
+        let height = self.font.size().1;
+        let rect = Rect::new(0, src * height, self.screen_width, count * height);
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.copy_rect(rect, 0, dst * height);
+        }
+
+        let width = self.width_chars;
+        self.cells.copy_within(src * width..(src + count) * width, dst * width);
+    }
+
+    /// Copy `count` cells of row `y` from column `src` to column `dst`
+    fn move_cells(&mut self, y: usize, src: usize, dst: usize, count: usize) {
+        let (width, height) = self.font.size();
+        let rect = Rect::new(src * width, y * height, count * width, height);
+        if let Some(framebuffer) = &mut self.framebuffer {
+            framebuffer.copy_rect(rect, dst * width, y * height);
+        }
+
+        let row = y * self.width_chars;
+        self.cells.copy_within(row + src..row + src + count, row + dst);
+    }
+
+    /// Scroll rows `top..=bottom` up by `n`, blanking the rows uncovered
+    ///
+    /// Rows leaving the top of the screen are kept as scrollback.
+    fn scroll_up(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        if top == 0 {
+            self.push_scrollback(n);
+        }
+        self.move_rows(top + n, top, bottom + 1 - top - n);
+        self.erase_rows(bottom + 1 - n, bottom + 1);
+    }
+
+    /// Keep the screen's first `n` rows as scrollback, unless they are on
+    /// the alternate screen
+    fn push_scrollback(&mut self, n: usize) {
+        if self.scrollback_limit == 0 || self.main_screen.is_some() {
+            return;
+        }
+        for row in self.cells.chunks(self.width_chars).take(n) {
+            if self.scrollback.len() == self.scrollback_limit {
+                self.scrollback.pop_front();
             }
+            self.scrollback.push_back(row.to_vec());
         }
     }
-    
+
+    /// Scroll rows `top..=bottom` down by `n`, blanking the rows uncovered
+    fn scroll_down(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        self.move_rows(top, top + n, bottom + 1 - top - n);
+        self.erase_rows(top, top + n);
+    }
+
     /// Set foreground color
     pub fn set_fg_color(&mut self, color: u32) {
         self.fg_color = color;
     }
-    
+
     /// Set background color
     pub fn set_bg_color(&mut self, color: u32) {
         self.bg_color = color;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+
+    /// A terminal of `width` by `height` cells with no framebuffer
+    fn terminal(width: usize, height: usize) -> Terminal {
+        let font = Font::new(8, 16, vec![0; 16 * 256], BTreeMap::new()).unwrap();
+        Terminal::new(width * 8, height * 16, Arc::new(font))
+    }
+
+    /// The visible text of row `y`
+    fn row(terminal: &Terminal, y: usize) -> String {
+        (0..terminal.width_chars).map(|x| terminal.char_at(x, y)).collect()
+    }
+
+    fn rows(terminal: &Terminal) -> Vec<String> {
+        (0..terminal.height_chars).map(|y| row(terminal, y)).collect()
+    }
+
+    #[test]
+    fn test_wrap_at_last_column() {
+        let mut t = terminal(4, 3);
+        t.write_str("abcd");
+        // The cursor stays on the last column until another character comes
+        assert_eq!((t.cursor_x, t.cursor_y), (3, 0));
+        t.write_str("e");
+        assert_eq!(rows(&t), ["abcd", "e   ", "    "]);
+        assert_eq!((t.cursor_x, t.cursor_y), (1, 1));
+    }
+
+    #[test]
+    fn test_pending_wrap_cleared() {
+        let mut t = terminal(4, 3);
+        t.write_str("abcd\rx");
+        assert_eq!(rows(&t), ["xbcd", "    ", "    "]);
+
+        let mut t = terminal(4, 3);
+        t.write_str("abcd\x1b[1;2Hy");
+        assert_eq!(rows(&t), ["aycd", "    ", "    "]);
+
+        // A newline after a full row moves down only once
+        let mut t = terminal(4, 3);
+        t.write_str("abcd\nz");
+        assert_eq!(rows(&t), ["abcd", "z   ", "    "]);
+    }
+
+    #[test]
+    fn test_autowrap_off() {
+        let mut t = terminal(4, 3);
+        t.write_str("\x1b[?7labcdef");
+        assert_eq!(rows(&t), ["abcf", "    ", "    "]);
+        t.write_str("\x1b[?7h\rwxyz1");
+        assert_eq!(rows(&t), ["wxyz", "1   ", "    "]);
+    }
+
+    #[test]
+    fn test_scroll_into_scrollback() {
+        let mut t = terminal(4, 3);
+        t.write_str("1\n2\n3\n4\n5");
+        assert_eq!(rows(&t), ["3   ", "4   ", "5   "]);
+        assert_eq!(t.scrollback.len(), 2);
+
+        t.scroll_view(1);
+        assert_eq!(rows(&t), ["2   ", "3   ", "4   "]);
+        // The view stops at the oldest line
+        t.scroll_view(10);
+        assert_eq!(rows(&t), ["1   ", "2   ", "3   "]);
+        t.scroll_view(-1);
+        assert_eq!(rows(&t), ["2   ", "3   ", "4   "]);
+
+        // Output brings the view back to the bottom
+        t.write_str("6");
+        assert_eq!(t.view_offset, 0);
+        assert_eq!(rows(&t), ["3   ", "4   ", "56  "]);
+    }
+
+    #[test]
+    fn test_scrollback_limit() {
+        let mut t = terminal(4, 2);
+        t.set_scrollback_limit(2);
+        t.write_str("1\n2\n3\n4\n5");
+        assert_eq!(t.scrollback.len(), 2);
+        t.scroll_view(5);
+        assert_eq!(rows(&t), ["2   ", "3   "]);
+
+        // Shrinking the limit drops the oldest lines and pulls the view in
+        t.set_scrollback_limit(1);
+        assert_eq!(t.view_offset, 1);
+        assert_eq!(rows(&t), ["3   ", "4   "]);
+
+        t.set_scrollback_limit(0);
+        t.write_str("\n6");
+        assert!(t.scrollback.is_empty());
+        assert_eq!(rows(&t), ["5   ", "6   "]);
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        let mut t = terminal(4, 4);
+        t.write_str("a\nb\nc\nd\x1b[2;3r");
+        // Setting the region homes the cursor
+        assert_eq!((t.cursor_x, t.cursor_y), (0, 0));
+
+        // A linefeed at the region's bottom scrolls only the region, and
+        // lines leaving a region below the top are not kept
+        t.write_str("\x1b[3;1H\nx");
+        assert_eq!(rows(&t), ["a   ", "c   ", "x   ", "d   "]);
+        assert!(t.scrollback.is_empty());
+
+        // Reverse index at the region's top scrolls it down
+        t.write_str("\x1b[2;1H\x1bMy");
+        assert_eq!(rows(&t), ["a   ", "y   ", "c   ", "d   "]);
+    }
+
+    #[test]
+    fn test_scroll_region_edges() {
+        let mut t = terminal(4, 4);
+        t.write_str("a\nb\nc\nd\x1b[1;2r");
+
+        // Below the region, a linefeed on the last row does not scroll
+        t.write_str("\x1b[4;1H\nz");
+        assert_eq!(rows(&t), ["a   ", "b   ", "c   ", "z   "]);
+
+        // A region from the top keeps what scrolls off it
+        t.write_str("\x1b[2;1H\ne");
+        assert_eq!(rows(&t), ["b   ", "e   ", "c   ", "z   "]);
+        assert_eq!(t.scrollback.len(), 1);
+
+        // A wrap at the region's bottom scrolls it too
+        t.write_str("fghi");
+        assert_eq!(rows(&t), ["efgh", "i   ", "c   ", "z   "]);
+
+        // Invalid regions are ignored
+        t.write_str("\x1b[3;3r\x1b[4;2r");
+        assert_eq!((t.scroll_top, t.scroll_bottom), (0, 1));
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut t = terminal(4, 3);
+        t.write_str("ab\ncd");
+        t.write_str("\x1b[?1049h");
+        assert_eq!(rows(&t), ["    ", "    ", "    "]);
+
+        // The alternate screen keeps no scrollback
+        t.write_str("1\n2\n3\n4");
+        assert_eq!(rows(&t), ["2   ", "3   ", "4   "]);
+        assert!(t.scrollback.is_empty());
+
+        t.write_str("\x1b[?1049l");
+        assert_eq!(rows(&t), ["ab  ", "cd  ", "    "]);
+        assert_eq!((t.cursor_x, t.cursor_y), (2, 1));
+    }
+
+    #[test]
+    fn test_alternate_screen_without_cursor() {
+        let mut t = terminal(4, 3);
+        t.write_str("ab\x1b[?47hx\x1b[?47h");
+        assert_eq!(rows(&t), ["  x ", "    ", "    "]);
+        t.write_str("\x1b[?47l");
+        assert_eq!(rows(&t), ["ab  ", "    ", "    "]);
+        assert_eq!((t.cursor_x, t.cursor_y), (3, 0));
+
+        // Leaving when not on the alternate screen changes nothing
+        t.write_str("\x1b[?47l");
+        assert_eq!(rows(&t), ["ab  ", "    ", "    "]);
+
+        // A full reset comes back to the main screen
+        t.write_str("\x1b[?47h\x1bc");
+        assert!(t.main_screen.is_none());
+    }
+
+    /// Decode `bytes` as the terminal does, ending any sequence left open
+    fn decode(bytes: &[u8]) -> String {
+        let mut decoder = Utf8Decoder::new();
+        let mut s: String = bytes
+            .iter()
+            .filter_map(|&b| if b < 0x80 { Some(b as char) } else { decoder.feed(b) })
+            .collect();
+        if decoder.abandon() {
+            s.push(REPLACEMENT);
+        }
+        s
+    }
+
+    #[test]
+    fn test_utf8_valid() {
+        assert_eq!(decode("aé€😀".as_bytes()), "aé€😀");
+    }
+
+    #[test]
+    fn test_utf8_truncated() {
+        // Cut short by another lead byte
+        assert_eq!(decode(b"\xE2\x82\xC3\xA9"), "\u{FFFD}é");
+        // Cut short by a byte that cannot start a sequence
+        assert_eq!(decode(b"\xE2\x82\xFFa"), "\u{FFFD}a");
+        assert_eq!(decode(b"\xF0\x9F\xC0\xA9"), "\u{FFFD}\u{FFFD}");
+        // The state is reset, so what follows decodes normally
+        assert_eq!(decode(b"\xE2\xF8\xC3\xA9"), "\u{FFFD}é");
+        // Cut short by the end of input
+        assert_eq!(decode(b"\xE2\x82"), "\u{FFFD}");
+        // Stray continuation bytes
+        assert_eq!(decode(b"\x80a\xBF"), "\u{FFFD}a\u{FFFD}");
+    }
+
+    #[test]
+    fn test_utf8_overlong_and_invalid() {
+        // Overlong encodings of '/' and of U+0000
+        assert_eq!(decode(b"\xC0\xAF"), "\u{FFFD}\u{FFFD}");
+        assert_eq!(decode(b"\xE0\x80\xAF"), "\u{FFFD}");
+        assert_eq!(decode(b"\xF0\x80\x80\x80"), "\u{FFFD}");
+        // A surrogate and a code point beyond U+10FFFF
+        assert_eq!(decode(b"\xED\xA0\x80"), "\u{FFFD}");
+        assert_eq!(decode(b"\xF4\x90\x80\x80"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn test_set_font_too_large() {
+        let mut t = terminal(4, 3);
+        t.write_str("ab");
+        let font = Font::new(32, 64, vec![0; 4 * 64 * 256], BTreeMap::new()).unwrap();
+        assert!(t.set_font(Arc::new(font)).is_err());
+        assert_eq!((t.width_chars, t.height_chars), (4, 3));
+        assert_eq!(row(&t, 0), "ab  ");
+
+        let font = Font::new(16, 16, vec![0; 2 * 16 * 256], BTreeMap::new()).unwrap();
+        assert!(t.set_font(Arc::new(font)).is_ok());
+        assert_eq!((t.width_chars, t.height_chars), (2, 3));
+        assert_eq!(row(&t, 0), "ab");
+    }
+
+    #[test]
+    fn test_ignored_private_modes() {
+        let mut t = terminal(4, 3);
+        t.write_str("ab\x1b[?25l\x1b[?25h\x1b[?1000h\x1b[>1mc");
+        assert_eq!(rows(&t), ["abc ", "    ", "    "]);
+        assert!(t.autowrap);
+    }
+}
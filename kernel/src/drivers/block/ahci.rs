@@ -0,0 +1,524 @@
+//! AHCI SATA disk driver
+//!
+//! The HBA fetches commands from a per-port list of 32 command slots and
+//! moves data by DMA, described by a physical region table in each slot's
+//! command table. Transfers go through a per-port bounce buffer, completion
+//! is polled. A command that times out or fails has its port stopped before
+//! the slot and buffer are used again. Disks are registered as `sda`, `sdb`,
+//! ...
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::drivers::pci::{self, Bar, PciDevice, PciDriver, PciId};
+use crate::memory::dma::DmaBuffer;
+use crate::memory::paging;
+
+/// Generic host control registers
+const HBA_CAP: usize = 0x00;
+const HBA_GHC: usize = 0x04;
+const HBA_PI: usize = 0x0C;
+const HBA_CAP2: usize = 0x24;
+const HBA_BOHC: usize = 0x28;
+
+const CAP_S64A: u32 = 1 << 31;
+const CAP_SSS: u32 = 1 << 27;
+const GHC_AE: u32 = 1 << 31;
+const CAP2_BOH: u32 = 1 << 0;
+const BOHC_BOS: u32 = 1 << 0;
+const BOHC_OOS: u32 = 1 << 1;
+
+/// Port registers, relative to the port's register block
+const PORT_BASE: usize = 0x100;
+const PORT_SIZE: usize = 0x80;
+const PX_CLB: usize = 0x00;
+const PX_CLBU: usize = 0x04;
+const PX_FB: usize = 0x08;
+const PX_FBU: usize = 0x0C;
+const PX_IS: usize = 0x10;
+const PX_IE: usize = 0x14;
+const PX_CMD: usize = 0x18;
+const PX_TFD: usize = 0x20;
+const PX_SIG: usize = 0x24;
+const PX_SSTS: usize = 0x28;
+const PX_SCTL: usize = 0x2C;
+const PX_SERR: usize = 0x30;
+const PX_SACT: usize = 0x34;
+const PX_CI: usize = 0x38;
+
+const CMD_ST: u32 = 1 << 0;
+const CMD_SUD: u32 = 1 << 1;
+const CMD_POD: u32 = 1 << 2;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+
+/// Task file error status
+const IS_TFES: u32 = 1 << 30;
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BSY: u32 = 1 << 7;
+
+/// SStatus: device present and PHY communication established
+const SSTS_DET_PRESENT: u32 = 3;
+/// SControl: send COMRESET while set
+const SCTL_DET_INIT: u32 = 1;
+/// Signature of a plain SATA disk (ATAPI devices report 0xEB140101)
+const SIG_ATA: u32 = 0x0000_0101;
+
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+
+const ATA_READ_DMA: u8 = 0xC8;
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA: u8 = 0xCA;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_FLUSH_CACHE: u8 = 0xE7;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xEA;
+const ATA_IDENTIFY: u8 = 0xEC;
+
+/// Number of command slots in a command list
+const SLOTS: usize = 32;
+/// Size of a command header in the command list
+const HEADER_SIZE: usize = 32;
+/// Command table size: 128-byte header plus 8 PRD entries
+const TABLE_SIZE: usize = 256;
+/// Offset of the PRD table within a command table
+const PRDT_OFFSET: usize = 0x80;
+
+/// Layout of the per-port control memory
+const CLB_OFFSET: usize = 0;
+const FB_OFFSET: usize = SLOTS * HEADER_SIZE;
+const TABLES_OFFSET: usize = 4096;
+const CONTROL_SIZE: usize = TABLES_OFFSET + SLOTS * TABLE_SIZE;
+
+/// Size of the per-port bounce buffer, the largest single transfer
+const BOUNCE_SIZE: usize = 128 * 1024;
+
+/// Register polls before a command is considered timed out
+const TIMEOUT: u32 = 10_000_000;
+
+/// Polls between raising and dropping COMRESET, comfortably over the 1 ms
+/// the specification asks for
+const COMRESET_POLLS: u32 = 100_000;
+
+/// Memory-mapped registers of one HBA
+#[derive(Clone, Copy)]
+struct Regs {
+    base: usize,
+}
+
+impl Regs {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Wait until `(read(offset) & mask) == value`
+    fn wait(&self, offset: usize, mask: u32, value: u32) -> Result<(), BlockError> {
+        for _ in 0..TIMEOUT {
+            if self.read(offset) & mask == value {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Io)
+    }
+}
+
+/// Per-port state, guarded by the port's lock
+struct PortState {
+    /// Command list, received FIS area and command tables
+    control: DmaBuffer,
+    bounce: DmaBuffer,
+}
+
+/// A SATA disk on one AHCI port
+pub struct AhciPort {
+    regs: Regs,
+    port: usize,
+    sectors: u64,
+    /// The disk supports 48-bit LBA commands
+    lba48: bool,
+    /// A failed command could not be stopped; the HBA may still write to
+    /// the port's memory, so it is never used again
+    dead: AtomicBool,
+    state: Mutex<PortState>,
+}
+
+/// Direction and shape of one ATA command
+struct Command {
+    ata: u8,
+    lba: u64,
+    count: u16,
+    write: bool,
+    /// Bytes moved through the bounce buffer
+    bytes: usize,
+}
+
+impl AhciPort {
+    fn reg(&self, offset: usize) -> usize {
+        PORT_BASE + self.port * PORT_SIZE + offset
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        self.regs.read(self.reg(offset))
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        self.regs.write(self.reg(offset), value)
+    }
+
+    /// Stop the command engine so the list pointers may be changed
+    fn stop(&self) -> Result<(), BlockError> {
+        self.write(PX_CMD, self.read(PX_CMD) & !CMD_ST);
+        self.regs.wait(self.reg(PX_CMD), CMD_CR, 0)?;
+        self.write(PX_CMD, self.read(PX_CMD) & !CMD_FRE);
+        self.regs.wait(self.reg(PX_CMD), CMD_FR, 0)
+    }
+
+    /// Stop a port with a command stuck or failed, so its slot and buffers
+    /// may be reused, and start it again
+    ///
+    /// Clearing ST makes the HBA drop every issued command; if it will not
+    /// stop, a COMRESET resets the link and the device.
+    fn recover(&self) -> Result<(), BlockError> {
+        self.write(PX_SERR, u32::MAX);
+        if self.stop().is_err() {
+            self.write(PX_SCTL, self.read(PX_SCTL) & !0xF | SCTL_DET_INIT);
+            for _ in 0..COMRESET_POLLS {
+                core::hint::spin_loop();
+            }
+            self.write(PX_SCTL, self.read(PX_SCTL) & !0xF);
+            self.regs.wait(self.reg(PX_SSTS), 0xF, SSTS_DET_PRESENT)?;
+            self.write(PX_SERR, u32::MAX);
+            self.stop()?;
+        }
+        self.write(PX_IS, u32::MAX);
+        self.start()
+    }
+
+    fn start(&self) -> Result<(), BlockError> {
+        self.regs.wait(self.reg(PX_CMD), CMD_CR, 0)?;
+        self.write(PX_CMD, self.read(PX_CMD) | CMD_FRE);
+        self.write(PX_CMD, self.read(PX_CMD) | CMD_ST);
+        Ok(())
+    }
+
+    /// Point the port at freshly allocated command memory and start it
+    fn setup(&self, st: &PortState, spin_up: bool) -> Result<(), BlockError> {
+        self.stop()?;
+
+        let clb = st.control.phys() + CLB_OFFSET as u64;
+        let fb = st.control.phys() + FB_OFFSET as u64;
+        self.write(PX_CLB, clb as u32);
+        self.write(PX_CLBU, (clb >> 32) as u32);
+        self.write(PX_FB, fb as u32);
+        self.write(PX_FBU, (fb >> 32) as u32);
+
+        // Each command header points at its own command table
+        for slot in 0..SLOTS {
+            let table = st.control.phys() + (TABLES_OFFSET + slot * TABLE_SIZE) as u64;
+            let header = st.control.ptr::<u32>(CLB_OFFSET + slot * HEADER_SIZE);
+            unsafe {
+                header.add(2).write_volatile(table as u32);
+                header.add(3).write_volatile((table >> 32) as u32);
+            }
+        }
+
+        // Clear stale errors and interrupts; completion is polled
+        self.write(PX_SERR, u32::MAX);
+        self.write(PX_IS, u32::MAX);
+        self.write(PX_IE, 0);
+        if spin_up {
+            self.write(PX_CMD, self.read(PX_CMD) | CMD_SUD | CMD_POD);
+        }
+        self.start()
+    }
+
+    /// Pick a slot that is neither issued nor active
+    fn free_slot(&self) -> Option<usize> {
+        let busy = self.read(PX_SACT) | self.read(PX_CI);
+        (0..SLOTS).find(|&slot| busy & (1 << slot) == 0)
+    }
+
+    /// Build the command in a free slot, issue it and wait for completion
+    fn issue(&self, st: &PortState, cmd: &Command) -> Result<(), BlockError> {
+        if self.dead.load(Ordering::Relaxed) {
+            return Err(BlockError::Io);
+        }
+        self.regs.wait(self.reg(PX_TFD), TFD_BSY | TFD_DRQ, 0)?;
+        let slot = self.free_slot().ok_or(BlockError::Io)?;
+
+        // Command header: FIS length in dwords, write flag, one PRD entry
+        let prdt_len: u32 = if cmd.bytes > 0 { 1 } else { 0 };
+        let flags = 5 | (cmd.write as u32) << 6 | prdt_len << 16;
+        let header = st.control.ptr::<u32>(CLB_OFFSET + slot * HEADER_SIZE);
+        unsafe {
+            header.write_volatile(flags);
+            header.add(1).write_volatile(0);
+        }
+
+        let table_offset = TABLES_OFFSET + slot * TABLE_SIZE;
+        let table = st.control.ptr::<u8>(table_offset);
+        unsafe {
+            core::ptr::write_bytes(table, 0, TABLE_SIZE);
+        }
+
+        // Register host-to-device FIS
+        let mut fis = [0u8; 20];
+        fis[0] = FIS_TYPE_REG_H2D;
+        fis[1] = 1 << 7; // command, not control
+        fis[2] = cmd.ata;
+        fis[4] = cmd.lba as u8;
+        fis[5] = (cmd.lba >> 8) as u8;
+        fis[6] = (cmd.lba >> 16) as u8;
+        // LBA mode; 28-bit commands take the top LBA bits here
+        fis[7] = 1 << 6 | if self.lba48 { 0 } else { (cmd.lba >> 24) as u8 & 0x0F };
+        fis[8] = (cmd.lba >> 24) as u8;
+        fis[9] = (cmd.lba >> 32) as u8;
+        fis[10] = (cmd.lba >> 40) as u8;
+        fis[12] = cmd.count as u8;
+        fis[13] = (cmd.count >> 8) as u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(fis.as_ptr(), table, fis.len());
+        }
+
+        if cmd.bytes > 0 {
+            let prd = st.control.ptr::<u32>(table_offset + PRDT_OFFSET);
+            let data = st.bounce.phys();
+            unsafe {
+                prd.write_volatile(data as u32);
+                prd.add(1).write_volatile((data >> 32) as u32);
+                prd.add(3).write_volatile(cmd.bytes as u32 - 1);
+            }
+        }
+
+        self.write(PX_IS, u32::MAX);
+        self.write(PX_CI, 1 << slot);
+
+        for _ in 0..TIMEOUT {
+            if self.read(PX_IS) & IS_TFES != 0 {
+                break;
+            }
+            if self.read(PX_CI) & (1 << slot) == 0 {
+                break;
+            }
+        }
+
+        let failed = self.read(PX_IS) & IS_TFES != 0 || self.read(PX_TFD) & TFD_ERR != 0;
+        let timed_out = self.read(PX_CI) & (1 << slot) != 0;
+        if failed || timed_out {
+            // Clear the error state, and take back the slot so the HBA is
+            // done with the bounce buffer before it is reused
+            if self.recover().is_err() {
+                crate::serial::print("AHCI: port ");
+                crate::memory::print_decimal(self.port as u64);
+                crate::serial::print(" did not recover, disabling it\n");
+                self.dead.store(true, Ordering::Relaxed);
+            }
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    /// Bring up a port and identify the disk on it
+    fn probe(regs: Regs, port: usize, cap: u32) -> Option<Self> {
+        let control = DmaBuffer::new(CONTROL_SIZE)?;
+        let bounce = DmaBuffer::new(BOUNCE_SIZE)?;
+        // Without 64-bit addressing the HBA only reaches the first 4 GiB
+        if cap & CAP_S64A == 0 && (control.phys() + CONTROL_SIZE as u64 > 1 << 32 || bounce.phys() + BOUNCE_SIZE as u64 > 1 << 32) {
+            crate::serial::print("AHCI: DMA memory above 4 GiB\n");
+            return None;
+        }
+
+        let mut disk = Self {
+            regs,
+            port,
+            sectors: 0,
+            lba48: false,
+            dead: AtomicBool::new(false),
+            state: Mutex::new(PortState { control, bounce }),
+        };
+
+        let st = disk.state.lock();
+        let identify = Command {
+            ata: ATA_IDENTIFY,
+            lba: 0,
+            count: 0,
+            write: false,
+            bytes: SECTOR_SIZE,
+        };
+        let identified = disk.setup(&st, cap & CAP_SSS != 0).and_then(|_| disk.issue(&st, &identify));
+        if identified.is_err() {
+            drop(st);
+            disk.abandon();
+            return None;
+        }
+
+        let data = &st.bounce.as_slice()[..SECTOR_SIZE];
+        let word = |n: usize| u16::from_le_bytes([data[n * 2], data[n * 2 + 1]]) as u64;
+        let lba48 = word(83) & (1 << 10) != 0;
+        let sectors = if lba48 {
+            word(100) | word(101) << 16 | word(102) << 32 | word(103) << 48
+        } else {
+            word(60) | word(61) << 16
+        };
+        drop(st);
+
+        disk.sectors = sectors;
+        disk.lba48 = lba48;
+        if sectors == 0 {
+            disk.abandon();
+            return None;
+        }
+        Some(disk)
+    }
+
+    /// Give up on a port that failed to probe, stopping it so its command
+    /// memory can be freed
+    fn abandon(self) {
+        if self.stop().is_err() {
+            // The HBA may still write there
+            core::mem::forget(self);
+        }
+    }
+
+    /// Run a read or write through the bounce buffer
+    fn transfer(&self, lba: u64, len: usize, write: bool, mut data: impl FnMut(&mut [u8], usize)) -> Result<(), BlockError> {
+        let mut st = self.state.lock();
+        let mut done = 0;
+        while done < len {
+            let bytes = (len - done).min(BOUNCE_SIZE);
+            let cmd = Command {
+                ata: match (write, self.lba48) {
+                    (false, false) => ATA_READ_DMA,
+                    (false, true) => ATA_READ_DMA_EXT,
+                    (true, false) => ATA_WRITE_DMA,
+                    (true, true) => ATA_WRITE_DMA_EXT,
+                },
+                lba: lba + (done / SECTOR_SIZE) as u64,
+                count: (bytes / SECTOR_SIZE) as u16,
+                write,
+                bytes,
+            };
+            if write {
+                data(&mut st.bounce.as_mut_slice()[..bytes], done);
+            }
+            self.issue(&st, &cmd)?;
+            if !write {
+                data(&mut st.bounce.as_mut_slice()[..bytes], done);
+            }
+            done += bytes;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AhciPort {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let len = buf.len();
+        self.transfer(lba, len, false, |bounce, offset| {
+            buf[offset..offset + bounce.len()].copy_from_slice(bounce);
+        })
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        self.transfer(lba, buf.len(), true, |bounce, offset| {
+            bounce.copy_from_slice(&buf[offset..offset + bounce.len()]);
+        })
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let st = self.state.lock();
+        let cmd = Command {
+            ata: if self.lba48 { ATA_FLUSH_CACHE_EXT } else { ATA_FLUSH_CACHE },
+            lba: 0,
+            count: 0,
+            write: false,
+            bytes: 0,
+        };
+        self.issue(&st, &cmd)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+/// Take the HBA over from firmware and switch it to AHCI mode
+fn take_ownership(regs: Regs) {
+    if regs.read(HBA_CAP2) & CAP2_BOH != 0 {
+        regs.write(HBA_BOHC, regs.read(HBA_BOHC) | BOHC_OOS);
+        if regs.wait(HBA_BOHC, BOHC_BOS, 0).is_err() {
+            crate::serial::print("AHCI: firmware did not release the controller\n");
+        }
+    }
+    regs.write(HBA_GHC, regs.read(HBA_GHC) | GHC_AE);
+}
+
+/// Disk letters handed out so far
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+/// Prog IF 1 is AHCI; other SATA controllers are vendor specific
+const IDS: &[PciId] = &[PciId::class_if(0x01, 0x06, 0x01)];
+
+/// PCI driver for AHCI SATA controllers
+pub struct AhciDriver;
+
+pub static DRIVER: AhciDriver = AhciDriver;
+
+impl PciDriver for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+
+    fn ids(&self) -> &'static [PciId] {
+        IDS
+    }
+
+    /// Take over the HBA and register a disk for each port with a drive
+    fn probe(&self, controller: &PciDevice) -> Result<(), &'static str> {
+        let Bar::Memory { addr: abar, .. } = controller.bar(5) else {
+            return Err("ABAR is not a memory BAR");
+        };
+        controller.enable(pci::COMMAND_MEMORY_SPACE | pci::COMMAND_BUS_MASTER);
+
+        // Generic registers plus 32 ports
+        let size = (PORT_BASE + SLOTS * PORT_SIZE) as u64;
+        let base = paging::map_mmio(PhysAddr::new(abar), size)?.as_u64() as usize;
+        let regs = Regs { base };
+        take_ownership(regs);
+
+        let cap = regs.read(HBA_CAP);
+        let implemented = regs.read(HBA_PI);
+        for port in (0..SLOTS).filter(|port| implemented & (1 << port) != 0) {
+            let port_reg = |offset: usize| regs.read(PORT_BASE + port * PORT_SIZE + offset);
+            if port_reg(PX_SSTS) & 0xF != SSTS_DET_PRESENT || port_reg(PX_SIG) != SIG_ATA {
+                continue;
+            }
+            let Some(disk) = AhciPort::probe(regs, port, cap) else {
+                crate::serial::print("AHCI: port ");
+                crate::memory::print_decimal(port as u64);
+                crate::serial::print(" failed to identify\n");
+                continue;
+            };
+
+            let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+            let name = alloc::format!("sd{}", (b'a' + index) as char);
+            crate::serial::print("AHCI: ");
+            crate::serial::print(&name);
+            crate::serial::print(" on port ");
+            crate::memory::print_decimal(port as u64);
+            crate::serial::print("\n");
+            super::register(name, Arc::new(disk));
+        }
+        Ok(())
+    }
+}
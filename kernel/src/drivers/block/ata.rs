@@ -0,0 +1,315 @@
+//! ATA PIO disk driver
+//!
+//! Drives disks on an IDE controller by programmed I/O: every sector is
+//! moved through the data port by the CPU. Slow, but it works on any PC and
+//! on QEMU's default machine. Disks are registered as `hda`..`hdd`.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::drivers::pci::{self, Bar, PciDevice, PciDriver, PciId};
+
+/// Legacy ports of the primary and secondary channels (command block, control block)
+const LEGACY_CHANNELS: [(u16, u16); 2] = [(0x1F0, 0x3F6), (0x170, 0x376)];
+
+/// Command block register offsets
+const REG_DATA: u16 = 0;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+/// Status register bits
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+/// Device control register: disable interrupts, we poll
+const CONTROL_NIEN: u8 = 1 << 1;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_CACHE_FLUSH_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+/// Status polls before a command is considered timed out
+const TIMEOUT: u32 = 10_000_000;
+
+/// Largest transfer per command (LBA28 encodes 256 as a count of 0)
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+
+/// One IDE channel; the master and slave drives share its registers
+struct Channel {
+    io_base: u16,
+    ctrl_base: u16,
+}
+
+impl Channel {
+    fn read_reg(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io_base + reg).read() }
+    }
+
+    fn write_reg(&self, reg: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io_base + reg).write(value) }
+    }
+
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.ctrl_base).read() }
+    }
+
+    /// Wait roughly 400ns for the drive to post a new status
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    /// Wait for BSY to clear, then optionally for DRQ
+    fn wait(&self, want_drq: bool) -> Result<(), BlockError> {
+        for _ in 0..TIMEOUT {
+            let status = self.alt_status();
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::Io);
+            }
+            if !want_drq || status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Io)
+    }
+
+    fn select(&self, slave: bool, lba_bits: u8) {
+        self.write_reg(REG_DRIVE, 0xE0 | (slave as u8) << 4 | lba_bits);
+        self.delay();
+    }
+
+    fn read_words(&self, buf: &mut [u8]) {
+        let mut port = Port::<u16>::new(self.io_base + REG_DATA);
+        for pair in buf.chunks_exact_mut(2) {
+            let word = unsafe { port.read() };
+            pair.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    fn write_words(&self, buf: &[u8]) {
+        let mut port = Port::<u16>::new(self.io_base + REG_DATA);
+        for pair in buf.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([pair[0], pair[1]])) };
+        }
+    }
+
+    /// Program the task file for a transfer of `count` sectors at `lba`
+    fn setup(&self, slave: bool, lba: u64, count: usize, lba48: bool) {
+        if lba48 {
+            self.select(slave, 0);
+            // High-order bytes go first, then the low-order bytes
+            self.write_reg(REG_SECTOR_COUNT, (count >> 8) as u8);
+            self.write_reg(REG_LBA_LOW, (lba >> 24) as u8);
+            self.write_reg(REG_LBA_MID, (lba >> 32) as u8);
+            self.write_reg(REG_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select(slave, (lba >> 24) as u8 & 0x0F);
+        }
+        self.write_reg(REG_SECTOR_COUNT, count as u8);
+        self.write_reg(REG_LBA_LOW, lba as u8);
+        self.write_reg(REG_LBA_MID, (lba >> 8) as u8);
+        self.write_reg(REG_LBA_HIGH, (lba >> 16) as u8);
+    }
+}
+
+/// A disk attached to an IDE channel
+pub struct AtaDrive {
+    channel: Arc<Mutex<Channel>>,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+}
+
+impl AtaDrive {
+    /// Issue IDENTIFY DEVICE, returning the drive if an ATA disk answers
+    fn identify(channel: &Arc<Mutex<Channel>>, slave: bool) -> Option<(Self, String)> {
+        let ch = channel.lock();
+        ch.select(slave, 0);
+        ch.write_reg(REG_SECTOR_COUNT, 0);
+        ch.write_reg(REG_LBA_LOW, 0);
+        ch.write_reg(REG_LBA_MID, 0);
+        ch.write_reg(REG_LBA_HIGH, 0);
+        ch.write_reg(REG_COMMAND, CMD_IDENTIFY);
+        ch.delay();
+
+        // A floating bus reads 0xFF, an absent drive 0
+        let status = ch.read_reg(REG_STATUS);
+        if status == 0 || status == 0xFF {
+            return None;
+        }
+        for _ in 0..TIMEOUT {
+            if ch.alt_status() & STATUS_BSY == 0 {
+                break;
+            }
+        }
+        // ATAPI and SATA bridges abort IDENTIFY and leave a signature here
+        if ch.read_reg(REG_LBA_MID) != 0 || ch.read_reg(REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        ch.wait(true).ok()?;
+
+        let mut data = [0u8; SECTOR_SIZE];
+        ch.read_words(&mut data);
+        let word = |n: usize| u16::from_le_bytes([data[n * 2], data[n * 2 + 1]]) as u64;
+
+        let lba48 = word(83) & (1 << 10) != 0;
+        let sectors = if lba48 {
+            word(100) | word(101) << 16 | word(102) << 32 | word(103) << 48
+        } else {
+            word(60) | word(61) << 16
+        };
+        if sectors == 0 {
+            return None;
+        }
+
+        // The model string is stored with the bytes of each word swapped
+        let mut model = String::new();
+        for n in 27..47 {
+            let w = word(n) as u16;
+            model.push((w >> 8) as u8 as char);
+            model.push(w as u8 as char);
+        }
+        let model = String::from(model.trim());
+
+        drop(ch);
+        let drive = Self {
+            channel: channel.clone(),
+            slave,
+            lba48,
+            sectors,
+        };
+        Some((drive, model))
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let ch = self.channel.lock();
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            let lba48 = self.lba48 && lba + count as u64 > 1 << 28;
+            ch.setup(self.slave, lba, count, lba48);
+            ch.write_reg(REG_COMMAND, if lba48 { CMD_READ_SECTORS_EXT } else { CMD_READ_SECTORS });
+            for sector in chunk.chunks_mut(SECTOR_SIZE) {
+                ch.delay();
+                ch.wait(true)?;
+                ch.read_words(sector);
+            }
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let ch = self.channel.lock();
+        let mut lba = lba;
+        for chunk in buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            let lba48 = self.lba48 && lba + count as u64 > 1 << 28;
+            ch.setup(self.slave, lba, count, lba48);
+            ch.write_reg(REG_COMMAND, if lba48 { CMD_WRITE_SECTORS_EXT } else { CMD_WRITE_SECTORS });
+            for sector in chunk.chunks(SECTOR_SIZE) {
+                ch.delay();
+                ch.wait(true)?;
+                ch.write_words(sector);
+            }
+            ch.delay();
+            ch.wait(false)?;
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let ch = self.channel.lock();
+        ch.select(self.slave, 0);
+        ch.write_reg(REG_COMMAND, if self.lba48 { CMD_CACHE_FLUSH_EXT } else { CMD_CACHE_FLUSH });
+        ch.delay();
+        ch.wait(false)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+/// Drive letters handed out so far, two per channel
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+/// IDE controllers in either legacy or native mode
+const IDS: &[PciId] = &[PciId::class(0x01, 0x01)];
+
+/// PCI driver for IDE controllers
+pub struct AtaDriver;
+
+pub static DRIVER: AtaDriver = AtaDriver;
+
+impl PciDriver for AtaDriver {
+    fn name(&self) -> &'static str {
+        "ata"
+    }
+
+    fn ids(&self) -> &'static [PciId] {
+        IDS
+    }
+
+    /// Register the disks on both channels of an IDE controller
+    fn probe(&self, controller: &PciDevice) -> Result<(), &'static str> {
+        controller.enable(pci::COMMAND_IO_SPACE);
+
+        for (channel_no, &(legacy_io, legacy_ctrl)) in LEGACY_CHANNELS.iter().enumerate() {
+            // Prog IF bit 0/2: the channel runs in native mode with its ports in BARs
+            let native = controller.prog_if & (1 << (channel_no * 2)) != 0;
+            let (io_base, ctrl_base) = match (native, controller.bar(channel_no * 2), controller.bar(channel_no * 2 + 1)) {
+                (true, Bar::Io { port: io, .. }, Bar::Io { port: ctrl, .. }) => (io, ctrl + 2),
+                (true, _, _) => continue,
+                (false, _, _) => (legacy_io, legacy_ctrl),
+            };
+
+            let first = NEXT_INDEX.fetch_add(2, Ordering::Relaxed);
+            let channel = Channel { io_base, ctrl_base };
+            // A channel with nothing attached floats high
+            if channel.read_reg(REG_STATUS) == 0xFF {
+                continue;
+            }
+            unsafe { Port::<u8>::new(ctrl_base).write(CONTROL_NIEN) };
+            let channel = Arc::new(Mutex::new(channel));
+
+            for (index, slave) in (first..).zip([false, true]) {
+                let name = alloc::format!("hd{}", (b'a' + index) as char);
+                let Some((drive, model)) = AtaDrive::identify(&channel, slave) else {
+                    continue;
+                };
+                crate::serial::print("ATA: ");
+                crate::serial::print(&name);
+                crate::serial::print(": ");
+                crate::serial::print(&model);
+                crate::serial::print(if drive.lba48 { " (LBA48)\n" } else { " (LBA28)\n" });
+                super::register(name, Arc::new(drive));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+//! Sector buffer cache
+//!
+//! `BufferCache` wraps a block device and keeps recently used sectors in
+//! memory. Writes only mark cached sectors dirty; they reach the device when
+//! the sector is evicted or the cache is flushed.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Default number of sectors kept per device (512 KiB)
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// A cached copy of one sector
+struct Entry {
+    data: Box<[u8; SECTOR_SIZE]>,
+    dirty: bool,
+    /// Key of this entry in `State::lru`
+    last_used: u64,
+}
+
+struct State {
+    entries: BTreeMap<u64, Entry>,
+    /// Access stamp to LBA, oldest first
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+impl State {
+    /// Mark `lba` as most recently used
+    fn touch(&mut self, lba: u64) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(&lba) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = clock;
+            self.lru.insert(clock, lba);
+        }
+    }
+}
+
+/// A write-back LRU cache in front of a block device
+pub struct BufferCache {
+    device: Arc<dyn BlockDevice>,
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl BufferCache {
+    /// Cache up to `capacity` sectors of `device`
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        Self {
+            device,
+            capacity: capacity.max(1),
+            state: Mutex::new(State {
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Add a sector to the cache, evicting the least recently used if full
+    fn insert(&self, st: &mut State, lba: u64, data: &[u8], dirty: bool) -> Result<(), BlockError> {
+        if let Some(entry) = st.entries.get_mut(&lba) {
+            entry.data.copy_from_slice(data);
+            entry.dirty |= dirty;
+            st.touch(lba);
+            return Ok(());
+        }
+
+        while st.entries.len() >= self.capacity {
+            let Some((&stamp, &victim)) = st.lru.first_key_value() else {
+                break;
+            };
+            // Write back before dropping so a failed write loses nothing
+            if let Some(old) = st.entries.get(&victim)
+                && old.dirty
+            {
+                self.device.write_sectors(victim, &old.data[..])?;
+            }
+            st.lru.remove(&stamp);
+            st.entries.remove(&victim);
+        }
+
+        let mut buf = Box::new([0u8; SECTOR_SIZE]);
+        buf.copy_from_slice(data);
+        st.clock += 1;
+        let clock = st.clock;
+        st.entries.insert(lba, Entry { data: buf, dirty, last_used: clock });
+        st.lru.insert(clock, lba);
+        Ok(())
+    }
+
+    /// Read a run of uncached sectors in one request and cache them
+    fn fill(&self, st: &mut State, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.device.read_sectors(lba, buf)?;
+        for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+            self.insert(st, lba + i as u64, sector, false)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for BufferCache {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let count = super::check_request(lba, buf.len(), self.device.sector_count())?;
+        let mut st = self.state.lock();
+
+        // Start of the current run of misses, as an index into the request
+        let mut run_start: Option<u64> = None;
+        for i in 0..count {
+            let offset = i as usize * SECTOR_SIZE;
+            let cached = match st.entries.get(&(lba + i)) {
+                Some(entry) => {
+                    buf[offset..offset + SECTOR_SIZE].copy_from_slice(&entry.data[..]);
+                    true
+                }
+                None => false,
+            };
+            if cached {
+                st.touch(lba + i);
+                if let Some(start) = run_start.take() {
+                    let from = start as usize * SECTOR_SIZE;
+                    self.fill(&mut st, lba + start, &mut buf[from..offset])?;
+                }
+            } else {
+                run_start.get_or_insert(i);
+            }
+        }
+        if let Some(start) = run_start {
+            let from = start as usize * SECTOR_SIZE;
+            self.fill(&mut st, lba + start, &mut buf[from..])?;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.device.sector_count())?;
+        let mut st = self.state.lock();
+        for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+            self.insert(&mut st, lba + i as u64, sector, true)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let mut st = self.state.lock();
+        // Entries iterate in LBA order, which keeps the writes sequential
+        for (&lba, entry) in st.entries.iter_mut() {
+            if entry.dirty {
+                self.device.write_sectors(lba, &entry.data[..])?;
+                entry.dirty = false;
+            }
+        }
+        self.device.flush()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const DISK_SECTORS: u64 = 16;
+
+    /// An in-memory disk that counts the writes reaching it
+    struct MemDisk {
+        data: Mutex<Vec<u8>>,
+        writes: Mutex<usize>,
+    }
+
+    impl MemDisk {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                data: Mutex::new(vec![0; DISK_SECTORS as usize * SECTOR_SIZE]),
+                writes: Mutex::new(0),
+            })
+        }
+
+        fn sector(&self, lba: u64) -> Vec<u8> {
+            let start = lba as usize * SECTOR_SIZE;
+            self.data.lock()[start..start + SECTOR_SIZE].to_vec()
+        }
+    }
+
+    impl BlockDevice for MemDisk {
+        fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+            super::super::check_request(lba, buf.len(), DISK_SECTORS)?;
+            let start = lba as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+            Ok(())
+        }
+
+        fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+            super::super::check_request(lba, buf.len(), DISK_SECTORS)?;
+            let start = lba as usize * SECTOR_SIZE;
+            self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+            *self.writes.lock() += 1;
+            Ok(())
+        }
+
+        fn sector_count(&self) -> u64 {
+            DISK_SECTORS
+        }
+    }
+
+    fn filled(byte: u8) -> [u8; SECTOR_SIZE] {
+        [byte; SECTOR_SIZE]
+    }
+
+    #[test]
+    fn test_writes_reach_device_on_flush() {
+        let disk = MemDisk::new();
+        let cache = BufferCache::new(disk.clone(), 4);
+
+        cache.write_sectors(3, &filled(0xAB)).unwrap();
+        assert_eq!(*disk.writes.lock(), 0);
+        assert_eq!(disk.sector(3), filled(0));
+
+        let mut buf = [0u8; SECTOR_SIZE];
+        cache.read_sectors(3, &mut buf).unwrap();
+        assert_eq!(buf, filled(0xAB));
+
+        cache.flush().unwrap();
+        assert_eq!(disk.sector(3), filled(0xAB));
+        // A clean cache has nothing more to write
+        cache.flush().unwrap();
+        assert_eq!(*disk.writes.lock(), 1);
+    }
+
+    #[test]
+    fn test_eviction_writes_back_least_recently_used() {
+        let disk = MemDisk::new();
+        let cache = BufferCache::new(disk.clone(), 2);
+
+        cache.write_sectors(0, &filled(1)).unwrap();
+        cache.write_sectors(1, &filled(2)).unwrap();
+        // Touch sector 0 so sector 1 becomes the eviction victim
+        let mut buf = [0u8; SECTOR_SIZE];
+        cache.read_sectors(0, &mut buf).unwrap();
+        cache.write_sectors(2, &filled(3)).unwrap();
+
+        assert_eq!(disk.sector(0), filled(0));
+        assert_eq!(disk.sector(1), filled(2));
+        assert_eq!(disk.sector(2), filled(0));
+        assert_eq!(*disk.writes.lock(), 1);
+
+        // The evicted sector is read back from the device
+        cache.read_sectors(1, &mut buf).unwrap();
+        assert_eq!(buf, filled(2));
+    }
+
+    #[test]
+    fn test_read_mixes_cached_and_uncached_sectors() {
+        let disk = MemDisk::new();
+        for lba in 0..4 {
+            disk.write_sectors(lba, &filled(lba as u8 + 1)).unwrap();
+        }
+        let cache = BufferCache::new(disk.clone(), 8);
+        cache.write_sectors(1, &filled(0xEE)).unwrap();
+
+        let mut buf = [0u8; 4 * SECTOR_SIZE];
+        cache.read_sectors(0, &mut buf).unwrap();
+        let sectors: Vec<u8> = buf.chunks(SECTOR_SIZE).map(|s| s[0]).collect();
+        assert_eq!(sectors, [1, 0xEE, 3, 4]);
+    }
+}
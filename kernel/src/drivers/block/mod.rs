@@ -0,0 +1,209 @@
+//! Block device layer
+//!
+//! Storage drivers expose their disks through the `BlockDevice` trait and
+//! register them by name so filesystems can be mounted on any of them.
+//! Registered disks are wrapped in a write-back `BufferCache` and scanned for
+//! partitions, each of which becomes a device of its own.
+
+pub mod ahci;
+pub mod ata;
+pub mod cache;
+pub mod nvme;
+pub mod partition;
+pub mod ramdisk;
+pub mod virtio;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Size of a logical sector in bytes
+pub const SECTOR_SIZE: usize = 512;
+
+/// Errors reported by block devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request extends past the end of the device
+    OutOfRange,
+    /// The buffer length is not a multiple of the sector size
+    BadBufferSize,
+    /// The device does not accept writes
+    ReadOnly,
+    /// The device reported an error or timed out
+    Io,
+}
+
+impl BlockError {
+    /// Human-readable description of the error
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockError::OutOfRange => "request out of range",
+            BlockError::BadBufferSize => "buffer is not sector aligned",
+            BlockError::ReadOnly => "device is read-only",
+            BlockError::Io => "I/O error",
+        }
+    }
+}
+
+/// A device addressed in fixed-size sectors
+pub trait BlockDevice: Send + Sync {
+    /// Read `buf.len() / SECTOR_SIZE` sectors starting at `lba`
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write `buf.len() / SECTOR_SIZE` sectors starting at `lba`
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Make all completed writes durable
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Total number of sectors on the device
+    fn sector_count(&self) -> u64;
+
+    /// Size of the device in bytes
+    fn size(&self) -> u64 {
+        self.sector_count() * SECTOR_SIZE as u64
+    }
+}
+
+/// Check that a request of `buf_len` bytes at `lba` fits on a device
+pub fn check_request(lba: u64, buf_len: usize, sector_count: u64) -> Result<u64, BlockError> {
+    if !buf_len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::BadBufferSize);
+    }
+    let count = (buf_len / SECTOR_SIZE) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= sector_count => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// What a registered device represents
+#[derive(Debug, Clone)]
+pub enum DeviceKind {
+    /// A whole disk
+    Disk,
+    /// A partition of the disk registered just before it
+    Partition {
+        start: u64,
+        type_name: &'static str,
+        label: String,
+    },
+}
+
+/// Description of a registered device, as listed by `lsblk`
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub size: u64,
+    pub kind: DeviceKind,
+}
+
+/// Registered block devices, in registration order
+static DEVICES: Mutex<Vec<(DeviceInfo, Arc<dyn BlockDevice>)>> = Mutex::new(Vec::new());
+
+fn add(info: DeviceInfo, device: Arc<dyn BlockDevice>) {
+    crate::serial::print("Block: registered ");
+    crate::serial::print(&info.name);
+    crate::serial::print(" (");
+    crate::memory::print_size(info.size);
+    crate::serial::print(")\n");
+
+    DEVICES.lock().push((info, device));
+}
+
+/// Register a disk under the given name
+///
+/// The disk is put behind a buffer cache and its partitions are registered
+/// as `<name>N`, or `<name>pN` when the name ends in a digit.
+pub fn register(name: String, device: Arc<dyn BlockDevice>) {
+    let disk: Arc<dyn BlockDevice> = Arc::new(cache::BufferCache::new(device, cache::DEFAULT_CAPACITY));
+    add(
+        DeviceInfo {
+            name: name.clone(),
+            size: disk.size(),
+            kind: DeviceKind::Disk,
+        },
+        disk.clone(),
+    );
+
+    let entries = match partition::scan(&*disk) {
+        Ok(entries) => entries,
+        Err(e) => {
+            crate::serial::print("Block: cannot read partition table of ");
+            crate::serial::print(&name);
+            crate::serial::print(": ");
+            crate::serial::print(e.as_str());
+            crate::serial::print("\n");
+            return;
+        }
+    };
+    for entry in entries {
+        // Skip entries that do not fit on the disk
+        if entry.start.checked_add(entry.sectors).is_none_or(|end| end > disk.sector_count()) {
+            continue;
+        }
+        let part = entry.device(disk.clone());
+        add(
+            DeviceInfo {
+                name: partition::partition_name(&name, entry.number),
+                size: part.size(),
+                kind: DeviceKind::Partition {
+                    start: entry.start,
+                    type_name: entry.type_name,
+                    label: entry.label,
+                },
+            },
+            part,
+        );
+    }
+}
+
+/// Look up a block device by name
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|(info, _)| info.name == name)
+        .map(|(_, device)| device.clone())
+}
+
+/// Names of all registered block devices
+pub fn names() -> Vec<String> {
+    DEVICES.lock().iter().map(|(info, _)| info.name.clone()).collect()
+}
+
+/// Descriptions of all registered block devices
+pub fn list() -> Vec<DeviceInfo> {
+    DEVICES.lock().iter().map(|(info, _)| info.clone()).collect()
+}
+
+/// Write back every device's cache
+pub fn flush_all() -> Result<(), BlockError> {
+    let disks: Vec<Arc<dyn BlockDevice>> = DEVICES
+        .lock()
+        .iter()
+        .filter(|(info, _)| matches!(info.kind, DeviceKind::Disk))
+        .map(|(_, device)| device.clone())
+        .collect();
+    let mut result = Ok(());
+    for disk in disks {
+        if let Err(e) = disk.flush() {
+            // Keep flushing the rest, but report the first failure
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
+/// Initialize the block layer and register the RAM disk
+///
+/// Disk controllers are PCI drivers and register their disks when probed.
+pub fn init() {
+    crate::serial::print("Initializing block devices...\n");
+    ramdisk::init();
+}
@@ -0,0 +1,609 @@
+//! NVMe disk driver
+//!
+//! Commands are 64-byte entries on submission queues in host memory and
+//! completions come back on paired completion queues, both signalled through
+//! doorbell registers. The driver uses the admin queue to identify the
+//! controller and its namespaces and to create one I/O queue pair, which
+//! all namespaces share. Data moves through a bounce buffer described by PRP
+//! entries, completion is polled. Namespaces with blocks larger than 512
+//! bytes are addressed in 512-byte sectors like every other disk, with
+//! partial blocks read, patched and written back. A command that never
+//! completes, or a completion for the wrong command, means the queues no
+//! longer match the controller, so the controller is reset and its queues
+//! rebuilt before the buffers are reused. Namespaces are registered as
+//! `nvme0n1`, ...
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::drivers::pci::{self, Bar, PciDevice, PciDriver, PciId};
+use crate::memory::dma::DmaBuffer;
+use crate::memory::paging;
+
+/// Controller registers
+const REG_CAP: usize = 0x00;
+const REG_VS: usize = 0x08;
+const REG_INTMS: usize = 0x0C;
+const REG_CC: usize = 0x14;
+const REG_CSTS: usize = 0x1C;
+const REG_AQA: usize = 0x24;
+const REG_ASQ: usize = 0x28;
+const REG_ACQ: usize = 0x30;
+const DOORBELL_BASE: usize = 0x1000;
+
+const CC_EN: u32 = 1 << 0;
+/// 64-byte submission and 16-byte completion queue entries
+const CC_IOSQES: u32 = 6 << 16;
+const CC_IOCQES: u32 = 4 << 20;
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_CFS: u32 = 1 << 1;
+
+/// Admin command opcodes
+const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+
+/// Identify CNS values
+const CNS_NAMESPACE: u32 = 0x00;
+const CNS_CONTROLLER: u32 = 0x01;
+const CNS_ACTIVE_NAMESPACES: u32 = 0x02;
+
+/// I/O command opcodes
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+/// The driver only uses 4 KiB memory pages
+const PAGE_SIZE: usize = 4096;
+
+const COMMAND_SIZE: usize = 64;
+const COMPLETION_SIZE: usize = 16;
+
+/// Entries per queue; a page of submissions for each
+const ADMIN_QUEUE_SIZE: u16 = 32;
+const IO_QUEUE_SIZE: u16 = 64;
+
+/// Size of the bounce buffer, the largest single transfer
+const BOUNCE_SIZE: usize = 128 * 1024;
+
+/// Register and completion polls before giving up
+const TIMEOUT: u32 = 10_000_000;
+
+/// Memory-mapped controller registers
+#[derive(Clone, Copy)]
+struct Regs {
+    base: usize,
+}
+
+impl Regs {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn read64(&self, offset: usize) -> u64 {
+        self.read(offset) as u64 | (self.read(offset + 4) as u64) << 32
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+
+    /// Wait until `(CSTS & mask) == value`, failing on a fatal status
+    fn wait_status(&self, mask: u32, value: u32) -> Result<(), &'static str> {
+        for _ in 0..TIMEOUT {
+            let csts = self.read(REG_CSTS);
+            if csts & CSTS_CFS != 0 {
+                return Err("controller fatal status");
+            }
+            if csts & mask == value {
+                return Ok(());
+            }
+        }
+        Err("controller timed out")
+    }
+}
+
+/// Why a command did not succeed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandError {
+    /// The controller completed the command with an error status
+    Failed,
+    /// No completion arrived, or one arrived for another command; the
+    /// controller may still own the command and its buffers
+    Lost,
+}
+
+/// A command to submit; unset fields are zero
+#[derive(Default)]
+struct Command {
+    opcode: u8,
+    nsid: u32,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+}
+
+/// A submission/completion queue pair
+struct QueuePair {
+    sq: DmaBuffer,
+    cq: DmaBuffer,
+    size: u16,
+    sq_tail: u16,
+    cq_head: u16,
+    /// Phase tag that marks a new completion; flips each pass through the queue
+    phase: bool,
+    sq_doorbell: usize,
+    cq_doorbell: usize,
+    next_cid: u16,
+}
+
+impl QueuePair {
+    fn new(id: u16, size: u16, stride: usize) -> Option<Self> {
+        Some(Self {
+            sq: DmaBuffer::new(size as usize * COMMAND_SIZE)?,
+            cq: DmaBuffer::new(size as usize * COMPLETION_SIZE)?,
+            size,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            sq_doorbell: DOORBELL_BASE + (2 * id as usize) * stride,
+            cq_doorbell: DOORBELL_BASE + (2 * id as usize + 1) * stride,
+            next_cid: 0,
+        })
+    }
+
+    /// Forget all entries, for a controller that has been reset
+    fn reset(&mut self) {
+        // Stale completions would otherwise match the phase tag again
+        self.cq.as_mut_slice().fill(0);
+        self.sq_tail = 0;
+        self.cq_head = 0;
+        self.phase = true;
+    }
+
+    /// Submit a command and poll for its completion
+    fn execute(&mut self, regs: Regs, cmd: &Command) -> Result<(), CommandError> {
+        let cid = self.next_cid;
+        self.next_cid = self.next_cid.wrapping_add(1);
+
+        let mut entry = [0u32; COMMAND_SIZE / 4];
+        entry[0] = cmd.opcode as u32 | (cid as u32) << 16;
+        entry[1] = cmd.nsid;
+        entry[6] = cmd.prp1 as u32;
+        entry[7] = (cmd.prp1 >> 32) as u32;
+        entry[8] = cmd.prp2 as u32;
+        entry[9] = (cmd.prp2 >> 32) as u32;
+        entry[10] = cmd.cdw10;
+        entry[11] = cmd.cdw11;
+        entry[12] = cmd.cdw12;
+        let slot = self.sq.ptr::<u32>(self.sq_tail as usize * COMMAND_SIZE);
+        for (i, dword) in entry.iter().enumerate() {
+            unsafe { slot.add(i).write_volatile(*dword) };
+        }
+        self.sq_tail = (self.sq_tail + 1) % self.size;
+        regs.write(self.sq_doorbell, self.sq_tail as u32);
+
+        for _ in 0..TIMEOUT {
+            let completion = self.cq.ptr::<u32>(self.cq_head as usize * COMPLETION_SIZE);
+            let dw3 = unsafe { completion.add(3).read_volatile() };
+            if (dw3 >> 16) & 1 != self.phase as u32 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            self.cq_head += 1;
+            if self.cq_head == self.size {
+                self.cq_head = 0;
+                self.phase = !self.phase;
+            }
+            regs.write(self.cq_doorbell, self.cq_head as u32);
+
+            // Commands run one at a time, so any other completion means
+            // the queue is out of step with the controller
+            if dw3 as u16 != cid {
+                return Err(CommandError::Lost);
+            }
+            if (dw3 >> 17) & 0x7FFF != 0 {
+                return Err(CommandError::Failed);
+            }
+            return Ok(());
+        }
+        Err(CommandError::Lost)
+    }
+}
+
+/// I/O queue and transfer memory, guarded by the controller's lock
+struct IoState {
+    queue: QueuePair,
+    bounce: DmaBuffer,
+    /// A page of PRP entries for transfers longer than two pages
+    prp_list: DmaBuffer,
+}
+
+/// An NVMe controller shared by its namespaces
+struct Controller {
+    regs: Regs,
+    /// The controller keeps the admin queue's addresses while enabled
+    admin: Mutex<QueuePair>,
+    /// Largest transfer per command in bytes
+    max_transfer: usize,
+    /// A lost command could not be recovered from; the controller is
+    /// disabled and never used again
+    dead: AtomicBool,
+    io: Mutex<IoState>,
+}
+
+impl Controller {
+    /// Fill in PRP1/PRP2 for a transfer of `bytes` from the bounce buffer
+    fn set_prps(st: &IoState, cmd: &mut Command, bytes: usize) {
+        let pages = bytes.div_ceil(PAGE_SIZE);
+        cmd.prp1 = st.bounce.phys();
+        if pages == 2 {
+            cmd.prp2 = st.bounce.phys() + PAGE_SIZE as u64;
+        } else if pages > 2 {
+            for page in 1..pages {
+                let entry = st.prp_list.ptr::<u64>((page - 1) * 8);
+                unsafe { entry.write_volatile(st.bounce.phys() + (page * PAGE_SIZE) as u64) };
+            }
+            cmd.prp2 = st.prp_list.phys();
+        }
+    }
+
+    /// Submit an I/O command, resetting the controller if it is lost
+    fn execute(&self, st: &mut IoState, cmd: &Command) -> Result<(), BlockError> {
+        if self.dead.load(Ordering::Relaxed) {
+            return Err(BlockError::Io);
+        }
+        match st.queue.execute(self.regs, cmd) {
+            Ok(()) => Ok(()),
+            Err(CommandError::Failed) => Err(BlockError::Io),
+            Err(CommandError::Lost) => {
+                self.recover(st);
+                Err(BlockError::Io)
+            }
+        }
+    }
+
+    /// Reset the controller so it lets go of a lost command, and rebuild
+    /// the queues; a controller that will not come back is disabled
+    fn recover(&self, st: &mut IoState) {
+        crate::serial::print("NVMe: command lost, resetting the controller\n");
+        let mut admin = self.admin.lock();
+        admin.reset();
+        st.queue.reset();
+        let restarted = enable(self.regs, &admin).and_then(|_| create_io_queues(self.regs, &mut admin, &st.queue));
+        if let Err(e) = restarted {
+            crate::serial::print("NVMe: ");
+            crate::serial::print(e);
+            crate::serial::print(", disabling the controller\n");
+            let _ = disable(self.regs);
+            self.dead.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Run a read or write of `len` bytes at sector `lba` through the
+    /// bounce buffer
+    ///
+    /// Commands cover whole blocks of `1 << block_shift` bytes; a write that
+    /// only covers part of a command's blocks reads them in first.
+    fn transfer(
+        &self,
+        nsid: u32,
+        block_shift: u32,
+        lba: u64,
+        len: usize,
+        write: bool,
+        mut data: impl FnMut(&mut [u8], usize),
+    ) -> Result<(), BlockError> {
+        let mut st = self.io.lock();
+        let block_mask = (1u64 << block_shift) - 1;
+        let start = lba * SECTOR_SIZE as u64;
+        let end = start + len as u64;
+        let mut pos = start;
+        while pos < end {
+            let first = pos & !block_mask;
+            let last = (first + self.max_transfer as u64).min((end + block_mask) & !block_mask);
+            let bytes = (last - first) as usize;
+            let head = (pos - first) as usize;
+            let count = (last.min(end) - pos) as usize;
+            let done = (pos - start) as usize;
+
+            let slba = first >> block_shift;
+            let mut cmd = Command {
+                opcode: IO_READ,
+                nsid,
+                cdw10: slba as u32,
+                cdw11: (slba >> 32) as u32,
+                // Number of blocks is zero-based
+                cdw12: ((bytes >> block_shift) - 1) as u32,
+                ..Command::default()
+            };
+            Self::set_prps(&st, &mut cmd, bytes);
+            if write {
+                if head != 0 || count != bytes {
+                    self.execute(&mut st, &cmd)?;
+                }
+                data(&mut st.bounce.as_mut_slice()[head..head + count], done);
+                cmd.opcode = IO_WRITE;
+            }
+            self.execute(&mut st, &cmd)?;
+            if !write {
+                data(&mut st.bounce.as_mut_slice()[head..head + count], done);
+            }
+            pos += count as u64;
+        }
+        Ok(())
+    }
+}
+
+/// A namespace, exposed as a disk
+pub struct NvmeNamespace {
+    controller: Arc<Controller>,
+    nsid: u32,
+    /// Log2 of the namespace's block size, at least that of a sector
+    block_shift: u32,
+    sectors: u64,
+}
+
+impl BlockDevice for NvmeNamespace {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let len = buf.len();
+        self.controller.transfer(self.nsid, self.block_shift, lba, len, false, |bounce, offset| {
+            buf[offset..offset + bounce.len()].copy_from_slice(bounce);
+        })
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        self.controller.transfer(self.nsid, self.block_shift, lba, buf.len(), true, |bounce, offset| {
+            bounce.copy_from_slice(&buf[offset..offset + bounce.len()]);
+        })
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let mut st = self.controller.io.lock();
+        let cmd = Command {
+            opcode: IO_FLUSH,
+            nsid: self.nsid,
+            ..Command::default()
+        };
+        self.controller.execute(&mut st, &cmd)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+/// Issue IDENTIFY with the given CNS into a one-page buffer
+fn identify(admin: &mut QueuePair, regs: Regs, buf: &DmaBuffer, cns: u32, nsid: u32) -> Result<(), CommandError> {
+    let cmd = Command {
+        opcode: ADMIN_IDENTIFY,
+        nsid,
+        prp1: buf.phys(),
+        cdw10: cns,
+        ..Command::default()
+    };
+    admin.execute(regs, &cmd)
+}
+
+/// Turn the controller off; once it is no longer ready it has stopped
+/// using the queues in host memory
+fn disable(regs: Regs) -> Result<(), &'static str> {
+    if regs.read(REG_CC) & CC_EN != 0 {
+        regs.write(REG_CC, regs.read(REG_CC) & !CC_EN);
+    }
+    regs.wait_status(CSTS_RDY, 0)
+}
+
+/// Reset the controller and bring it up with a fresh admin queue
+fn enable(regs: Regs, admin: &QueuePair) -> Result<(), &'static str> {
+    disable(regs)?;
+
+    let size = (admin.size - 1) as u32;
+    regs.write(REG_AQA, size << 16 | size);
+    regs.write64(REG_ASQ, admin.sq.phys());
+    regs.write64(REG_ACQ, admin.cq.phys());
+    // NVM command set, 4 KiB pages, round-robin arbitration
+    regs.write(REG_CC, CC_EN | CC_IOSQES | CC_IOCQES);
+    regs.wait_status(CSTS_RDY, CSTS_RDY)?;
+    // Completion is polled
+    regs.write(REG_INTMS, u32::MAX);
+    Ok(())
+}
+
+/// Identify the controller, create the I/O queue pair and list the active
+/// namespaces
+///
+/// Returns the model, the largest transfer per command and the namespace IDs.
+fn setup(regs: Regs, admin: &mut QueuePair, io: &QueuePair, buf: &DmaBuffer) -> Result<(String, usize, Vec<u32>), &'static str> {
+    identify(admin, regs, buf, CNS_CONTROLLER, 0).map_err(|_| "IDENTIFY failed")?;
+    let model = ascii(&buf.as_slice()[24..64]);
+    // MDTS: a power of two in units of the minimum page size, 0 if unlimited
+    let mdts = buf.as_slice()[77] as u32;
+    let max_transfer = match mdts {
+        0 => BOUNCE_SIZE,
+        n => 1usize
+            .checked_shl(n)
+            .and_then(|pages| pages.checked_mul(PAGE_SIZE))
+            .map_or(BOUNCE_SIZE, |max| max.min(BOUNCE_SIZE)),
+    };
+
+    create_io_queues(regs, admin, io)?;
+
+    identify(admin, regs, buf, CNS_ACTIVE_NAMESPACES, 0).map_err(|_| "IDENTIFY failed")?;
+    let nsids = buf
+        .as_slice()
+        .chunks_exact(4)
+        .map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+        .take_while(|&id| id != 0)
+        .collect();
+    Ok((model, max_transfer, nsids))
+}
+
+/// Create the I/O queue pair with ID 1; the completion queue must exist first
+fn create_io_queues(regs: Regs, admin: &mut QueuePair, io: &QueuePair) -> Result<(), &'static str> {
+    let create_cq = Command {
+        opcode: ADMIN_CREATE_IO_CQ,
+        prp1: io.cq.phys(),
+        cdw10: ((io.size - 1) as u32) << 16 | 1,
+        cdw11: 1, // physically contiguous, interrupts off
+        ..Command::default()
+    };
+    admin.execute(regs, &create_cq).map_err(|_| "cannot create I/O completion queue")?;
+    let create_sq = Command {
+        opcode: ADMIN_CREATE_IO_SQ,
+        prp1: io.sq.phys(),
+        cdw10: ((io.size - 1) as u32) << 16 | 1,
+        cdw11: 1 << 16 | 1, // completion queue 1, physically contiguous
+        ..Command::default()
+    };
+    admin.execute(regs, &create_sq).map_err(|_| "cannot create I/O submission queue")?;
+    Ok(())
+}
+
+/// Extract an ASCII string field from identify data
+fn ascii(data: &[u8]) -> String {
+    String::from(String::from_utf8_lossy(data).trim())
+}
+
+/// Controller numbers handed out so far
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+/// Prog IF 2 is the NVM Express interface
+const IDS: &[PciId] = &[PciId::class_if(0x01, 0x08, 0x02)];
+
+/// PCI driver for NVMe controllers
+pub struct NvmeDriver;
+
+pub static DRIVER: NvmeDriver = NvmeDriver;
+
+impl PciDriver for NvmeDriver {
+    fn name(&self) -> &'static str {
+        "nvme"
+    }
+
+    fn ids(&self) -> &'static [PciId] {
+        IDS
+    }
+
+    /// Bring up the controller and register each active namespace
+    fn probe(&self, dev: &PciDevice) -> Result<(), &'static str> {
+        let Bar::Memory { addr, size, .. } = dev.bar(0) else {
+            return Err("BAR0 is not a memory BAR");
+        };
+        dev.enable(pci::COMMAND_MEMORY_SPACE | pci::COMMAND_BUS_MASTER);
+
+        // The register page holds CAP, which gives the doorbell stride
+        let header = paging::map_mmio(PhysAddr::new(addr), DOORBELL_BASE as u64)?.as_u64() as usize;
+        let cap = Regs { base: header }.read64(REG_CAP);
+        if (cap >> 37) & 1 == 0 {
+            return Err("NVM command set not supported");
+        }
+        if (cap >> 48) & 0xF != 0 {
+            return Err("4 KiB pages not supported");
+        }
+        let stride = 4usize << ((cap >> 32) & 0xF);
+        let max_entries = (cap & 0xFFFF) as u16 + 1;
+
+        // Registers plus the admin and one I/O doorbell pair
+        let mapped = (DOORBELL_BASE + 4 * stride) as u64;
+        if mapped > size {
+            return Err("BAR0 too small for the doorbells");
+        }
+        let base = paging::map_mmio(PhysAddr::new(addr), mapped)?.as_u64() as usize;
+        let regs = Regs { base };
+
+        // Everything the controller will be pointed at is allocated before
+        // it is enabled, so a failure can turn it off before freeing any
+        let mut admin = QueuePair::new(0, ADMIN_QUEUE_SIZE.min(max_entries), stride).ok_or("out of DMA memory")?;
+        let io = QueuePair::new(1, IO_QUEUE_SIZE.min(max_entries), stride).ok_or("out of DMA memory")?;
+        let buf = DmaBuffer::new(PAGE_SIZE).ok_or("out of DMA memory")?;
+        let bounce = DmaBuffer::new(BOUNCE_SIZE).ok_or("out of DMA memory")?;
+        let prp_list = DmaBuffer::new(PAGE_SIZE).ok_or("out of DMA memory")?;
+
+        let (model, max_transfer, nsids) = match enable(regs, &admin).and_then(|_| setup(regs, &mut admin, &io, &buf)) {
+            Ok(found) => found,
+            Err(e) => {
+                let _ = disable(regs);
+                return Err(e);
+            }
+        };
+
+        let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        let version = regs.read(REG_VS);
+        crate::serial::print("NVMe: nvme");
+        crate::memory::print_decimal(index as u64);
+        crate::serial::print(": ");
+        crate::serial::print(&model);
+        crate::serial::print(", version ");
+        crate::memory::print_decimal((version >> 16) as u64);
+        crate::serial::print(".");
+        crate::memory::print_decimal(((version >> 8) & 0xFF) as u64);
+        crate::serial::print("\n");
+
+        let controller = Arc::new(Controller {
+            regs,
+            admin: Mutex::new(admin),
+            max_transfer,
+            dead: AtomicBool::new(false),
+            io: Mutex::new(IoState { queue: io, bounce, prp_list }),
+        });
+
+        for nsid in nsids {
+            if controller.dead.load(Ordering::Relaxed) {
+                break;
+            }
+            let identified = identify(&mut controller.admin.lock(), regs, &buf, CNS_NAMESPACE, nsid);
+            if identified == Err(CommandError::Lost) {
+                controller.recover(&mut controller.io.lock());
+            }
+            if identified.is_err() {
+                continue;
+            }
+            let data = buf.as_slice();
+            let blocks = u64::from_le_bytes(data[0..8].try_into().unwrap());
+            let format = (data[26] & 0xF) as usize;
+            let block_shift = data[128 + format * 4 + 2] as u32;
+            let name = alloc::format!("nvme{}n{}", index, nsid);
+            // A command moves at least one block through the bounce buffer
+            let sectors = (SECTOR_SIZE.ilog2()..=max_transfer.ilog2())
+                .contains(&block_shift)
+                .then(|| blocks.checked_mul(1 << (block_shift - SECTOR_SIZE.ilog2())))
+                .flatten();
+            let Some(sectors) = sectors else {
+                crate::serial::print("NVMe: ");
+                crate::serial::print(&name);
+                crate::serial::print(": unsupported block size\n");
+                continue;
+            };
+
+            crate::serial::print("NVMe: ");
+            crate::serial::print(&name);
+            crate::serial::print(", ");
+            crate::memory::print_decimal(sectors);
+            crate::serial::print(" sectors\n");
+            let namespace = NvmeNamespace {
+                controller: controller.clone(),
+                nsid,
+                block_shift,
+                sectors,
+            };
+            super::register(name, Arc::new(namespace));
+        }
+        Ok(())
+    }
+}
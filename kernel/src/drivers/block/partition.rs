@@ -0,0 +1,484 @@
+//! Partition tables
+//!
+//! Disks are scanned for a GPT (preferred when the MBR is protective) or an
+//! MBR with extended partitions, and every partition found is exposed as a
+//! block device of its own.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// A contiguous range of sectors on a parent device
+pub struct Partition {
+    parent: Arc<dyn BlockDevice>,
+    start: u64,
+    sectors: u64,
+}
+
+impl BlockDevice for Partition {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        self.parent.read_sectors(self.start + lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        self.parent.write_sectors(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.parent.flush()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+/// A partition table entry
+pub struct PartitionEntry {
+    /// 1-based partition number; MBR logical partitions start at 5
+    pub number: u32,
+    pub start: u64,
+    pub sectors: u64,
+    /// Partition type, e.g. `Linux filesystem`
+    pub type_name: &'static str,
+    /// GPT partition name, empty for MBR
+    pub label: String,
+}
+
+impl PartitionEntry {
+    /// Create the block device for this partition
+    pub fn device(&self, parent: Arc<dyn BlockDevice>) -> Arc<dyn BlockDevice> {
+        Arc::new(Partition {
+            parent,
+            start: self.start,
+            sectors: self.sectors,
+        })
+    }
+}
+
+/// Name of partition `number` on `disk`: `ram0` gives `ram0p1`, `hda` gives `hda1`
+pub fn partition_name(disk: &str, number: u32) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        alloc::format!("{}p{}", disk, number)
+    } else {
+        alloc::format!("{}{}", disk, number)
+    }
+}
+
+/// Read the partition table of a device, if it has one
+pub fn scan(device: &dyn BlockDevice) -> Result<Vec<PartitionEntry>, BlockError> {
+    let mut mbr = [0u8; SECTOR_SIZE];
+    device.read_sectors(0, &mut mbr)?;
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return Ok(Vec::new());
+    }
+
+    // A protective MBR covers the disk with a single 0xEE entry
+    let protective = (0..4).any(|i| mbr[MBR_TABLE + i * MBR_ENTRY_SIZE + 4] == 0xEE);
+    if protective {
+        if let Some(entries) = scan_gpt(device)? {
+            return Ok(entries);
+        }
+        crate::serial::print("Partition: protective MBR without a valid GPT\n");
+        return Ok(Vec::new());
+    }
+
+    scan_mbr(device, &mbr)
+}
+
+const MBR_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// Upper bound on logical partitions, in case the EBR chain loops
+const MAX_LOGICAL: u32 = 128;
+
+/// A raw MBR entry: (type, start LBA, sector count)
+fn mbr_entry(sector: &[u8], index: usize) -> (u8, u64, u64) {
+    let e = &sector[MBR_TABLE + index * MBR_ENTRY_SIZE..];
+    (e[4], le32(&e[8..]) as u64, le32(&e[12..]) as u64)
+}
+
+fn is_extended(kind: u8) -> bool {
+    matches!(kind, 0x05 | 0x0F | 0x85)
+}
+
+fn scan_mbr(device: &dyn BlockDevice, mbr: &[u8]) -> Result<Vec<PartitionEntry>, BlockError> {
+    let mut entries = Vec::new();
+    let mut extended = None;
+
+    for index in 0..4 {
+        let (kind, start, sectors) = mbr_entry(mbr, index);
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+        if is_extended(kind) {
+            extended = Some(start);
+            continue;
+        }
+        entries.push(PartitionEntry {
+            number: index as u32 + 1,
+            start,
+            sectors,
+            type_name: mbr_type_name(kind),
+            label: String::new(),
+        });
+    }
+
+    // Logical partitions form a chain of EBRs inside the extended partition;
+    // each EBR locates its partition relative to itself and the next EBR
+    // relative to the start of the extended partition.
+    if let Some(ext_start) = extended {
+        let mut ebr_lba = ext_start;
+        let mut ebr = [0u8; SECTOR_SIZE];
+        for number in 5..5 + MAX_LOGICAL {
+            device.read_sectors(ebr_lba, &mut ebr)?;
+            if ebr[510] != 0x55 || ebr[511] != 0xAA {
+                break;
+            }
+            let (kind, start, sectors) = mbr_entry(&ebr, 0);
+            if kind != 0 && sectors != 0 {
+                entries.push(PartitionEntry {
+                    number,
+                    start: ebr_lba + start,
+                    sectors,
+                    type_name: mbr_type_name(kind),
+                    label: String::new(),
+                });
+            }
+            let (next_kind, next_start, _) = mbr_entry(&ebr, 1);
+            if !is_extended(next_kind) || next_start == 0 {
+                break;
+            }
+            ebr_lba = ext_start + next_start;
+        }
+    }
+
+    Ok(entries)
+}
+
+fn mbr_type_name(kind: u8) -> &'static str {
+    match kind {
+        0x01 | 0x04 | 0x06 | 0x0E => "FAT16",
+        0x07 => "NTFS/exFAT",
+        0x0B | 0x0C => "FAT32",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x8E => "Linux LVM",
+        0xEF => "EFI System",
+        _ => "unknown",
+    }
+}
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+const GPT_MAX_ENTRY_SIZE: usize = 4096;
+/// Refuse tables larger than this many entries
+const GPT_MAX_ENTRIES: usize = 1024;
+/// Refuse entry arrays larger than this many bytes, enough for the
+/// largest table of 128-byte entries
+const GPT_MAX_TABLE_SIZE: usize = GPT_MAX_ENTRIES * GPT_MIN_ENTRY_SIZE;
+
+/// Try the primary GPT header, then the backup in the last sector
+///
+/// A primary table that is damaged or cannot be read falls back to the
+/// backup; a read error is only reported if neither copy is usable.
+fn scan_gpt(device: &dyn BlockDevice) -> Result<Option<Vec<PartitionEntry>>, BlockError> {
+    let mut error = None;
+    for header_lba in [1, device.sector_count().saturating_sub(1)] {
+        match read_gpt(device, header_lba) {
+            Ok(Some(entries)) => return Ok(Some(entries)),
+            Ok(None) => {}
+            Err(e) => {
+                crate::serial::print("Partition: read error in GPT\n");
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
+
+fn read_gpt(device: &dyn BlockDevice, header_lba: u64) -> Result<Option<Vec<PartitionEntry>>, BlockError> {
+    let mut header = [0u8; SECTOR_SIZE];
+    device.read_sectors(header_lba, &mut header)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+
+    let header_size = le32(&header[12..]) as usize;
+    if !(GPT_MIN_HEADER_SIZE..=SECTOR_SIZE).contains(&header_size) {
+        return Ok(None);
+    }
+    let header_crc = le32(&header[16..]);
+    let mut check = header;
+    check[16..20].fill(0);
+    if crc32(&check[..header_size]) != header_crc {
+        crate::serial::print("Partition: GPT header checksum mismatch\n");
+        return Ok(None);
+    }
+
+    let entries_lba = le64(&header[72..]);
+    let count = le32(&header[80..]) as usize;
+    let entry_size = le32(&header[84..]) as usize;
+    let entries_crc = le32(&header[88..]);
+    // Entries are 128 bytes times a power of two; both values come from
+    // disk, so bound them before sizing the buffer
+    if !entry_size.is_power_of_two()
+        || !(GPT_MIN_ENTRY_SIZE..=GPT_MAX_ENTRY_SIZE).contains(&entry_size)
+        || count > GPT_MAX_ENTRIES
+        || count * entry_size > GPT_MAX_TABLE_SIZE
+    {
+        crate::serial::print("Partition: GPT entry array size out of range\n");
+        return Ok(None);
+    }
+
+    let bytes = count * entry_size;
+    let mut table = vec![0u8; bytes.div_ceil(SECTOR_SIZE) * SECTOR_SIZE];
+    device.read_sectors(entries_lba, &mut table)?;
+    if crc32(&table[..bytes]) != entries_crc {
+        crate::serial::print("Partition: GPT entry array checksum mismatch\n");
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    for (index, raw) in table[..bytes].chunks(entry_size).enumerate() {
+        let type_guid: [u8; 16] = raw[0..16].try_into().unwrap();
+        if type_guid == [0; 16] {
+            continue;
+        }
+        let first = le64(&raw[32..]);
+        let last = le64(&raw[40..]);
+        if last < first {
+            continue;
+        }
+        entries.push(PartitionEntry {
+            number: index as u32 + 1,
+            start: first,
+            sectors: last - first + 1,
+            type_name: gpt_type_name(&type_guid),
+            label: utf16_name(&raw[56..128]),
+        });
+    }
+    Ok(Some(entries))
+}
+
+/// Encode a GUID in its on-disk mixed-endian form
+const fn guid(a: u32, b: u16, c: u16, d: [u8; 8]) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7],
+    ]
+}
+
+const GPT_TYPES: &[([u8; 16], &str)] = &[
+    (guid(0xC12A7328, 0xF81F, 0x11D2, [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]), "EFI System"),
+    (guid(0x21686148, 0x6449, 0x6E6F, [0x74, 0x4E, 0x65, 0x65, 0x64, 0x45, 0x46, 0x49]), "BIOS boot"),
+    (guid(0x0FC63DAF, 0x8483, 0x4772, [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4]), "Linux filesystem"),
+    (guid(0x0657FD6D, 0xA4AB, 0x43C4, [0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B, 0x4F, 0x4F]), "Linux swap"),
+    (guid(0xEBD0A0A2, 0xB9E5, 0x4433, [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]), "Microsoft basic data"),
+];
+
+fn gpt_type_name(type_guid: &[u8; 16]) -> &'static str {
+    GPT_TYPES
+        .iter()
+        .find(|(g, _)| g == type_guid)
+        .map(|(_, name)| *name)
+        .unwrap_or("unknown")
+}
+
+/// Decode a NUL-terminated UTF-16LE partition name
+fn utf16_name(raw: &[u8]) -> String {
+    let units = raw
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0);
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// CRC-32 (IEEE 802.3), as used by GPT
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    le32(bytes) as u64 | (le32(&bytes[4..]) as u64) << 32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spin::Mutex;
+
+    const DISK_SECTORS: u64 = 8192;
+
+    /// An in-memory disk for feeding partition tables to `scan`
+    struct MemDisk(Mutex<Vec<u8>>);
+
+    impl MemDisk {
+        fn new() -> Self {
+            Self(Mutex::new(vec![0; DISK_SECTORS as usize * SECTOR_SIZE]))
+        }
+
+        fn put(&self, offset: usize, bytes: &[u8]) {
+            self.0.lock()[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        /// Write an MBR-style entry into the sector at `lba`
+        fn put_mbr_entry(&self, lba: u64, index: usize, kind: u8, start: u32, sectors: u32) {
+            let offset = lba as usize * SECTOR_SIZE + MBR_TABLE + index * MBR_ENTRY_SIZE;
+            self.put(offset + 4, &[kind]);
+            self.put(offset + 8, &start.to_le_bytes());
+            self.put(offset + 12, &sectors.to_le_bytes());
+            self.put(lba as usize * SECTOR_SIZE + 510, &[0x55, 0xAA]);
+        }
+    }
+
+    impl BlockDevice for MemDisk {
+        fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+            super::super::check_request(lba, buf.len(), DISK_SECTORS)?;
+            let start = lba as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&self.0.lock()[start..start + buf.len()]);
+            Ok(())
+        }
+
+        fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+            super::super::check_request(lba, buf.len(), DISK_SECTORS)?;
+            self.put(lba as usize * SECTOR_SIZE, buf);
+            Ok(())
+        }
+
+        fn sector_count(&self) -> u64 {
+            DISK_SECTORS
+        }
+    }
+
+    /// Entry array and header CRCs of the disk built by `gpt_disk`,
+    /// computed independently with zlib
+    const ENTRIES_CRC: u32 = 0xC17E_9948;
+    const HEADER_CRC: u32 = 0xC914_8F73;
+
+    /// A GPT disk with one Linux partition named `root` at sectors 34..=2081
+    fn gpt_disk() -> MemDisk {
+        let disk = MemDisk::new();
+        disk.put_mbr_entry(0, 0, 0xEE, 1, DISK_SECTORS as u32 - 1);
+
+        let mut entries = [0u8; 4 * 128];
+        entries[0..16].copy_from_slice(&GPT_TYPES[2].0);
+        entries[32..40].copy_from_slice(&34u64.to_le_bytes());
+        entries[40..48].copy_from_slice(&2081u64.to_le_bytes());
+        for (i, unit) in "root".encode_utf16().enumerate() {
+            entries[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        disk.put(2 * SECTOR_SIZE, &entries);
+
+        let mut header = [0u8; 92];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&1u64.to_le_bytes());
+        header[32..40].copy_from_slice(&(DISK_SECTORS - 1).to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(DISK_SECTORS - 34).to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+        let crc = crc32(&header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        disk.put(SECTOR_SIZE, &header);
+        disk
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_gpt_checksums() {
+        let disk = gpt_disk();
+        let mut sector = [0u8; SECTOR_SIZE];
+        disk.read_sectors(1, &mut sector).unwrap();
+        assert_eq!(le32(&sector[88..]), ENTRIES_CRC);
+        assert_eq!(le32(&sector[16..]), HEADER_CRC);
+    }
+
+    #[test]
+    fn test_scan_gpt() {
+        let entries = scan(&gpt_disk()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].number, 1);
+        assert_eq!(entries[0].start, 34);
+        assert_eq!(entries[0].sectors, 2048);
+        assert_eq!(entries[0].type_name, "Linux filesystem");
+        assert_eq!(entries[0].label, "root");
+    }
+
+    #[test]
+    fn test_scan_gpt_falls_back_to_backup() {
+        let disk = gpt_disk();
+        let mut header = [0u8; SECTOR_SIZE];
+        disk.read_sectors(1, &mut header).unwrap();
+        disk.write_sectors(DISK_SECTORS - 1, &header).unwrap();
+
+        // Damage the primary header so its checksum no longer matches
+        disk.put(SECTOR_SIZE + 40, &[0xFF]);
+        let entries = scan(&disk).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].label, "root");
+
+        disk.put((DISK_SECTORS as usize - 1) * SECTOR_SIZE + 40, &[0xFF]);
+        assert!(scan(&disk).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_scan_mbr_with_logical_partitions() {
+        let disk = MemDisk::new();
+        disk.put_mbr_entry(0, 0, 0x83, 2048, 1000);
+        disk.put_mbr_entry(0, 1, 0x05, 4096, 4096);
+        // Each EBR locates its partition relative to itself and the next
+        // EBR relative to the extended partition
+        disk.put_mbr_entry(4096, 0, 0x83, 63, 100);
+        disk.put_mbr_entry(4096, 1, 0x05, 200, 200);
+        disk.put_mbr_entry(4296, 0, 0x82, 63, 50);
+
+        let entries = scan(&disk).unwrap();
+        let found: Vec<_> = entries.iter().map(|e| (e.number, e.start, e.sectors, e.type_name)).collect();
+        assert_eq!(
+            found,
+            [(1, 2048, 1000, "Linux"), (5, 4159, 100, "Linux"), (6, 4359, 50, "Linux swap")]
+        );
+    }
+
+    #[test]
+    fn test_scan_without_signature() {
+        let disk = MemDisk::new();
+        disk.put(MBR_TABLE + 4, &[0x83]);
+        assert!(scan(&disk).unwrap().is_empty());
+    }
+}
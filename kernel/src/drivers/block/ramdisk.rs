@@ -0,0 +1,89 @@
+//! RAM-backed block devices
+//!
+//! Every module loaded by Limine (see `boot/limine.conf`) is exposed as a
+//! `ramN` block device, which lets disk images built on the host be mounted
+//! before any real disk driver is available. Modules whose string is
+//! `font` are console fonts instead, and are left to the console.
+
+use alloc::format;
+use alloc::sync::Arc;
+use limine::request::ModuleRequest;
+use spin::Mutex;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Request boot modules from Limine
+#[used]
+#[unsafe(link_section = ".limine_requests")]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+/// Module string marking a console font
+pub const FONT_MODULE: &str = "font";
+
+/// A block device backed by a region of memory
+pub struct RamDisk {
+    data: Mutex<&'static mut [u8]>,
+    sectors: u64,
+}
+
+impl RamDisk {
+    /// Wrap a memory region; any trailing partial sector is ignored
+    pub fn new(data: &'static mut [u8]) -> Self {
+        let sectors = (data.len() / SECTOR_SIZE) as u64;
+        Self {
+            data: Mutex::new(data),
+            sectors,
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let start = lba as usize * SECTOR_SIZE;
+        let data = self.data.lock();
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let start = lba as usize * SECTOR_SIZE;
+        let mut data = self.data.lock();
+        data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+/// Register each Limine boot module as a RAM disk
+pub fn init() {
+    let Some(response) = MODULE_REQUEST.get_response() else {
+        crate::serial::print("Ramdisk: no boot modules\n");
+        return;
+    };
+
+    let disks = response.modules().iter().filter(|module| module.string().to_bytes() != FONT_MODULE.as_bytes());
+    for (index, module) in disks.enumerate() {
+        // Modules live in bootloader-provided memory that is never reclaimed,
+        // so the slice stays valid for the lifetime of the kernel.
+        let data = unsafe { core::slice::from_raw_parts_mut(module.addr(), module.size() as usize) };
+
+        crate::serial::print("Ramdisk: module ");
+        crate::serial::print(module.path().to_str().unwrap_or("?"));
+        crate::serial::print("\n");
+
+        super::register(format!("ram{}", index), Arc::new(RamDisk::new(data)));
+    }
+}
+
+/// Contents of the first boot module whose string is `string`
+pub fn find_module(string: &str) -> Option<&'static [u8]> {
+    let response = MODULE_REQUEST.get_response()?;
+    let module = response.modules().iter().find(|module| module.string().to_bytes() == string.as_bytes())?;
+    // Boot modules are never reclaimed
+    Some(unsafe { core::slice::from_raw_parts(module.addr(), module.size() as usize) })
+}
@@ -0,0 +1,247 @@
+//! Virtio block device driver
+//!
+//! Each request is a three-part descriptor chain: a header naming the
+//! operation and sector, the data, and a status byte the device fills in.
+//! Data goes through a bounce buffer and completion is polled on the used
+//! ring; see the virtqueue module for why there is no interrupt path. A
+//! request that times out leaves its chain with the device, so the device
+//! is reset and the disk fails every later request rather than reuse the
+//! buffers. Disks are registered as `vda`, `vdb`, ...
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::drivers::pci::{PciDevice, PciDriver, PciId};
+use crate::drivers::virtio::{self, Buffer, VirtioDevice, Virtqueue};
+use crate::memory::dma::DmaBuffer;
+
+/// Feature bits
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+/// Request types
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+
+/// Device configuration: capacity in 512-byte sectors
+const CONFIG_CAPACITY: usize = 0;
+
+/// Layout of the request buffer: 16-byte header, then the status byte
+const HEADER_SIZE: usize = 16;
+const STATUS_OFFSET: usize = HEADER_SIZE;
+
+/// Size of the bounce buffer, the largest single transfer
+const BOUNCE_SIZE: usize = 64 * 1024;
+
+/// Used ring polls before a request is considered timed out
+const TIMEOUT: u32 = 10_000_000;
+
+/// Request memory and the queue, guarded by the disk's lock
+struct State {
+    queue: Virtqueue,
+    request: DmaBuffer,
+    bounce: DmaBuffer,
+}
+
+/// A virtio block device
+pub struct VirtioBlk {
+    device: VirtioDevice,
+    sectors: u64,
+    read_only: bool,
+    flush: bool,
+    /// A request timed out and the device was reset
+    dead: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl VirtioBlk {
+    fn probe(dev: &PciDevice) -> Result<Self, &'static str> {
+        let device = VirtioDevice::new(dev)?;
+        let features = device.negotiate(F_RO | F_FLUSH)?;
+        let queue = match device.setup_queue(0) {
+            Ok(queue) => queue,
+            Err(e) => {
+                device.fail();
+                return Err(e);
+            }
+        };
+        let buffers = || -> Result<(DmaBuffer, DmaBuffer), &'static str> {
+            if queue.size() < 3 {
+                return Err("request queue too small");
+            }
+            let request = DmaBuffer::new(HEADER_SIZE + 1).ok_or("out of DMA memory")?;
+            let bounce = DmaBuffer::new(BOUNCE_SIZE).ok_or("out of DMA memory")?;
+            Ok((request, bounce))
+        };
+        let (request, bounce) = match buffers() {
+            Ok(buffers) => buffers,
+            Err(e) => {
+                // The queue is with the device; take it back before its
+                // memory is freed
+                device.fail();
+                device.reset();
+                return Err(e);
+            }
+        };
+        let state = State { queue, request, bounce };
+        device.driver_ok();
+
+        Ok(Self {
+            sectors: device.config_u64(CONFIG_CAPACITY),
+            read_only: features & F_RO != 0,
+            flush: features & F_FLUSH != 0,
+            dead: AtomicBool::new(false),
+            device,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Submit one request and wait for the device to complete it
+    fn request(&self, st: &mut State, kind: u32, sector: u64, bytes: usize) -> Result<(), BlockError> {
+        if self.dead.load(Ordering::Relaxed) {
+            return Err(BlockError::Io);
+        }
+        unsafe {
+            st.request.ptr::<u32>(0).write_volatile(kind);
+            st.request.ptr::<u32>(4).write_volatile(0);
+            st.request.ptr::<u64>(8).write_volatile(sector);
+            st.request.ptr::<u8>(STATUS_OFFSET).write_volatile(0xFF);
+        }
+
+        let header = Buffer {
+            phys: st.request.phys(),
+            len: HEADER_SIZE as u32,
+            writable: false,
+        };
+        let data = Buffer {
+            phys: st.bounce.phys(),
+            len: bytes as u32,
+            writable: kind == T_IN,
+        };
+        let status = Buffer {
+            phys: st.request.phys() + STATUS_OFFSET as u64,
+            len: 1,
+            writable: true,
+        };
+        let head = if bytes > 0 {
+            st.queue.add(&[header, data, status])
+        } else {
+            st.queue.add(&[header, status])
+        };
+        let head = head.ok_or(BlockError::Io)?;
+        self.device.notify(0);
+
+        for _ in 0..TIMEOUT {
+            let Some((id, _)) = st.queue.pop_used() else {
+                core::hint::spin_loop();
+                continue;
+            };
+            if id != head {
+                continue;
+            }
+            let status = unsafe { st.request.ptr::<u8>(STATUS_OFFSET).read_volatile() };
+            return if status == S_OK { Ok(()) } else { Err(BlockError::Io) };
+        }
+
+        // The device still owns the chain and may write into the buffers
+        // at any time; stop it for good
+        crate::serial::print("virtio-blk: request timed out, resetting the device\n");
+        self.device.reset();
+        self.dead.store(true, Ordering::Relaxed);
+        Err(BlockError::Io)
+    }
+
+    /// Run a read or write through the bounce buffer
+    fn transfer(&self, lba: u64, len: usize, write: bool, mut data: impl FnMut(&mut [u8], usize)) -> Result<(), BlockError> {
+        let mut st = self.state.lock();
+        let mut done = 0;
+        while done < len {
+            let bytes = (len - done).min(BOUNCE_SIZE);
+            let sector = lba + (done / SECTOR_SIZE) as u64;
+            if write {
+                data(&mut st.bounce.as_mut_slice()[..bytes], done);
+                self.request(&mut st, T_OUT, sector, bytes)?;
+            } else {
+                self.request(&mut st, T_IN, sector, bytes)?;
+                data(&mut st.bounce.as_mut_slice()[..bytes], done);
+            }
+            done += bytes;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        let len = buf.len();
+        self.transfer(lba, len, false, |bounce, offset| {
+            buf[offset..offset + bounce.len()].copy_from_slice(bounce);
+        })
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(lba, buf.len(), self.sectors)?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        self.transfer(lba, buf.len(), true, |bounce, offset| {
+            bounce.copy_from_slice(&buf[offset..offset + bounce.len()]);
+        })
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if !self.flush || self.read_only {
+            return Ok(());
+        }
+        let mut st = self.state.lock();
+        self.request(&mut st, T_FLUSH, 0, 0)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+/// Transitional and modern-only device IDs of virtio-blk
+const IDS: &[PciId] = &[
+    PciId::device(virtio::VENDOR_ID, 0x1001),
+    PciId::device(virtio::VENDOR_ID, 0x1042),
+];
+
+/// Disk letters handed out so far
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+/// PCI driver for virtio block devices
+pub struct VirtioBlkDriver;
+
+pub static DRIVER: VirtioBlkDriver = VirtioBlkDriver;
+
+impl PciDriver for VirtioBlkDriver {
+    fn name(&self) -> &'static str {
+        "virtio-blk"
+    }
+
+    fn ids(&self) -> &'static [PciId] {
+        IDS
+    }
+
+    fn probe(&self, dev: &PciDevice) -> Result<(), &'static str> {
+        let disk = VirtioBlk::probe(dev)?;
+        let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        let name = alloc::format!("vd{}", (b'a' + index) as char);
+
+        crate::serial::print("virtio-blk: ");
+        crate::serial::print(&name);
+        crate::serial::print(if disk.device.is_legacy() { " (legacy), " } else { " (modern), " });
+        crate::memory::print_decimal(disk.sectors);
+        crate::serial::print(if disk.read_only { " sectors, read-only\n" } else { " sectors\n" });
+        super::register(name, Arc::new(disk));
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Character devices
+//!
+//! Drivers expose byte-stream devices through the `Device` trait and register
+//! them by name; devfs makes every registered device available under `/dev`.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Errors reported by character devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The device does not support the operation
+    NotSupported,
+}
+
+/// A device accessed as a stream of bytes
+///
+/// Stream devices such as terminals ignore `offset`; memory-like devices such
+/// as the framebuffer treat it as a byte position. Reads never block and
+/// return zero when no data is available.
+pub trait Device: Send + Sync {
+    /// Read into `buf`, returning the number of bytes read
+    fn read(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, DeviceError> {
+        Err(DeviceError::NotSupported)
+    }
+
+    /// Write from `buf`, returning the number of bytes written
+    fn write(&self, _offset: u64, _buf: &[u8]) -> Result<usize, DeviceError> {
+        Err(DeviceError::NotSupported)
+    }
+
+    /// Size in bytes for seekable devices, zero for streams
+    fn size(&self) -> u64 {
+        0
+    }
+}
+
+/// Registered character devices, in registration order
+static DEVICES: Mutex<Vec<(String, Arc<dyn Device>)>> = Mutex::new(Vec::new());
+
+/// Register a character device under the given name
+pub fn register(name: &str, device: Arc<dyn Device>) {
+    crate::serial::print("Device: registered ");
+    crate::serial::print(name);
+    crate::serial::print("\n");
+
+    DEVICES.lock().push((String::from(name), device));
+}
+
+/// Look up a character device by name
+pub fn get(name: &str) -> Option<Arc<dyn Device>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|(dev_name, _)| dev_name == name)
+        .map(|(_, device)| device.clone())
+}
+
+/// Names of all registered character devices
+pub fn names() -> Vec<String> {
+    DEVICES.lock().iter().map(|(name, _)| name.clone()).collect()
+}
+
+/// `/dev/null`: discards writes, reads return end of file
+pub struct NullDevice;
+
+impl Device for NullDevice {
+    fn read(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, DeviceError> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/zero`: discards writes, reads return zero bytes
+pub struct ZeroDevice;
+
+impl Device for ZeroDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/random`: RDRAND when the CPU has it, otherwise a TSC-seeded xorshift
+///
+/// The fallback generator is not cryptographically secure.
+pub struct RandomDevice {
+    has_rdrand: bool,
+    state: Mutex<u64>,
+}
+
+impl RandomDevice {
+    fn new() -> Self {
+        Self {
+            has_rdrand: has_rdrand(),
+            // xorshift needs a non-zero seed
+            state: Mutex::new(rdtsc() | 1),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        if self.has_rdrand
+            && let Some(value) = rdrand()
+        {
+            return value;
+        }
+        let mut state = self.state.lock();
+        let mut x = *state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        *state = x;
+        x
+    }
+}
+
+impl Device for RandomDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        // Mix written bytes into the fallback state
+        let mut state = self.state.lock();
+        for &byte in buf {
+            *state = (state.rotate_left(8) ^ byte as u64) | 1;
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Check CPUID.01H:ECX.RDRAND[bit 30]
+fn has_rdrand() -> bool {
+    let ecx: u32;
+    unsafe {
+        // rbx is reserved by LLVM, so preserve it around CPUID
+        core::arch::asm!(
+            "mov {tmp:r}, rbx",
+            "cpuid",
+            "mov rbx, {tmp:r}",
+            tmp = out(reg) _,
+            inout("eax") 1u32 => _,
+            inout("ecx") 0u32 => ecx,
+            out("edx") _,
+            options(nostack),
+        );
+    }
+    ecx & (1 << 30) != 0
+}
+
+/// Read the hardware random number generator, retrying a few times
+fn rdrand() -> Option<u64> {
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!(
+                "rdrand {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack),
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Read the time-stamp counter
+fn rdtsc() -> u64 {
+    let lo: u32;
+    let hi: u32;
+    unsafe {
+        core::arch::asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack));
+    }
+    ((hi as u64) << 32) | lo as u64
+}
+
+/// Register the memory-like devices that need no hardware
+pub fn init() {
+    register("null", Arc::new(NullDevice));
+    register("zero", Arc::new(ZeroDevice));
+    register("random", Arc::new(RandomDevice::new()));
+}
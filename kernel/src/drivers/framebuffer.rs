@@ -0,0 +1,322 @@
+//! Framebuffer device
+//!
+//! Exposes the boot framebuffer's memory as `/dev/fb0`; byte offsets map
+//! directly onto video memory, `pitch` bytes per scanline.
+//!
+//! For drawing, a framebuffer can be given a shadow buffer in RAM holding
+//! `0x00RRGGBB` pixels. Drawing only touches the shadow and records the
+//! rectangles it changed; `flush` converts those to the framebuffer's
+//! pixel format, whatever its depth and channel layout, and copies them
+//! out. Video memory is slow to read and often slow to write piecemeal, so
+//! this keeps scrolling to a memmove in RAM and one pass over the screen.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use limine::framebuffer::Framebuffer;
+
+use super::device::{Device, DeviceError};
+use crate::memory::dma::DmaBuffer;
+
+/// Dirty rectangles kept before they are merged into their bounding box
+const MAX_DIRTY: usize = 8;
+
+/// A rectangle in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Smallest rectangle covering both
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// Whether the rectangles overlap or share an edge
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right() && other.x <= self.right() && self.y <= other.bottom() && other.y <= self.bottom()
+    }
+
+    /// The part inside a `width` by `height` area
+    fn clip(&self, width: usize, height: usize) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Rect::new(x, y, self.right().min(width) - x, self.bottom().min(height) - y)
+    }
+}
+
+/// Where a color channel sits in a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Channel {
+    size: u8,
+    shift: u8,
+}
+
+impl Channel {
+    /// Place an 8-bit intensity in the channel
+    fn encode(self, value: u32) -> u32 {
+        let scaled = if self.size <= 8 {
+            value >> (8 - self.size)
+        } else {
+            value << (self.size - 8)
+        };
+        scaled << self.shift
+    }
+}
+
+/// The boot framebuffer, for `/dev/fb0` and for drawing
+pub struct FramebufferDevice {
+    addr: usize,
+    size: usize,
+    width: usize,
+    height: usize,
+    /// Bytes per scanline
+    pitch: usize,
+    bytes_per_pixel: usize,
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+    /// Drawing target, `width` pixels per row
+    shadow: Option<DmaBuffer>,
+    /// Parts of the shadow not yet copied to the framebuffer
+    dirty: Vec<Rect>,
+}
+
+impl FramebufferDevice {
+    /// Wrap the memory of a Limine framebuffer
+    pub fn new(framebuffer: &Framebuffer) -> Self {
+        let channel = |size, shift| Channel { size, shift };
+        Self {
+            addr: framebuffer.addr() as usize,
+            size: framebuffer.pitch() as usize * framebuffer.height() as usize,
+            width: framebuffer.width() as usize,
+            height: framebuffer.height() as usize,
+            pitch: framebuffer.pitch() as usize,
+            bytes_per_pixel: (framebuffer.bpp() as usize).div_ceil(8),
+            red: channel(framebuffer.red_mask_size(), framebuffer.red_mask_shift()),
+            green: channel(framebuffer.green_mask_size(), framebuffer.green_mask_shift()),
+            blue: channel(framebuffer.blue_mask_size(), framebuffer.blue_mask_shift()),
+            shadow: None,
+            dirty: Vec::new(),
+        }
+    }
+
+    /// Wrap a framebuffer for drawing, with a shadow buffer
+    ///
+    /// The shadow is megabytes in size, too much for the kernel heap, so
+    /// it takes whole frames. Returns `None` if there are not enough.
+    pub fn with_shadow(framebuffer: &Framebuffer) -> Option<Self> {
+        let mut device = Self::new(framebuffer);
+        device.shadow = Some(DmaBuffer::new(device.width * device.height * 4)?);
+        Some(device)
+    }
+
+    /// Size in pixels
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Whether pixels are 32-bit `0x00RRGGBB`, the shadow's own format
+    fn is_native(&self) -> bool {
+        self.bytes_per_pixel == 4
+            && self.red == (Channel { size: 8, shift: 16 })
+            && self.green == (Channel { size: 8, shift: 8 })
+            && self.blue == (Channel { size: 8, shift: 0 })
+    }
+
+    /// Convert a `0x00RRGGBB` color to the framebuffer's pixel format
+    fn encode(&self, rgb: u32) -> u32 {
+        self.red.encode(rgb >> 16 & 0xFF) | self.green.encode(rgb >> 8 & 0xFF) | self.blue.encode(rgb & 0xFF)
+    }
+
+    /// The shadow's pixels, row by row
+    fn pixels(&mut self) -> Option<&mut [u32]> {
+        let len = self.width * self.height;
+        let shadow = self.shadow.as_mut()?;
+        Some(unsafe { core::slice::from_raw_parts_mut(shadow.ptr::<u32>(0), len) })
+    }
+
+    /// Note that a rectangle of the shadow changed
+    fn mark_dirty(&mut self, rect: Rect) {
+        let mut rect = rect.clip(self.width, self.height);
+        if rect.is_empty() {
+            return;
+        }
+        // Absorb every rectangle it touches, then keep the list short
+        while let Some(index) = self.dirty.iter().position(|other| other.touches(&rect)) {
+            rect = rect.union(&self.dirty.swap_remove(index));
+        }
+        self.dirty.push(rect);
+        if self.dirty.len() > MAX_DIRTY {
+            let bounds = self.dirty.iter().fold(rect, |bounds, other| bounds.union(other));
+            self.dirty.clear();
+            self.dirty.push(bounds);
+        }
+    }
+
+    /// Fill a rectangle with a color
+    pub fn fill_rect(&mut self, rect: Rect, rgb: u32) {
+        let rect = rect.clip(self.width, self.height);
+        let width = self.width;
+        let Some(pixels) = self.pixels() else {
+            return;
+        };
+        for y in rect.y..rect.bottom() {
+            pixels[y * width + rect.x..y * width + rect.right()].fill(rgb);
+        }
+        self.mark_dirty(rect);
+    }
+
+    /// Draw a 1-bit bitmap `width` pixels wide at `(x, y)`
+    ///
+    /// Rows are padded to whole bytes and the leftmost pixel of each byte
+    /// is its high bit, as in PSF fonts.
+    pub fn draw_bitmap(&mut self, x: usize, y: usize, width: usize, bitmap: &[u8], fg: u32, bg: u32) {
+        let row_bytes = width.div_ceil(8).max(1);
+        let rect = Rect::new(x, y, width, bitmap.len() / row_bytes).clip(self.width, self.height);
+        if rect.is_empty() {
+            return;
+        }
+        let stride = self.width;
+        let Some(pixels) = self.pixels() else {
+            return;
+        };
+        for (row, bits) in bitmap.chunks(row_bytes).enumerate().take(rect.height) {
+            let line = &mut pixels[(y + row) * stride + x..][..rect.width];
+            for (col, pixel) in line.iter_mut().enumerate() {
+                *pixel = if bits[col / 8] & (0x80 >> (col % 8)) != 0 { fg } else { bg };
+            }
+        }
+        self.mark_dirty(rect);
+    }
+
+    /// Copy a rectangle so its top left corner lands on `(x, y)`; the
+    /// source and destination may overlap
+    pub fn copy_rect(&mut self, src: Rect, x: usize, y: usize) {
+        let src = src.clip(self.width, self.height);
+        let dst = Rect::new(x, y, src.width, src.height).clip(self.width, self.height);
+        let (width, height) = (dst.width.min(src.width), dst.height.min(src.height));
+        let stride = self.width;
+        let Some(pixels) = self.pixels() else {
+            return;
+        };
+        if src.x == 0 && x == 0 && width == stride {
+            // Whole rows are contiguous: one memmove
+            pixels.copy_within(src.y * stride..(src.y + height) * stride, y * stride);
+        } else {
+            // Go against the direction of movement so rows are read before
+            // they are overwritten
+            let copy_row = |row: usize| {
+                let from = (src.y + row) * stride + src.x;
+                pixels.copy_within(from..from + width, (y + row) * stride + x);
+            };
+            if y <= src.y {
+                (0..height).for_each(copy_row);
+            } else {
+                (0..height).rev().for_each(copy_row);
+            }
+        }
+        self.mark_dirty(Rect::new(x, y, width, height));
+    }
+
+    /// Copy the changed parts of the shadow to the framebuffer
+    pub fn flush(&mut self) {
+        let Some(shadow) = &self.shadow else {
+            return;
+        };
+        let pixels = unsafe { core::slice::from_raw_parts(shadow.ptr::<u32>(0), self.width * self.height) };
+        let native = self.is_native();
+        let stride = self.width;
+        for rect in &self.dirty {
+            for y in rect.y..rect.bottom() {
+                let row = &pixels[y * stride + rect.x..y * stride + rect.right()];
+                let dst = (self.addr + y * self.pitch + rect.x * self.bytes_per_pixel) as *mut u8;
+                unsafe {
+                    if native {
+                        core::ptr::copy_nonoverlapping(row.as_ptr(), dst as *mut u32, row.len());
+                    } else {
+                        self.write_converted(row, dst);
+                    }
+                }
+            }
+        }
+        self.dirty.clear();
+    }
+
+    /// Write `0x00RRGGBB` pixels to video memory at `dst` in the
+    /// framebuffer's format
+    unsafe fn write_converted(&self, row: &[u32], dst: *mut u8) {
+        for (i, &rgb) in row.iter().enumerate() {
+            let pixel = self.encode(rgb);
+            unsafe {
+                let dst = dst.add(i * self.bytes_per_pixel);
+                match self.bytes_per_pixel {
+                    4 => (dst as *mut u32).write_unaligned(pixel),
+                    2 => (dst as *mut u16).write_unaligned(pixel as u16),
+                    bytes => {
+                        for byte in 0..bytes {
+                            *dst.add(byte) = (pixel >> (byte * 8)) as u8;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Clamp a request to the framebuffer, returning its start and length
+    fn range(&self, offset: u64, len: usize) -> (usize, usize) {
+        if offset >= self.size as u64 {
+            return (self.size, 0);
+        }
+        let start = offset as usize;
+        (start, len.min(self.size - start))
+    }
+}
+
+impl Device for FramebufferDevice {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        let (start, len) = self.range(offset, buf.len());
+        unsafe {
+            core::ptr::copy_nonoverlapping((self.addr + start) as *const u8, buf.as_mut_ptr(), len);
+        }
+        Ok(len)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        let (start, len) = self.range(offset, buf.len());
+        unsafe {
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), (self.addr + start) as *mut u8, len);
+        }
+        Ok(len)
+    }
+
+    fn size(&self) -> u64 {
+        self.size as u64
+    }
+}
+
+/// Register the boot framebuffer as `fb0`
+pub fn init(framebuffer: &Framebuffer) {
+    super::device::register("fb0", Arc::new(FramebufferDevice::new(framebuffer)));
+}
@@ -0,0 +1,331 @@
+//! Keyboard layouts
+//!
+//! A layout maps each key to up to four characters: plain, with Shift,
+//! with AltGr, and with Shift and AltGr. Keys a layout leaves out fall
+//! back to its base layout. Dead keys are written as the combining mark
+//! they add (U+0300..U+036F); they type nothing themselves and change the
+//! next letter instead.
+
+use super::{KeyCode, Modifiers};
+
+/// Placeholder for a level with no character in a layout table
+const NONE: char = '\0';
+
+/// A keyboard layout
+pub struct Layout {
+    /// Name used by `loadkeys`
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Layout consulted for keys missing from `keys`
+    base: Option<&'static Layout>,
+    /// Characters of each key, by level
+    keys: &'static [(KeyCode, &'static str)],
+}
+
+impl Layout {
+    /// The level string of `key`, from this layout or its base
+    fn levels(&self, key: KeyCode) -> Option<&'static str> {
+        match self.keys.iter().find(|(code, _)| *code == key) {
+            Some((_, levels)) => Some(levels),
+            None => self.base?.levels(key),
+        }
+    }
+
+    /// The character or dead key on `key` with the given modifiers
+    pub fn symbol(&self, key: KeyCode, modifiers: Modifiers) -> Option<char> {
+        let levels = self.levels(key)?;
+        let mut chars = levels.chars();
+        let normal = chars.next()?;
+        let shifted = chars.next();
+
+        // Caps Lock acts as Shift on letters whose shifted form is the capital
+        let is_letter = normal.is_alphabetic() && shifted.is_some_and(|c| normal.to_uppercase().eq(c.to_uppercase()));
+        let shift = modifiers.shift() != (is_letter && modifiers.contains(Modifiers::CAPS_LOCK));
+
+        let level = shift as usize + if modifiers.alt_gr() { 2 } else { 0 };
+        levels.chars().nth(level).filter(|&c| c != NONE)
+    }
+}
+
+/// Whether a layout entry is a dead key
+pub fn is_dead(c: char) -> bool {
+    ('\u{300}'..='\u{36F}').contains(&c)
+}
+
+/// A dead key, the character it types on its own, and the letters it
+/// combines with paired with the results
+struct DeadKey {
+    mark: char,
+    spacing: char,
+    base: &'static str,
+    composed: &'static str,
+}
+
+const DEAD_KEYS: &[DeadKey] = &[
+    DeadKey { mark: '\u{300}', spacing: '`', base: "aeiouAEIOU", composed: "àèìòùÀÈÌÒÙ" },
+    DeadKey { mark: '\u{301}', spacing: '´', base: "aeiouyAEIOUY", composed: "áéíóúýÁÉÍÓÚÝ" },
+    DeadKey { mark: '\u{302}', spacing: '^', base: "aeiouAEIOU", composed: "âêîôûÂÊÎÔÛ" },
+    DeadKey { mark: '\u{303}', spacing: '~', base: "anoANO", composed: "ãñõÃÑÕ" },
+    DeadKey { mark: '\u{308}', spacing: '¨', base: "aeiouyAEIOU", composed: "äëïöüÿÄËÏÖÜ" },
+];
+
+fn dead_key(mark: char) -> Option<&'static DeadKey> {
+    DEAD_KEYS.iter().find(|dead| dead.mark == mark)
+}
+
+/// Combines dead keys with the character typed after them
+#[derive(Debug, Default)]
+pub struct Composer {
+    pending: Option<char>,
+}
+
+impl Composer {
+    pub const fn new() -> Self {
+        Self { pending: None }
+    }
+
+    /// Feed the symbol of a key press, returning what it types: a dead
+    /// key left over from an unsuccessful composition, then the character
+    ///
+    /// A dead key followed by Space or by itself types its accent alone.
+    pub fn feed(&mut self, c: char) -> (Option<char>, Option<char>) {
+        let Some(pending) = self.pending.take().and_then(dead_key) else {
+            if is_dead(c) {
+                self.pending = Some(c);
+                return (None, None);
+            }
+            return (None, Some(c));
+        };
+
+        if c == ' ' || c == pending.mark {
+            return (None, Some(pending.spacing));
+        }
+        if is_dead(c) {
+            self.pending = Some(c);
+            return (Some(pending.spacing), None);
+        }
+        match pending.base.chars().position(|b| b == c) {
+            Some(i) => (None, pending.composed.chars().nth(i)),
+            None => (Some(pending.spacing), Some(c)),
+        }
+    }
+}
+
+pub static US: Layout = Layout {
+    name: "us",
+    description: "US English",
+    base: None,
+    keys: &[
+        (KeyCode::Backtick, "`~"),
+        (KeyCode::Key1, "1!"),
+        (KeyCode::Key2, "2@"),
+        (KeyCode::Key3, "3#"),
+        (KeyCode::Key4, "4$"),
+        (KeyCode::Key5, "5%"),
+        (KeyCode::Key6, "6^"),
+        (KeyCode::Key7, "7&"),
+        (KeyCode::Key8, "8*"),
+        (KeyCode::Key9, "9("),
+        (KeyCode::Key0, "0)"),
+        (KeyCode::Minus, "-_"),
+        (KeyCode::Equals, "=+"),
+        (KeyCode::Q, "qQ"),
+        (KeyCode::W, "wW"),
+        (KeyCode::E, "eE"),
+        (KeyCode::R, "rR"),
+        (KeyCode::T, "tT"),
+        (KeyCode::Y, "yY"),
+        (KeyCode::U, "uU"),
+        (KeyCode::I, "iI"),
+        (KeyCode::O, "oO"),
+        (KeyCode::P, "pP"),
+        (KeyCode::LeftBracket, "[{"),
+        (KeyCode::RightBracket, "]}"),
+        (KeyCode::A, "aA"),
+        (KeyCode::S, "sS"),
+        (KeyCode::D, "dD"),
+        (KeyCode::F, "fF"),
+        (KeyCode::G, "gG"),
+        (KeyCode::H, "hH"),
+        (KeyCode::J, "jJ"),
+        (KeyCode::K, "kK"),
+        (KeyCode::L, "lL"),
+        (KeyCode::Semicolon, ";:"),
+        (KeyCode::Apostrophe, "'\""),
+        (KeyCode::Backslash, "\\|"),
+        (KeyCode::NonUsBackslash, "\\|"),
+        (KeyCode::Z, "zZ"),
+        (KeyCode::X, "xX"),
+        (KeyCode::C, "cC"),
+        (KeyCode::V, "vV"),
+        (KeyCode::B, "bB"),
+        (KeyCode::N, "nN"),
+        (KeyCode::M, "mM"),
+        (KeyCode::Comma, ",<"),
+        (KeyCode::Period, ".>"),
+        (KeyCode::Slash, "/?"),
+    ],
+};
+
+pub static UK: Layout = Layout {
+    name: "uk",
+    description: "UK English",
+    base: Some(&US),
+    keys: &[
+        (KeyCode::Backtick, "`¬¦"),
+        (KeyCode::Key2, "2\""),
+        (KeyCode::Key3, "3£"),
+        (KeyCode::Key4, "4$€"),
+        (KeyCode::E, "eEé"),
+        (KeyCode::A, "aAá"),
+        (KeyCode::Apostrophe, "'@"),
+        (KeyCode::Backslash, "#~"),
+        (KeyCode::NonUsBackslash, "\\|"),
+    ],
+};
+
+pub static DE: Layout = Layout {
+    name: "de",
+    description: "German (QWERTZ)",
+    base: Some(&US),
+    keys: &[
+        (KeyCode::Backtick, "\u{302}°"),
+        (KeyCode::Key2, "2\"²"),
+        (KeyCode::Key3, "3§³"),
+        (KeyCode::Key6, "6&"),
+        (KeyCode::Key7, "7/{"),
+        (KeyCode::Key8, "8(["),
+        (KeyCode::Key9, "9)]"),
+        (KeyCode::Key0, "0=}"),
+        (KeyCode::Minus, "ß?\\"),
+        (KeyCode::Equals, "\u{301}\u{300}"),
+        (KeyCode::Q, "qQ@"),
+        (KeyCode::E, "eE€"),
+        (KeyCode::Y, "zZ"),
+        (KeyCode::LeftBracket, "üÜ"),
+        (KeyCode::RightBracket, "+*~"),
+        (KeyCode::Semicolon, "öÖ"),
+        (KeyCode::Apostrophe, "äÄ"),
+        (KeyCode::Backslash, "#'"),
+        (KeyCode::NonUsBackslash, "<>|"),
+        (KeyCode::Z, "yY"),
+        (KeyCode::M, "mMµ"),
+        (KeyCode::Comma, ",;"),
+        (KeyCode::Period, ".:"),
+        (KeyCode::Slash, "-_"),
+    ],
+};
+
+pub static FR: Layout = Layout {
+    name: "fr",
+    description: "French (AZERTY)",
+    base: Some(&US),
+    keys: &[
+        (KeyCode::Backtick, "²"),
+        (KeyCode::Key1, "&1"),
+        (KeyCode::Key2, "é2\u{303}"),
+        (KeyCode::Key3, "\"3#"),
+        (KeyCode::Key4, "'4{"),
+        (KeyCode::Key5, "(5["),
+        (KeyCode::Key6, "-6|"),
+        (KeyCode::Key7, "è7\u{300}"),
+        (KeyCode::Key8, "_8\\"),
+        (KeyCode::Key9, "ç9^"),
+        (KeyCode::Key0, "à0@"),
+        (KeyCode::Minus, ")°]"),
+        (KeyCode::Equals, "=+}"),
+        (KeyCode::Q, "aA"),
+        (KeyCode::W, "zZ"),
+        (KeyCode::E, "eE€"),
+        (KeyCode::LeftBracket, "\u{302}\u{308}"),
+        (KeyCode::RightBracket, "$£¤"),
+        (KeyCode::A, "qQ"),
+        (KeyCode::Semicolon, "mM"),
+        (KeyCode::Apostrophe, "ù%"),
+        (KeyCode::Backslash, "*µ"),
+        (KeyCode::NonUsBackslash, "<>"),
+        (KeyCode::Z, "wW"),
+        (KeyCode::M, ",?"),
+        (KeyCode::Comma, ";."),
+        (KeyCode::Period, ":/"),
+        (KeyCode::Slash, "!§"),
+    ],
+};
+
+pub static DVORAK: Layout = Layout {
+    name: "dvorak",
+    description: "US Dvorak",
+    base: Some(&US),
+    keys: &[
+        (KeyCode::Minus, "[{"),
+        (KeyCode::Equals, "]}"),
+        (KeyCode::Q, "'\""),
+        (KeyCode::W, ",<"),
+        (KeyCode::E, ".>"),
+        (KeyCode::R, "pP"),
+        (KeyCode::T, "yY"),
+        (KeyCode::Y, "fF"),
+        (KeyCode::U, "gG"),
+        (KeyCode::I, "cC"),
+        (KeyCode::O, "rR"),
+        (KeyCode::P, "lL"),
+        (KeyCode::LeftBracket, "/?"),
+        (KeyCode::RightBracket, "=+"),
+        (KeyCode::S, "oO"),
+        (KeyCode::D, "eE"),
+        (KeyCode::F, "uU"),
+        (KeyCode::G, "iI"),
+        (KeyCode::H, "dD"),
+        (KeyCode::J, "hH"),
+        (KeyCode::K, "tT"),
+        (KeyCode::L, "nN"),
+        (KeyCode::Semicolon, "sS"),
+        (KeyCode::Apostrophe, "-_"),
+        (KeyCode::Z, ";:"),
+        (KeyCode::X, "qQ"),
+        (KeyCode::C, "jJ"),
+        (KeyCode::V, "kK"),
+        (KeyCode::B, "xX"),
+        (KeyCode::N, "bB"),
+        (KeyCode::Comma, "wW"),
+        (KeyCode::Period, "vV"),
+        (KeyCode::Slash, "zZ"),
+    ],
+};
+
+/// Every built-in layout
+pub static LAYOUTS: &[&Layout] = &[&US, &UK, &DE, &FR, &DVORAK];
+
+/// Look up a built-in layout by name
+pub fn find(name: &str) -> Option<&'static Layout> {
+    LAYOUTS.iter().copied().find(|layout| layout.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_and_base_fallback() {
+        assert_eq!(DE.symbol(KeyCode::Y, Modifiers::empty()), Some('z'));
+        assert_eq!(DE.symbol(KeyCode::Q, Modifiers::RIGHT_ALT), Some('@'));
+        assert_eq!(DE.symbol(KeyCode::Semicolon, Modifiers::CAPS_LOCK), Some('Ö'));
+        // Inherited from the US layout
+        assert_eq!(DE.symbol(KeyCode::Key1, Modifiers::LEFT_SHIFT), Some('!'));
+        // Caps Lock leaves non-letters alone
+        assert_eq!(FR.symbol(KeyCode::Key2, Modifiers::CAPS_LOCK), Some('é'));
+        assert_eq!(US.symbol(KeyCode::A, Modifiers::RIGHT_ALT), None);
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        let mut composer = Composer::new();
+        assert_eq!(composer.feed('\u{302}'), (None, None));
+        assert_eq!(composer.feed('e'), (None, Some('ê')));
+        assert_eq!(composer.feed('\u{301}'), (None, None));
+        assert_eq!(composer.feed(' '), (None, Some('´')));
+        assert_eq!(composer.feed('\u{308}'), (None, None));
+        assert_eq!(composer.feed('x'), (Some('¨'), Some('x')));
+        assert_eq!(composer.feed('x'), (None, Some('x')));
+    }
+}
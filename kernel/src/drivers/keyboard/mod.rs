@@ -0,0 +1,454 @@
+//! PS/2 Keyboard driver
+//!
+//! Scancode set 1 bytes from the controller are decoded into `KeyEvent`s
+//! carrying the physical key, whether it went down or up, the modifier and
+//! lock state at that moment, and the character it types, if any. Holding
+//! a key makes the keyboard resend its make code; those presses are marked
+//! as repeats and never toggle lock keys. Characters come from the
+//! selected layout, which can be changed at runtime.
+//!
+//! Setting the LEDs means waiting for the keyboard to acknowledge two
+//! bytes, too long for the interrupt handler; it only notes that they are
+//! out of date, and `update_leds` sends them from the idle loop.
+
+pub mod layout;
+pub mod scancode;
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use bitflags::bitflags;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::device::{Device, DeviceError};
+use super::ps2::{self, PortId};
+use layout::{Composer, Layout};
+pub use scancode::KeyCode;
+use scancode::Decoder;
+
+/// Controller data port
+const DATA_PORT: u16 = 0x60;
+
+/// Keyboard commands
+const COMMAND_SET_LEDS: u8 = 0xED;
+const COMMAND_SET_TYPEMATIC: u8 = 0xF3;
+
+/// LED bits for `COMMAND_SET_LEDS`
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Typematic setting: 500 ms before repeating, then about 30 repeats a second
+const TYPEMATIC: u8 = 0b01 << 5;
+
+bitflags! {
+    /// Modifier keys held and lock keys engaged
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Modifiers: u16 {
+        const LEFT_SHIFT = 1 << 0;
+        const RIGHT_SHIFT = 1 << 1;
+        const LEFT_CTRL = 1 << 2;
+        const RIGHT_CTRL = 1 << 3;
+        const LEFT_ALT = 1 << 4;
+        /// AltGr on international layouts
+        const RIGHT_ALT = 1 << 5;
+        const LEFT_META = 1 << 6;
+        const RIGHT_META = 1 << 7;
+        const CAPS_LOCK = 1 << 8;
+        const NUM_LOCK = 1 << 9;
+        const SCROLL_LOCK = 1 << 10;
+    }
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.intersects(Self::LEFT_SHIFT | Self::RIGHT_SHIFT)
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.intersects(Self::LEFT_CTRL | Self::RIGHT_CTRL)
+    }
+
+    /// Left Alt; right Alt is AltGr
+    pub fn alt(&self) -> bool {
+        self.contains(Self::LEFT_ALT)
+    }
+
+    pub fn alt_gr(&self) -> bool {
+        self.contains(Self::RIGHT_ALT)
+    }
+
+    /// The flag a modifier key sets while held
+    fn held_by(key: KeyCode) -> Option<Self> {
+        Some(match key {
+            KeyCode::LeftShift => Self::LEFT_SHIFT,
+            KeyCode::RightShift => Self::RIGHT_SHIFT,
+            KeyCode::LeftCtrl => Self::LEFT_CTRL,
+            KeyCode::RightCtrl => Self::RIGHT_CTRL,
+            KeyCode::LeftAlt => Self::LEFT_ALT,
+            KeyCode::RightAlt => Self::RIGHT_ALT,
+            KeyCode::LeftMeta => Self::LEFT_META,
+            KeyCode::RightMeta => Self::RIGHT_META,
+            _ => return None,
+        })
+    }
+
+    /// The flag a lock key toggles when pressed
+    fn toggled_by(key: KeyCode) -> Option<Self> {
+        Some(match key {
+            KeyCode::CapsLock => Self::CAPS_LOCK,
+            KeyCode::NumLock => Self::NUM_LOCK,
+            KeyCode::ScrollLock => Self::SCROLL_LOCK,
+            _ => return None,
+        })
+    }
+}
+
+/// A key going down or up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// A press generated by holding the key down
+    pub repeat: bool,
+    /// Modifier and lock state, including this key's own effect
+    pub modifiers: Modifiers,
+    /// What the key types, for presses only; dead keys type nothing
+    pub char: Option<char>,
+}
+
+/// Decoder, layout and key state
+struct State {
+    decoder: Decoder,
+    layout: &'static Layout,
+    composer: Composer,
+    modifiers: Modifiers,
+    /// Keys currently down, one bit per `KeyCode` value
+    down: [u64; 4],
+}
+
+impl State {
+    fn is_down(&self, key: KeyCode) -> bool {
+        self.down[key as usize / 64] & (1 << (key as usize % 64)) != 0
+    }
+
+    fn set_down(&mut self, key: KeyCode, down: bool) {
+        let bit = 1 << (key as usize % 64);
+        if down {
+            self.down[key as usize / 64] |= bit;
+        } else {
+            self.down[key as usize / 64] &= !bit;
+        }
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    decoder: Decoder::new(),
+    layout: &layout::US,
+    composer: Composer::new(),
+    modifiers: Modifiers::empty(),
+    down: [0; 4],
+});
+
+/// Set when a lock key changed and the LEDs no longer match
+static LEDS_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Bytes kept for device readers; the oldest are dropped when full
+const INPUT_BUFFER_SIZE: usize = 256;
+
+/// Raw scancodes for `/dev/kbd`
+static SCANCODES: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+/// Translated characters for `/dev/console`, UTF-8 encoded
+static CHARS: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+/// Decoded events for `/dev/keyevents`
+static EVENTS: Mutex<VecDeque<KeyEvent>> = Mutex::new(VecDeque::new());
+
+/// Initialize keyboard driver
+pub fn init() {
+    crate::serial::print("Initializing PS/2 keyboard...\n");
+
+    // Start with the LEDs matching the lock state, and a sane repeat rate
+    if !send_command(COMMAND_SET_TYPEMATIC, TYPEMATIC) || !send_command(COMMAND_SET_LEDS, 0) {
+        crate::serial::print("Keyboard: no acknowledgement from keyboard\n");
+    }
+
+    // Route IRQ 1 to the keyboard handler
+    if let Err(e) = crate::interrupts::register_irq(1, handle_interrupt) {
+        crate::serial::print("Keyboard: ");
+        crate::serial::print(e);
+        crate::serial::print("\n");
+    }
+    ps2::enable_irq(PortId::First);
+
+    super::device::register("kbd", Arc::new(KeyboardDevice));
+    super::device::register("keyevents", Arc::new(KeyEventDevice));
+
+    crate::serial::print("Keyboard initialized. Try typing!\n");
+}
+
+/// Keyboard interrupt handler (IRQ 1)
+fn handle_interrupt() {
+    let scancode: u8 = unsafe { Port::new(DATA_PORT).read() };
+    handle_scancode(scancode);
+}
+
+/// Send a command with one argument byte
+fn send_command(command: u8, argument: u8) -> bool {
+    ps2::send_with_arg(PortId::First, command, argument)
+}
+
+/// Whether the LEDs are waiting for `update_leds`
+pub fn leds_pending() -> bool {
+    LEDS_PENDING.load(Ordering::Relaxed)
+}
+
+/// Light the LEDs of the engaged lock keys, if a lock key changed since
+/// they were last set
+///
+/// Not for interrupt context: it waits for the keyboard's acknowledgements.
+pub fn update_leds() {
+    if !LEDS_PENDING.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let modifiers = x86_64::instructions::interrupts::without_interrupts(|| STATE.lock().modifiers);
+    let mut leds = 0;
+    if modifiers.contains(Modifiers::SCROLL_LOCK) {
+        leds |= LED_SCROLL_LOCK;
+    }
+    if modifiers.contains(Modifiers::NUM_LOCK) {
+        leds |= LED_NUM_LOCK;
+    }
+    if modifiers.contains(Modifiers::CAPS_LOCK) {
+        leds |= LED_CAPS_LOCK;
+    }
+    send_command(COMMAND_SET_LEDS, leds);
+}
+
+/// Append a byte to an input buffer, dropping the oldest byte when full
+fn push_input<T>(buffer: &Mutex<VecDeque<T>>, item: T) {
+    let mut buffer = buffer.lock();
+    if buffer.len() == INPUT_BUFFER_SIZE {
+        buffer.pop_front();
+    }
+    buffer.push_back(item);
+}
+
+/// Move buffered input into `buf` without blocking
+fn pop_input(buffer: &Mutex<VecDeque<u8>>, buf: &mut [u8]) -> usize {
+    // The interrupt handler takes the same lock
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut buffer = buffer.lock();
+        let len = buf.len().min(buffer.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = src;
+        }
+        len
+    })
+}
+
+/// The layout characters are translated with
+pub fn layout() -> &'static Layout {
+    x86_64::instructions::interrupts::without_interrupts(|| STATE.lock().layout)
+}
+
+/// Translate characters with `layout` from now on
+pub fn set_layout(layout: &'static Layout) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut state = STATE.lock();
+        state.layout = layout;
+        state.composer = Composer::new();
+    });
+}
+
+/// Read translated characters typed since the last read
+pub fn read_chars(buf: &mut [u8]) -> usize {
+    pop_input(&CHARS, buf)
+}
+
+/// Take the oldest key event not yet read
+pub fn read_event() -> Option<KeyEvent> {
+    x86_64::instructions::interrupts::without_interrupts(|| EVENTS.lock().pop_front())
+}
+
+/// `/dev/kbd`: raw scancodes as received from the controller
+pub struct KeyboardDevice;
+
+impl Device for KeyboardDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        Ok(pop_input(&SCANCODES, buf))
+    }
+}
+
+/// Size of one record read from `/dev/keyevents`
+const EVENT_RECORD_SIZE: usize = 8;
+
+/// `/dev/keyevents`: decoded key events as 8-byte records
+///
+/// Each record is the key code, 1 for a press or 0 for a release (bit 1
+/// set for repeats), the modifiers as a little-endian `u16`, and the
+/// character as a little-endian `u32`, 0 if none. Only whole records are
+/// returned.
+pub struct KeyEventDevice;
+
+impl Device for KeyEventDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        let mut len = 0;
+        while buf.len() - len >= EVENT_RECORD_SIZE {
+            let Some(event) = read_event() else {
+                break;
+            };
+            let record = &mut buf[len..len + EVENT_RECORD_SIZE];
+            record[0] = event.code as u8;
+            record[1] = event.pressed as u8 | (event.repeat as u8) << 1;
+            record[2..4].copy_from_slice(&event.modifiers.bits().to_le_bytes());
+            record[4..8].copy_from_slice(&(event.char.map_or(0, |c| c as u32)).to_le_bytes());
+            len += EVENT_RECORD_SIZE;
+        }
+        Ok(len)
+    }
+}
+
+/// Character typed by a key on the numeric keypad
+fn keypad_char(key: KeyCode, modifiers: Modifiers) -> Option<char> {
+    let c = match key {
+        KeyCode::KeypadDivide => '/',
+        KeyCode::KeypadMultiply => '*',
+        KeyCode::KeypadMinus => '-',
+        KeyCode::KeypadPlus => '+',
+        KeyCode::KeypadEnter => '\n',
+        // With Num Lock off, or Shift held, the rest act as navigation keys
+        _ if !modifiers.contains(Modifiers::NUM_LOCK) || modifiers.shift() => return None,
+        KeyCode::Keypad0 => '0',
+        KeyCode::Keypad1 => '1',
+        KeyCode::Keypad2 => '2',
+        KeyCode::Keypad3 => '3',
+        KeyCode::Keypad4 => '4',
+        KeyCode::Keypad5 => '5',
+        KeyCode::Keypad6 => '6',
+        KeyCode::Keypad7 => '7',
+        KeyCode::Keypad8 => '8',
+        KeyCode::Keypad9 => '9',
+        KeyCode::KeypadPeriod => '.',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// The character or dead key a key press types with the given modifiers
+fn translate(layout: &Layout, key: KeyCode, modifiers: Modifiers) -> Option<char> {
+    match key {
+        KeyCode::Enter => return Some('\n'),
+        KeyCode::Tab => return Some('\t'),
+        // DEL, the terminal's erase character
+        KeyCode::Backspace => return Some('\x7f'),
+        KeyCode::Escape => return Some('\x1b'),
+        KeyCode::Space => return Some(' '),
+        _ if key.is_keypad() => return keypad_char(key, modifiers),
+        _ => {}
+    }
+
+    let c = layout.symbol(key, modifiers)?;
+    if modifiers.ctrl() && !modifiers.alt_gr() {
+        // Ctrl with a letter or one of @[\]^_ types the matching control code
+        return match c.to_ascii_uppercase() {
+            c @ '@'..='_' => Some((c as u8 - b'@') as char),
+            _ => None,
+        };
+    }
+    Some(c)
+}
+
+/// Update the key state for a press or release and build its event, along
+/// with the accent of a dead key that did not combine with this key
+fn process(state: &mut State, code: KeyCode, pressed: bool) -> (KeyEvent, Option<char>) {
+    let repeat = pressed && state.is_down(code);
+    state.set_down(code, pressed);
+
+    if let Some(flag) = Modifiers::held_by(code) {
+        state.modifiers.set(flag, pressed);
+    }
+    if let Some(flag) = Modifiers::toggled_by(code)
+        && pressed
+        && !repeat
+    {
+        state.modifiers.toggle(flag);
+        LEDS_PENDING.store(true, Ordering::Relaxed);
+    }
+
+    let (accent, char) = match translate(state.layout, code, state.modifiers) {
+        Some(c) if pressed => state.composer.feed(c),
+        _ => (None, None),
+    };
+    let event = KeyEvent {
+        code,
+        pressed,
+        repeat,
+        modifiers: state.modifiers,
+        char,
+    };
+    (event, accent)
+}
+
+/// Handle a keyboard scancode
+fn handle_scancode(scancode: u8) {
+    push_input(&SCANCODES, scancode);
+
+    let (event, accent) = {
+        let mut state = STATE.lock();
+        let Some((code, pressed)) = state.decoder.feed(scancode) else {
+            return;
+        };
+        process(&mut state, code, pressed)
+    };
+    push_input(&EVENTS, event);
+
+    // Shift+PageUp and Shift+PageDown browse the console's scrollback
+    if event.pressed && event.modifiers.shift() {
+        let steps = match event.code {
+            KeyCode::PageUp => Some(1),
+            KeyCode::PageDown => Some(-1),
+            _ => None,
+        };
+        if let Some(steps) = steps {
+            crate::console::scroll_view(steps);
+            return;
+        }
+    }
+    // Alt+F1 to Alt+F6 switch virtual consoles
+    if event.pressed && event.modifiers.alt() {
+        let vt = match event.code {
+            KeyCode::F1 => Some(0),
+            KeyCode::F2 => Some(1),
+            KeyCode::F3 => Some(2),
+            KeyCode::F4 => Some(3),
+            KeyCode::F5 => Some(4),
+            KeyCode::F6 => Some(5),
+            _ => None,
+        };
+        if let Some(vt) = vt {
+            crate::console::switch(vt);
+            return;
+        }
+    }
+
+    if let Some(c) = accent {
+        deliver(c);
+    }
+    if let Some(c) = event.char {
+        deliver(c);
+    }
+}
+
+/// Pass a typed character to raw readers and the terminal on screen
+fn deliver(c: char) {
+    let mut utf8 = [0; 4];
+    for &byte in c.encode_utf8(&mut utf8).as_bytes() {
+        push_input(&CHARS, byte);
+    }
+    if let Some(tty) = crate::tty::foreground() {
+        crate::tty::input(tty, c);
+    }
+}
@@ -0,0 +1,346 @@
+//! Scancode set 1 decoding
+//!
+//! Most keys send a single make code and the same code with bit 7 set on
+//! release. Keys added after the XT send an 0xE0 prefix first, and Pause
+//! sends a six-byte 0xE1 sequence with no release. Print Screen and the
+//! extended navigation keys wrap themselves in fake Shift presses, which
+//! are dropped here.
+
+/// A physical key, named after its legend on a US keyboard
+///
+/// The value is the set 1 make code, with bit 7 set for extended keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeyCode {
+    Escape = 0x01,
+    Key1 = 0x02,
+    Key2 = 0x03,
+    Key3 = 0x04,
+    Key4 = 0x05,
+    Key5 = 0x06,
+    Key6 = 0x07,
+    Key7 = 0x08,
+    Key8 = 0x09,
+    Key9 = 0x0A,
+    Key0 = 0x0B,
+    Minus = 0x0C,
+    Equals = 0x0D,
+    Backspace = 0x0E,
+    Tab = 0x0F,
+    Q = 0x10,
+    W = 0x11,
+    E = 0x12,
+    R = 0x13,
+    T = 0x14,
+    Y = 0x15,
+    U = 0x16,
+    I = 0x17,
+    O = 0x18,
+    P = 0x19,
+    LeftBracket = 0x1A,
+    RightBracket = 0x1B,
+    Enter = 0x1C,
+    LeftCtrl = 0x1D,
+    A = 0x1E,
+    S = 0x1F,
+    D = 0x20,
+    F = 0x21,
+    G = 0x22,
+    H = 0x23,
+    J = 0x24,
+    K = 0x25,
+    L = 0x26,
+    Semicolon = 0x27,
+    Apostrophe = 0x28,
+    Backtick = 0x29,
+    LeftShift = 0x2A,
+    Backslash = 0x2B,
+    Z = 0x2C,
+    X = 0x2D,
+    C = 0x2E,
+    V = 0x2F,
+    B = 0x30,
+    N = 0x31,
+    M = 0x32,
+    Comma = 0x33,
+    Period = 0x34,
+    Slash = 0x35,
+    RightShift = 0x36,
+    KeypadMultiply = 0x37,
+    LeftAlt = 0x38,
+    Space = 0x39,
+    CapsLock = 0x3A,
+    F1 = 0x3B,
+    F2 = 0x3C,
+    F3 = 0x3D,
+    F4 = 0x3E,
+    F5 = 0x3F,
+    F6 = 0x40,
+    F7 = 0x41,
+    F8 = 0x42,
+    F9 = 0x43,
+    F10 = 0x44,
+    NumLock = 0x45,
+    ScrollLock = 0x46,
+    Keypad7 = 0x47,
+    Keypad8 = 0x48,
+    Keypad9 = 0x49,
+    KeypadMinus = 0x4A,
+    Keypad4 = 0x4B,
+    Keypad5 = 0x4C,
+    Keypad6 = 0x4D,
+    KeypadPlus = 0x4E,
+    Keypad1 = 0x4F,
+    Keypad2 = 0x50,
+    Keypad3 = 0x51,
+    Keypad0 = 0x52,
+    KeypadPeriod = 0x53,
+    /// The extra key left of Z on ISO keyboards
+    NonUsBackslash = 0x56,
+    F11 = 0x57,
+    F12 = 0x58,
+
+    KeypadEnter = 0x9C,
+    RightCtrl = 0x9D,
+    KeypadDivide = 0xB5,
+    PrintScreen = 0xB7,
+    /// AltGr on international layouts
+    RightAlt = 0xB8,
+    Home = 0xC7,
+    Up = 0xC8,
+    PageUp = 0xC9,
+    Left = 0xCB,
+    Right = 0xCD,
+    End = 0xCF,
+    Down = 0xD0,
+    PageDown = 0xD1,
+    Insert = 0xD2,
+    Delete = 0xD3,
+    LeftMeta = 0xDB,
+    RightMeta = 0xDC,
+    Menu = 0xDD,
+    /// Sent as 0xE1 0x1D 0x45 0xE1 0x9D 0xC5
+    Pause = 0xC5,
+}
+
+impl KeyCode {
+    /// The key with make code `code`, after an 0xE0 prefix if `extended`
+    pub fn from_scancode(code: u8, extended: bool) -> Option<KeyCode> {
+        use KeyCode::*;
+        let key = match (extended, code) {
+            (false, 0x01) => Escape,
+            (false, 0x02) => Key1,
+            (false, 0x03) => Key2,
+            (false, 0x04) => Key3,
+            (false, 0x05) => Key4,
+            (false, 0x06) => Key5,
+            (false, 0x07) => Key6,
+            (false, 0x08) => Key7,
+            (false, 0x09) => Key8,
+            (false, 0x0A) => Key9,
+            (false, 0x0B) => Key0,
+            (false, 0x0C) => Minus,
+            (false, 0x0D) => Equals,
+            (false, 0x0E) => Backspace,
+            (false, 0x0F) => Tab,
+            (false, 0x10) => Q,
+            (false, 0x11) => W,
+            (false, 0x12) => E,
+            (false, 0x13) => R,
+            (false, 0x14) => T,
+            (false, 0x15) => Y,
+            (false, 0x16) => U,
+            (false, 0x17) => I,
+            (false, 0x18) => O,
+            (false, 0x19) => P,
+            (false, 0x1A) => LeftBracket,
+            (false, 0x1B) => RightBracket,
+            (false, 0x1C) => Enter,
+            (false, 0x1D) => LeftCtrl,
+            (false, 0x1E) => A,
+            (false, 0x1F) => S,
+            (false, 0x20) => D,
+            (false, 0x21) => F,
+            (false, 0x22) => G,
+            (false, 0x23) => H,
+            (false, 0x24) => J,
+            (false, 0x25) => K,
+            (false, 0x26) => L,
+            (false, 0x27) => Semicolon,
+            (false, 0x28) => Apostrophe,
+            (false, 0x29) => Backtick,
+            (false, 0x2A) => LeftShift,
+            (false, 0x2B) => Backslash,
+            (false, 0x2C) => Z,
+            (false, 0x2D) => X,
+            (false, 0x2E) => C,
+            (false, 0x2F) => V,
+            (false, 0x30) => B,
+            (false, 0x31) => N,
+            (false, 0x32) => M,
+            (false, 0x33) => Comma,
+            (false, 0x34) => Period,
+            (false, 0x35) => Slash,
+            (false, 0x36) => RightShift,
+            (false, 0x37) => KeypadMultiply,
+            (false, 0x38) => LeftAlt,
+            (false, 0x39) => Space,
+            (false, 0x3A) => CapsLock,
+            (false, 0x3B) => F1,
+            (false, 0x3C) => F2,
+            (false, 0x3D) => F3,
+            (false, 0x3E) => F4,
+            (false, 0x3F) => F5,
+            (false, 0x40) => F6,
+            (false, 0x41) => F7,
+            (false, 0x42) => F8,
+            (false, 0x43) => F9,
+            (false, 0x44) => F10,
+            (false, 0x45) => NumLock,
+            (false, 0x46) => ScrollLock,
+            (false, 0x47) => Keypad7,
+            (false, 0x48) => Keypad8,
+            (false, 0x49) => Keypad9,
+            (false, 0x4A) => KeypadMinus,
+            (false, 0x4B) => Keypad4,
+            (false, 0x4C) => Keypad5,
+            (false, 0x4D) => Keypad6,
+            (false, 0x4E) => KeypadPlus,
+            (false, 0x4F) => Keypad1,
+            (false, 0x50) => Keypad2,
+            (false, 0x51) => Keypad3,
+            (false, 0x52) => Keypad0,
+            (false, 0x53) => KeypadPeriod,
+            (false, 0x56) => NonUsBackslash,
+            (false, 0x57) => F11,
+            (false, 0x58) => F12,
+            (true, 0x1C) => KeypadEnter,
+            (true, 0x1D) => RightCtrl,
+            (true, 0x35) => KeypadDivide,
+            (true, 0x37) => PrintScreen,
+            (true, 0x38) => RightAlt,
+            (true, 0x47) => Home,
+            (true, 0x48) => Up,
+            (true, 0x49) => PageUp,
+            (true, 0x4B) => Left,
+            (true, 0x4D) => Right,
+            (true, 0x4F) => End,
+            (true, 0x50) => Down,
+            (true, 0x51) => PageDown,
+            (true, 0x52) => Insert,
+            (true, 0x53) => Delete,
+            (true, 0x5B) => LeftMeta,
+            (true, 0x5C) => RightMeta,
+            (true, 0x5D) => Menu,
+            _ => return None,
+        };
+        Some(key)
+    }
+
+    /// Whether the key is on the numeric keypad
+    pub fn is_keypad(self) -> bool {
+        matches!(self as u8, 0x37 | 0x47..=0x53) || matches!(self, KeyCode::KeypadEnter | KeyCode::KeypadDivide)
+    }
+}
+
+/// Bytes the controller sends that are not key codes
+const RESPONSE_ACK: u8 = 0xFA;
+const RESPONSE_RESEND: u8 = 0xFE;
+const RESPONSE_ECHO: u8 = 0xEE;
+const RESPONSE_ERROR: u8 = 0xFF;
+const RESPONSE_OVERRUN: u8 = 0x00;
+
+/// Prefix bytes
+const PREFIX_EXTENDED: u8 = 0xE0;
+const PREFIX_PAUSE: u8 = 0xE1;
+
+/// Bytes that follow the 0xE1 prefix of the Pause sequence
+const PAUSE_LENGTH: u8 = 5;
+
+/// Fake Shift make and break codes wrapped around extended keys
+const FAKE_SHIFTS: [u8; 4] = [0x2A, 0x36, 0xAA, 0xB6];
+
+/// Turns a byte stream into key presses and releases
+#[derive(Debug, Default)]
+pub struct Decoder {
+    extended: bool,
+    /// Bytes of the Pause sequence still to come
+    pause_remaining: u8,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            pause_remaining: 0,
+        }
+    }
+
+    /// Feed one byte, returning the key and whether it was pressed once a
+    /// sequence completes
+    pub fn feed(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+            return (self.pause_remaining == 0).then_some((KeyCode::Pause, true));
+        }
+        match byte {
+            RESPONSE_ACK | RESPONSE_RESEND | RESPONSE_ECHO | RESPONSE_ERROR | RESPONSE_OVERRUN => None,
+            PREFIX_EXTENDED => {
+                self.extended = true;
+                None
+            }
+            PREFIX_PAUSE => {
+                self.pause_remaining = PAUSE_LENGTH;
+                None
+            }
+            _ => {
+                let extended = core::mem::take(&mut self.extended);
+                if extended && FAKE_SHIFTS.contains(&byte) {
+                    return None;
+                }
+                let key = KeyCode::from_scancode(byte & 0x7F, extended)?;
+                Some((key, byte & 0x80 == 0))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn feed_all(bytes: &[u8]) -> Vec<(KeyCode, bool)> {
+        let mut decoder = Decoder::new();
+        bytes.iter().filter_map(|&b| decoder.feed(b)).collect()
+    }
+
+    #[test]
+    fn test_plain_press_release() {
+        assert_eq!(feed_all(&[0x1E, 0x9E]), vec![(KeyCode::A, true), (KeyCode::A, false)]);
+    }
+
+    #[test]
+    fn test_extended_keys() {
+        assert_eq!(
+            feed_all(&[0xE0, 0x48, 0xE0, 0xC8, 0xE0, 0x1D]),
+            vec![(KeyCode::Up, true), (KeyCode::Up, false), (KeyCode::RightCtrl, true)]
+        );
+    }
+
+    #[test]
+    fn test_print_screen_drops_fake_shift() {
+        assert_eq!(
+            feed_all(&[0xE0, 0x2A, 0xE0, 0x37, 0xE0, 0xB7, 0xE0, 0xAA]),
+            vec![(KeyCode::PrintScreen, true), (KeyCode::PrintScreen, false)]
+        );
+    }
+
+    #[test]
+    fn test_pause_and_acks() {
+        assert_eq!(
+            feed_all(&[0xFA, 0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5, 0x1C]),
+            vec![(KeyCode::Pause, true), (KeyCode::Enter, true)]
+        );
+    }
+}
@@ -2,16 +2,43 @@
 
 pub mod timer;
 pub mod keyboard;
+pub mod mouse;
+pub mod block;
+pub mod device;
+pub mod framebuffer;
+pub mod pci;
+pub mod ps2;
+pub mod rtc;
+pub mod uart;
+pub mod virtio;
 
 /// Initialize all device drivers
 pub fn init() {
     crate::serial::print("Initializing device drivers...\n");
+
+    // Register software devices and the serial ports
+    device::init();
+    uart::init();
     
     // Initialize timer (PIT)
     timer::init();
     
-    // Initialize keyboard
-    keyboard::init();
+    // Set up the PS/2 controller, then the keyboard and mouse it found
+    ps2::init();
+    if matches!(ps2::device(ps2::PortId::First), ps2::DeviceKind::Keyboard | ps2::DeviceKind::Unknown) {
+        keyboard::init();
+    }
+    mouse::init();
+    
+    // Register block devices
+    block::init();
+
+    // Enumerate PCI devices and bind their drivers
+    pci::register_driver(&block::ata::DRIVER);
+    pci::register_driver(&block::ahci::DRIVER);
+    pci::register_driver(&block::nvme::DRIVER);
+    pci::register_driver(&block::virtio::DRIVER);
+    pci::init();
     
     crate::serial::print("Device drivers initialized.\n");
 }
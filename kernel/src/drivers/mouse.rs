@@ -0,0 +1,225 @@
+//! PS/2 mouse driver
+//!
+//! The mouse on the controller's second port reports motion in packets
+//! of three bytes, or four once the IntelliMouse sequence (sample rates
+//! 200, 100, 80) has switched on its scroll wheel. Each packet becomes a
+//! `MouseEvent`, passed to registered listeners such as the console and
+//! queued for readers of `/dev/mouse`.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::device::{Device, DeviceError};
+use super::ps2::{self, DeviceKind, PortId};
+
+/// Controller data port
+const DATA_PORT: u16 = 0x60;
+
+/// Mouse commands
+const COMMAND_SET_RESOLUTION: u8 = 0xE8;
+const COMMAND_IDENTIFY: u8 = 0xF2;
+const COMMAND_SET_SAMPLE_RATE: u8 = 0xF3;
+const COMMAND_ENABLE_REPORTING: u8 = 0xF4;
+const COMMAND_SET_DEFAULTS: u8 = 0xF6;
+
+/// Sample rates that unlock the scroll wheel when set in this order
+const INTELLIMOUSE_SEQUENCE: [u8; 3] = [200, 100, 80];
+
+/// Reports per second and 4 counts per millimetre
+const SAMPLE_RATE: u8 = 100;
+const RESOLUTION: u8 = 2;
+
+/// Bits of the first packet byte
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+/// Events kept for `/dev/mouse`; the oldest are dropped when full
+const EVENT_BUFFER_SIZE: usize = 256;
+
+bitflags! {
+    /// Mouse buttons held down
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Buttons: u8 {
+        const LEFT = 1 << 0;
+        const RIGHT = 1 << 1;
+        const MIDDLE = 1 << 2;
+    }
+}
+
+/// Motion and button state from one packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Horizontal motion in counts, positive to the right
+    pub dx: i16,
+    /// Vertical motion in counts, positive downwards like screen coordinates
+    pub dy: i16,
+    /// Scroll wheel clicks, positive towards the user
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+/// Packet assembly state
+struct State {
+    packet: [u8; 4],
+    received: usize,
+    /// 3 for a standard mouse, 4 with a scroll wheel
+    packet_size: usize,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    packet: [0; 4],
+    received: 0,
+    packet_size: 3,
+});
+
+/// Functions called with every event
+static LISTENERS: Mutex<Vec<fn(&MouseEvent)>> = Mutex::new(Vec::new());
+
+static EVENTS: Mutex<VecDeque<MouseEvent>> = Mutex::new(VecDeque::new());
+
+/// Initialize the mouse on the controller's second port, if there is one
+pub fn init() {
+    if !ps2::device(PortId::Second).is_mouse() {
+        return;
+    }
+    crate::serial::print("Initializing PS/2 mouse...\n");
+
+    let wheel = x86_64::instructions::interrupts::without_interrupts(|| {
+        ps2::send(PortId::Second, COMMAND_SET_DEFAULTS);
+        for rate in INTELLIMOUSE_SEQUENCE {
+            ps2::send_with_arg(PortId::Second, COMMAND_SET_SAMPLE_RATE, rate);
+        }
+        let wheel = ps2::send(PortId::Second, COMMAND_IDENTIFY)
+            && ps2::read(PortId::Second).is_some_and(|id| id != 0x00);
+        ps2::send_with_arg(PortId::Second, COMMAND_SET_SAMPLE_RATE, SAMPLE_RATE);
+        ps2::send_with_arg(PortId::Second, COMMAND_SET_RESOLUTION, RESOLUTION);
+        wheel
+    });
+    STATE.lock().packet_size = if wheel { 4 } else { 3 };
+
+    if let Err(e) = crate::interrupts::register_irq(12, handle_interrupt) {
+        crate::serial::print("Mouse: ");
+        crate::serial::print(e);
+        crate::serial::print("\n");
+        return;
+    }
+    ps2::forward(PortId::Second, handle_byte);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        ps2::send(PortId::Second, COMMAND_ENABLE_REPORTING);
+    });
+    ps2::enable_irq(PortId::Second);
+
+    super::device::register("mouse", Arc::new(MouseDevice));
+
+    crate::serial::print("Mouse initialized (");
+    crate::serial::print(if wheel { DeviceKind::WheelMouse.name() } else { DeviceKind::Mouse.name() });
+    crate::serial::print(")\n");
+}
+
+/// Call `listener` with every mouse event from now on
+pub fn add_listener(listener: fn(&MouseEvent)) {
+    x86_64::instructions::interrupts::without_interrupts(|| LISTENERS.lock().push(listener));
+}
+
+/// Take the oldest event not yet read
+pub fn read_event() -> Option<MouseEvent> {
+    x86_64::instructions::interrupts::without_interrupts(|| EVENTS.lock().pop_front())
+}
+
+/// Mouse interrupt handler (IRQ 12)
+fn handle_interrupt() {
+    let byte: u8 = unsafe { Port::new(DATA_PORT).read() };
+    handle_byte(byte);
+}
+
+/// Add a byte to the packet being assembled, reporting the event once it
+/// is complete
+fn handle_byte(byte: u8) {
+    let event = {
+        let mut state = STATE.lock();
+        // The first byte always has bit 3 set; anything else means the
+        // stream is out of step, so wait for a byte that could start a packet
+        if state.received == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return;
+        }
+        let index = state.received;
+        state.packet[index] = byte;
+        state.received += 1;
+        if state.received < state.packet_size {
+            return;
+        }
+        state.received = 0;
+        decode(&state.packet[..state.packet_size])
+    };
+    let Some(event) = event else {
+        return;
+    };
+
+    {
+        let mut events = EVENTS.lock();
+        if events.len() == EVENT_BUFFER_SIZE {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+    let listeners = LISTENERS.lock().clone();
+    for listener in listeners {
+        listener(&event);
+    }
+}
+
+/// Turn a complete packet into an event, dropping packets whose motion
+/// overflowed
+fn decode(packet: &[u8]) -> Option<MouseEvent> {
+    let flags = packet[0];
+    if flags & (PACKET_X_OVERFLOW | PACKET_Y_OVERFLOW) != 0 {
+        return None;
+    }
+    // Motion is 9-bit two's complement with the sign bit in the first byte
+    let dx = packet[1] as i16 - if flags & PACKET_X_SIGN != 0 { 0x100 } else { 0 };
+    let dy = packet[2] as i16 - if flags & PACKET_Y_SIGN != 0 { 0x100 } else { 0 };
+    // The wheel is a 4-bit signed value in the low nibble
+    let wheel = packet.get(3).map_or(0, |&z| ((z << 4) as i8) >> 4);
+
+    Some(MouseEvent {
+        dx,
+        dy: -dy,
+        wheel,
+        buttons: Buttons::from_bits_truncate(flags),
+    })
+}
+
+/// Size of one record read from `/dev/mouse`
+const EVENT_RECORD_SIZE: usize = 6;
+
+/// `/dev/mouse`: events as 6-byte records
+///
+/// Each record is the buttons, the wheel motion as an `i8`, then the
+/// horizontal and vertical motion as little-endian `i16`s. Only whole
+/// records are returned.
+pub struct MouseDevice;
+
+impl Device for MouseDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        let mut len = 0;
+        while buf.len() - len >= EVENT_RECORD_SIZE {
+            let Some(event) = read_event() else {
+                break;
+            };
+            let record = &mut buf[len..len + EVENT_RECORD_SIZE];
+            record[0] = event.buttons.bits();
+            record[1] = event.wheel as u8;
+            record[2..4].copy_from_slice(&event.dx.to_le_bytes());
+            record[4..6].copy_from_slice(&event.dy.to_le_bytes());
+            len += EVENT_RECORD_SIZE;
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,119 @@
+//! PCI configuration space access
+//!
+//! Two mechanisms are supported: the legacy 0xCF8/0xCFC port pair, which
+//! reaches the first 256 bytes of every function, and ECAM, which maps the
+//! full 4 KiB of each function into memory at the address the ACPI MCFG
+//! table gives. ECAM is used when the firmware describes it.
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::PhysAddr;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// Size of one bus in ECAM space: 32 devices x 8 functions x 4 KiB
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+/// An ECAM region for PCI segment 0
+struct Ecam {
+    phys: u64,
+    start_bus: u8,
+    end_bus: u8,
+    /// Virtual address of each bus once mapped, zero if not yet mapped
+    mapped: [usize; 256],
+}
+
+/// How configuration space is reached
+enum Access {
+    Legacy,
+    /// Boxed, as the table of mapped buses is large
+    Ecam(Box<Ecam>),
+}
+
+static ACCESS: Mutex<Access> = Mutex::new(Access::Legacy);
+
+/// Use ECAM if the MCFG table describes a region for segment 0
+///
+/// Returns the bus range it covers.
+pub fn init() -> Option<(u8, u8)> {
+    let entry = crate::acpi::mcfg()
+        .into_iter()
+        .find(|entry| entry.segment == 0 && entry.start_bus <= entry.end_bus)?;
+
+    crate::serial::print("PCI: ECAM at ");
+    crate::memory::print_hex(entry.base);
+    crate::serial::print("\n");
+    *ACCESS.lock() = Access::Ecam(Box::new(Ecam {
+        phys: entry.base,
+        start_bus: entry.start_bus,
+        end_bus: entry.end_bus,
+        mapped: [0; 256],
+    }));
+    Some((entry.start_bus, entry.end_bus))
+}
+
+/// Whether ECAM is in use, so offsets up to 4 KiB are reachable
+pub fn extended() -> bool {
+    matches!(*ACCESS.lock(), Access::Ecam(_))
+}
+
+/// Virtual address of a function's ECAM space, mapping its bus on first use
+fn ecam_address(ecam: &mut Ecam, bus: u8, device: u8, function: u8) -> Option<usize> {
+    if bus < ecam.start_bus || bus > ecam.end_bus {
+        return None;
+    }
+    if ecam.mapped[bus as usize] == 0 {
+        // The MCFG base address corresponds to bus 0, not start_bus
+        let phys = ecam.phys + bus as u64 * ECAM_BUS_SIZE;
+        let virt = crate::memory::paging::map_mmio(PhysAddr::new(phys), ECAM_BUS_SIZE).ok()?;
+        ecam.mapped[bus as usize] = virt.as_u64() as usize;
+    }
+    Some(ecam.mapped[bus as usize] + ((device as usize) << 15 | (function as usize) << 12))
+}
+
+fn legacy_address(bus: u8, device: u8, function: u8, offset: u16) -> u32 {
+    0x8000_0000 | (bus as u32) << 16 | (device as u32) << 11 | (function as u32) << 8 | (offset as u32 & 0xFC)
+}
+
+/// Read a dword from configuration space; `offset` must be dword aligned
+pub fn read(bus: u8, device: u8, function: u8, offset: u16) -> u32 {
+    let mut access = ACCESS.lock();
+    match &mut *access {
+        Access::Ecam(ecam) => match ecam_address(ecam, bus, device, function) {
+            Some(base) => unsafe { core::ptr::read_volatile((base + (offset as usize & 0xFFC)) as *const u32) },
+            None => u32::MAX,
+        },
+        Access::Legacy => {
+            if offset >= 0x100 {
+                return u32::MAX;
+            }
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(legacy_address(bus, device, function, offset));
+                Port::<u32>::new(CONFIG_DATA).read()
+            }
+        }
+    }
+}
+
+/// Write a dword to configuration space; `offset` must be dword aligned
+pub fn write(bus: u8, device: u8, function: u8, offset: u16, value: u32) {
+    let mut access = ACCESS.lock();
+    match &mut *access {
+        Access::Ecam(ecam) => {
+            if let Some(base) = ecam_address(ecam, bus, device, function) {
+                unsafe { core::ptr::write_volatile((base + (offset as usize & 0xFFC)) as *mut u32, value) }
+            }
+        }
+        Access::Legacy => {
+            if offset >= 0x100 {
+                return;
+            }
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(legacy_address(bus, device, function, offset));
+                Port::<u32>::new(CONFIG_DATA).write(value);
+            }
+        }
+    }
+}
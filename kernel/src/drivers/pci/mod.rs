@@ -0,0 +1,411 @@
+//! PCI bus enumeration and driver binding
+//!
+//! Every bus, device and function is probed through configuration space.
+//! Base address registers are decoded and sized once at enumeration, and
+//! each function is offered to the registered drivers whose IDs match it.
+
+mod config;
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Configuration space register offsets
+pub const REG_VENDOR_ID: u16 = 0x00;
+pub const REG_COMMAND: u16 = 0x04;
+pub const REG_CLASS: u16 = 0x08;
+pub const REG_HEADER_TYPE: u16 = 0x0C;
+pub const REG_BAR0: u16 = 0x10;
+pub const REG_CAPABILITIES: u16 = 0x34;
+pub const REG_INTERRUPT: u16 = 0x3C;
+
+/// Command register bits
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// Status register bit: the capability list pointer is valid
+const STATUS_CAPABILITIES: u32 = 1 << 4;
+
+/// Capability IDs
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// A base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// Memory-mapped registers at a physical address
+    Memory {
+        addr: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64: bool,
+    },
+    /// Registers in I/O port space
+    Io { port: u16, size: u32 },
+    /// Not implemented by the device, or the upper half of a 64-bit BAR
+    None,
+}
+
+/// A function found on the bus
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    /// Legacy interrupt line routed by firmware, 0xFF if none
+    pub irq_line: u8,
+    pub bars: [Bar; 6],
+}
+
+impl PciDevice {
+    /// Read a dword from this function's configuration space
+    pub fn read(&self, offset: u16) -> u32 {
+        config::read(self.bus, self.device, self.function, offset)
+    }
+
+    /// Write a dword to this function's configuration space
+    pub fn write(&self, offset: u16, value: u32) {
+        config::write(self.bus, self.device, self.function, offset, value)
+    }
+
+    /// Read a byte at any offset
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        (self.read(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Decoded base address register `index` (0-5)
+    pub fn bar(&self, index: usize) -> Bar {
+        self.bars[index]
+    }
+
+    /// Set bits in the command register
+    pub fn enable(&self, bits: u16) {
+        let value = self.read(REG_COMMAND);
+        // The upper half is the status register, whose bits are write-1-to-clear
+        self.write(REG_COMMAND, (value & 0xFFFF) | bits as u32);
+    }
+
+    /// Capabilities as (ID, offset) pairs, in list order
+    pub fn capabilities(&self) -> Vec<(u8, u16)> {
+        let mut caps = Vec::new();
+        if self.read(REG_COMMAND) & (STATUS_CAPABILITIES << 16) == 0 {
+            return caps;
+        }
+        let mut offset = (self.read_u8(REG_CAPABILITIES) & 0xFC) as u16;
+        // A malformed list could loop; there is only room for 48 entries
+        while offset >= 0x40 && caps.len() < 48 {
+            let header = self.read(offset);
+            caps.push((header as u8, offset));
+            offset = ((header >> 8) & 0xFC) as u16;
+        }
+        caps
+    }
+
+    /// Decode and size the BARs of a type 0 (6 BARs) or type 1 (2 BARs) header
+    fn decode_bars(&mut self) {
+        let count = match self.header_type & 0x7F {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        };
+        // Decoding must be off while the BARs hold all-ones
+        let command = self.read(REG_COMMAND) & 0xFFFF;
+        self.write(REG_COMMAND, command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE) as u32);
+
+        let mut index = 0;
+        while index < count {
+            let offset = REG_BAR0 + index as u16 * 4;
+            let low = self.read(offset);
+            self.write(offset, u32::MAX);
+            let low_mask = self.read(offset);
+            self.write(offset, low);
+
+            if low & 1 != 0 {
+                let mask = low_mask & !0x3 & 0xFFFF;
+                if mask != 0 {
+                    self.bars[index] = Bar::Io {
+                        port: (low & !0x3) as u16,
+                        size: (!mask & 0xFFFF) + 1,
+                    };
+                }
+                index += 1;
+                continue;
+            }
+
+            // Type 2 is a 64-bit BAR spanning this register and the next
+            let is_64 = (low >> 1) & 0x3 == 2 && index + 1 < count;
+            let mut addr = (low & !0xF) as u64;
+            let mut mask = (low_mask & !0xF) as u64;
+            if is_64 {
+                let high = self.read(offset + 4);
+                self.write(offset + 4, u32::MAX);
+                let high_mask = self.read(offset + 4);
+                self.write(offset + 4, high);
+                addr |= (high as u64) << 32;
+                mask |= (high_mask as u64) << 32;
+            } else if mask != 0 {
+                mask |= 0xFFFF_FFFF_0000_0000;
+            }
+            if mask != 0 {
+                self.bars[index] = Bar::Memory {
+                    addr,
+                    size: !mask + 1,
+                    prefetchable: low & (1 << 3) != 0,
+                    is_64,
+                };
+            }
+            index += if is_64 { 2 } else { 1 };
+        }
+
+        self.write(REG_COMMAND, command);
+    }
+}
+
+/// Which functions a driver handles; `None` fields match anything
+#[derive(Debug, Clone, Copy)]
+pub struct PciId {
+    pub vendor_id: Option<u16>,
+    pub device_id: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub prog_if: Option<u8>,
+}
+
+impl PciId {
+    const ANY: Self = Self {
+        vendor_id: None,
+        device_id: None,
+        class: None,
+        subclass: None,
+        prog_if: None,
+    };
+
+    /// Match one vendor/device pair
+    pub const fn device(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            ..Self::ANY
+        }
+    }
+
+    /// Match a class and subclass
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self {
+            class: Some(class),
+            subclass: Some(subclass),
+            ..Self::ANY
+        }
+    }
+
+    /// Match a class, subclass and programming interface
+    pub const fn class_if(class: u8, subclass: u8, prog_if: u8) -> Self {
+        Self {
+            prog_if: Some(prog_if),
+            ..Self::class(class, subclass)
+        }
+    }
+
+    pub fn matches(&self, dev: &PciDevice) -> bool {
+        self.vendor_id.is_none_or(|v| v == dev.vendor_id)
+            && self.device_id.is_none_or(|d| d == dev.device_id)
+            && self.class.is_none_or(|c| c == dev.class)
+            && self.subclass.is_none_or(|s| s == dev.subclass)
+            && self.prog_if.is_none_or(|p| p == dev.prog_if)
+    }
+}
+
+/// A driver for PCI functions
+pub trait PciDriver: Sync {
+    /// Short name shown by `lspci`
+    fn name(&self) -> &'static str;
+
+    /// Functions this driver may handle
+    fn ids(&self) -> &'static [PciId];
+
+    /// Take over a matching function; an error leaves it for other drivers
+    fn probe(&self, dev: &PciDevice) -> Result<(), &'static str>;
+}
+
+/// A function and the driver bound to it, if any
+#[derive(Clone, Copy)]
+pub struct PciEntry {
+    pub device: PciDevice,
+    pub driver: Option<&'static str>,
+}
+
+static DRIVERS: Mutex<Vec<&'static dyn PciDriver>> = Mutex::new(Vec::new());
+
+/// Functions found by the last scan
+static DEVICES: Mutex<Vec<PciEntry>> = Mutex::new(Vec::new());
+
+/// Register a driver; it is offered any unbound functions already found
+pub fn register_driver(driver: &'static dyn PciDriver) {
+    DRIVERS.lock().push(driver);
+    let count = DEVICES.lock().len();
+    for index in 0..count {
+        bind(index, driver);
+    }
+}
+
+/// Offer function `index` to `driver`, recording the binding on success
+fn bind(index: usize, driver: &'static dyn PciDriver) {
+    // Probing may take a while and register other devices; don't hold the lock
+    let Some(dev) = DEVICES
+        .lock()
+        .get(index)
+        .filter(|entry| entry.driver.is_none())
+        .map(|entry| entry.device)
+    else {
+        return;
+    };
+    if !driver.ids().iter().any(|id| id.matches(&dev)) {
+        return;
+    }
+    match driver.probe(&dev) {
+        Ok(()) => DEVICES.lock()[index].driver = Some(driver.name()),
+        Err(e) => {
+            crate::serial::print("PCI: ");
+            crate::serial::print(driver.name());
+            crate::serial::print(" failed on ");
+            crate::serial::print(&address(&dev));
+            crate::serial::print(": ");
+            crate::serial::print(e);
+            crate::serial::print("\n");
+        }
+    }
+}
+
+/// Bus address in `bb:dd.f` form
+pub fn address(dev: &PciDevice) -> alloc::string::String {
+    alloc::format!("{:02x}:{:02x}.{}", dev.bus, dev.device, dev.function)
+}
+
+/// Probe one function, returning it if present
+fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let id = config::read(bus, device, function, REG_VENDOR_ID);
+    let vendor_id = id as u16;
+    if vendor_id == 0xFFFF {
+        return None;
+    }
+    let class = config::read(bus, device, function, REG_CLASS);
+    let header = config::read(bus, device, function, REG_HEADER_TYPE);
+    let interrupt = config::read(bus, device, function, REG_INTERRUPT);
+    let mut dev = PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+        header_type: (header >> 16) as u8,
+        irq_line: interrupt as u8,
+        bars: [Bar::None; 6],
+    };
+    dev.decode_bars();
+    Some(dev)
+}
+
+/// Scan every device and function on the given buses
+fn scan(buses: core::ops::RangeInclusive<u8>) -> Vec<PciDevice> {
+    let mut found = Vec::new();
+    for bus in buses {
+        for device in 0..32u8 {
+            let Some(first) = probe(bus, device, 0) else {
+                continue;
+            };
+            let multifunction = first.header_type & 0x80 != 0;
+            found.push(first);
+            if multifunction {
+                found.extend((1..8).filter_map(|function| probe(bus, device, function)));
+            }
+        }
+    }
+    found
+}
+
+/// Every function found, with its bound driver
+pub fn devices() -> Vec<PciEntry> {
+    DEVICES.lock().clone()
+}
+
+/// Human-readable name for a class code
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, _) => "Unclassified device",
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x05) => "ATA controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x01) => "Audio device",
+        (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, 0x00) => "Serial controller",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        (0x0D, _) => "Wireless controller",
+        _ => "Unknown device",
+    }
+}
+
+/// Human-readable name for a capability ID
+pub fn capability_name(id: u8) -> &'static str {
+    match id {
+        CAP_POWER_MANAGEMENT => "Power Management",
+        CAP_MSI => "MSI",
+        CAP_VENDOR => "Vendor Specific",
+        CAP_PCI_EXPRESS => "Express",
+        CAP_MSIX => "MSI-X",
+        0x12 => "SATA",
+        _ => "Unknown",
+    }
+}
+
+/// Enumerate the bus and bind registered drivers
+pub fn init() {
+    crate::serial::print("Scanning PCI bus...\n");
+    let buses = match config::init() {
+        Some((start, end)) => start..=end,
+        None => 0..=255,
+    };
+    let found = scan(buses);
+    crate::serial::print("PCI: found ");
+    crate::memory::print_decimal(found.len() as u64);
+    crate::serial::print(if config::extended() { " functions (ECAM)\n" } else { " functions\n" });
+    *DEVICES.lock() = found.into_iter().map(|device| PciEntry { device, driver: None }).collect();
+
+    let drivers = DRIVERS.lock().clone();
+    for index in 0..DEVICES.lock().len() {
+        for &driver in &drivers {
+            bind(index, driver);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! 8042 PS/2 controller
+//!
+//! The controller has two ports: the first normally holds the keyboard
+//! and raises IRQ 1, the second (auxiliary) normally holds a mouse and
+//! raises IRQ 12. Initialization disables both, runs the controller and
+//! interface self-tests, then resets and identifies whatever is attached
+//! so the keyboard and mouse drivers only bind to devices that answered.
+//! Scancode translation stays enabled, as the keyboard driver decodes set 1.
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// Controller data, status and command ports
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+/// Status register bits
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The byte in the output buffer came from the second port
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+/// Controller commands
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xA7;
+const COMMAND_ENABLE_SECOND: u8 = 0xA8;
+const COMMAND_TEST_SECOND: u8 = 0xA9;
+const COMMAND_SELF_TEST: u8 = 0xAA;
+const COMMAND_TEST_FIRST: u8 = 0xAB;
+const COMMAND_DISABLE_FIRST: u8 = 0xAD;
+const COMMAND_ENABLE_FIRST: u8 = 0xAE;
+/// Send the next data byte to the second port instead of the first
+const COMMAND_WRITE_SECOND: u8 = 0xD4;
+
+/// Configuration byte bits
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+
+/// Controller responses
+const SELF_TEST_PASSED: u8 = 0x55;
+const INTERFACE_TEST_PASSED: u8 = 0x00;
+
+/// Commands understood by both keyboards and mice
+const DEVICE_IDENTIFY: u8 = 0xF2;
+const DEVICE_ENABLE_SCANNING: u8 = 0xF4;
+const DEVICE_DISABLE_SCANNING: u8 = 0xF5;
+const DEVICE_RESET: u8 = 0xFF;
+
+/// Device responses
+const RESPONSE_ACK: u8 = 0xFA;
+const RESPONSE_RESEND: u8 = 0xFE;
+const RESPONSE_RESET_PASSED: u8 = 0xAA;
+
+/// Status polls before a byte is considered lost
+const TIMEOUT: u32 = 100_000;
+
+/// Attempts at a device command the device asks to have resent
+const RETRIES: usize = 3;
+
+/// A controller port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortId {
+    First,
+    Second,
+}
+
+/// What a port has attached, from its identify response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    None,
+    /// AT keyboard with translation, which identifies with no bytes or 0xAB xx
+    Keyboard,
+    /// Standard mouse (ID 0x00)
+    Mouse,
+    /// Mouse with a scroll wheel (ID 0x03)
+    WheelMouse,
+    /// Mouse with a wheel and five buttons (ID 0x04)
+    FiveButtonMouse,
+    Unknown,
+}
+
+impl DeviceKind {
+    fn from_id(id: &[u8]) -> Self {
+        match id {
+            [] | [0xAB, _] => Self::Keyboard,
+            [0x00] => Self::Mouse,
+            [0x03] => Self::WheelMouse,
+            [0x04] => Self::FiveButtonMouse,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn is_mouse(&self) -> bool {
+        matches!(self, Self::Mouse | Self::WheelMouse | Self::FiveButtonMouse)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Keyboard => "keyboard",
+            Self::Mouse => "mouse",
+            Self::WheelMouse => "wheel mouse",
+            Self::FiveButtonMouse => "5-button mouse",
+            Self::Unknown => "unknown device",
+        }
+    }
+}
+
+/// Devices found on each port by `init`
+static DEVICES: Mutex<[DeviceKind; 2]> = Mutex::new([DeviceKind::None; 2]);
+
+/// Where each port's bytes go when they arrive while `read` waits on the
+/// other port
+static FORWARD: Mutex<[Option<fn(u8)>; 2]> = Mutex::new([None; 2]);
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+/// Wait until the controller can take another byte
+fn wait_input_empty() -> bool {
+    (0..TIMEOUT).any(|_| status() & STATUS_INPUT_FULL == 0)
+}
+
+fn write_command(command: u8) -> bool {
+    if !wait_input_empty() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    true
+}
+
+fn write_data(byte: u8) -> bool {
+    if !wait_input_empty() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    true
+}
+
+/// Read the next byte from the controller, from either port
+fn read_data() -> Option<u8> {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_OUTPUT_FULL != 0 {
+            return Some(unsafe { Port::<u8>::new(DATA_PORT).read() });
+        }
+    }
+    None
+}
+
+/// Read the next byte sent by a device on `port`
+///
+/// Bytes that arrive from the other port meanwhile go to its forwarding
+/// handler, or are dropped if it has none.
+pub fn read(port: PortId) -> Option<u8> {
+    for _ in 0..TIMEOUT {
+        let status = status();
+        if status & STATUS_OUTPUT_FULL == 0 {
+            continue;
+        }
+        let byte = unsafe { Port::<u8>::new(DATA_PORT).read() };
+        let from = if status & STATUS_AUX_DATA != 0 { PortId::Second } else { PortId::First };
+        if from == port {
+            return Some(byte);
+        }
+        let handler = FORWARD.lock()[from as usize];
+        if let Some(handler) = handler {
+            handler(byte);
+        }
+    }
+    None
+}
+
+/// Pass bytes from `port` that arrive during another port's command
+/// exchange to `handler`, as its interrupt handler would have
+pub fn forward(port: PortId, handler: fn(u8)) {
+    FORWARD.lock()[port as usize] = Some(handler);
+}
+
+/// Run a controller command that answers with one byte
+fn command_response(command: u8) -> Option<u8> {
+    if !write_command(command) {
+        return None;
+    }
+    read_data()
+}
+
+/// Discard anything left in the output buffer
+fn flush() {
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+/// Send a byte to the device on `port`
+fn write(port: PortId, byte: u8) -> bool {
+    if port == PortId::Second && !write_command(COMMAND_WRITE_SECOND) {
+        return false;
+    }
+    write_data(byte)
+}
+
+/// Send a byte to the device on `port` and wait for its acknowledgement,
+/// resending when asked to
+///
+/// Must run with interrupts disabled, or the acknowledgement may be taken
+/// by the device's interrupt handler instead.
+pub fn send(port: PortId, byte: u8) -> bool {
+    for _ in 0..RETRIES {
+        if !write(port, byte) {
+            return false;
+        }
+        match read(port) {
+            Some(RESPONSE_ACK) => return true,
+            Some(RESPONSE_RESEND) => continue,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Send a command and its argument, each of which must be acknowledged
+pub fn send_with_arg(port: PortId, command: u8, argument: u8) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| send(port, command) && send(port, argument))
+}
+
+/// Reset the device on `port` and identify it
+fn probe(port: PortId) -> DeviceKind {
+    if !send(port, DEVICE_RESET) || read(port) != Some(RESPONSE_RESET_PASSED) {
+        return DeviceKind::None;
+    }
+    // Mice follow the self-test result with their ID
+    if port == PortId::Second {
+        read(port);
+    }
+
+    if !send(port, DEVICE_DISABLE_SCANNING) || !send(port, DEVICE_IDENTIFY) {
+        return DeviceKind::Unknown;
+    }
+    let mut id = [0u8; 2];
+    let mut len = 0;
+    while len < id.len()
+        && let Some(byte) = read(port)
+    {
+        id[len] = byte;
+        len += 1;
+    }
+    let kind = DeviceKind::from_id(&id[..len]);
+    // Drivers enable reporting once they have configured the device
+    if !kind.is_mouse() {
+        send(port, DEVICE_ENABLE_SCANNING);
+    }
+    kind
+}
+
+/// Whether ACPI says there is no 8042; only revision 2 and later FADTs
+/// carry the flag
+fn absent_per_acpi() -> bool {
+    crate::acpi::fadt().is_some_and(|fadt| {
+        fadt.revision >= 2 && fadt.boot_arch & crate::acpi::fadt::BOOT_ARCH_8042 == 0
+    })
+}
+
+/// Set up the controller and identify the devices on its ports
+///
+/// Interrupts from both ports stay off until the drivers call `enable_irq`.
+pub fn init() {
+    crate::serial::print("Initializing PS/2 controller...\n");
+    if absent_per_acpi() {
+        crate::serial::print("PS/2: no 8042 controller according to ACPI\n");
+        return;
+    }
+
+    let devices = x86_64::instructions::interrupts::without_interrupts(|| {
+        // Disable both ports so nothing interferes with the tests
+        if !write_command(COMMAND_DISABLE_FIRST) || !write_command(COMMAND_DISABLE_SECOND) {
+            return Err("controller not responding");
+        }
+        flush();
+
+        let mut config = command_response(COMMAND_READ_CONFIG).ok_or("cannot read configuration")?;
+        config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+        write_command(COMMAND_WRITE_CONFIG);
+        write_data(config);
+
+        if command_response(COMMAND_SELF_TEST) != Some(SELF_TEST_PASSED) {
+            return Err("controller self-test failed");
+        }
+        // Some controllers reset their configuration during the self-test
+        write_command(COMMAND_WRITE_CONFIG);
+        write_data(config);
+
+        // A second port exists if enabling it starts its clock
+        let mut dual = false;
+        if config & CONFIG_SECOND_CLOCK_DISABLED != 0 {
+            write_command(COMMAND_ENABLE_SECOND);
+            dual = command_response(COMMAND_READ_CONFIG).is_some_and(|c| c & CONFIG_SECOND_CLOCK_DISABLED == 0);
+            write_command(COMMAND_DISABLE_SECOND);
+        }
+
+        let first_ok = command_response(COMMAND_TEST_FIRST) == Some(INTERFACE_TEST_PASSED);
+        let second_ok = dual && command_response(COMMAND_TEST_SECOND) == Some(INTERFACE_TEST_PASSED);
+
+        let mut devices = [DeviceKind::None; 2];
+        if first_ok {
+            write_command(COMMAND_ENABLE_FIRST);
+            devices[0] = probe(PortId::First);
+        }
+        if second_ok {
+            write_command(COMMAND_ENABLE_SECOND);
+            devices[1] = probe(PortId::Second);
+        }
+        flush();
+        Ok(devices)
+    });
+
+    match devices {
+        Ok(devices) => {
+            *DEVICES.lock() = devices;
+            crate::serial::print("PS/2: port 1: ");
+            crate::serial::print(devices[0].name());
+            crate::serial::print(", port 2: ");
+            crate::serial::print(devices[1].name());
+            crate::serial::print("\n");
+        }
+        Err(e) => {
+            crate::serial::print("PS/2: ");
+            crate::serial::print(e);
+            crate::serial::print("\n");
+        }
+    }
+}
+
+/// The device `init` found on `port`
+pub fn device(port: PortId) -> DeviceKind {
+    DEVICES.lock()[port as usize]
+}
+
+/// Let the device on `port` raise its interrupt
+pub fn enable_irq(port: PortId) {
+    let bit = match port {
+        PortId::First => CONFIG_FIRST_IRQ,
+        PortId::Second => CONFIG_SECOND_IRQ,
+    };
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if let Some(config) = command_response(COMMAND_READ_CONFIG) {
+            write_command(COMMAND_WRITE_CONFIG);
+            write_data(config | bit);
+        }
+    });
+}
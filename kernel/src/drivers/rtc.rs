@@ -0,0 +1,180 @@
+//! CMOS real-time clock
+//!
+//! The RTC keeps the date across power cycles in CMOS registers, in BCD or
+//! binary and in 12 or 24 hour form depending on status register B. The
+//! registers are read twice, outside an update cycle, until two reads
+//! agree. It can also raise a periodic interrupt on IRQ 8 at a power of
+//! two rate.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::time::calendar::DateTime;
+
+/// CMOS index and data ports
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Time and date registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+
+/// Status registers
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+
+/// Status A: update in progress, and the rate selector in the low nibble
+const STATUS_A_UPDATING: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0F;
+/// Status B: periodic interrupt enable, binary mode, 24 hour mode
+const STATUS_B_PERIODIC: u8 = 1 << 6;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_24H: u8 = 1 << 1;
+/// PM flag in the hours register in 12 hour mode
+const HOURS_PM: u8 = 0x80;
+
+/// Reads attempted before giving up on getting two that agree
+const READ_ATTEMPTS: u32 = 16;
+
+/// Polls of the update flag before giving up on the clock; an update takes
+/// about 2 ms and a port read about a microsecond
+const UPDATE_POLLS: u32 = 10_000;
+
+/// Periodic interrupt rate in Hz, 0 while disabled
+static PERIODIC_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// Periodic interrupts received
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_INDEX).write(reg);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn write_register(reg: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(CMOS_INDEX).write(reg);
+        Port::<u8>::new(CMOS_DATA).write(value);
+    }
+}
+
+fn updating() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0
+}
+
+/// Raw time registers: seconds, minutes, hours, day, month, year, century
+///
+/// Returns `None` if the clock never leaves its update cycle, as when
+/// there is no RTC and the flag reads as set.
+fn read_raw(century_reg: u8) -> Option<[u8; 7]> {
+    (0..UPDATE_POLLS).find(|_| !updating())?;
+    Some([
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+        if century_reg != 0 { read_register(century_reg) } else { 0 },
+    ])
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Read the current date and time
+pub fn read() -> Option<DateTime> {
+    let century_reg = crate::acpi::fadt().map(|fadt| fadt.century).unwrap_or(0);
+
+    // The registers may change between reads; retry until two reads agree
+    let mut previous = read_raw(century_reg)?;
+    let mut raw = None;
+    for _ in 0..READ_ATTEMPTS {
+        let current = read_raw(century_reg)?;
+        if current == previous {
+            raw = Some(current);
+            break;
+        }
+        previous = current;
+    }
+    let [second, minute, hour, day, month, year, century] = raw?;
+
+    let status_b = read_register(REG_STATUS_B);
+    let decode = |value: u8| if status_b & STATUS_B_BINARY != 0 { value } else { from_bcd(value) };
+
+    let pm = hour & HOURS_PM != 0;
+    let mut hour = decode(hour & !HOURS_PM);
+    if status_b & STATUS_B_24H == 0 {
+        // 12 AM is midnight and 12 PM noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    // Without a century register, assume the 21st century
+    let year = decode(year) as u16;
+    let year = match decode(century) {
+        century @ 19..=99 => century as u16 * 100 + year,
+        _ => 2000 + year,
+    };
+
+    Some(DateTime {
+        year,
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    })
+}
+
+/// IRQ 8: acknowledge by reading status C, or no further interrupts come
+fn handle_interrupt() {
+    read_register(REG_STATUS_C);
+    PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Start the periodic interrupt at the power of two nearest below `hz`
+///
+/// Rates run from 2 Hz to 8192 Hz. Returns the rate chosen.
+pub fn enable_periodic(hz: u32) -> Result<u32, &'static str> {
+    if hz < 2 {
+        return Err("rate too low");
+    }
+    // Rate selector n gives 32768 >> (n - 1) Hz; 3 is the fastest usable
+    let rate = (16 - hz.min(8192).ilog2()) as u8;
+    let actual = 32768 >> (rate - 1);
+
+    if PERIODIC_HZ.swap(actual, Ordering::Relaxed) == 0 {
+        crate::interrupts::register_irq(8, handle_interrupt).inspect_err(|_| {
+            PERIODIC_HZ.store(0, Ordering::Relaxed);
+        })?;
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let status_a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC);
+        read_register(REG_STATUS_C);
+    });
+    Ok(actual)
+}
+
+/// Rate of the periodic interrupt, 0 if it is off
+pub fn periodic_hz() -> u32 {
+    PERIODIC_HZ.load(Ordering::Relaxed)
+}
+
+/// Periodic interrupts received since it was enabled
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
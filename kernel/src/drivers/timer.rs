@@ -2,10 +2,9 @@
 
 use spin::Mutex;
 use x86_64::instructions::port::Port;
-use x86_64::structures::idt::InterruptStackFrame;
 
 const PIT_FREQUENCY: u32 = 1193182; // PIT base frequency in Hz
-const TARGET_FREQUENCY: u32 = 100;  // 100 Hz = 10ms intervals
+pub const TARGET_FREQUENCY: u32 = 100;  // 100 Hz = 10ms intervals
 
 static TICK_COUNT: Mutex<u64> = Mutex::new(0);
 
@@ -30,25 +29,15 @@ pub fn init() {
         data_port.write((divisor >> 8) as u8);
     }
     
-    // Enable timer interrupt (IRQ 0 -> IRQ 32)
+    // Route IRQ 0 to the timer handler
     // TODO: Temporarily disabled to avoid double fault
-    // enable_timer_interrupt();
+    // crate::interrupts::register_irq(0, handle_interrupt);
     
     crate::serial::print("PIT timer initialized.\n");
 }
 
-/// Enable timer interrupt in PIC
-fn enable_timer_interrupt() {
-    unsafe {
-        let mut pic1_data = Port::new(0x21);
-        let mask: u8 = pic1_data.read();
-        // Clear bit 0 to enable IRQ 0 (timer)
-        pic1_data.write(mask & !0x01);
-    }
-}
-
-/// Timer interrupt handler (IRQ 0 -> IRQ 32)
-pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// Timer interrupt handler (IRQ 0)
+fn handle_interrupt() {
     // Increment tick counter
     *TICK_COUNT.lock() += 1;
     
@@ -66,9 +55,6 @@ pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptSta
     
     // Notify scheduler of timer tick
     crate::task::scheduler::timer_tick();
-    
-    // Send EOI to PIC
-    crate::interrupts::pic::send_eoi(0);
 }
 
 /// Get current tick count
@@ -76,15 +62,7 @@ pub fn ticks() -> u64 {
     *TICK_COUNT.lock()
 }
 
-/// Sleep for approximately the given number of milliseconds
+/// Sleep for at least the given number of milliseconds
 pub fn sleep_ms(ms: u64) {
-    let target_ticks = ticks() + (ms * TARGET_FREQUENCY as u64) / 1000;
-    while ticks() < target_ticks {
-        x86_64::instructions::hlt();
-    }
-}
-
-/// Get uptime in seconds
-pub fn uptime_seconds() -> u64 {
-    ticks() / TARGET_FREQUENCY as u64
+    crate::time::sleep(crate::time::Duration::from_millis(ms));
 }
@@ -0,0 +1,527 @@
+//! 16550 UART driver
+//!
+//! Drives up to four COM ports at their standard I/O addresses. COM1 and
+//! COM3 share IRQ 4, COM2 and COM4 share IRQ 3. Received bytes are queued
+//! in a ring for readers of `/dev/ttySn`; bytes received on COM1 are also
+//! fed to the console terminal, which decodes them as UTF-8, so the system
+//! can be used over a serial line alone. Writes are queued in a transmit
+//! ring drained by the transmitter-empty interrupt, except for kernel log
+//! output, which is written synchronously so it survives a hang with
+//! interrupts disabled. The rings have fixed sizes, so the interrupt
+//! handler never allocates.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use super::device::{Device, DeviceError};
+
+/// Register offsets from the port base; the first two are the divisor
+/// latch while `LCR_DLAB` is set
+const REG_DATA: u16 = 0;
+const REG_IER: u16 = 1;
+const REG_DIVISOR_LOW: u16 = 0;
+const REG_DIVISOR_HIGH: u16 = 1;
+const REG_IIR: u16 = 2;
+const REG_FCR: u16 = 2;
+const REG_LCR: u16 = 3;
+const REG_MCR: u16 = 4;
+const REG_LSR: u16 = 5;
+const REG_MSR: u16 = 6;
+const REG_SCRATCH: u16 = 7;
+
+/// Interrupt enable bits
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+
+/// Interrupt identification: bit 0 clear when an interrupt is pending,
+/// bits 1-3 the cause
+const IIR_NONE_PENDING: u8 = 1 << 0;
+const IIR_CAUSE_MASK: u8 = 0x0E;
+const IIR_MODEM_STATUS: u8 = 0x00;
+const IIR_TX_EMPTY: u8 = 0x02;
+const IIR_RX_AVAILABLE: u8 = 0x04;
+const IIR_LINE_STATUS: u8 = 0x06;
+const IIR_RX_TIMEOUT: u8 = 0x0C;
+
+/// Enable and clear both FIFOs, interrupting at 14 received bytes
+const FCR_ENABLE_14: u8 = 0xC7;
+
+/// Line control bits
+const LCR_DLAB: u8 = 1 << 7;
+const LCR_TWO_STOP_BITS: u8 = 1 << 2;
+
+/// Modem control: DTR, RTS and OUT2, which gates the IRQ line
+const MCR_NORMAL: u8 = 0x0B;
+/// Loopback with OUT1/OUT2/RTS, used to test the port
+const MCR_LOOPBACK: u8 = 0x1E;
+
+/// Line status bits
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+/// Base clock divided by the divisor gives the baud rate
+const UART_CLOCK: u32 = 115_200;
+
+/// Bytes written per transmitter-empty interrupt
+const TX_FIFO_SIZE: usize = 16;
+
+/// Ring sizes; received bytes overwrite the oldest when full, writers
+/// wait for transmission instead
+const RX_BUFFER_SIZE: usize = 1024;
+const TX_BUFFER_SIZE: usize = 4096;
+
+/// COM1 input waiting to be passed to the console terminal, which happens
+/// before the interrupt handler returns
+const CONSOLE_BUFFER_SIZE: usize = 256;
+
+/// Parity setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+impl Parity {
+    /// Line control bits 3-5
+    fn lcr_bits(&self) -> u8 {
+        match self {
+            Parity::None => 0b000 << 3,
+            Parity::Odd => 0b001 << 3,
+            Parity::Even => 0b011 << 3,
+            Parity::Mark => 0b101 << 3,
+            Parity::Space => 0b111 << 3,
+        }
+    }
+
+    fn letter(&self) -> char {
+        match self {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+            Parity::Mark => 'M',
+            Parity::Space => 'S',
+        }
+    }
+}
+
+/// Line settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub baud: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8,
+}
+
+impl Config {
+    /// 38400 baud, 8 data bits, no parity, 1 stop bit
+    pub const DEFAULT: Config = Config {
+        baud: 38_400,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+    };
+
+    /// Parse a frame format such as `8N1`
+    pub fn parse_format(&mut self, format: &str) -> Result<(), &'static str> {
+        let &[data, parity, stop] = format.as_bytes() else {
+            return Err("format must look like 8N1");
+        };
+        self.data_bits = match data {
+            b'5'..=b'8' => data - b'0',
+            _ => return Err("data bits must be 5 to 8"),
+        };
+        self.parity = match parity.to_ascii_uppercase() {
+            b'N' => Parity::None,
+            b'O' => Parity::Odd,
+            b'E' => Parity::Even,
+            b'M' => Parity::Mark,
+            b'S' => Parity::Space,
+            _ => return Err("parity must be N, O, E, M or S"),
+        };
+        self.stop_bits = match stop {
+            b'1' | b'2' => stop - b'0',
+            _ => return Err("stop bits must be 1 or 2"),
+        };
+        Ok(())
+    }
+
+    fn divisor(&self) -> Result<u16, &'static str> {
+        if self.baud == 0 || !UART_CLOCK.is_multiple_of(self.baud) {
+            return Err("baud rate must divide 115200");
+        }
+        Ok((UART_CLOCK / self.baud) as u16)
+    }
+
+    fn lcr(&self) -> u8 {
+        let stop = if self.stop_bits == 2 { LCR_TWO_STOP_BITS } else { 0 };
+        (self.data_bits - 5) | stop | self.parity.lcr_bits()
+    }
+}
+
+impl core::fmt::Display for Config {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} {}{}{}", self.baud, self.data_bits, self.parity.letter(), self.stop_bits)
+    }
+}
+
+/// A byte queue of fixed capacity
+struct Ring<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self { buf: [0; N], head: 0, len: 0 }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a byte, dropping the oldest if the ring is full
+    fn push_back(&mut self, byte: u8) {
+        if self.len == N {
+            self.pop_front();
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+    }
+
+    fn pop_front(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// State shared with the interrupt handler
+struct Inner {
+    config: Config,
+    rx: Ring<RX_BUFFER_SIZE>,
+    tx: Ring<TX_BUFFER_SIZE>,
+    /// Received bytes for the console terminal; only COM1 fills it
+    console: Ring<CONSOLE_BUFFER_SIZE>,
+    /// Whether the port raises interrupts; writes are synchronous until then
+    irq_enabled: bool,
+    ier: u8,
+}
+
+/// One COM port
+pub struct Uart {
+    /// Device name, also used in messages
+    pub name: &'static str,
+    base: u16,
+    irq: u8,
+    present: AtomicBool,
+    inner: Mutex<Inner>,
+}
+
+impl Uart {
+    const fn new(name: &'static str, base: u16, irq: u8) -> Self {
+        Self {
+            name,
+            base,
+            irq,
+            present: AtomicBool::new(false),
+            inner: Mutex::new(Inner {
+                config: Config::DEFAULT,
+                rx: Ring::new(),
+                tx: Ring::new(),
+                console: Ring::new(),
+                irq_enabled: false,
+                ier: 0,
+            }),
+        }
+    }
+
+    fn read_reg(&self, reg: u16) -> u8 {
+        unsafe { Port::new(self.base + reg).read() }
+    }
+
+    fn write_reg(&self, reg: u16, value: u8) {
+        unsafe { Port::new(self.base + reg).write(value) }
+    }
+
+    /// Check that a working UART answers at this address
+    fn probe(&self) -> bool {
+        self.write_reg(REG_SCRATCH, 0x5A);
+        if self.read_reg(REG_SCRATCH) != 0x5A {
+            return false;
+        }
+        // Send a byte to ourselves through the loopback path
+        self.write_reg(REG_IER, 0);
+        self.write_reg(REG_MCR, MCR_LOOPBACK);
+        self.write_reg(REG_DATA, 0xAE);
+        let echoed = (0..1000).any(|_| self.read_reg(REG_LSR) & LSR_DATA_READY != 0) && self.read_reg(REG_DATA) == 0xAE;
+        self.write_reg(REG_MCR, MCR_NORMAL);
+        echoed
+    }
+
+    /// Program the line settings, waiting for queued output first
+    pub fn configure(&self, config: Config) -> Result<(), &'static str> {
+        let divisor = config.divisor()?;
+        without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            self.drain(&mut inner);
+            self.write_reg(REG_IER, 0);
+            self.write_reg(REG_LCR, LCR_DLAB);
+            self.write_reg(REG_DIVISOR_LOW, divisor as u8);
+            self.write_reg(REG_DIVISOR_HIGH, (divisor >> 8) as u8);
+            self.write_reg(REG_LCR, config.lcr());
+            self.write_reg(REG_FCR, FCR_ENABLE_14);
+            self.write_reg(REG_MCR, MCR_NORMAL);
+            self.write_reg(REG_IER, inner.ier);
+            inner.config = config;
+        });
+        Ok(())
+    }
+
+    /// The current line settings
+    pub fn config(&self) -> Config {
+        without_interrupts(|| self.inner.lock().config)
+    }
+
+    /// Whether the port was found during initialization
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Acquire)
+    }
+
+    fn write_polled(&self, byte: u8) {
+        while self.read_reg(REG_LSR) & LSR_TX_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_reg(REG_DATA, byte);
+    }
+
+    /// Send everything queued by polling
+    fn drain(&self, inner: &mut Inner) {
+        while let Some(byte) = inner.tx.pop_front() {
+            self.write_polled(byte);
+        }
+    }
+
+    /// Queue bytes for the transmitter interrupt, sending synchronously
+    /// whenever the ring is full or interrupts are not set up yet
+    pub fn write(&self, bytes: &[u8]) {
+        without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            if !inner.irq_enabled {
+                bytes.iter().for_each(|&byte| self.write_polled(byte));
+                return;
+            }
+            for &byte in bytes {
+                if inner.tx.len() == TX_BUFFER_SIZE {
+                    self.drain(&mut inner);
+                }
+                inner.tx.push_back(byte);
+            }
+            // The UART interrupts right away if the transmitter is idle
+            if inner.ier & IER_TX_EMPTY == 0 {
+                inner.ier |= IER_TX_EMPTY;
+                self.write_reg(REG_IER, inner.ier);
+            }
+        });
+    }
+
+    /// Write bytes before returning, after anything already queued
+    pub fn write_sync(&self, bytes: &[u8]) {
+        without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            self.drain(&mut inner);
+            bytes.iter().for_each(|&byte| self.write_polled(byte));
+        });
+    }
+
+    /// Take received bytes without blocking
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            if !inner.irq_enabled {
+                // Nothing drains the receiver yet, so poll it directly
+                let mut len = 0;
+                while len < buf.len() && self.read_reg(REG_LSR) & LSR_DATA_READY != 0 {
+                    buf[len] = self.read_reg(REG_DATA);
+                    len += 1;
+                }
+                return len;
+            }
+            let mut len = 0;
+            while len < buf.len()
+                && let Some(byte) = inner.rx.pop_front()
+            {
+                buf[len] = byte;
+                len += 1;
+            }
+            len
+        })
+    }
+
+    /// Turn on receive interrupts
+    fn enable_irq(&self) {
+        without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            inner.irq_enabled = true;
+            inner.ier = IER_RX_AVAILABLE;
+            self.write_reg(REG_MCR, MCR_NORMAL);
+            self.write_reg(REG_IER, inner.ier);
+        });
+    }
+
+    /// Service every interrupt condition the port has pending
+    fn handle_interrupt(&self) {
+        let console = core::ptr::eq(self, com1());
+        let mut inner = self.inner.lock();
+        loop {
+            let iir = self.read_reg(REG_IIR);
+            if iir & IIR_NONE_PENDING != 0 {
+                break;
+            }
+            match iir & IIR_CAUSE_MASK {
+                IIR_RX_AVAILABLE | IIR_RX_TIMEOUT => {
+                    while self.read_reg(REG_LSR) & LSR_DATA_READY != 0 {
+                        let byte = self.read_reg(REG_DATA);
+                        inner.rx.push_back(byte);
+                        if console {
+                            inner.console.push_back(byte);
+                        }
+                    }
+                }
+                IIR_TX_EMPTY => {
+                    for _ in 0..TX_FIFO_SIZE {
+                        let Some(byte) = inner.tx.pop_front() else {
+                            break;
+                        };
+                        self.write_reg(REG_DATA, byte);
+                    }
+                    if inner.tx.is_empty() {
+                        inner.ier &= !IER_TX_EMPTY;
+                        self.write_reg(REG_IER, inner.ier);
+                    }
+                }
+                IIR_LINE_STATUS => {
+                    self.read_reg(REG_LSR);
+                }
+                IIR_MODEM_STATUS => {
+                    self.read_reg(REG_MSR);
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+/// The standard COM ports
+pub static PORTS: [Uart; 4] = [
+    Uart::new("ttyS0", 0x3F8, 4),
+    Uart::new("ttyS1", 0x2F8, 3),
+    Uart::new("ttyS2", 0x3E8, 4),
+    Uart::new("ttyS3", 0x2E8, 3),
+];
+
+/// The port used for kernel messages and the console
+pub fn com1() -> &'static Uart {
+    &PORTS[0]
+}
+
+/// Look up a port by device name
+pub fn find(name: &str) -> Option<&'static Uart> {
+    PORTS.iter().find(|port| port.name == name && port.is_present())
+}
+
+/// Set up COM1 for polled output, before anything else is initialized
+pub fn init_early() {
+    let com1 = com1();
+    com1.write_reg(REG_IER, 0);
+    com1.present.store(true, Ordering::Release);
+    let _ = com1.configure(Config::DEFAULT);
+}
+
+/// Find the other ports and switch every port to interrupt-driven I/O
+pub fn init() {
+    for port in &PORTS[1..] {
+        if port.probe() {
+            let _ = port.configure(Config::DEFAULT);
+            port.present.store(true, Ordering::Release);
+        }
+    }
+
+    for irq in [4, 3] {
+        let handler = if irq == 4 { handle_irq4 as fn() } else { handle_irq3 };
+        if let Err(e) = crate::interrupts::register_irq(irq, handler) {
+            crate::serial::print("UART: ");
+            crate::serial::print(e);
+            crate::serial::print("\n");
+        }
+    }
+
+    for port in PORTS.iter().filter(|port| port.is_present()) {
+        port.enable_irq();
+        super::device::register(port.name, Arc::new(SerialDevice(port)));
+
+        crate::serial::print("UART: ");
+        crate::serial::print(port.name);
+        crate::serial::print(" at ");
+        crate::memory::print_hex(port.base as u64);
+        crate::serial::print(", IRQ ");
+        crate::memory::print_decimal(port.irq as u64);
+        crate::serial::print("\n");
+    }
+}
+
+fn handle_irq4() {
+    handle_interrupt(4);
+}
+
+fn handle_irq3() {
+    handle_interrupt(3);
+}
+
+/// Service the ports sharing `irq`, then pass COM1 input to the console
+/// terminal
+fn handle_interrupt(irq: u8) {
+    for port in PORTS.iter().filter(|port| port.irq == irq && port.is_present()) {
+        port.handle_interrupt();
+    }
+
+    // The port lock is not held while the terminal runs, as its echo may
+    // be written back to COM1
+    loop {
+        let Some(byte) = com1().inner.lock().console.pop_front() else {
+            break;
+        };
+        crate::tty::input_byte(crate::tty::vt(0), byte);
+    }
+}
+
+/// `/dev/ttySn`: a COM port
+pub struct SerialDevice(&'static Uart);
+
+impl Device for SerialDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        Ok(self.0.read(buf))
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,402 @@
+//! Virtio devices on PCI
+//!
+//! Virtio devices are reached through one of two PCI transports. Legacy
+//! (and transitional) devices put their registers in an I/O BAR; modern
+//! devices describe memory-mapped register blocks with vendor-specific PCI
+//! capabilities. `VirtioDevice` hides the difference behind `Transport` and
+//! runs the common initialization sequence.
+
+pub mod queue;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::PhysAddr;
+use x86_64::instructions::port::Port;
+
+use crate::drivers::pci::{self, Bar, PciDevice};
+pub use queue::{Buffer, Virtqueue};
+
+/// PCI vendor ID of all virtio devices
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+/// Device status bits
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 0x80;
+
+/// Feature bit: the device follows virtio 1.0 rather than the legacy interface
+const F_VERSION_1: u64 = 1 << 32;
+
+/// Largest queue we allocate when the device lets us choose
+const MAX_QUEUE_SIZE: u16 = 128;
+
+/// Status polls while waiting for a reset to finish
+const RESET_POLLS: u32 = 1_000_000;
+
+/// Register access common to both transports
+pub trait Transport: Send + Sync {
+    /// Whether this is the legacy interface
+    fn is_legacy(&self) -> bool;
+
+    fn device_features(&self) -> u64;
+
+    fn set_driver_features(&self, features: u64);
+
+    fn status(&self) -> u8;
+
+    fn set_status(&self, status: u8);
+
+    /// Largest size queue `index` supports, 0 if it doesn't exist
+    fn max_queue_size(&self, index: u16) -> u16;
+
+    /// Hand a queue's rings to the device and enable it
+    fn enable_queue(&self, index: u16, queue: &Virtqueue);
+
+    /// Tell the device new buffers are available on queue `index`
+    fn notify(&self, index: u16);
+
+    /// Read a byte of the device-specific configuration
+    fn read_config(&self, offset: usize) -> u8;
+}
+
+/// Legacy registers in I/O space, relative to BAR0
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+/// Device configuration follows the common registers when MSI-X is off
+const LEGACY_CONFIG: u16 = 0x14;
+
+/// The legacy interface through an I/O BAR
+struct LegacyTransport {
+    port: u16,
+}
+
+impl LegacyTransport {
+    fn read8(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.port + reg).read() }
+    }
+
+    fn read16(&self, reg: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.port + reg).read() }
+    }
+
+    fn read32(&self, reg: u16) -> u32 {
+        unsafe { Port::<u32>::new(self.port + reg).read() }
+    }
+
+    fn write8(&self, reg: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.port + reg).write(value) }
+    }
+
+    fn write16(&self, reg: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.port + reg).write(value) }
+    }
+
+    fn write32(&self, reg: u16, value: u32) {
+        unsafe { Port::<u32>::new(self.port + reg).write(value) }
+    }
+}
+
+impl Transport for LegacyTransport {
+    fn is_legacy(&self) -> bool {
+        true
+    }
+
+    fn device_features(&self) -> u64 {
+        self.read32(LEGACY_DEVICE_FEATURES) as u64
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write32(LEGACY_DRIVER_FEATURES, features as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.read8(LEGACY_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.write8(LEGACY_STATUS, status);
+    }
+
+    fn max_queue_size(&self, index: u16) -> u16 {
+        self.write16(LEGACY_QUEUE_SELECT, index);
+        self.read16(LEGACY_QUEUE_SIZE)
+    }
+
+    fn enable_queue(&self, index: u16, queue: &Virtqueue) {
+        // The legacy queue size is fixed; the rings are found from one page number
+        self.write16(LEGACY_QUEUE_SELECT, index);
+        self.write32(LEGACY_QUEUE_PFN, (queue.desc_phys() >> 12) as u32);
+    }
+
+    fn notify(&self, index: u16) {
+        self.write16(LEGACY_QUEUE_NOTIFY, index);
+    }
+
+    fn read_config(&self, offset: usize) -> u8 {
+        self.read8(LEGACY_CONFIG + offset as u16)
+    }
+}
+
+/// Vendor capability configuration types
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_DEVICE_CFG: u8 = 4;
+
+/// Common configuration registers of the modern interface
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1E;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+/// The modern interface through memory-mapped register blocks
+struct ModernTransport {
+    common: usize,
+    notify: usize,
+    notify_multiplier: u32,
+    device: usize,
+    /// Notification offset of each enabled queue
+    notify_offsets: Mutex<Vec<u16>>,
+}
+
+impl ModernTransport {
+    fn read<T>(&self, base: usize, offset: usize) -> T {
+        unsafe { core::ptr::read_volatile((base + offset) as *const T) }
+    }
+
+    fn write<T>(&self, base: usize, offset: usize, value: T) {
+        unsafe { core::ptr::write_volatile((base + offset) as *mut T, value) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        // 64-bit fields are written as two dwords, low half first
+        self.write::<u32>(self.common, offset, value as u32);
+        self.write::<u32>(self.common, offset + 4, (value >> 32) as u32);
+    }
+}
+
+impl Transport for ModernTransport {
+    fn is_legacy(&self) -> bool {
+        false
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write::<u32>(self.common, COMMON_DEVICE_FEATURE_SELECT, 0);
+        let low = self.read::<u32>(self.common, COMMON_DEVICE_FEATURE);
+        self.write::<u32>(self.common, COMMON_DEVICE_FEATURE_SELECT, 1);
+        let high = self.read::<u32>(self.common, COMMON_DEVICE_FEATURE);
+        (high as u64) << 32 | low as u64
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write::<u32>(self.common, COMMON_DRIVER_FEATURE_SELECT, 0);
+        self.write::<u32>(self.common, COMMON_DRIVER_FEATURE, features as u32);
+        self.write::<u32>(self.common, COMMON_DRIVER_FEATURE_SELECT, 1);
+        self.write::<u32>(self.common, COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.read(self.common, COMMON_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.write(self.common, COMMON_STATUS, status);
+    }
+
+    fn max_queue_size(&self, index: u16) -> u16 {
+        self.write(self.common, COMMON_QUEUE_SELECT, index);
+        self.read(self.common, COMMON_QUEUE_SIZE)
+    }
+
+    fn enable_queue(&self, index: u16, queue: &Virtqueue) {
+        self.write(self.common, COMMON_QUEUE_SELECT, index);
+        self.write(self.common, COMMON_QUEUE_SIZE, queue.size());
+        self.write64(COMMON_QUEUE_DESC, queue.desc_phys());
+        self.write64(COMMON_QUEUE_DRIVER, queue.avail_phys());
+        self.write64(COMMON_QUEUE_DEVICE, queue.used_phys());
+
+        let notify_off = self.read::<u16>(self.common, COMMON_QUEUE_NOTIFY_OFF);
+        let mut offsets = self.notify_offsets.lock();
+        if offsets.len() <= index as usize {
+            offsets.resize(index as usize + 1, 0);
+        }
+        offsets[index as usize] = notify_off;
+        self.write::<u16>(self.common, COMMON_QUEUE_ENABLE, 1);
+    }
+
+    fn notify(&self, index: u16) {
+        let notify_off = self.notify_offsets.lock()[index as usize];
+        let offset = notify_off as usize * self.notify_multiplier as usize;
+        self.write(self.notify, offset, index);
+    }
+
+    fn read_config(&self, offset: usize) -> u8 {
+        self.read(self.device, offset)
+    }
+}
+
+/// Map the register block a vendor capability at `cap` points to
+fn map_capability(dev: &PciDevice, cap: u16) -> Result<usize, &'static str> {
+    let bar = dev.read_u8(cap + 4) as usize;
+    let offset = dev.read(cap + 8) as u64;
+    let length = dev.read(cap + 12) as u64;
+    if bar > 5 {
+        return Err("capability names a bad BAR");
+    }
+    let Bar::Memory { addr, .. } = dev.bar(bar) else {
+        return Err("capability BAR is not memory");
+    };
+    let virt = crate::memory::paging::map_mmio(PhysAddr::new(addr + offset), length)?;
+    Ok(virt.as_u64() as usize)
+}
+
+/// Set up the modern transport if the device has the vendor capabilities
+fn modern_transport(dev: &PciDevice) -> Result<Option<ModernTransport>, &'static str> {
+    let (mut common, mut notify, mut device) = (None, None, None);
+    // The first capability of each type is the preferred one
+    for (id, cap) in dev.capabilities() {
+        if id != pci::CAP_VENDOR {
+            continue;
+        }
+        let slot = match dev.read_u8(cap + 3) {
+            CAP_COMMON_CFG => &mut common,
+            CAP_NOTIFY_CFG => &mut notify,
+            CAP_DEVICE_CFG => &mut device,
+            _ => continue,
+        };
+        if slot.is_none() {
+            *slot = Some(cap);
+        }
+    }
+    let (Some(common), Some(notify), Some(device)) = (common, notify, device) else {
+        return Ok(None);
+    };
+
+    Ok(Some(ModernTransport {
+        common: map_capability(dev, common)?,
+        notify: map_capability(dev, notify)?,
+        notify_multiplier: dev.read(notify + 16),
+        device: map_capability(dev, device)?,
+        notify_offsets: Mutex::new(Vec::new()),
+    }))
+}
+
+/// A virtio device being driven through either transport
+pub struct VirtioDevice {
+    transport: Box<dyn Transport>,
+}
+
+impl VirtioDevice {
+    /// Pick a transport for a virtio PCI function and reset the device
+    pub fn new(dev: &PciDevice) -> Result<Self, &'static str> {
+        dev.enable(pci::COMMAND_IO_SPACE | pci::COMMAND_MEMORY_SPACE | pci::COMMAND_BUS_MASTER);
+
+        let transport: Box<dyn Transport> = match modern_transport(dev)? {
+            Some(modern) => Box::new(modern),
+            // Device IDs from 0x1040 are modern-only
+            None => match dev.bar(0) {
+                Bar::Io { port, .. } if dev.device_id < 0x1040 => Box::new(LegacyTransport { port }),
+                _ => return Err("no usable virtio transport"),
+            },
+        };
+        let device = Self { transport };
+        device.reset();
+        device.transport.set_status(STATUS_ACKNOWLEDGE);
+        device.transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        Ok(device)
+    }
+
+    /// Reset the device; afterwards it no longer touches its queues
+    ///
+    /// Modern devices may take a while and are done once the status reads
+    /// back as zero.
+    pub fn reset(&self) {
+        self.transport.set_status(0);
+        for _ in 0..RESET_POLLS {
+            if self.transport.status() == 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.transport.is_legacy()
+    }
+
+    /// Accept the offered subset of `wanted` device feature bits
+    ///
+    /// Returns the negotiated device features.
+    pub fn negotiate(&self, wanted: u64) -> Result<u64, &'static str> {
+        let offered = self.transport.device_features();
+        let mut features = offered & wanted;
+        if self.is_legacy() {
+            // Legacy devices have only 32 feature bits and no FEATURES_OK step
+            self.transport.set_driver_features(features & 0xFFFF_FFFF);
+            return Ok(features & 0xFFFF_FFFF);
+        }
+
+        if offered & F_VERSION_1 == 0 {
+            return Err("device does not offer VERSION_1");
+        }
+        features |= F_VERSION_1;
+        self.transport.set_driver_features(features);
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        self.transport.set_status(status);
+        if self.transport.status() & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            return Err("device rejected features");
+        }
+        Ok(features & !F_VERSION_1)
+    }
+
+    /// Allocate queue `index` and hand it to the device
+    pub fn setup_queue(&self, index: u16) -> Result<Virtqueue, &'static str> {
+        let max = self.transport.max_queue_size(index);
+        if max == 0 {
+            return Err("queue does not exist");
+        }
+        // Legacy devices only accept their own queue size
+        let size = if self.is_legacy() { max } else { max.min(MAX_QUEUE_SIZE) };
+        let queue = Virtqueue::new(size).ok_or("out of DMA memory")?;
+        self.transport.enable_queue(index, &queue);
+        Ok(queue)
+    }
+
+    /// Finish initialization; the device may now use its queues
+    pub fn driver_ok(&self) {
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_DRIVER_OK);
+    }
+
+    /// Give up on the device
+    pub fn fail(&self) {
+        let status = self.transport.status();
+        self.transport.set_status(status | STATUS_FAILED);
+    }
+
+    pub fn notify(&self, index: u16) {
+        self.transport.notify(index);
+    }
+
+    /// Read a little-endian field of the device-specific configuration
+    pub fn config_u64(&self, offset: usize) -> u64 {
+        let bytes = core::array::from_fn(|i| self.transport.read_config(offset + i));
+        u64::from_le_bytes(bytes)
+    }
+}
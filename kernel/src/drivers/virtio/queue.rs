@@ -0,0 +1,190 @@
+//! Split virtqueues
+//!
+//! A queue is three rings in one DMA buffer: the descriptor table, the
+//! available ring the driver fills and the used ring the device returns
+//! completed chains on. The layout follows the legacy interface (used ring
+//! on its own page) so the same queue works with both transports.
+
+use core::sync::atomic::{Ordering, fence};
+
+use crate::memory::dma::DmaBuffer;
+
+/// Descriptor flags
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Available ring flag: the driver polls and wants no interrupts
+///
+/// Always set: drivers poll the used ring, as the AHCI and NVMe drivers poll
+/// their completion queues. An interrupt would have nothing to wake, since a
+/// task cannot sleep (the scheduler never switches away from a running task),
+/// so the caller would spin on the same CPU either way, now with a handler
+/// racing it for the used ring. There is no reliable line to claim either:
+/// in APIC mode the GSI of a PCI INTx pin is only known from the ACPI `_PRT`,
+/// which is not parsed, and MSI-X would need vectors beyond the IRQ lines
+/// `register_irq` hands out. Revisit once tasks can block on I/O.
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+const DESC_SIZE: usize = 16;
+
+/// Alignment of the used ring required by legacy devices
+const USED_ALIGN: usize = 4096;
+
+/// One buffer of a descriptor chain
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub phys: u64,
+    pub len: u32,
+    /// Written by the device rather than read by it
+    pub writable: bool,
+}
+
+/// A split virtqueue
+pub struct Virtqueue {
+    memory: DmaBuffer,
+    size: u16,
+    avail_offset: usize,
+    used_offset: usize,
+    /// Head of the free descriptor list, linked through `next`
+    free_head: u16,
+    num_free: u16,
+    /// Our copy of the available index
+    avail_idx: u16,
+    /// Used ring entries consumed so far
+    last_used: u16,
+}
+
+impl Virtqueue {
+    /// Allocate a queue of `size` descriptors (a power of two)
+    pub fn new(size: u16) -> Option<Self> {
+        let n = size as usize;
+        let avail_offset = n * DESC_SIZE;
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(USED_ALIGN);
+        let memory = DmaBuffer::new(used_offset + 6 + 8 * n)?;
+
+        let queue = Self {
+            memory,
+            size,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+        };
+        for i in 0..size {
+            queue.write_desc(i, 0, 0, 0, i.wrapping_add(1));
+        }
+        unsafe {
+            queue.memory.ptr::<u16>(avail_offset).write_volatile(AVAIL_F_NO_INTERRUPT);
+        }
+        Some(queue)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Physical addresses of the descriptor table, available ring and used ring
+    pub fn desc_phys(&self) -> u64 {
+        self.memory.phys()
+    }
+
+    pub fn avail_phys(&self) -> u64 {
+        self.memory.phys() + self.avail_offset as u64
+    }
+
+    pub fn used_phys(&self) -> u64 {
+        self.memory.phys() + self.used_offset as u64
+    }
+
+    fn write_desc(&self, index: u16, addr: u64, len: u32, flags: u16, next: u16) {
+        let desc = self.memory.ptr::<u8>(index as usize * DESC_SIZE);
+        unsafe {
+            (desc as *mut u64).write_volatile(addr);
+            (desc.add(8) as *mut u32).write_volatile(len);
+            (desc.add(12) as *mut u16).write_volatile(flags);
+            (desc.add(14) as *mut u16).write_volatile(next);
+        }
+    }
+
+    fn desc_flags(&self, index: u16) -> u16 {
+        unsafe { self.memory.ptr::<u16>(index as usize * DESC_SIZE + 12).read_volatile() }
+    }
+
+    fn desc_next(&self, index: u16) -> u16 {
+        unsafe { self.memory.ptr::<u16>(index as usize * DESC_SIZE + 14).read_volatile() }
+    }
+
+    /// Post a descriptor chain, returning its head, or `None` if the queue is full
+    ///
+    /// The device is not told; call the transport's notify afterwards.
+    pub fn add(&mut self, chain: &[Buffer]) -> Option<u16> {
+        if chain.is_empty() || chain.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut index = head;
+        for (i, buffer) in chain.iter().enumerate() {
+            let next = self.desc_next(index);
+            let mut flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < chain.len() {
+                flags |= DESC_F_NEXT;
+            }
+            self.write_desc(index, buffer.phys, buffer.len, flags, next);
+            if i + 1 < chain.len() {
+                index = next;
+            } else {
+                self.free_head = next;
+            }
+        }
+        self.num_free -= chain.len() as u16;
+
+        let slot = self.avail_offset + 4 + 2 * (self.avail_idx % self.size) as usize;
+        unsafe {
+            self.memory.ptr::<u16>(slot).write_volatile(head);
+        }
+        // The ring entry must be visible before the index that publishes it
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe {
+            self.memory.ptr::<u16>(self.avail_offset + 2).write_volatile(self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    /// Take the next completed chain off the used ring
+    ///
+    /// Returns its head and the number of bytes the device wrote; the chain's
+    /// descriptors go back on the free list.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { self.memory.ptr::<u16>(self.used_offset + 2).read_volatile() };
+        if used_idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let elem = self.used_offset + 4 + 8 * (self.last_used % self.size) as usize;
+        let (id, len) = unsafe {
+            (
+                self.memory.ptr::<u32>(elem).read_volatile(),
+                self.memory.ptr::<u32>(elem + 4).read_volatile(),
+            )
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // Walk to the tail and splice the whole chain onto the free list
+        let head = id as u16;
+        let mut tail = head;
+        let mut count = 1;
+        while self.desc_flags(tail) & DESC_F_NEXT != 0 {
+            tail = self.desc_next(tail);
+            count += 1;
+        }
+        self.write_desc(tail, 0, 0, 0, self.free_head);
+        self.free_head = head;
+        self.num_free += count;
+        Some((head, len))
+    }
+}
@@ -0,0 +1,152 @@
+//! devfs: registered devices as files under `/dev`
+//!
+//! Character devices come from `drivers::device` and block devices from
+//! `drivers::block`. The directory is generated on every lookup, so devices
+//! registered after mounting appear automatically.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::device::{self, Device};
+
+/// Inode numbers: root is 1, then character devices, then block devices
+const ROOT_INO: u64 = 1;
+const CHAR_INO_BASE: u64 = 2;
+const BLOCK_INO_BASE: u64 = 0x1000;
+
+/// The devfs filesystem
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DevRoot)
+    }
+}
+
+/// The `/dev` directory itself
+struct DevRoot;
+
+impl Inode for DevRoot {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(ROOT_INO, FileType::Directory, 0o755, 0))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        if let Some(index) = device::names().iter().position(|dev| dev == name) {
+            let dev = device::get(name).ok_or(FsError::NotFound)?;
+            return Ok(Arc::new(CharNode {
+                ino: CHAR_INO_BASE + index as u64,
+                dev,
+            }));
+        }
+        if let Some(index) = block::names().iter().position(|dev| dev == name) {
+            let dev = block::get(name).ok_or(FsError::NotFound)?;
+            return Ok(Arc::new(BlockNode {
+                ino: BLOCK_INO_BASE + index as u64,
+                dev,
+            }));
+        }
+        Err(FsError::NotFound)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let chars = device::names().into_iter().enumerate().map(|(index, name)| DirEntry {
+            name,
+            inode: CHAR_INO_BASE + index as u64,
+            file_type: FileType::CharDevice,
+        });
+        let blocks = block::names().into_iter().enumerate().map(|(index, name)| DirEntry {
+            name,
+            inode: BLOCK_INO_BASE + index as u64,
+            file_type: FileType::BlockDevice,
+        });
+        Ok(chars.chain(blocks).collect())
+    }
+}
+
+/// A character device node
+struct CharNode {
+    ino: u64,
+    dev: Arc<dyn Device>,
+}
+
+impl Inode for CharNode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(self.ino, FileType::CharDevice, 0o666, self.dev.size()))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        Ok(self.dev.read(offset, buf)?)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        Ok(self.dev.write(offset, buf)?)
+    }
+}
+
+/// A block device node, addressed in bytes
+///
+/// Unaligned requests go through a one-sector bounce buffer.
+struct BlockNode {
+    ino: u64,
+    dev: Arc<dyn BlockDevice>,
+}
+
+impl BlockNode {
+    /// Split a byte request into the sector, offset within it and length to copy
+    fn chunk(&self, offset: u64, remaining: usize) -> Option<(u64, usize, usize)> {
+        if offset >= self.dev.size() {
+            return None;
+        }
+        let lba = offset / SECTOR_SIZE as u64;
+        let within = (offset % SECTOR_SIZE as u64) as usize;
+        Some((lba, within, remaining.min(SECTOR_SIZE - within)))
+    }
+}
+
+impl Inode for BlockNode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(self.ino, FileType::BlockDevice, 0o660, self.dev.size()))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let Some((lba, within, len)) = self.chunk(offset + done as u64, buf.len() - done)
+            else {
+                break;
+            };
+            self.dev.read_sectors(lba, &mut sector)?;
+            buf[done..done + len].copy_from_slice(&sector[within..within + len]);
+            done += len;
+        }
+        Ok(done)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let Some((lba, within, len)) = self.chunk(offset + done as u64, buf.len() - done)
+            else {
+                break;
+            };
+            // Partial sectors keep the bytes around the written range
+            if len < SECTOR_SIZE {
+                self.dev.read_sectors(lba, &mut sector)?;
+            }
+            sector[within..within + len].copy_from_slice(&buf[done..done + len]);
+            self.dev.write_sectors(lba, &sector)?;
+            done += len;
+        }
+        Ok(done)
+    }
+}
+
@@ -0,0 +1,398 @@
+//! ext2 on-disk structures
+//!
+//! All fields are little-endian. Structures are decoded from and encoded
+//! into raw byte buffers so unknown fields are preserved on write-back.
+
+/// Superblock magic number
+pub const EXT2_MAGIC: u16 = 0xEF53;
+
+/// Byte offset of the superblock from the start of the device
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+
+/// Size of the superblock on disk
+pub const SUPERBLOCK_SIZE: usize = 1024;
+
+/// Inode number of the root directory
+pub const ROOT_INO: u32 = 2;
+
+/// Size of a block group descriptor
+pub const GROUP_DESC_SIZE: usize = 32;
+
+/// Direct block pointers in an inode
+pub const DIRECT_BLOCKS: usize = 12;
+
+/// Slot of the singly, doubly and triply indirect block pointers
+pub const IND_BLOCK: usize = 12;
+pub const DIND_BLOCK: usize = 13;
+pub const TIND_BLOCK: usize = 14;
+
+/// Incompatible feature: directory entries record the file type
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+/// Read-only compatible features we can safely write
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+pub const RO_COMPAT_BTREE_DIR: u32 = 0x0004;
+
+/// Inode flag: directory uses hashed b-tree indexing
+pub const INDEX_FL: u32 = 0x1000;
+
+/// File type bits of `i_mode`
+pub const S_IFMT: u16 = 0xF000;
+pub const S_IFLNK: u16 = 0xA000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFBLK: u16 = 0x6000;
+pub const S_IFDIR: u16 = 0x4000;
+pub const S_IFCHR: u16 = 0x2000;
+
+/// File type codes stored in directory entries
+pub const FT_UNKNOWN: u8 = 0;
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_CHRDEV: u8 = 3;
+pub const FT_BLKDEV: u8 = 4;
+pub const FT_SYMLINK: u8 = 7;
+
+#[inline]
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+#[inline]
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+#[inline]
+pub fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[inline]
+pub fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The fields of the superblock the driver uses
+#[derive(Debug, Clone)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    pub log_block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub mtime: u32,
+    pub wtime: u32,
+    pub mnt_count: u16,
+    pub magic: u16,
+    pub state: u16,
+    pub rev_level: u32,
+    pub first_ino: u32,
+    pub inode_size: u16,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    pub volume_name: [u8; 16],
+}
+
+impl Superblock {
+    /// Decode a superblock from its 1024-byte on-disk form
+    pub fn parse(buf: &[u8]) -> Self {
+        let rev_level = read_u32(buf, 76);
+        let (first_ino, inode_size) = if rev_level == 0 {
+            (11, 128)
+        } else {
+            (read_u32(buf, 84), read_u16(buf, 88))
+        };
+
+        let mut volume_name = [0u8; 16];
+        volume_name.copy_from_slice(&buf[120..136]);
+
+        Self {
+            inodes_count: read_u32(buf, 0),
+            blocks_count: read_u32(buf, 4),
+            free_blocks_count: read_u32(buf, 12),
+            free_inodes_count: read_u32(buf, 16),
+            first_data_block: read_u32(buf, 20),
+            log_block_size: read_u32(buf, 24),
+            blocks_per_group: read_u32(buf, 32),
+            inodes_per_group: read_u32(buf, 40),
+            mtime: read_u32(buf, 44),
+            wtime: read_u32(buf, 48),
+            mnt_count: read_u16(buf, 52),
+            magic: read_u16(buf, 56),
+            state: read_u16(buf, 58),
+            rev_level,
+            first_ino,
+            inode_size,
+            feature_compat: read_u32(buf, 92),
+            feature_incompat: read_u32(buf, 96),
+            feature_ro_compat: read_u32(buf, 100),
+            volume_name,
+        }
+    }
+
+    /// Encode the fields the driver may modify back into `buf`
+    pub fn write_to(&self, buf: &mut [u8]) {
+        write_u32(buf, 12, self.free_blocks_count);
+        write_u32(buf, 16, self.free_inodes_count);
+        write_u32(buf, 44, self.mtime);
+        write_u32(buf, 48, self.wtime);
+        write_u16(buf, 52, self.mnt_count);
+        write_u16(buf, 58, self.state);
+    }
+
+    /// Block size in bytes
+    pub fn block_size(&self) -> usize {
+        1024 << self.log_block_size
+    }
+
+    /// Number of block groups on the filesystem, or `None` if the block
+    /// counts are inconsistent
+    pub fn group_count(&self) -> Option<u32> {
+        let blocks = self.blocks_count.checked_sub(self.first_data_block).filter(|&blocks| blocks != 0)?;
+        Some(blocks.div_ceil(self.blocks_per_group))
+    }
+
+    /// Volume label without trailing NULs
+    pub fn volume_name(&self) -> &str {
+        let len = self.volume_name.iter().position(|&b| b == 0).unwrap_or(16);
+        core::str::from_utf8(&self.volume_name[..len]).unwrap_or("")
+    }
+}
+
+/// A block group descriptor
+#[derive(Debug, Clone, Copy)]
+pub struct GroupDesc {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+}
+
+impl GroupDesc {
+    pub fn parse(buf: &[u8]) -> Self {
+        Self {
+            block_bitmap: read_u32(buf, 0),
+            inode_bitmap: read_u32(buf, 4),
+            inode_table: read_u32(buf, 8),
+            free_blocks_count: read_u16(buf, 12),
+            free_inodes_count: read_u16(buf, 14),
+            used_dirs_count: read_u16(buf, 16),
+        }
+    }
+
+    pub fn write_to(&self, buf: &mut [u8]) {
+        write_u32(buf, 0, self.block_bitmap);
+        write_u32(buf, 4, self.inode_bitmap);
+        write_u32(buf, 8, self.inode_table);
+        write_u16(buf, 12, self.free_blocks_count);
+        write_u16(buf, 14, self.free_inodes_count);
+        write_u16(buf, 16, self.used_dirs_count);
+    }
+}
+
+/// The first 128 bytes of an on-disk inode
+#[derive(Debug, Clone)]
+pub struct RawInode {
+    pub mode: u16,
+    pub uid: u16,
+    pub size: u32,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub gid: u16,
+    pub links_count: u16,
+    /// Allocated space in 512-byte units
+    pub blocks: u32,
+    pub flags: u32,
+    pub block: [u32; 15],
+    pub file_acl: u32,
+    /// Upper 32 bits of the size for regular files
+    pub size_high: u32,
+    pub uid_high: u16,
+    pub gid_high: u16,
+}
+
+impl RawInode {
+    /// A zeroed inode with the given mode
+    pub fn new(mode: u16, now: u32) -> Self {
+        Self {
+            mode,
+            uid: 0,
+            size: 0,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            gid: 0,
+            links_count: 1,
+            blocks: 0,
+            flags: 0,
+            block: [0; 15],
+            file_acl: 0,
+            size_high: 0,
+            uid_high: 0,
+            gid_high: 0,
+        }
+    }
+
+    pub fn parse(buf: &[u8]) -> Self {
+        let mut block = [0u32; 15];
+        for (i, ptr) in block.iter_mut().enumerate() {
+            *ptr = read_u32(buf, 40 + i * 4);
+        }
+
+        Self {
+            mode: read_u16(buf, 0),
+            uid: read_u16(buf, 2),
+            size: read_u32(buf, 4),
+            atime: read_u32(buf, 8),
+            ctime: read_u32(buf, 12),
+            mtime: read_u32(buf, 16),
+            dtime: read_u32(buf, 20),
+            gid: read_u16(buf, 24),
+            links_count: read_u16(buf, 26),
+            blocks: read_u32(buf, 28),
+            flags: read_u32(buf, 32),
+            block,
+            file_acl: read_u32(buf, 104),
+            size_high: read_u32(buf, 108),
+            uid_high: read_u16(buf, 120),
+            gid_high: read_u16(buf, 122),
+        }
+    }
+
+    pub fn write_to(&self, buf: &mut [u8]) {
+        write_u16(buf, 0, self.mode);
+        write_u16(buf, 2, self.uid);
+        write_u32(buf, 4, self.size);
+        write_u32(buf, 8, self.atime);
+        write_u32(buf, 12, self.ctime);
+        write_u32(buf, 16, self.mtime);
+        write_u32(buf, 20, self.dtime);
+        write_u16(buf, 24, self.gid);
+        write_u16(buf, 26, self.links_count);
+        write_u32(buf, 28, self.blocks);
+        write_u32(buf, 32, self.flags);
+        for (i, ptr) in self.block.iter().enumerate() {
+            write_u32(buf, 40 + i * 4, *ptr);
+        }
+        write_u32(buf, 104, self.file_acl);
+        write_u32(buf, 108, self.size_high);
+        write_u16(buf, 120, self.uid_high);
+        write_u16(buf, 122, self.gid_high);
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    /// File size in bytes; only regular files use the high word
+    pub fn file_size(&self) -> u64 {
+        if self.mode & S_IFMT == S_IFREG {
+            self.size as u64 | ((self.size_high as u64) << 32)
+        } else {
+            self.size as u64
+        }
+    }
+
+    pub fn set_file_size(&mut self, size: u64) {
+        self.size = size as u32;
+        if self.mode & S_IFMT == S_IFREG {
+            self.size_high = (size >> 32) as u32;
+        }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid as u32 | ((self.uid_high as u32) << 16)
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid as u32 | ((self.gid_high as u32) << 16)
+    }
+}
+
+/// Length of a directory entry header before the name
+pub const DIRENT_HEADER: usize = 8;
+
+/// Space a directory entry with a name of `name_len` bytes occupies
+pub fn dirent_size(name_len: usize) -> usize {
+    (DIRENT_HEADER + name_len + 3) & !3
+}
+
+/// A decoded directory entry header
+#[derive(Debug, Clone, Copy)]
+pub struct DirentHeader {
+    pub inode: u32,
+    pub rec_len: u16,
+    pub name_len: u8,
+    pub file_type: u8,
+}
+
+impl DirentHeader {
+    pub fn parse(buf: &[u8]) -> Self {
+        Self {
+            inode: read_u32(buf, 0),
+            rec_len: read_u16(buf, 4),
+            name_len: buf[6],
+            file_type: buf[7],
+        }
+    }
+
+    pub fn write_to(&self, buf: &mut [u8]) {
+        write_u32(buf, 0, self.inode);
+        write_u16(buf, 4, self.rec_len);
+        buf[6] = self.name_len;
+        buf[7] = self.file_type;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inode_roundtrip() {
+        let mut raw = RawInode::new(S_IFREG | 0o644, 1234);
+        raw.block[0] = 42;
+        raw.set_file_size(0x1_0000_0010);
+
+        let mut buf = [0u8; 128];
+        raw.write_to(&mut buf);
+        let parsed = RawInode::parse(&buf);
+
+        assert_eq!(parsed.mode, S_IFREG | 0o644);
+        assert_eq!(parsed.block[0], 42);
+        assert_eq!(parsed.file_size(), 0x1_0000_0010);
+    }
+
+    #[test]
+    fn test_group_count_rejects_bad_counts() {
+        let mut buf = [0u8; SUPERBLOCK_SIZE];
+        write_u32(&mut buf, 4, 8193); // blocks_count
+        write_u32(&mut buf, 20, 1); // first_data_block
+        write_u32(&mut buf, 32, 4096); // blocks_per_group
+        assert_eq!(Superblock::parse(&buf).group_count(), Some(2));
+
+        write_u32(&mut buf, 20, 9000);
+        assert_eq!(Superblock::parse(&buf).group_count(), None);
+    }
+
+    #[test]
+    fn test_dirent_size_is_aligned() {
+        assert_eq!(dirent_size(1), 12);
+        assert_eq!(dirent_size(4), 12);
+        assert_eq!(dirent_size(5), 16);
+    }
+}
@@ -0,0 +1,758 @@
+//! ext2 inodes: block mapping, file data and directory operations
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::disk::{self, DirentHeader, RawInode};
+use super::{Ext2Fs, State};
+use crate::fs::{DirEntry, FileType, FsError, FsResult, Inode, Metadata};
+
+/// Longest symlink target stored inline in the block pointers
+const FAST_SYMLINK_MAX: usize = 60;
+
+/// An inode on a mounted ext2 filesystem
+pub struct Ext2Inode {
+    fs: Arc<Ext2Fs>,
+    ino: u32,
+}
+
+/// Where a logical block's pointer lives: slot in `i_block`, then an index
+/// into each level of indirect blocks
+struct BlockPath {
+    slot: usize,
+    offsets: [usize; 3],
+    depth: usize,
+}
+
+/// A directory entry with its position inside the directory
+struct LocatedEntry {
+    block_index: u32,
+    offset: usize,
+    header: DirentHeader,
+    name: String,
+}
+
+fn mode_to_file_type(mode: u16) -> FileType {
+    match mode & disk::S_IFMT {
+        disk::S_IFDIR => FileType::Directory,
+        disk::S_IFLNK => FileType::Symlink,
+        disk::S_IFCHR => FileType::CharDevice,
+        disk::S_IFBLK => FileType::BlockDevice,
+        _ => FileType::Regular,
+    }
+}
+
+fn dirent_code_to_file_type(code: u8) -> Option<FileType> {
+    match code {
+        disk::FT_REG_FILE => Some(FileType::Regular),
+        disk::FT_DIR => Some(FileType::Directory),
+        disk::FT_SYMLINK => Some(FileType::Symlink),
+        disk::FT_CHRDEV => Some(FileType::CharDevice),
+        disk::FT_BLKDEV => Some(FileType::BlockDevice),
+        _ => None,
+    }
+}
+
+fn file_type_to_dirent_code(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::Regular => disk::FT_REG_FILE,
+        FileType::Directory => disk::FT_DIR,
+        FileType::Symlink => disk::FT_SYMLINK,
+        FileType::CharDevice => disk::FT_CHRDEV,
+        FileType::BlockDevice => disk::FT_BLKDEV,
+    }
+}
+
+impl Ext2Inode {
+    pub(super) fn new(fs: Arc<Ext2Fs>, ino: u32) -> Self {
+        Self { fs, ino }
+    }
+
+    /// Work out where the pointer for logical block `index` is stored
+    fn block_path(&self, index: u64) -> FsResult<BlockPath> {
+        let p = self.fs.ptrs_per_block();
+        let mut i = index;
+
+        if i < disk::DIRECT_BLOCKS as u64 {
+            return Ok(BlockPath { slot: i as usize, offsets: [0; 3], depth: 0 });
+        }
+        i -= disk::DIRECT_BLOCKS as u64;
+
+        if i < p {
+            return Ok(BlockPath { slot: disk::IND_BLOCK, offsets: [i as usize, 0, 0], depth: 1 });
+        }
+        i -= p;
+
+        if i < p * p {
+            return Ok(BlockPath {
+                slot: disk::DIND_BLOCK,
+                offsets: [(i / p) as usize, (i % p) as usize, 0],
+                depth: 2,
+            });
+        }
+        i -= p * p;
+
+        if i < p * p * p {
+            return Ok(BlockPath {
+                slot: disk::TIND_BLOCK,
+                offsets: [(i / (p * p)) as usize, ((i / p) % p) as usize, (i % p) as usize],
+                depth: 3,
+            });
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    /// Whether a symlink stores its target inline in the block pointers
+    fn is_fast_symlink(&self, inode: &RawInode) -> bool {
+        let acl_blocks = if inode.file_acl != 0 { (self.fs.block_size / 512) as u32 } else { 0 };
+        inode.is_symlink() && inode.blocks == acl_blocks && (inode.file_size() as usize) < FAST_SYMLINK_MAX
+    }
+
+    /// Map a logical block to a physical block, or 0 for a hole
+    fn map_block(&self, inode: &RawInode, index: u64) -> FsResult<u32> {
+        let path = self.block_path(index)?;
+        let mut ptr = inode.block[path.slot];
+        let mut buf = vec![0u8; self.fs.block_size];
+
+        for &offset in &path.offsets[..path.depth] {
+            if ptr == 0 {
+                return Ok(0);
+            }
+            self.fs.read_block(ptr, &mut buf)?;
+            ptr = disk::read_u32(&buf, offset * 4);
+        }
+        Ok(ptr)
+    }
+
+    /// Map a logical block, allocating it and any indirect blocks on the way
+    fn map_block_alloc(&self, st: &mut State, inode: &mut RawInode, index: u64) -> FsResult<u32> {
+        let path = self.block_path(index)?;
+        let goal = self.fs.inode_group(st, self.ino);
+        let sectors_per_block = (self.fs.block_size / 512) as u32;
+
+        if inode.block[path.slot] == 0 {
+            inode.block[path.slot] = self.fs.alloc_block(st, goal)?;
+            inode.blocks += sectors_per_block;
+        }
+
+        let mut ptr = inode.block[path.slot];
+        let mut buf = vec![0u8; self.fs.block_size];
+
+        for &offset in &path.offsets[..path.depth] {
+            self.fs.read_block(ptr, &mut buf)?;
+            let entry_offset = offset * 4;
+            let mut next = disk::read_u32(&buf, entry_offset);
+
+            if next == 0 {
+                next = self.fs.alloc_block(st, goal)?;
+                inode.blocks += sectors_per_block;
+                disk::write_u32(&mut buf, entry_offset, next);
+                self.fs.write_block(ptr, &buf)?;
+            }
+            ptr = next;
+        }
+        Ok(ptr)
+    }
+
+    /// Free a block tree, keeping only its first `keep` leaf blocks
+    ///
+    /// `depth` is 0 for a data block. Returns whether `block` itself was
+    /// freed, in which case the caller must clear its pointer.
+    fn truncate_tree(&self, st: &mut State, inode: &mut RawInode, block: u32, depth: usize, keep: u64) -> FsResult<bool> {
+        if block == 0 {
+            return Ok(false);
+        }
+        let sectors_per_block = (self.fs.block_size / 512) as u32;
+
+        if depth > 0 {
+            let p = self.fs.ptrs_per_block();
+            let span = p.pow(depth as u32 - 1);
+            let mut buf = vec![0u8; self.fs.block_size];
+            self.fs.read_block(block, &mut buf)?;
+            let mut changed = false;
+
+            for i in 0..p {
+                let child_start = i * span;
+                if child_start + span <= keep {
+                    continue;
+                }
+                let child = disk::read_u32(&buf, i as usize * 4);
+                let child_keep = keep.saturating_sub(child_start);
+                if self.truncate_tree(st, inode, child, depth - 1, child_keep)? {
+                    disk::write_u32(&mut buf, i as usize * 4, 0);
+                    changed = true;
+                }
+            }
+
+            if keep > 0 {
+                if changed {
+                    self.fs.write_block(block, &buf)?;
+                }
+                return Ok(false);
+            }
+        } else if keep > 0 {
+            return Ok(false);
+        }
+
+        self.fs.free_block(st, block)?;
+        inode.blocks = inode.blocks.saturating_sub(sectors_per_block);
+        Ok(true)
+    }
+
+    /// Release all data blocks beyond the first `keep` logical blocks
+    fn free_blocks_from(&self, st: &mut State, inode: &mut RawInode, keep: u64) -> FsResult<()> {
+        let p = self.fs.ptrs_per_block();
+
+        for slot in 0..disk::DIRECT_BLOCKS {
+            let ptr = inode.block[slot];
+            if slot as u64 >= keep && self.truncate_tree(st, inode, ptr, 0, 0)? {
+                inode.block[slot] = 0;
+            }
+        }
+
+        let mut start = disk::DIRECT_BLOCKS as u64;
+        for (slot, depth) in [(disk::IND_BLOCK, 1), (disk::DIND_BLOCK, 2), (disk::TIND_BLOCK, 3)] {
+            let span = p.pow(depth as u32);
+            let subtree_keep = keep.saturating_sub(start).min(span);
+            let ptr = inode.block[slot];
+            if subtree_keep < span && self.truncate_tree(st, inode, ptr, depth, subtree_keep)? {
+                inode.block[slot] = 0;
+            }
+            start += span;
+        }
+        Ok(())
+    }
+
+    /// Read file data, treating holes as zeros
+    fn read_data(&self, inode: &RawInode, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let size = inode.file_size();
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let bs = self.fs.block_size as u64;
+        let len = buf.len().min((size - offset) as usize);
+        let mut block_buf = vec![0u8; self.fs.block_size];
+        let mut done = 0;
+
+        while done < len {
+            let pos = offset + done as u64;
+            let within = (pos % bs) as usize;
+            let chunk = (self.fs.block_size - within).min(len - done);
+
+            let phys = self.map_block(inode, pos / bs)?;
+            if phys == 0 {
+                buf[done..done + chunk].fill(0);
+            } else {
+                self.fs.read_block(phys, &mut block_buf)?;
+                buf[done..done + chunk].copy_from_slice(&block_buf[within..within + chunk]);
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// Write file data, allocating blocks as needed and extending the size
+    fn write_data(&self, st: &mut State, inode: &mut RawInode, offset: u64, data: &[u8]) -> FsResult<usize> {
+        let end = offset.checked_add(data.len() as u64).ok_or(FsError::NoSpace)?;
+        if end > self.fs.max_file_size(st) {
+            return Err(FsError::NoSpace);
+        }
+
+        let bs = self.fs.block_size as u64;
+        let mut block_buf = vec![0u8; self.fs.block_size];
+        let mut done = 0;
+
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let within = (pos % bs) as usize;
+            let chunk = (self.fs.block_size - within).min(data.len() - done);
+
+            let phys = match self.map_block_alloc(st, inode, pos / bs) {
+                Ok(phys) => phys,
+                // Report a partial write rather than losing what was written
+                Err(_) if done > 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            if chunk == self.fs.block_size {
+                self.fs.write_block(phys, &data[done..done + chunk])?;
+            } else {
+                self.fs.read_block(phys, &mut block_buf)?;
+                block_buf[within..within + chunk].copy_from_slice(&data[done..done + chunk]);
+                self.fs.write_block(phys, &block_buf)?;
+            }
+            done += chunk;
+        }
+
+        let written_end = offset + done as u64;
+        if written_end > inode.file_size() {
+            inode.set_file_size(written_end);
+        }
+        Ok(done)
+    }
+
+    /// Collect every entry of a directory, including `.` and `..`
+    fn dir_entries(&self, st: &State, dir: &RawInode) -> FsResult<Vec<LocatedEntry>> {
+        let bs = self.fs.block_size;
+        let block_count = (dir.file_size() as usize).div_ceil(bs);
+        let mut buf = vec![0u8; bs];
+        let mut entries = Vec::new();
+        let with_type = self.fs.has_filetype(st);
+
+        for block_index in 0..block_count as u32 {
+            let phys = self.map_block(dir, block_index as u64)?;
+            if phys == 0 {
+                continue;
+            }
+            self.fs.read_block(phys, &mut buf)?;
+
+            let mut offset = 0;
+            while offset + disk::DIRENT_HEADER <= bs {
+                let mut header = DirentHeader::parse(&buf[offset..]);
+                let rec_len = header.rec_len as usize;
+                if rec_len < disk::DIRENT_HEADER || offset + rec_len > bs {
+                    return Err(FsError::Corrupted);
+                }
+                if !with_type {
+                    header.file_type = disk::FT_UNKNOWN;
+                }
+
+                if header.inode != 0 {
+                    let name_start = offset + disk::DIRENT_HEADER;
+                    let name_end = (name_start + header.name_len as usize).min(offset + rec_len);
+                    entries.push(LocatedEntry {
+                        block_index,
+                        offset,
+                        header,
+                        name: String::from_utf8_lossy(&buf[name_start..name_end]).into_owned(),
+                    });
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Find a named entry in a directory
+    fn find_entry(&self, st: &State, dir: &RawInode, name: &str) -> FsResult<Option<LocatedEntry>> {
+        Ok(self.dir_entries(st, dir)?.into_iter().find(|e| e.name == name))
+    }
+
+    /// Insert a directory entry, splitting free space or growing the directory
+    fn add_entry(&self, st: &mut State, dir: &mut RawInode, name: &str, ino: u32, file_type: FileType) -> FsResult<()> {
+        let bs = self.fs.block_size;
+        let needed = disk::dirent_size(name.len());
+        let type_code = if self.fs.has_filetype(st) { file_type_to_dirent_code(file_type) } else { 0 };
+        let block_count = (dir.file_size() as usize).div_ceil(bs);
+        let mut buf = vec![0u8; bs];
+
+        // Hashed indexes are not maintained, so mark the directory linear
+        dir.flags &= !disk::INDEX_FL;
+
+        for block_index in 0..block_count as u64 {
+            let phys = self.map_block(dir, block_index)?;
+            if phys == 0 {
+                continue;
+            }
+            self.fs.read_block(phys, &mut buf)?;
+
+            let mut offset = 0;
+            while offset + disk::DIRENT_HEADER <= bs {
+                let header = DirentHeader::parse(&buf[offset..]);
+                let rec_len = header.rec_len as usize;
+                if rec_len < disk::DIRENT_HEADER || offset + rec_len > bs {
+                    return Err(FsError::Corrupted);
+                }
+
+                let used = if header.inode == 0 { 0 } else { disk::dirent_size(header.name_len as usize) };
+                if rec_len - used >= needed {
+                    let new_offset = offset + used;
+                    if used > 0 {
+                        DirentHeader { rec_len: used as u16, ..header }.write_to(&mut buf[offset..]);
+                    }
+                    write_dirent(&mut buf[new_offset..], ino, (rec_len - used) as u16, name, type_code);
+                    return self.fs.write_block(phys, &buf);
+                }
+                offset += rec_len;
+            }
+        }
+
+        // No room: append a fresh block holding just this entry
+        let phys = self.map_block_alloc(st, dir, block_count as u64)?;
+        buf.fill(0);
+        write_dirent(&mut buf, ino, bs as u16, name, type_code);
+        self.fs.write_block(phys, &buf)?;
+        dir.set_file_size(((block_count + 1) * bs) as u64);
+        Ok(())
+    }
+
+    /// Remove a directory entry by merging it into its predecessor
+    fn remove_entry(&self, st: &State, dir: &mut RawInode, entry: &LocatedEntry) -> FsResult<()> {
+        let bs = self.fs.block_size;
+        let phys = self.map_block(dir, entry.block_index as u64)?;
+        let mut buf = vec![0u8; bs];
+        self.fs.read_block(phys, &mut buf)?;
+
+        let previous = self
+            .dir_entries(st, dir)?
+            .into_iter()
+            .filter(|e| e.block_index == entry.block_index && e.offset < entry.offset)
+            .max_by_key(|e| e.offset);
+
+        // The previous live entry may be followed by deleted slack, so extend
+        // it all the way to the end of the removed record.
+        match previous {
+            Some(prev) => {
+                let new_len = entry.offset + entry.header.rec_len as usize - prev.offset;
+                DirentHeader { rec_len: new_len as u16, ..prev.header }.write_to(&mut buf[prev.offset..]);
+            }
+            None => {
+                DirentHeader { inode: 0, ..entry.header }.write_to(&mut buf[entry.offset..]);
+            }
+        }
+
+        dir.flags &= !disk::INDEX_FL;
+        self.fs.write_block(phys, &buf)
+    }
+
+    /// Resolve a directory entry's file type, reading the inode if needed
+    fn entry_file_type(&self, st: &State, entry: &LocatedEntry) -> FsResult<FileType> {
+        match dirent_code_to_file_type(entry.header.file_type) {
+            Some(file_type) => Ok(file_type),
+            None => Ok(mode_to_file_type(self.fs.read_inode(st, entry.header.inode)?.mode)),
+        }
+    }
+
+    /// Allocate a new inode, fill it in with `init` and link it into this
+    /// directory, all under one hold of the state lock
+    fn create_child(
+        &self,
+        name: &str,
+        mode: u16,
+        file_type: FileType,
+        init: impl FnOnce(&mut State, &Ext2Inode, &mut RawInode) -> FsResult<()>,
+    ) -> FsResult<u32> {
+        self.fs.check_writable()?;
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+        if name.len() > 255 {
+            return Err(FsError::NameTooLong);
+        }
+
+        let mut st = self.fs.state.lock();
+        let mut dir = self.fs.read_inode(&st, self.ino)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        if self.find_entry(&st, &dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let is_dir = file_type == FileType::Directory;
+        let goal = self.fs.inode_group(&st, self.ino);
+        let ino = self.fs.alloc_inode(&mut st, goal, is_dir)?;
+        let now = self.fs.now(&st);
+        let mut child = RawInode::new(mode, now);
+
+        // The contents go in before the entry, so a failure leaves nothing
+        // that refers to the child
+        let child_fs = Ext2Inode::new(self.fs.clone(), ino);
+        let linked = init(&mut st, &child_fs, &mut child)
+            .and_then(|_| self.link_child(&mut st, &mut dir, name, ino, &mut child, file_type));
+        if let Err(e) = linked {
+            // Nothing refers to the child yet, so give back what it took
+            let _ = child_fs.free_blocks_from(&mut st, &mut child, 0);
+            child.links_count = 0;
+            child.dtime = now;
+            child.set_file_size(0);
+            let _ = self.fs.write_inode(&st, ino, &child);
+            let _ = self.fs.free_inode(&mut st, ino, is_dir);
+            return Err(e);
+        }
+        dir.mtime = now;
+        dir.ctime = now;
+        self.fs.write_inode(&st, self.ino, &dir)?;
+
+        Ok(ino)
+    }
+
+    /// Initialize a freshly allocated inode and add its entry to `dir`
+    fn link_child(
+        &self,
+        st: &mut State,
+        dir: &mut RawInode,
+        name: &str,
+        ino: u32,
+        child: &mut RawInode,
+        file_type: FileType,
+    ) -> FsResult<()> {
+        if file_type == FileType::Directory {
+            // A new directory holds `.` and `..` and is linked from its parent
+            let child_fs = Ext2Inode::new(self.fs.clone(), ino);
+            let phys = child_fs.map_block_alloc(st, child, 0)?;
+            let bs = self.fs.block_size;
+            let type_code = if self.fs.has_filetype(st) { disk::FT_DIR } else { 0 };
+            let mut buf = vec![0u8; bs];
+            let dot_len = disk::dirent_size(1);
+            write_dirent(&mut buf, ino, dot_len as u16, ".", type_code);
+            write_dirent(&mut buf[dot_len..], self.ino, (bs - dot_len) as u16, "..", type_code);
+            self.fs.write_block(phys, &buf)?;
+
+            child.set_file_size(bs as u64);
+            child.links_count = 2;
+            dir.links_count += 1;
+        }
+
+        self.fs.write_inode(st, ino, child)?;
+        self.add_entry(st, dir, name, ino, file_type)
+    }
+}
+
+/// Encode a directory entry header and name at the start of `buf`
+fn write_dirent(buf: &mut [u8], ino: u32, rec_len: u16, name: &str, file_type: u8) {
+    DirentHeader {
+        inode: ino,
+        rec_len,
+        name_len: name.len() as u8,
+        file_type,
+    }
+    .write_to(buf);
+    buf[disk::DIRENT_HEADER..disk::DIRENT_HEADER + name.len()].copy_from_slice(name.as_bytes());
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let st = self.fs.state.lock();
+        let raw = self.fs.read_inode(&st, self.ino)?;
+
+        Ok(Metadata {
+            inode: self.ino as u64,
+            file_type: mode_to_file_type(raw.mode),
+            mode: raw.mode & 0o7777,
+            uid: raw.uid(),
+            gid: raw.gid(),
+            nlink: raw.links_count as u32,
+            size: raw.file_size(),
+            atime: raw.atime,
+            mtime: raw.mtime,
+            ctime: raw.ctime,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let st = self.fs.state.lock();
+        let raw = self.fs.read_inode(&st, self.ino)?;
+        if raw.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        self.read_data(&raw, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.fs.check_writable()?;
+        let mut st = self.fs.state.lock();
+        let mut raw = self.fs.read_inode(&st, self.ino)?;
+        if raw.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+
+        let written = self.write_data(&mut st, &mut raw, offset, buf)?;
+        let now = self.fs.now(&st);
+        raw.mtime = now;
+        raw.ctime = now;
+        self.fs.write_inode(&st, self.ino, &raw)?;
+        Ok(written)
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        self.fs.check_writable()?;
+        let mut st = self.fs.state.lock();
+        let mut raw = self.fs.read_inode(&st, self.ino)?;
+        if raw.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        if size > self.fs.max_file_size(&st) {
+            return Err(FsError::NoSpace);
+        }
+
+        let bs = self.fs.block_size as u64;
+        if size < raw.file_size() {
+            self.free_blocks_from(&mut st, &mut raw, size.div_ceil(bs))?;
+
+            // Zero the tail of the last block so a later extension reads zeros
+            let within = (size % bs) as usize;
+            if within != 0 {
+                let phys = self.map_block(&raw, size / bs)?;
+                if phys != 0 {
+                    let mut buf = vec![0u8; self.fs.block_size];
+                    self.fs.read_block(phys, &mut buf)?;
+                    buf[within..].fill(0);
+                    self.fs.write_block(phys, &buf)?;
+                }
+            }
+        }
+
+        raw.set_file_size(size);
+        let now = self.fs.now(&st);
+        raw.mtime = now;
+        raw.ctime = now;
+        self.fs.write_inode(&st, self.ino, &raw)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let st = self.fs.state.lock();
+        let dir = self.fs.read_inode(&st, self.ino)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        match self.find_entry(&st, &dir, name)? {
+            Some(entry) => Ok(self.fs.inode(entry.header.inode)),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let st = self.fs.state.lock();
+        let dir = self.fs.read_inode(&st, self.ino)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let mut result = Vec::new();
+        for entry in self.dir_entries(&st, &dir)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            result.push(DirEntry {
+                file_type: self.entry_file_type(&st, &entry)?,
+                inode: entry.header.inode as u64,
+                name: entry.name,
+            });
+        }
+        Ok(result)
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>> {
+        let type_bits = match file_type {
+            FileType::Regular => disk::S_IFREG,
+            FileType::Directory => disk::S_IFDIR,
+            _ => return Err(FsError::NotSupported),
+        };
+
+        let ino = self.create_child(name, type_bits | (mode & 0o7777), file_type, |_, _, _| Ok(()))?;
+        Ok(self.fs.inode(ino))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.fs.check_writable()?;
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut st = self.fs.state.lock();
+        let mut dir = self.fs.read_inode(&st, self.ino)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let entry = self.find_entry(&st, &dir, name)?.ok_or(FsError::NotFound)?;
+        let target_ino = entry.header.inode;
+        let target_fs = Ext2Inode::new(self.fs.clone(), target_ino);
+        let mut target = self.fs.read_inode(&st, target_ino)?;
+        let is_dir = target.is_dir();
+
+        if is_dir {
+            let has_children = target_fs
+                .dir_entries(&st, &target)?
+                .iter()
+                .any(|e| e.name != "." && e.name != "..");
+            if has_children {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+            // Drop the child's `..` reference to us
+            dir.links_count = dir.links_count.saturating_sub(1);
+            target.links_count = 0;
+        } else {
+            target.links_count = target.links_count.saturating_sub(1);
+        }
+
+        self.remove_entry(&st, &mut dir, &entry)?;
+        let now = self.fs.now(&st);
+        dir.mtime = now;
+        dir.ctime = now;
+        self.fs.write_inode(&st, self.ino, &dir)?;
+
+        if target.links_count == 0 {
+            // Fast symlinks keep their target in the block pointers
+            if !self.is_fast_symlink(&target) {
+                target_fs.free_blocks_from(&mut st, &mut target, 0)?;
+            }
+            target.dtime = now;
+            target.set_file_size(0);
+            self.fs.write_inode(&st, target_ino, &target)?;
+            self.fs.free_inode(&mut st, target_ino, is_dir)?;
+        } else {
+            target.ctime = now;
+            self.fs.write_inode(&st, target_ino, &target)?;
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        if target.is_empty() || target.len() >= self.fs.block_size {
+            return Err(FsError::NameTooLong);
+        }
+
+        let ino = self.create_child(name, disk::S_IFLNK | 0o777, FileType::Symlink, |st, child, raw| {
+            if target.len() >= FAST_SYMLINK_MAX {
+                return child.write_data(st, raw, 0, target.as_bytes()).map(|_| ());
+            }
+            let mut inline = [0u8; FAST_SYMLINK_MAX];
+            inline[..target.len()].copy_from_slice(target.as_bytes());
+            for (i, ptr) in raw.block.iter_mut().enumerate() {
+                *ptr = disk::read_u32(&inline, i * 4);
+            }
+            raw.set_file_size(target.len() as u64);
+            Ok(())
+        })?;
+        Ok(self.fs.inode(ino))
+    }
+
+    fn readlink(&self) -> FsResult<String> {
+        let st = self.fs.state.lock();
+        let raw = self.fs.read_inode(&st, self.ino)?;
+        if !raw.is_symlink() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let len = raw.file_size() as usize;
+        let mut data = vec![0u8; len];
+
+        if self.is_fast_symlink(&raw) {
+            let mut inline = [0u8; FAST_SYMLINK_MAX];
+            for (i, ptr) in raw.block.iter().enumerate() {
+                disk::write_u32(&mut inline, i * 4, *ptr);
+            }
+            data.copy_from_slice(&inline[..len]);
+        } else {
+            self.read_data(&raw, 0, &mut data)?;
+        }
+
+        String::from_utf8(data).map_err(|_| FsError::Corrupted)
+    }
+
+    fn chmod(&self, mode: u16) -> FsResult<()> {
+        self.fs.check_writable()?;
+        let st = self.fs.state.lock();
+        let mut raw = self.fs.read_inode(&st, self.ino)?;
+        raw.mode = (raw.mode & disk::S_IFMT) | (mode & 0o7777);
+        raw.ctime = self.fs.now(&st);
+        self.fs.write_inode(&st, self.ino, &raw)
+    }
+}
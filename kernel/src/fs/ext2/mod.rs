@@ -0,0 +1,399 @@
+//! ext2 filesystem driver
+//!
+//! Supports revision 0 and 1 filesystems with 1-4 KiB blocks, direct and
+//! singly/doubly/triply indirect block maps, linear directories and fast
+//! symlinks. Images created with `mkfs.ext2` mount read-write; any unknown
+//! read-only-compatible feature forces a read-only mount.
+
+pub mod disk;
+mod inode;
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::drivers::block::{BlockDevice, SECTOR_SIZE};
+
+use super::{FileSystem, FsError, FsResult, Inode};
+use disk::{GroupDesc, RawInode, Superblock};
+use inode::Ext2Inode;
+
+/// Mutable filesystem state, guarded by a single lock
+pub(super) struct State {
+    sb: Superblock,
+    groups: Vec<GroupDesc>,
+    /// Free counts changed since the superblock and descriptors were written
+    dirty: bool,
+}
+
+/// A mounted ext2 filesystem
+pub struct Ext2Fs {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    inode_size: usize,
+    read_only: bool,
+    state: Mutex<State>,
+    this: Weak<Ext2Fs>,
+}
+
+impl Ext2Fs {
+    /// Mount the ext2 filesystem on `device`
+    pub fn mount(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Self>> {
+        let mut raw_sb = [0u8; disk::SUPERBLOCK_SIZE];
+        device.read_sectors((disk::SUPERBLOCK_OFFSET / SECTOR_SIZE) as u64, &mut raw_sb)?;
+        let mut sb = Superblock::parse(&raw_sb);
+
+        if sb.magic != disk::EXT2_MAGIC {
+            return Err(FsError::NotSupported);
+        }
+        if sb.log_block_size > 2 || sb.blocks_per_group == 0 || sb.inodes_per_group == 0 {
+            return Err(FsError::Corrupted);
+        }
+        if sb.feature_incompat & !disk::INCOMPAT_FILETYPE != 0 {
+            crate::serial::print("ext2: unsupported incompatible features\n");
+            return Err(FsError::NotSupported);
+        }
+
+        let supported_ro = disk::RO_COMPAT_SPARSE_SUPER | disk::RO_COMPAT_LARGE_FILE | disk::RO_COMPAT_BTREE_DIR;
+        let read_only = sb.feature_ro_compat & !supported_ro != 0;
+        if read_only {
+            crate::serial::print("ext2: unknown read-only features, mounting read-only\n");
+        }
+
+        let block_size = sb.block_size();
+        let inode_size = sb.inode_size as usize;
+        if inode_size < 128 || inode_size > block_size {
+            return Err(FsError::Corrupted);
+        }
+        // Each group's bitmaps are one block, and the blocks must be on the device
+        let bits_per_block = 8 * block_size as u32;
+        let spb = (block_size / SECTOR_SIZE) as u64;
+        if sb.blocks_per_group > bits_per_block
+            || sb.inodes_per_group > bits_per_block
+            || sb.blocks_count as u64 * spb > device.sector_count()
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        // Every group has its inodes, so the groups must hold them all
+        let group_count = sb.group_count().ok_or(FsError::Corrupted)?;
+        if (group_count as u64) * (sb.inodes_per_group as u64) < sb.inodes_count as u64 {
+            return Err(FsError::Corrupted);
+        }
+        let group_count = group_count as usize;
+
+        // The group descriptor table starts in the block after the superblock
+        let table_blocks = (group_count * disk::GROUP_DESC_SIZE).div_ceil(block_size);
+        let mut table = vec![0u8; table_blocks * block_size];
+        device.read_sectors((sb.first_data_block as u64 + 1) * spb, &mut table)?;
+
+        let groups = (0..group_count)
+            .map(|g| GroupDesc::parse(&table[g * disk::GROUP_DESC_SIZE..]))
+            .collect();
+
+        crate::serial::print("ext2: volume '");
+        crate::serial::print(sb.volume_name());
+        crate::serial::print("', ");
+        crate::memory::print_decimal(sb.blocks_count as u64);
+        crate::serial::print(" blocks of ");
+        crate::memory::print_decimal(block_size as u64);
+        crate::serial::print(" bytes, ");
+        crate::memory::print_decimal(group_count as u64);
+        crate::serial::print(" groups\n");
+
+        if !read_only {
+            sb.mnt_count = sb.mnt_count.wrapping_add(1);
+            sb.mtime = super::timestamp().max(sb.wtime);
+        }
+
+        let fs = Arc::new_cyclic(|this| Self {
+            device,
+            block_size,
+            inode_size,
+            read_only,
+            state: Mutex::new(State { sb, groups, dirty: false }),
+            this: this.clone(),
+        });
+
+        if !read_only {
+            fs.flush_metadata(&mut fs.state.lock())?;
+        }
+
+        Ok(fs)
+    }
+
+    /// Number of block pointers in an indirect block
+    fn ptrs_per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    fn sectors_per_block(&self) -> u64 {
+        (self.block_size / SECTOR_SIZE) as u64
+    }
+
+    /// Read a filesystem block
+    fn read_block(&self, block: u32, buf: &mut [u8]) -> FsResult<()> {
+        self.device.read_sectors(block as u64 * self.sectors_per_block(), buf)?;
+        Ok(())
+    }
+
+    /// Write a filesystem block
+    fn write_block(&self, block: u32, buf: &[u8]) -> FsResult<()> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        self.device.write_sectors(block as u64 * self.sectors_per_block(), buf)?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        if self.read_only {
+            Err(FsError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write the superblock and group descriptor table back to disk
+    ///
+    /// Allocation only updates the free counts in memory; they reach the
+    /// disk here, on mount and on sync.
+    fn flush_metadata(&self, st: &mut State) -> FsResult<()> {
+        let mut raw_sb = [0u8; disk::SUPERBLOCK_SIZE];
+        let sb_lba = (disk::SUPERBLOCK_OFFSET / SECTOR_SIZE) as u64;
+        self.device.read_sectors(sb_lba, &mut raw_sb)?;
+        let mut sb = st.sb.clone();
+        sb.wtime = self.now(st);
+        sb.write_to(&mut raw_sb);
+        self.device.write_sectors(sb_lba, &raw_sb)?;
+
+        let table_blocks = (st.groups.len() * disk::GROUP_DESC_SIZE).div_ceil(self.block_size);
+        let mut table = vec![0u8; table_blocks * self.block_size];
+        let table_start = st.sb.first_data_block + 1;
+        for (i, block) in table.chunks_mut(self.block_size).enumerate() {
+            self.read_block(table_start + i as u32, block)?;
+        }
+        for (g, desc) in st.groups.iter().enumerate() {
+            desc.write_to(&mut table[g * disk::GROUP_DESC_SIZE..]);
+        }
+        for (i, block) in table.chunks(self.block_size).enumerate() {
+            self.write_block(table_start + i as u32, block)?;
+        }
+        st.dirty = false;
+        Ok(())
+    }
+
+    /// Timestamp for on-disk times
+    ///
+    /// Without a wall clock this falls back to the superblock's last write
+    /// time, since fsck misreads small inode deletion times as orphan links.
+    fn now(&self, st: &State) -> u32 {
+        super::timestamp().max(st.sb.wtime)
+    }
+
+    /// Locate an inode: (block containing it, byte offset within that block)
+    fn inode_location(&self, st: &State, ino: u32) -> FsResult<(u32, usize)> {
+        if ino == 0 || ino > st.sb.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+        let group = ((ino - 1) / st.sb.inodes_per_group) as usize;
+        let index = ((ino - 1) % st.sb.inodes_per_group) as usize;
+        let offset = index * self.inode_size;
+        let table = st.groups.get(group).ok_or(FsError::Corrupted)?.inode_table;
+        Ok((table + (offset / self.block_size) as u32, offset % self.block_size))
+    }
+
+    /// Read an inode from the inode table
+    fn read_inode(&self, st: &State, ino: u32) -> FsResult<RawInode> {
+        let (block, offset) = self.inode_location(st, ino)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+        Ok(RawInode::parse(&buf[offset..offset + self.inode_size]))
+    }
+
+    /// Write an inode back to the inode table
+    fn write_inode(&self, st: &State, ino: u32, inode: &RawInode) -> FsResult<()> {
+        let (block, offset) = self.inode_location(st, ino)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+        inode.write_to(&mut buf[offset..offset + self.inode_size]);
+        self.write_block(block, &buf)
+    }
+
+    /// Block group an inode belongs to
+    fn inode_group(&self, st: &State, ino: u32) -> u32 {
+        (ino - 1) / st.sb.inodes_per_group
+    }
+
+    /// Find and set the first clear bit in a bitmap block, limited to `limit` bits
+    fn take_free_bit(&self, bitmap_block: u32, limit: u32) -> FsResult<Option<u32>> {
+        let mut bitmap = vec![0u8; self.block_size];
+        self.read_block(bitmap_block, &mut bitmap)?;
+
+        for bit in 0..limit {
+            let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+            if bitmap[byte] & mask == 0 {
+                bitmap[byte] |= mask;
+                self.write_block(bitmap_block, &bitmap)?;
+                return Ok(Some(bit));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Clear a bit in a bitmap block, returning whether it was set
+    fn clear_bit(&self, bitmap_block: u32, bit: u32) -> FsResult<bool> {
+        let mut bitmap = vec![0u8; self.block_size];
+        self.read_block(bitmap_block, &mut bitmap)?;
+
+        let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+        let was_set = bitmap[byte] & mask != 0;
+        bitmap[byte] &= !mask;
+        self.write_block(bitmap_block, &bitmap)?;
+        Ok(was_set)
+    }
+
+    /// Allocate a zeroed data block, preferring the given group
+    fn alloc_block(&self, st: &mut State, goal_group: u32) -> FsResult<u32> {
+        self.check_writable()?;
+        let group_count = st.groups.len() as u32;
+
+        for i in 0..group_count {
+            let g = (goal_group + i) % group_count;
+            if st.groups[g as usize].free_blocks_count == 0 {
+                continue;
+            }
+
+            // The last group may be shorter than blocks_per_group
+            let group_start = st.sb.first_data_block + g * st.sb.blocks_per_group;
+            let in_group = st
+                .sb
+                .blocks_count
+                .checked_sub(group_start)
+                .ok_or(FsError::Corrupted)?
+                .min(st.sb.blocks_per_group);
+
+            if let Some(bit) = self.take_free_bit(st.groups[g as usize].block_bitmap, in_group)? {
+                st.groups[g as usize].free_blocks_count -= 1;
+                st.sb.free_blocks_count = st.sb.free_blocks_count.saturating_sub(1);
+                st.dirty = true;
+
+                let block = group_start + bit;
+                self.write_block(block, &vec![0u8; self.block_size])?;
+                return Ok(block);
+            }
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    /// Return a data block to the free pool
+    fn free_block(&self, st: &mut State, block: u32) -> FsResult<()> {
+        if block < st.sb.first_data_block || block >= st.sb.blocks_count {
+            return Err(FsError::Corrupted);
+        }
+        let relative = block - st.sb.first_data_block;
+        let g = (relative / st.sb.blocks_per_group) as usize;
+        let bit = relative % st.sb.blocks_per_group;
+
+        if self.clear_bit(st.groups[g].block_bitmap, bit)? {
+            st.groups[g].free_blocks_count += 1;
+            st.sb.free_blocks_count += 1;
+            st.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Allocate an inode number, preferring the given group
+    fn alloc_inode(&self, st: &mut State, goal_group: u32, is_dir: bool) -> FsResult<u32> {
+        self.check_writable()?;
+        let group_count = st.groups.len() as u32;
+
+        for i in 0..group_count {
+            let g = (goal_group + i) % group_count;
+            if st.groups[g as usize].free_inodes_count == 0 {
+                continue;
+            }
+
+            if let Some(bit) = self.take_free_bit(st.groups[g as usize].inode_bitmap, st.sb.inodes_per_group)? {
+                let ino = g * st.sb.inodes_per_group + bit + 1;
+                if ino < st.sb.first_ino {
+                    // Reserved inodes should already be marked in use; put
+                    // the bitmap back as it was
+                    self.clear_bit(st.groups[g as usize].inode_bitmap, bit)?;
+                    return Err(FsError::Corrupted);
+                }
+
+                let desc = &mut st.groups[g as usize];
+                desc.free_inodes_count -= 1;
+                if is_dir {
+                    desc.used_dirs_count += 1;
+                }
+                st.sb.free_inodes_count = st.sb.free_inodes_count.saturating_sub(1);
+                st.dirty = true;
+                return Ok(ino);
+            }
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    /// Return an inode number to the free pool
+    fn free_inode(&self, st: &mut State, ino: u32, is_dir: bool) -> FsResult<()> {
+        let g = self.inode_group(st, ino) as usize;
+        let bit = (ino - 1) % st.sb.inodes_per_group;
+
+        if self.clear_bit(st.groups[g].inode_bitmap, bit)? {
+            let desc = &mut st.groups[g];
+            desc.free_inodes_count += 1;
+            if is_dir {
+                desc.used_dirs_count = desc.used_dirs_count.saturating_sub(1);
+            }
+            st.sb.free_inodes_count += 1;
+            st.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Whether directory entries carry a file type byte
+    fn has_filetype(&self, st: &State) -> bool {
+        st.sb.feature_incompat & disk::INCOMPAT_FILETYPE != 0
+    }
+
+    /// Largest file size the filesystem's features allow
+    fn max_file_size(&self, st: &State) -> u64 {
+        if st.sb.feature_ro_compat & disk::RO_COMPAT_LARGE_FILE != 0 {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        }
+    }
+
+    /// Wrap an inode number in a VFS inode
+    fn inode(&self, ino: u32) -> Arc<dyn Inode> {
+        let fs = self.this.upgrade().expect("ext2 filesystem dropped while in use");
+        Arc::new(Ext2Inode::new(fs, ino))
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.inode(disk::ROOT_INO)
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let mut st = self.state.lock();
+        if st.dirty {
+            self.flush_metadata(&mut st)?;
+        }
+        Ok(self.device.flush()?)
+    }
+}
@@ -0,0 +1,165 @@
+//! Open file handles and path-based convenience functions
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::path;
+use super::{DirEntry, FileType, FsError, FsResult, Inode, Metadata};
+
+/// Position to seek from
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file with its own read/write position
+pub struct File {
+    inode: Arc<dyn Inode>,
+    offset: u64,
+}
+
+impl File {
+    /// Open an existing file
+    pub fn open(path: &str) -> FsResult<Self> {
+        let inode = path::resolve(path, true)?;
+        Ok(Self { inode, offset: 0 })
+    }
+
+    /// Open a file for writing, creating it or truncating it to zero length
+    pub fn create(path: &str) -> FsResult<Self> {
+        let inode = match path::resolve(path, true) {
+            Ok(inode) => {
+                if inode.metadata()?.file_type == FileType::Regular {
+                    inode.truncate(0)?;
+                }
+                inode
+            }
+            Err(FsError::NotFound) => {
+                let (parent, name) = path::split_parent(path)?;
+                path::resolve(&parent, true)?.create(&name, FileType::Regular, 0o644)?
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Self { inode, offset: 0 })
+    }
+
+    /// Wrap an inode obtained by other means
+    pub fn from_inode(inode: Arc<dyn Inode>) -> Self {
+        Self { inode, offset: 0 }
+    }
+
+    /// Read at the current position, advancing it
+    pub fn read(&mut self, buf: &mut [u8]) -> FsResult<usize> {
+        let n = self.inode.read_at(self.offset, buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    /// Write at the current position, advancing it
+    pub fn write(&mut self, buf: &[u8]) -> FsResult<usize> {
+        let n = self.inode.write_at(self.offset, buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    /// Move the read/write position
+    pub fn seek(&mut self, pos: SeekFrom) -> FsResult<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inode.metadata()?.size.checked_add_signed(delta),
+        };
+        self.offset = new_offset.ok_or(FsError::InvalidArgument)?;
+        Ok(self.offset)
+    }
+
+    /// Read everything from the current position to the end
+    pub fn read_to_end(&mut self) -> FsResult<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 512];
+        loop {
+            let n = self.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Attributes of the underlying inode
+    pub fn metadata(&self) -> FsResult<Metadata> {
+        self.inode.metadata()
+    }
+
+    /// The underlying inode
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+}
+
+/// Read a whole file
+pub fn read(path: &str) -> FsResult<Vec<u8>> {
+    File::open(path)?.read_to_end()
+}
+
+/// Read a whole file as UTF-8 text, replacing invalid sequences
+pub fn read_to_string(path: &str) -> FsResult<String> {
+    Ok(String::from_utf8_lossy(&read(path)?).into_owned())
+}
+
+/// Replace the contents of a file, creating it if needed
+pub fn write(path: &str, data: &[u8]) -> FsResult<()> {
+    let mut file = File::create(path)?;
+    let mut written = 0;
+    while written < data.len() {
+        let n = file.write(&data[written..])?;
+        if n == 0 {
+            return Err(FsError::NoSpace);
+        }
+        written += n;
+    }
+    Ok(())
+}
+
+/// Attributes of the object at `path`, without following a final symlink
+pub fn metadata(path: &str) -> FsResult<Metadata> {
+    path::resolve(path, false)?.metadata()
+}
+
+/// List a directory
+pub fn read_dir(path: &str) -> FsResult<Vec<DirEntry>> {
+    path::resolve(path, true)?.readdir()
+}
+
+/// Create a directory
+pub fn create_dir(path: &str) -> FsResult<()> {
+    let (parent, name) = path::split_parent(path)?;
+    path::resolve(&parent, true)?.create(&name, FileType::Directory, 0o755)?;
+    Ok(())
+}
+
+/// Remove a file, symlink or empty directory
+pub fn remove(path: &str) -> FsResult<()> {
+    let (parent, name) = path::split_parent(path)?;
+    path::resolve(&parent, true)?.unlink(&name)
+}
+
+/// Create a symlink at `path` pointing to `target`
+pub fn symlink(target: &str, path: &str) -> FsResult<()> {
+    let (parent, name) = path::split_parent(path)?;
+    path::resolve(&parent, true)?.symlink(&name, target)?;
+    Ok(())
+}
+
+/// Read the target of the symlink at `path`
+pub fn readlink(path: &str) -> FsResult<String> {
+    path::resolve(path, false)?.readlink()
+}
+
+/// Change the permission bits of the object at `path`
+pub fn chmod(path: &str, mode: u16) -> FsResult<()> {
+    path::resolve(path, true)?.chmod(mode)
+}
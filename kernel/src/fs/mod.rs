@@ -0,0 +1,260 @@
+//! Virtual filesystem layer
+//!
+//! Filesystems implement `FileSystem` and hand out `Inode` objects; the mount
+//! table maps absolute paths onto them and path lookup walks across mount
+//! points and symlinks. Callers use the path-based helpers in `file` or open
+//! a `file::File` handle.
+
+pub mod devfs;
+pub mod ext2;
+pub mod file;
+pub mod mount;
+pub mod path;
+pub mod procfs;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::drivers::block::BlockError;
+use crate::drivers::device::DeviceError;
+
+/// Result type used throughout the filesystem layer
+pub type FsResult<T> = Result<T, FsError>;
+
+/// Filesystem errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidPath,
+    InvalidArgument,
+    NameTooLong,
+    NoSpace,
+    ReadOnly,
+    NotSupported,
+    TooManySymlinks,
+    Busy,
+    Corrupted,
+    Io,
+}
+
+impl FsError {
+    /// Human-readable description of the error
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::NotADirectory => "not a directory",
+            FsError::IsADirectory => "is a directory",
+            FsError::AlreadyExists => "file exists",
+            FsError::DirectoryNotEmpty => "directory not empty",
+            FsError::InvalidPath => "invalid path",
+            FsError::InvalidArgument => "invalid argument",
+            FsError::NameTooLong => "file name too long",
+            FsError::NoSpace => "no space left on device",
+            FsError::ReadOnly => "read-only filesystem",
+            FsError::NotSupported => "operation not supported",
+            FsError::TooManySymlinks => "too many levels of symbolic links",
+            FsError::Busy => "resource busy",
+            FsError::Corrupted => "filesystem corrupted",
+            FsError::Io => "I/O error",
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(_: BlockError) -> Self {
+        FsError::Io
+    }
+}
+
+impl From<DeviceError> for FsError {
+    fn from(err: DeviceError) -> Self {
+        match err {
+            DeviceError::NotSupported => FsError::NotSupported,
+        }
+    }
+}
+
+/// Kind of object an inode refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+impl FileType {
+    /// Single-character tag as shown by `ls -l`
+    pub fn as_char(&self) -> char {
+        match self {
+            FileType::Regular => '-',
+            FileType::Directory => 'd',
+            FileType::Symlink => 'l',
+            FileType::CharDevice => 'c',
+            FileType::BlockDevice => 'b',
+        }
+    }
+}
+
+/// File attributes
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub inode: u64,
+    pub file_type: FileType,
+    /// Permission bits (`0o7777`)
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub size: u64,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+}
+
+impl Metadata {
+    /// Metadata for a synthetic object with no backing store
+    pub fn synthetic(inode: u64, file_type: FileType, mode: u16, size: u64) -> Self {
+        Self {
+            inode,
+            file_type,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            size,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+
+    /// `ls -l` style permission string, e.g. `drwxr-xr-x`
+    pub fn mode_string(&self) -> String {
+        let mut s = String::with_capacity(10);
+        s.push(self.file_type.as_char());
+        for shift in [6u16, 3, 0] {
+            let bits = (self.mode >> shift) & 0o7;
+            s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        s
+    }
+}
+
+/// A single directory entry
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+/// A file, directory or other object within a filesystem
+///
+/// Operations a node does not support fall back to an error, so simple
+/// filesystems only implement what they need.
+pub trait Inode: Send + Sync {
+    /// Get the attributes of this node
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    /// Read from the node at `offset`, returning the number of bytes read
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Write to the node at `offset`, returning the number of bytes written
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Change the size of a regular file
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Find a child of this directory by name
+    fn lookup(&self, _name: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// List the children of this directory, excluding `.` and `..`
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a regular file or directory in this directory
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u16) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Remove a child of this directory
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Create a symbolic link in this directory
+    fn symlink(&self, _name: &str, _target: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Read the target of a symbolic link
+    fn readlink(&self) -> FsResult<String> {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// Change the permission bits
+    fn chmod(&self, _mode: u16) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+}
+
+/// A mounted filesystem instance
+pub trait FileSystem: Send + Sync {
+    /// Filesystem type name, e.g. `ext2`
+    fn name(&self) -> &'static str;
+
+    /// The root directory of this filesystem
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write any cached state back to the underlying device
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// Mount the filesystem found on a registered block device
+pub fn mount_device(device_name: &str, path: &str, fs_type: &str) -> FsResult<()> {
+    let device = crate::drivers::block::get(device_name).ok_or(FsError::NotFound)?;
+
+    let fs: Arc<dyn FileSystem> = match fs_type {
+        "ext2" => ext2::Ext2Fs::mount(device)?,
+        _ => return Err(FsError::NotSupported),
+    };
+
+    mount::mount(path, fs)
+}
+
+/// Seconds since the UNIX epoch for on-disk timestamps
+pub fn timestamp() -> u32 {
+    crate::time::now() as u32
+}
+
+/// Initialize the filesystem layer
+pub fn init() {
+    crate::serial::print("Initializing VFS...\n");
+
+    if mount::mount("/dev", Arc::new(devfs::DevFs)).is_err() {
+        crate::serial::print("Failed to mount /dev\n");
+    }
+    if mount::mount("/proc", Arc::new(procfs::ProcFs)).is_err() {
+        crate::serial::print("Failed to mount /proc\n");
+    }
+}
@@ -0,0 +1,145 @@
+//! Mount table
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::path;
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+
+/// A filesystem attached at a path
+struct Mount {
+    components: Vec<String>,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Active mounts
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// Attach a filesystem at `path`
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult<()> {
+    let components = path::normalize(path)?;
+    let mut mounts = MOUNTS.lock();
+
+    if mounts.iter().any(|m| m.components == components) {
+        return Err(FsError::Busy);
+    }
+
+    crate::serial::print("VFS: mounted ");
+    crate::serial::print(fs.name());
+    crate::serial::print(" on ");
+    crate::serial::print(&path::join(&components));
+    crate::serial::print("\n");
+
+    mounts.push(Mount { components, fs });
+    Ok(())
+}
+
+/// Detach the filesystem mounted at `path`, syncing it first
+pub fn unmount(path: &str) -> FsResult<()> {
+    let components = path::normalize(path)?;
+    let mut mounts = MOUNTS.lock();
+
+    // Refuse while other filesystems are mounted beneath this one
+    if mounts
+        .iter()
+        .any(|m| m.components.len() > components.len() && m.components.starts_with(&components))
+    {
+        return Err(FsError::Busy);
+    }
+
+    let index = mounts
+        .iter()
+        .position(|m| m.components == components)
+        .ok_or(FsError::NotFound)?;
+
+    mounts[index].fs.sync()?;
+    mounts.remove(index);
+    Ok(())
+}
+
+/// List active mounts as (path, filesystem type)
+pub fn list() -> Vec<(String, &'static str)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|m| (path::join(&m.components), m.fs.name()))
+        .collect()
+}
+
+/// Sync every mounted filesystem, returning the first error seen
+pub fn sync_all() -> FsResult<()> {
+    let filesystems: Vec<Arc<dyn FileSystem>> = MOUNTS.lock().iter().map(|m| m.fs.clone()).collect();
+
+    let mut result = Ok(());
+    for fs in filesystems {
+        if let Err(e) = fs.sync()
+            && result.is_ok()
+        {
+            result = Err(e);
+        }
+    }
+
+    // Also write back sectors written directly through /dev block nodes
+    if let Err(e) = crate::drivers::block::flush_all()
+        && result.is_ok()
+    {
+        result = Err(e.into());
+    }
+    result
+}
+
+/// Find the mount covering `components`
+///
+/// Returns how many leading components the mount point consumed together
+/// with the root inode of that mount. Without a filesystem on `/`, the root
+/// directory is a synthetic listing of the mount points.
+pub fn root_for(components: &[String]) -> FsResult<(usize, Arc<dyn Inode>)> {
+    let mounts = MOUNTS.lock();
+
+    let best = mounts
+        .iter()
+        .filter(|m| components.starts_with(&m.components))
+        .max_by_key(|m| m.components.len());
+
+    match best {
+        Some(m) => Ok((m.components.len(), m.fs.root())),
+        None if components.is_empty() => Ok((0, Arc::new(MountRoot))),
+        None => Err(FsError::NotFound),
+    }
+}
+
+/// Stand-in root directory used until a filesystem is mounted on `/`
+struct MountRoot;
+
+impl Inode for MountRoot {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(1, FileType::Directory, 0o755, 0))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        MOUNTS
+            .lock()
+            .iter()
+            .find(|m| m.components.len() == 1 && m.components[0] == name)
+            .map(|m| m.fs.root())
+            .ok_or(FsError::NotFound)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let mut entries: Vec<DirEntry> = Vec::new();
+        for m in MOUNTS.lock().iter() {
+            if let Some(first) = m.components.first()
+                && !entries.iter().any(|e| &e.name == first)
+            {
+                entries.push(DirEntry {
+                    name: first.clone(),
+                    inode: 0,
+                    file_type: FileType::Directory,
+                });
+            }
+        }
+        Ok(entries)
+    }
+}
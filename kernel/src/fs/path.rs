@@ -0,0 +1,120 @@
+//! Path parsing and lookup across mount points
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{FileType, FsError, FsResult, Inode};
+
+/// Maximum number of symlinks followed while resolving one path
+const MAX_SYMLINKS: usize = 8;
+
+/// Maximum length of a single path component
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Split an absolute path into components, resolving `.` and `..` lexically
+///
+/// Relative paths are interpreted from the root directory.
+pub fn normalize(path: &str) -> FsResult<Vec<String>> {
+    let mut components: Vec<String> = Vec::new();
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name if name.len() > MAX_NAME_LEN => return Err(FsError::NameTooLong),
+            name => components.push(String::from(name)),
+        }
+    }
+
+    Ok(components)
+}
+
+/// Join components back into an absolute path
+pub fn join(components: &[String]) -> String {
+    if components.is_empty() {
+        return String::from("/");
+    }
+
+    let mut path = String::new();
+    for component in components {
+        path.push('/');
+        path.push_str(component);
+    }
+    path
+}
+
+/// Split a path into its parent directory and final component
+pub fn split_parent(path: &str) -> FsResult<(String, String)> {
+    let mut components = normalize(path)?;
+    let name = components.pop().ok_or(FsError::InvalidPath)?;
+    Ok((join(&components), name))
+}
+
+/// Resolve a path to an inode
+///
+/// Symlinks in intermediate components are always followed; the final
+/// component is followed only when `follow_last` is set.
+pub fn resolve(path: &str, follow_last: bool) -> FsResult<Arc<dyn Inode>> {
+    let mut components = normalize(path)?;
+    let mut symlinks = 0;
+
+    'restart: loop {
+        let (depth, mut node) = super::mount::root_for(&components)?;
+
+        for i in depth..components.len() {
+            let next = node.lookup(&components[i])?;
+            let is_last = i + 1 == components.len();
+
+            if (!is_last || follow_last) && next.metadata()?.file_type == FileType::Symlink {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(FsError::TooManySymlinks);
+                }
+
+                // Splice the link target in place of the link and start over,
+                // since the target may point into another mount.
+                let target = next.readlink()?;
+                let mut new_path = if target.starts_with('/') {
+                    String::new()
+                } else {
+                    join(&components[..i])
+                };
+                new_path.push('/');
+                new_path.push_str(&target);
+                for rest in &components[i + 1..] {
+                    new_path.push('/');
+                    new_path.push_str(rest);
+                }
+
+                components = normalize(&new_path)?;
+                continue 'restart;
+            }
+
+            node = next;
+        }
+
+        return Ok(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_dots() {
+        assert_eq!(normalize("/a/./b/../c").unwrap(), vec!["a", "c"]);
+        assert_eq!(normalize("/../..").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_split_parent() {
+        let (parent, name) = split_parent("/mnt/dir/file.txt").unwrap();
+        assert_eq!(parent, "/mnt/dir");
+        assert_eq!(name, "file.txt");
+        assert!(split_parent("/").is_err());
+    }
+}
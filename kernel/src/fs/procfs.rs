@@ -0,0 +1,359 @@
+//! procfs: kernel state exposed as read-only text files
+//!
+//! A file is generated when it is read from the start, so its contents
+//! reflect the current state and its reported size is zero. Reads further in
+//! are served from that same text, so reading in chunks never mixes two
+//! generations.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use limine::request::ExecutableCmdlineRequest;
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+use crate::task::task::{TaskId, TaskInfo};
+
+/// Request the kernel command line from Limine
+#[used]
+#[unsafe(link_section = ".limine_requests")]
+static CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
+/// Generates a file in the procfs root
+type Generator = fn() -> String;
+
+/// Generates a file under `tasks/<id>/`
+type TaskGenerator = fn(&TaskInfo) -> String;
+
+/// Files in the procfs root and the functions that generate them
+const FILES: &[(&str, Generator)] = &[
+    ("cmdline", cmdline),
+    ("cpuinfo", cpuinfo),
+    ("interrupts", interrupts),
+    ("meminfo", meminfo),
+    ("memmap", memmap),
+    ("rtc", rtc),
+    ("uptime", uptime),
+];
+
+/// Per-task files under `tasks/<id>/`
+const TASK_FILES: &[(&str, TaskGenerator)] = &[
+    ("name", task_name),
+    ("stack", task_stack),
+    ("status", task_status),
+];
+
+/// Inode numbers: root is 1, root files follow, task nodes are derived from the ID
+const ROOT_INO: u64 = 1;
+const TASKS_INO: u64 = 2;
+const FIRST_FILE_INO: u64 = 16;
+const TASK_INO_BASE: u64 = 0x1000;
+
+fn task_ino(id: TaskId, file: usize) -> u64 {
+    TASK_INO_BASE + id.0 * 16 + file as u64
+}
+
+/// The procfs filesystem
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(ProcRoot)
+    }
+}
+
+/// Text of a generated file as of its last read from the start
+#[derive(Default)]
+struct Snapshot(Mutex<Option<String>>);
+
+impl Snapshot {
+    /// Serve a slice of the text, generating it afresh at offset 0 or if
+    /// there is none yet
+    fn read(&self, offset: u64, buf: &mut [u8], generate: impl FnOnce() -> FsResult<String>) -> FsResult<usize> {
+        let mut content = self.0.lock();
+        if offset == 0 || content.is_none() {
+            *content = Some(generate()?);
+        }
+        read_generated(content.as_deref().unwrap_or(""), offset, buf)
+    }
+}
+
+/// Serve a slice of generated text
+fn read_generated(content: &str, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+    let bytes = content.as_bytes();
+    if offset >= bytes.len() as u64 {
+        return Ok(0);
+    }
+    let start = offset as usize;
+    let len = buf.len().min(bytes.len() - start);
+    buf[..len].copy_from_slice(&bytes[start..start + len]);
+    Ok(len)
+}
+
+fn find_task(id: u64) -> Option<TaskInfo> {
+    crate::task::with_scheduler(|scheduler| scheduler.task_infos())
+        .into_iter()
+        .find(|info| info.id.0 == id)
+}
+
+/// The `/proc` directory itself
+struct ProcRoot;
+
+impl Inode for ProcRoot {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(ROOT_INO, FileType::Directory, 0o555, 0))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        if name == "tasks" {
+            return Ok(Arc::new(TasksDir));
+        }
+        FILES
+            .iter()
+            .position(|(file_name, _)| *file_name == name)
+            .map(|index| {
+                Arc::new(ProcFile {
+                    ino: FIRST_FILE_INO + index as u64,
+                    generate: FILES[index].1,
+                    snapshot: Snapshot::default(),
+                }) as Arc<dyn Inode>
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let mut entries: Vec<DirEntry> = FILES
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| DirEntry {
+                name: String::from(*name),
+                inode: FIRST_FILE_INO + index as u64,
+                file_type: FileType::Regular,
+            })
+            .collect();
+        entries.push(DirEntry {
+            name: String::from("tasks"),
+            inode: TASKS_INO,
+            file_type: FileType::Directory,
+        });
+        Ok(entries)
+    }
+}
+
+/// A file in the procfs root
+struct ProcFile {
+    ino: u64,
+    generate: Generator,
+    snapshot: Snapshot,
+}
+
+impl Inode for ProcFile {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(self.ino, FileType::Regular, 0o444, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        self.snapshot.read(offset, buf, || Ok((self.generate)()))
+    }
+}
+
+/// `/proc/tasks`: one directory per task
+struct TasksDir;
+
+impl Inode for TasksDir {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(TASKS_INO, FileType::Directory, 0o555, 0))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let id = name.parse::<u64>().map_err(|_| FsError::NotFound)?;
+        find_task(id).ok_or(FsError::NotFound)?;
+        Ok(Arc::new(TaskDir { id: TaskId(id) }))
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        Ok(crate::task::with_scheduler(|scheduler| scheduler.task_infos())
+            .iter()
+            .map(|info| DirEntry {
+                name: format!("{}", info.id.0),
+                inode: task_ino(info.id, 0),
+                file_type: FileType::Directory,
+            })
+            .collect())
+    }
+}
+
+/// `/proc/tasks/<id>`
+struct TaskDir {
+    id: TaskId,
+}
+
+impl Inode for TaskDir {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(task_ino(self.id, 0), FileType::Directory, 0o555, 0))
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        TASK_FILES
+            .iter()
+            .position(|(file_name, _)| *file_name == name)
+            .map(|index| {
+                Arc::new(TaskFile {
+                    id: self.id,
+                    index,
+                    snapshot: Snapshot::default(),
+                }) as Arc<dyn Inode>
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        Ok(TASK_FILES
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| DirEntry {
+                name: String::from(*name),
+                inode: task_ino(self.id, index + 1),
+                file_type: FileType::Regular,
+            })
+            .collect())
+    }
+}
+
+/// A file under `/proc/tasks/<id>`
+struct TaskFile {
+    id: TaskId,
+    index: usize,
+    snapshot: Snapshot,
+}
+
+impl Inode for TaskFile {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata::synthetic(task_ino(self.id, self.index + 1), FileType::Regular, 0o444, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        self.snapshot.read(offset, buf, || {
+            // The task may have exited since it was looked up
+            let info = find_task(self.id.0).ok_or(FsError::NotFound)?;
+            Ok((TASK_FILES[self.index].1)(&info))
+        })
+    }
+}
+
+fn cmdline() -> String {
+    let mut text = CMDLINE_REQUEST
+        .get_response()
+        .and_then(|response| response.cmdline().to_str().ok())
+        .map(String::from)
+        .unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+fn interrupts() -> String {
+    let mut text = String::new();
+    for vector in 0..=255u8 {
+        let count = crate::interrupts::count(vector);
+        let name = crate::interrupts::vector_name(vector);
+        if count == 0 && name.is_none() {
+            continue;
+        }
+        let _ = writeln!(text, "{:>3}: {:>10}  {}", vector, count, name.unwrap_or("-"));
+    }
+    text
+}
+
+fn meminfo() -> String {
+    let map = crate::memory::memory_map();
+    let total: u64 = map.iter().map(|entry| entry.length).sum();
+    let usable: u64 = map
+        .iter()
+        .filter(|entry| crate::memory::is_usable(entry.entry_type))
+        .map(|entry| entry.length)
+        .sum();
+
+    let mut text = String::new();
+    let _ = writeln!(text, "MemTotal:    {:>10} kB", total / 1024);
+    let _ = writeln!(text, "MemUsable:   {:>10} kB", usable / 1024);
+    let _ = writeln!(text, "MemFree:     {:>10} kB", crate::memory::physical::free_memory() / 1024);
+    let _ = writeln!(text, "FreeFrames:  {:>10}", crate::memory::physical::free_frames());
+    let _ = writeln!(text, "FrameSize:   {:>10} B", crate::memory::physical::FRAME_SIZE);
+    text
+}
+
+fn memmap() -> String {
+    let mut text = String::new();
+    for entry in crate::memory::memory_map() {
+        let _ = writeln!(
+            text,
+            "{:#018x}-{:#018x} {}",
+            entry.base,
+            entry.base + entry.length,
+            crate::memory::entry_type_name(entry.entry_type)
+        );
+    }
+    text
+}
+
+fn cpuinfo() -> String {
+    let mut text = String::new();
+    for cpu in crate::smp::percpu::all() {
+        let _ = writeln!(text, "processor\t: {}", cpu.index);
+        let _ = writeln!(text, "apicid\t\t: {}", cpu.apic_id);
+        let _ = writeln!(
+            text,
+            "online\t\t: {}\n",
+            if cpu.online.load(core::sync::atomic::Ordering::Acquire) { "yes" } else { "no" }
+        );
+    }
+    text
+}
+
+fn rtc() -> String {
+    let mut text = String::new();
+    match crate::drivers::rtc::read() {
+        Some(date) => {
+            let _ = writeln!(text, "rtc_time\t: {:02}:{:02}:{:02}", date.hour, date.minute, date.second);
+            let _ = writeln!(text, "rtc_date\t: {}-{:02}-{:02}", date.year, date.month, date.day);
+        }
+        None => text.push_str("rtc_time\t: unreadable\n"),
+    }
+    let hz = crate::drivers::rtc::periodic_hz();
+    let _ = writeln!(text, "periodic_IRQ\t: {}", if hz != 0 { "yes" } else { "no" });
+    let _ = writeln!(text, "periodic_freq\t: {}", hz);
+    let _ = writeln!(text, "periodic_ticks\t: {}", crate::drivers::rtc::periodic_ticks());
+    text
+}
+
+fn uptime() -> String {
+    let uptime = crate::time::uptime();
+    format!("{}.{:02} {}\n", uptime.as_secs(), uptime.subsec_millis() / 10, crate::drivers::timer::ticks())
+}
+
+fn task_name(info: &TaskInfo) -> String {
+    format!("{}\n", info.name)
+}
+
+fn task_stack(info: &TaskInfo) -> String {
+    format!(
+        "Base:\t{:#x}\nSize:\t{}\nRSP:\t{:#x}\nRIP:\t{:#x}\n",
+        info.stack_base, info.stack_size, info.rsp, info.rip
+    )
+}
+
+fn task_status(info: &TaskInfo) -> String {
+    format!(
+        "Name:\t{}\nId:\t{}\nState:\t{}\nCpu:\t{}\n",
+        info.name,
+        info.id.0,
+        info.state.as_str(),
+        info.cpu
+    )
+}
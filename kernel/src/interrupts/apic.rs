@@ -0,0 +1,223 @@
+//! Local APIC
+//!
+//! Every CPU has a local APIC that receives interrupts from the I/O APICs
+//! and other CPUs and must be told when a handler is done (EOI). Its
+//! registers are memory mapped at the address the MADT gives.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use x86_64::PhysAddr;
+use x86_64::registers::model_specific::Msr;
+
+use crate::acpi::MadtEntry;
+
+/// Register offsets
+const REG_ID: usize = 0x20;
+const REG_TPR: usize = 0x80;
+const REG_EOI: usize = 0xB0;
+const REG_SVR: usize = 0xF0;
+const REG_ESR: usize = 0x280;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_LVT_LINT0: usize = 0x350;
+const REG_LVT_LINT1: usize = 0x360;
+const REG_LVT_ERROR: usize = 0x370;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+/// Spurious interrupt vector register: software enable bit
+const SVR_ENABLE: u32 = 1 << 8;
+
+/// LVT bits
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL: u32 = 1 << 15;
+
+/// Interrupt command register: NMI delivery mode, delivery status and the
+/// all-but-self shorthand
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_OTHERS: u32 = 0b11 << 18;
+
+/// Timer divide configuration for dividing the bus clock by 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+/// Window used to measure the timer's rate against the TSC
+const TIMER_CALIBRATION: Duration = Duration::from_millis(10);
+
+/// IA32_APIC_BASE MSR and its global enable bit
+const MSR_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// Vectors owned by the local APIC
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+pub const ERROR_VECTOR: u8 = 0xFE;
+pub const TIMER_VECTOR: u8 = 0xFD;
+pub const CALL_VECTOR: u8 = 0xFC;
+
+/// Destination of an inter-processor interrupt
+#[derive(Debug, Clone, Copy)]
+pub enum IpiTarget {
+    /// The CPU with this APIC ID
+    Cpu(u32),
+    /// Every CPU but the sender
+    Others,
+}
+
+/// Virtual address of the register block, 0 until mapped
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Timer counts per second after the divider, 0 until calibrated
+static TIMER_HZ: AtomicU64 = AtomicU64::new(0);
+
+fn read(offset: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((BASE.load(Ordering::Relaxed) + offset) as *const u32) }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { core::ptr::write_volatile((BASE.load(Ordering::Relaxed) + offset) as *mut u32, value) }
+}
+
+/// Whether the CPU has a local APIC at all
+pub fn supported() -> bool {
+    let result = core::arch::x86_64::__cpuid(1);
+    result.edx & (1 << 9) != 0
+}
+
+/// This CPU's APIC ID
+pub fn id() -> u32 {
+    read(REG_ID) >> 24
+}
+
+/// Signal the end of the interrupt being handled
+pub fn eoi() {
+    write(REG_EOI, 0);
+}
+
+/// Send a fixed interrupt with `vector` to `target`
+pub fn send_ipi(target: IpiTarget, vector: u8) {
+    send(target, vector as u32);
+}
+
+/// Send a non-maskable interrupt, which arrives even while the target has
+/// interrupts disabled
+pub fn send_nmi(target: IpiTarget) {
+    send(target, ICR_DELIVERY_NMI);
+}
+
+/// Write the interrupt command register for `target`
+fn send(target: IpiTarget, command: u32) {
+    let (destination, shorthand) = match target {
+        IpiTarget::Cpu(apic_id) => (apic_id << 24, 0),
+        IpiTarget::Others => (0, ICR_OTHERS),
+    };
+    // Writing the low half sends, so both halves must come from one context
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        while read(REG_ICR_LOW) & ICR_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+        write(REG_ICR_HIGH, destination);
+        write(REG_ICR_LOW, shorthand | command);
+    });
+}
+
+/// Map the local APIC registers; the address is the same on every CPU
+pub fn map(phys: u64) -> Result<(), &'static str> {
+    let virt = crate::memory::paging::map_mmio(PhysAddr::new(phys), 4096)?;
+    BASE.store(virt.as_u64() as usize, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Enable this CPU's local APIC
+///
+/// LINT pins are masked unless the MADT wires them to NMI; external
+/// interrupts arrive through the I/O APIC instead of the 8259 virtual wire.
+pub fn init_local(entries: &[MadtEntry]) {
+    unsafe {
+        let mut msr = Msr::new(MSR_APIC_BASE);
+        let value = msr.read();
+        msr.write(value | APIC_BASE_ENABLE);
+    }
+
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_LVT_LINT0, LVT_MASKED);
+    write(REG_LVT_LINT1, LVT_MASKED);
+    write(REG_LVT_ERROR, ERROR_VECTOR as u32);
+
+    let id = id();
+    for entry in entries {
+        let MadtEntry::LocalApicNmi { processor_id, flags, lint } = *entry else {
+            continue;
+        };
+        // Processor 0xFF applies to all; other IDs are ACPI processor IDs
+        let applies = processor_id == 0xFF
+            || entries.iter().any(|e| {
+                matches!(*e, MadtEntry::LocalApic { processor_id: p, apic_id, .. } if p == processor_id && apic_id as u32 == id)
+            });
+        if !applies {
+            continue;
+        }
+        let mut lvt = LVT_DELIVERY_NMI;
+        if flags & crate::acpi::madt::POLARITY_MASK == crate::acpi::madt::POLARITY_ACTIVE_LOW {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if flags & crate::acpi::madt::TRIGGER_MASK == crate::acpi::madt::TRIGGER_LEVEL {
+            lvt |= LVT_LEVEL;
+        }
+        write(if lint == 0 { REG_LVT_LINT0 } else { REG_LVT_LINT1 }, lvt);
+    }
+
+    // Clear stale errors (the ESR must be written before it is read)
+    write(REG_ESR, 0);
+    write(REG_ESR, 0);
+    // Accept all priorities, then software-enable with the spurious vector
+    write(REG_TPR, 0);
+    write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    eoi();
+}
+
+/// Whether the timer has been calibrated and can be armed
+pub fn timer_ready() -> bool {
+    TIMER_HZ.load(Ordering::Relaxed) != 0
+}
+
+/// Fire `TIMER_VECTOR` once on this CPU after `delay`
+pub fn arm_timer(delay: Duration) {
+    let hz = TIMER_HZ.load(Ordering::Relaxed) as u128;
+    let count = (delay.as_nanos() * hz / 1_000_000_000).clamp(1, u32::MAX as u128) as u32;
+    write(REG_LVT_TIMER, TIMER_VECTOR as u32);
+    write(REG_TIMER_INITIAL, count);
+}
+
+/// Cancel a pending one-shot on this CPU
+pub fn stop_timer() {
+    if timer_ready() {
+        write(REG_TIMER_INITIAL, 0);
+    }
+}
+
+/// Set this CPU's timer up for one-shot use, measuring its rate against
+/// the TSC the first time; the bus clock is the same on every CPU
+pub fn init_timer() {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    if !timer_ready() {
+        write(REG_LVT_TIMER, LVT_MASKED);
+        write(REG_TIMER_INITIAL, u32::MAX);
+        let start = crate::time::Instant::now();
+        while start.elapsed() < TIMER_CALIBRATION {
+            core::hint::spin_loop();
+        }
+        let counted = u32::MAX - read(REG_TIMER_CURRENT);
+        let elapsed = start.elapsed().as_nanos() as u64;
+        write(REG_TIMER_INITIAL, 0);
+        TIMER_HZ.store(counted as u64 * 1_000_000_000 / elapsed, Ordering::Relaxed);
+
+        crate::serial::print("LAPIC timer: ");
+        crate::memory::print_decimal(TIMER_HZ.load(Ordering::Relaxed) / 1000);
+        crate::serial::print(" kHz, one-shot\n");
+    }
+    write(REG_LVT_TIMER, TIMER_VECTOR as u32);
+}
@@ -1,5 +1,10 @@
 //! Global Descriptor Table (GDT) and Task State Segment (TSS) setup
+//!
+//! Every CPU needs its own TSS, and so its own GDT to hold the TSS
+//! descriptor. The bootstrap processor's tables are static; those of the
+//! application processors are allocated as they come up.
 
+use alloc::boxed::Box;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
@@ -7,48 +12,75 @@ use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Size of each CPU's double fault stack
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
 lazy_static! {
     static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            
-            let stack_start = VirtAddr::from_ptr(unsafe { &raw const STACK });
-            let stack_end = stack_start + STACK_SIZE as u64;
-            stack_end
-        };
-        tss
+        static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+        let stack_start = VirtAddr::from_ptr(unsafe { &raw const STACK });
+        new_tss(stack_start)
     };
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
 }
 
 struct Selectors {
     code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
-pub fn init() {
-    use x86_64::instructions::segmentation::{Segment, CS};
+/// A TSS whose double fault stack starts at `stack_start`
+fn new_tss(stack_start: VirtAddr) -> TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_start + DOUBLE_FAULT_STACK_SIZE as u64;
+    tss
+}
+
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            data_selector,
+            tss_selector,
+        },
+    )
+}
+
+/// Load a GDT and reload every segment register from it
+///
+/// The bootloader's data selectors index its own GDT, so they are replaced
+/// too; a stale SS would fault on the first `iretq`.
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.0.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(gdt.1.code_selector);
+        SS::set_reg(gdt.1.data_selector);
+        DS::set_reg(gdt.1.data_selector);
+        ES::set_reg(gdt.1.data_selector);
+        load_tss(gdt.1.tss_selector);
     }
 }
+
+/// Load the bootstrap processor's GDT and TSS
+pub fn init() {
+    load(&GDT);
+}
+
+/// Give an application processor its own GDT, TSS and double fault stack
+pub fn init_ap() {
+    let stack: &'static mut [u8] = Box::leak(alloc::vec![0u8; DOUBLE_FAULT_STACK_SIZE].into_boxed_slice());
+    let tss = Box::leak(Box::new(new_tss(VirtAddr::from_ptr(stack.as_ptr()))));
+    load(Box::leak(Box::new(new_gdt(tss))));
+}
@@ -11,6 +11,9 @@ lazy_static! {
         // Divide-by-zero (#DE)
         idt.divide_error.set_handler_fn(divide_by_zero_handler);
 
+        // Non-maskable interrupt, used to stop the other CPUs on panic
+        idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
+
         // Page fault (#PF)
         idt.page_fault.set_handler_fn(page_fault_handler);
 
@@ -21,9 +24,8 @@ lazy_static! {
                 .set_stack_index(super::gdt::DOUBLE_FAULT_IST_INDEX);
         }
 
-        // Hardware interrupts (remapped by PIC)
-        idt[32].set_handler_fn(crate::drivers::timer::timer_interrupt_handler);   // IRQ 0 -> 32
-        idt[33].set_handler_fn(crate::drivers::keyboard::keyboard_interrupt_handler); // IRQ 1 -> 33
+        // Hardware interrupts, dispatched to handlers claimed with register_irq
+        super::irq::install(&mut idt);
 
         idt
     };
@@ -35,12 +37,23 @@ pub unsafe fn init() {
 }
 
 extern "x86-interrupt" fn divide_by_zero_handler(stack_frame: InterruptStackFrame) {
+    super::record(0);
     crate::serial::print("EXCEPTION: DIVIDE BY ZERO\n");
     log_stack_frame(&stack_frame);
     crate::panic::hcf();
 }
 
+/// Halt if a panic on another CPU sent this NMI; other NMIs, such as from
+/// LINT pins the MADT wires to NMI, are ignored
+extern "x86-interrupt" fn nmi_handler(_stack_frame: InterruptStackFrame) {
+    super::record(2);
+    if crate::smp::stopping() {
+        crate::panic::hcf();
+    }
+}
+
 extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    super::record(14);
     let addr = Cr2::read().expect("CR2 read failed").as_u64();
     crate::serial::print("EXCEPTION: PAGE FAULT\n");
     crate::serial::print("Accessed Address: ");
@@ -53,6 +66,7 @@ extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, e
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    super::record(8);
     crate::serial::print("EXCEPTION: DOUBLE FAULT\n");
     log_stack_frame(&stack_frame);
     crate::panic::hcf();
@@ -0,0 +1,153 @@
+//! I/O APIC
+//!
+//! Each I/O APIC owns a range of global system interrupts (GSIs) and has
+//! one redirection entry per input, naming the vector and destination CPU.
+//! ISA IRQs map to the GSI of the same number unless the MADT has an
+//! interrupt source override saying otherwise.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+use crate::acpi::{Madt, MadtEntry, madt};
+
+/// Indirect register access: select a register, then read or write the window
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+/// Redirection entry bits (low dword)
+const ENTRY_ACTIVE_LOW: u32 = 1 << 13;
+const ENTRY_LEVEL: u32 = 1 << 15;
+const ENTRY_MASKED: u32 = 1 << 16;
+
+/// One I/O APIC
+struct IoApic {
+    id: u8,
+    base: usize,
+    gsi_base: u32,
+    inputs: u32,
+}
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            core::ptr::read_volatile((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            core::ptr::write_volatile((self.base + IOWIN) as *mut u32, value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.inputs
+    }
+}
+
+/// How an interrupt line is signalled
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level: bool,
+}
+
+static IO_APICS: Mutex<Vec<IoApic>> = Mutex::new(Vec::new());
+
+/// Interrupt source overrides from the MADT: (ISA IRQ, GSI, flags)
+static OVERRIDES: Mutex<Vec<(u8, u32, u16)>> = Mutex::new(Vec::new());
+
+/// Map every I/O APIC in the MADT and mask all of their inputs
+pub fn init(madt: &Madt) -> usize {
+    let mut io_apics = IO_APICS.lock();
+    for (id, address, gsi_base) in madt.io_apics() {
+        let Ok(virt) = crate::memory::paging::map_mmio(PhysAddr::new(address as u64), 0x20) else {
+            continue;
+        };
+        let mut io_apic = IoApic {
+            id,
+            base: virt.as_u64() as usize,
+            gsi_base,
+            inputs: 0,
+        };
+        io_apic.inputs = ((io_apic.read(REG_VERSION) >> 16) & 0xFF) + 1;
+        for input in 0..io_apic.inputs {
+            io_apic.write(REG_REDIRECTION + input * 2, ENTRY_MASKED);
+        }
+
+        crate::serial::print("IOAPIC: id ");
+        crate::memory::print_decimal(io_apic.id as u64);
+        crate::serial::print(", GSIs ");
+        crate::memory::print_decimal(gsi_base as u64);
+        crate::serial::print("-");
+        crate::memory::print_decimal((gsi_base + io_apic.inputs - 1) as u64);
+        crate::serial::print("\n");
+        if (gsi_base + io_apic.inputs) as usize > super::irq::IRQ_LINES {
+            crate::serial::print("IOAPIC: GSIs from ");
+            crate::memory::print_decimal(gsi_base.max(super::irq::IRQ_LINES as u32) as u64);
+            crate::serial::print(" have no vector and cannot be used\n");
+        }
+        io_apics.push(io_apic);
+    }
+
+    *OVERRIDES.lock() = madt
+        .entries
+        .iter()
+        .filter_map(|entry| match *entry {
+            MadtEntry::InterruptOverride { bus: 0, source, gsi, flags } => Some((source, gsi, flags)),
+            _ => None,
+        })
+        .collect();
+    io_apics.len()
+}
+
+/// Where legacy IRQ `line` arrives and how it is triggered
+///
+/// ISA lines are edge triggered and active high by default; lines 16 and
+/// up are PCI interrupts, which are level triggered and active low.
+pub fn route(line: u8) -> Route {
+    let isa = line < 16;
+    let default = Route {
+        gsi: line as u32,
+        active_low: !isa,
+        level: !isa,
+    };
+    let overrides = OVERRIDES.lock();
+    let Some(&(_, gsi, flags)) = overrides.iter().find(|&&(source, _, _)| source == line) else {
+        return default;
+    };
+    // Flag values of 0 mean "conforms to the bus", i.e. the defaults above
+    let polarity = flags & madt::POLARITY_MASK;
+    let trigger = flags & madt::TRIGGER_MASK;
+    Route {
+        gsi,
+        active_low: if polarity == 0 { default.active_low } else { polarity == madt::POLARITY_ACTIVE_LOW },
+        level: if trigger == 0 { default.level } else { trigger == madt::TRIGGER_LEVEL },
+    }
+}
+
+/// Program the redirection entry for `route` and unmask it
+pub fn enable(route: Route, vector: u8, apic_id: u32) -> Result<(), &'static str> {
+    let io_apics = IO_APICS.lock();
+    let io_apic = io_apics.iter().find(|a| a.handles(route.gsi)).ok_or("no I/O APIC for GSI")?;
+    let input = route.gsi - io_apic.gsi_base;
+
+    let mut low = vector as u32;
+    if route.active_low {
+        low |= ENTRY_ACTIVE_LOW;
+    }
+    if route.level {
+        low |= ENTRY_LEVEL;
+    }
+    // Destination first, so the entry is complete when it is unmasked
+    io_apic.write(REG_REDIRECTION + input * 2 + 1, apic_id << 24);
+    io_apic.write(REG_REDIRECTION + input * 2, low);
+    Ok(())
+}
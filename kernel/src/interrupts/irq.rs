@@ -0,0 +1,139 @@
+//! Hardware IRQ dispatch
+//!
+//! IRQ line `n` is delivered on vector `IRQ_BASE + n`, whether it comes
+//! from the I/O APIC or, as a fallback, the 8259 PIC. Lines 0-15 are the
+//! legacy ISA IRQs and 16 and up the I/O APIC's PCI inputs. Drivers claim a
+//! line with `register_irq`; each vector's stub calls the handler and sends
+//! the EOI to whichever controller is in use.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use super::{apic, ioapic, pic};
+
+/// First IRQ vector; the PIC is remapped to the same place
+pub const IRQ_BASE: u8 = 32;
+
+/// Number of IRQ lines with vectors, the inputs of one standard I/O APIC
+///
+/// GSIs from here up, on larger or additional I/O APICs, have no vector
+/// and cannot be claimed.
+pub const IRQ_LINES: usize = 24;
+
+/// Handler of each line as a function pointer, 0 if unclaimed
+static HANDLERS: [AtomicUsize; IRQ_LINES] = [const { AtomicUsize::new(0) }; IRQ_LINES];
+
+/// Whether interrupts are routed through the APICs rather than the PIC
+static APIC_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_apic_mode(enabled: bool) {
+    APIC_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn apic_mode() -> bool {
+    APIC_MODE.load(Ordering::Relaxed)
+}
+
+/// Claim IRQ `line` for `handler` and unmask it
+///
+/// The handler runs with interrupts disabled; the EOI is sent after it returns.
+pub fn register_irq(line: u8, handler: fn()) -> Result<u8, &'static str> {
+    let slot = HANDLERS.get(line as usize).ok_or("IRQ line has no vector")?;
+    if slot
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return Err("IRQ line already in use");
+    }
+
+    let vector = IRQ_BASE + line;
+    let unmasked = if apic_mode() {
+        ioapic::enable(ioapic::route(line), vector, apic::id())
+    } else if line < 16 {
+        pic::unmask(line);
+        Ok(())
+    } else {
+        Err("PCI IRQs need an I/O APIC")
+    };
+    if let Err(e) = unmasked {
+        slot.store(0, Ordering::Release);
+        return Err(e);
+    }
+
+    crate::serial::print("IRQ ");
+    crate::memory::print_decimal(line as u64);
+    crate::serial::print(" -> vector ");
+    crate::memory::print_decimal(vector as u64);
+    crate::serial::print("\n");
+    Ok(vector)
+}
+
+/// Run the handler for `line` and acknowledge the interrupt
+fn dispatch(line: u8) {
+    super::record(IRQ_BASE + line);
+    if !apic_mode() && pic::is_spurious(line) {
+        // A spurious IRQ 15 still came through the master's cascade input
+        if line >= 8 {
+            pic::send_eoi(2);
+        }
+        return;
+    }
+    let handler = HANDLERS[line as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: fn() = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+    if apic_mode() {
+        apic::eoi();
+    } else {
+        pic::send_eoi(line);
+    }
+}
+
+macro_rules! irq_stubs {
+    ($($line:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+                dispatch($line);
+            }
+        )*
+
+        /// Point each IRQ vector at its stub
+        pub fn install(idt: &mut InterruptDescriptorTable) {
+            $(idt[IRQ_BASE + $line].set_handler_fn($name);)*
+            idt[apic::SPURIOUS_VECTOR].set_handler_fn(spurious_handler);
+            idt[apic::ERROR_VECTOR].set_handler_fn(apic_error_handler);
+            idt[apic::TIMER_VECTOR].set_handler_fn(apic_timer_handler);
+            idt[apic::CALL_VECTOR].set_handler_fn(call_handler);
+        }
+    };
+}
+
+irq_stubs! {
+    0 => irq0, 1 => irq1, 2 => irq2, 3 => irq3, 4 => irq4, 5 => irq5,
+    6 => irq6, 7 => irq7, 8 => irq8, 9 => irq9, 10 => irq10, 11 => irq11,
+    12 => irq12, 13 => irq13, 14 => irq14, 15 => irq15, 16 => irq16, 17 => irq17,
+    18 => irq18, 19 => irq19, 20 => irq20, 21 => irq21, 22 => irq22, 23 => irq23,
+}
+
+/// Spurious interrupts from the local APIC must not be acknowledged
+extern "x86-interrupt" fn spurious_handler(_stack_frame: InterruptStackFrame) {
+    super::record(apic::SPURIOUS_VECTOR);
+}
+
+extern "x86-interrupt" fn apic_error_handler(_stack_frame: InterruptStackFrame) {
+    super::record(apic::ERROR_VECTOR);
+    apic::eoi();
+}
+
+extern "x86-interrupt" fn apic_timer_handler(_stack_frame: InterruptStackFrame) {
+    super::record(apic::TIMER_VECTOR);
+    crate::time::sleep::handle_interrupt();
+    apic::eoi();
+}
+
+extern "x86-interrupt" fn call_handler(_stack_frame: InterruptStackFrame) {
+    super::record(apic::CALL_VECTOR);
+    crate::smp::call::handle_interrupt();
+    apic::eoi();
+}
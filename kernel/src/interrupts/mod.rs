@@ -1,10 +1,69 @@
-//! Interrupts subsystem: IDT, GDT, PIC, and exception handlers
+//! Interrupts subsystem: IDT, GDT, interrupt controllers and exception handlers
+//!
+//! External interrupts are routed through the local and I/O APICs described
+//! by the ACPI MADT. Without them, the 8259 PIC is used instead.
 
+pub mod apic;
 pub mod gdt;
 pub mod idt;
+pub mod ioapic;
+pub mod irq;
 pub mod pic;
 
-/// Initialize the interrupts subsystem (GDT, IDT, PIC)
+pub use irq::register_irq;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of times each interrupt vector has fired
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Record that an interrupt vector fired
+#[inline]
+pub fn record(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// How many times an interrupt vector has fired
+pub fn count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Short description of what an interrupt vector is used for
+pub fn vector_name(vector: u8) -> Option<&'static str> {
+    match vector {
+        0 => Some("divide error"),
+        2 => Some("NMI"),
+        8 => Some("double fault"),
+        14 => Some("page fault"),
+        32 => Some("timer"),
+        33 => Some("keyboard"),
+        apic::CALL_VECTOR => Some("function call IPI"),
+        apic::TIMER_VECTOR => Some("APIC timer"),
+        apic::ERROR_VECTOR => Some("APIC error"),
+        apic::SPURIOUS_VECTOR => Some("spurious"),
+        _ => None,
+    }
+}
+
+/// Switch interrupt delivery to the APICs if the MADT describes them
+///
+/// The PIC stays remapped and fully masked, so a stray interrupt from it
+/// still lands on an IRQ vector rather than an exception.
+fn init_apic() -> Result<(), &'static str> {
+    if !apic::supported() {
+        return Err("no local APIC");
+    }
+    let madt = crate::acpi::madt().ok_or("no MADT")?;
+    if ioapic::init(&madt) == 0 {
+        return Err("no I/O APIC");
+    }
+    apic::map(madt.local_apic_address)?;
+    apic::init_local(&madt.entries);
+    irq::set_apic_mode(true);
+    Ok(())
+}
+
+/// Initialize the interrupts subsystem (GDT, IDT, interrupt controllers)
 pub fn init() {
     crate::serial::print("Initializing GDT...\n");
     gdt::init();
@@ -14,6 +73,29 @@ pub fn init() {
     
     crate::serial::print("Remapping PIC...\n");
     pic::init();
+
+    match init_apic() {
+        Ok(()) => {
+            crate::serial::print("Interrupts: using APIC, local APIC ID ");
+            crate::memory::print_decimal(apic::id() as u64);
+            crate::serial::print("\n");
+        }
+        Err(e) => {
+            crate::serial::print("Interrupts: ");
+            crate::serial::print(e);
+            crate::serial::print(", falling back to the 8259 PIC\n");
+        }
+    }
     
     crate::serial::print("Interrupts subsystem initialized.\n");
 }
+
+/// Set up an application processor: its own GDT and TSS, the shared IDT
+/// and its local APIC
+pub fn init_ap() {
+    gdt::init_ap();
+    unsafe { idt::init(); }
+    if let Some(madt) = crate::acpi::madt() {
+        apic::init_local(&madt.entries);
+    }
+}
@@ -48,14 +48,28 @@ pub fn init() {
         PIC2_DAT.lock().write(ICW4_8086);
         io_wait();
 
-        // Mask all interrupts for now
+        // Mask everything; lines are unmasked as drivers register handlers
         PIC1_DAT.lock().write(0xFF);
         PIC2_DAT.lock().write(0xFF);
     }
 }
 
+/// Unmask one IRQ line, and the cascade line for the slave PIC's IRQs
+pub fn unmask(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            let mask = PIC2_DAT.lock().read();
+            PIC2_DAT.lock().write(mask & !(1 << (irq - 8)));
+            let mask = PIC1_DAT.lock().read();
+            PIC1_DAT.lock().write(mask & !(1 << 2));
+        } else {
+            let mask = PIC1_DAT.lock().read();
+            PIC1_DAT.lock().write(mask & !(1 << irq));
+        }
+    }
+}
+
 /// Send End of Interrupt signal
-#[allow(dead_code)]
 pub fn send_eoi(irq: u8) {
     unsafe {
         if irq >= 8 {
@@ -65,6 +79,27 @@ pub fn send_eoi(irq: u8) {
     }
 }
 
+/// OCW3: read the in-service register on the next command port read
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// Whether IRQ 7 or 15 is spurious, raised for a request that went away
+/// before it was acknowledged
+///
+/// The PIC then reports its lowest priority line without marking it in
+/// service, and must not get an EOI for it.
+pub fn is_spurious(irq: u8) -> bool {
+    let command = match irq {
+        7 => &PIC1_CMD,
+        15 => &PIC2_CMD,
+        _ => return false,
+    };
+    let mut port = command.lock();
+    unsafe {
+        port.write(OCW3_READ_ISR);
+        port.read() & 0x80 == 0
+    }
+}
+
 /// Small delay for PIC operations
 fn io_wait() {
     unsafe {
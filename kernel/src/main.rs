@@ -3,15 +3,21 @@
 #![feature(abi_x86_interrupt)]
 
 // Import modules
+mod acpi;
 mod graphics;
 mod memory;
 mod interrupts;
 mod drivers;
+mod fs;
 mod task;
 mod console;
 mod shell;
 mod panic;
+mod power;
 mod serial;
+mod smp;
+mod time;
+mod tty;
 
 use limine::request::{FramebufferRequest, StackSizeRequest};
 use limine::framebuffer::Framebuffer;
@@ -48,15 +54,30 @@ pub extern "C" fn _start() -> ! {
     // Initialize memory management
     memory::init();
 
+    // Set up this CPU's per-CPU data
+    smp::init_bsp();
+
+    // Locate ACPI tables
+    acpi::init();
+
     // Initialize IDT and exception handlers
     interrupts::init();
 
+    // Calibrate the clocks and timers
+    time::init();
+
     // Initialize device drivers
     drivers::init();
 
+    // Initialize the virtual filesystem
+    fs::init();
+
     // Initialize task management
     task::init();
 
+    // Start the other processors
+    smp::init();
+
     // Test frame allocation
     serial::print("\nTesting frame allocation...\n");
     if let Some(frame1) = memory::physical::alloc_frame() {
@@ -109,7 +130,7 @@ pub extern "C" fn _start() -> ! {
     
     // Show scheduler stats
     serial::print("Getting scheduler stats...\n");
-    let (ready_tasks, switches) = task::SCHEDULER.lock().stats();
+    let (ready_tasks, switches) = task::with_scheduler(|scheduler| scheduler.stats());
     serial::print("Scheduler: ");
     memory::print_decimal(ready_tasks as u64);
     serial::print(" ready tasks, ");
@@ -138,21 +159,37 @@ pub extern "C" fn _start() -> ! {
             memory::print_decimal(framebuffer.height() as u64);
             serial::print(")\n");
             
+            drivers::framebuffer::init(fb_static);
             console::init(fb_static);
-            shell::init();
             
-            serial::print("Console and shell initialized!\n");
+            serial::print("Console initialized!\n");
         }
     } else {
         serial::print("No framebuffer available - console disabled!\n");
     }
+
+    // The shell is also reachable over COM1 without a framebuffer
+    tty::init();
+    shell::init();
+    serial::print("Shell initialized!\n");
     
     // Optional: Test exception handler (uncomment to test)
     // serial::print("Testing divide by zero exception...\n");
     // let _x = 1 / 0; // This will trigger divide_by_zero_handler
     
     loop {
-        x86_64::instructions::hlt();
+        // Check for work left by interrupt handlers with interrupts off, so
+        // none can arrive between the check and the halt
+        x86_64::instructions::interrupts::disable();
+        if drivers::keyboard::leds_pending() {
+            x86_64::instructions::interrupts::enable();
+            drivers::keyboard::update_leds();
+        } else if console::replies_pending() {
+            x86_64::instructions::interrupts::enable();
+            console::deliver_replies();
+        } else {
+            x86_64::instructions::interrupts::enable_and_hlt();
+        }
     }
 }
 
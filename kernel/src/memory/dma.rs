@@ -0,0 +1,65 @@
+//! Physically contiguous buffers for device DMA
+//!
+//! Devices address memory physically, so DMA buffers come straight from the
+//! frame allocator and are accessed by the kernel through the HHDM.
+
+use x86_64::PhysAddr;
+
+use super::{paging, physical};
+
+/// A zeroed, physically contiguous buffer of whole frames
+pub struct DmaBuffer {
+    phys: u64,
+    virt: *mut u8,
+    size: usize,
+}
+
+// The buffer is plain memory owned by whoever holds the DmaBuffer
+unsafe impl Send for DmaBuffer {}
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    /// Allocate at least `size` bytes, rounded up to whole frames
+    pub fn new(size: usize) -> Option<Self> {
+        let frames = size.div_ceil(physical::FRAME_SIZE).max(1);
+        let frame = physical::alloc_contiguous(frames)?;
+        let virt = paging::phys_to_virt(PhysAddr::new(frame.addr), paging::hhdm_offset()).as_mut_ptr::<u8>();
+        let size = frames * physical::FRAME_SIZE;
+        unsafe {
+            core::ptr::write_bytes(virt, 0, size);
+        }
+        Some(Self {
+            phys: frame.addr,
+            virt,
+            size,
+        })
+    }
+
+    /// Physical address of the first byte, for programming into a device
+    pub fn phys(&self) -> u64 {
+        self.phys
+    }
+
+    /// Kernel pointer to the byte at `offset`
+    pub fn ptr<T>(&self, offset: usize) -> *mut T {
+        debug_assert!(offset + core::mem::size_of::<T>() <= self.size);
+        unsafe { self.virt.add(offset) as *mut T }
+    }
+
+    /// The buffer contents
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt, self.size) }
+    }
+
+    /// The buffer contents, mutably
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt, self.size) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let frame = physical::PhysFrame::containing_address(self.phys);
+        physical::dealloc_contiguous(frame, self.size / physical::FRAME_SIZE);
+    }
+}
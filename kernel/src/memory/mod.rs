@@ -5,8 +5,9 @@
 pub mod physical;
 pub mod paging;
 pub mod heap;
+pub mod dma;
 
-use limine::memory_map::EntryType;
+use limine::memory_map::{Entry, EntryType};
 use limine::request::MemoryMapRequest;
 
 /// Request memory map from Limine bootloader
@@ -31,23 +32,10 @@ pub fn init() {
     let mut usable_memory: u64 = 0;
     
     for entry in entries.iter() {
-        let entry_type_str = match entry.entry_type {
-            EntryType::USABLE => {
-                usable_memory += entry.length;
-                "USABLE"
-            }
-            EntryType::RESERVED => "RESERVED",
-            EntryType::ACPI_RECLAIMABLE => "ACPI_RECLAIMABLE",
-            EntryType::ACPI_NVS => "ACPI_NVS",
-            EntryType::BAD_MEMORY => "BAD_MEMORY",
-            EntryType::BOOTLOADER_RECLAIMABLE => {
-                usable_memory += entry.length;
-                "BOOTLOADER_RECLAIMABLE"
-            }
-            EntryType::EXECUTABLE_AND_MODULES => "EXECUTABLE_AND_MODULES",
-            EntryType::FRAMEBUFFER => "FRAMEBUFFER",
-            _ => "UNKNOWN",
-        };
+        let entry_type_str = entry_type_name(entry.entry_type);
+        if is_usable(entry.entry_type) {
+            usable_memory += entry.length;
+        }
         
         total_memory += entry.length;
         
@@ -87,6 +75,34 @@ pub fn init() {
     crate::serial::print("Memory management initialized!\n");
 }
 
+/// The memory map handed over by Limine
+pub fn memory_map() -> &'static [&'static Entry] {
+    MEMORY_MAP_REQUEST
+        .get_response()
+        .map(|response| response.entries())
+        .unwrap_or(&[])
+}
+
+/// Name of a memory map entry type
+pub fn entry_type_name(entry_type: EntryType) -> &'static str {
+    match entry_type {
+        EntryType::USABLE => "USABLE",
+        EntryType::RESERVED => "RESERVED",
+        EntryType::ACPI_RECLAIMABLE => "ACPI_RECLAIMABLE",
+        EntryType::ACPI_NVS => "ACPI_NVS",
+        EntryType::BAD_MEMORY => "BAD_MEMORY",
+        EntryType::BOOTLOADER_RECLAIMABLE => "BOOTLOADER_RECLAIMABLE",
+        EntryType::EXECUTABLE_AND_MODULES => "EXECUTABLE_AND_MODULES",
+        EntryType::FRAMEBUFFER => "FRAMEBUFFER",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Whether memory of this type can be handed out once the bootloader is done
+pub fn is_usable(entry_type: EntryType) -> bool {
+    matches!(entry_type, EntryType::USABLE | EntryType::BOOTLOADER_RECLAIMABLE)
+}
+
 /// Print a hexadecimal number
 pub fn print_hex(value: u64) {
     crate::serial::print("0x");
@@ -35,9 +35,15 @@ pub fn init() {
 
 /// Returns a new OffsetPageTable using the active level 4 table and stored HHDM offset
 pub unsafe fn mapper() -> OffsetPageTable<'static> {
-    // For now, return a dummy implementation to avoid compilation issues
-    // TODO: Fix the proper OffsetPageTable creation
-    todo!("OffsetPageTable creation not implemented yet");
+    let offset = hhdm_offset();
+    let (level_4_frame, _) = Cr3::read();
+    let table = (offset + level_4_frame.start_address().as_u64()).as_mut_ptr::<PageTable>();
+    unsafe { OffsetPageTable::new(&mut *table, offset) }
+}
+
+/// The HHDM offset; all usable physical memory is mapped at `offset + phys`
+pub fn hhdm_offset() -> VirtAddr {
+    PHYS_OFFSET.lock().expect("Paging not initialized")
 }
 
 /// Translate a physical address to virtual via HHDM
@@ -46,6 +52,90 @@ pub fn phys_to_virt(phys: PhysAddr, phys_offset: VirtAddr) -> VirtAddr {
     VirtAddr::new(phys.as_u64() + phys_offset.as_u64())
 }
 
+/// Start of the virtual window used for device memory and firmware tables
+///
+/// The HHDM only covers RAM, so MMIO registers and ACPI tables get mappings
+/// of their own in PML4 slot 510, just below the kernel image.
+const MMIO_BASE: u64 = 0xFFFF_FF00_0000_0000;
+
+/// Next free address in the MMIO window
+static NEXT_MMIO: Mutex<u64> = Mutex::new(MMIO_BASE);
+
+/// Map device registers at physical `phys` as uncached memory
+///
+/// Returns the virtual address corresponding to `phys`.
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Result<VirtAddr, &'static str> {
+    map_window(phys, size, PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH)
+}
+
+/// Map ordinary memory outside the HHDM, such as firmware tables
+pub fn map_physical(phys: PhysAddr, size: u64) -> Result<VirtAddr, &'static str> {
+    map_window(phys, size, PageTableFlags::empty())
+}
+
+/// Map `[phys, phys + size)` into the next free part of the MMIO window
+fn map_window(phys: PhysAddr, size: u64, extra: PageTableFlags) -> Result<VirtAddr, &'static str> {
+    let start = phys.align_down(4096u64);
+    let end = (phys + size.max(1)).align_up(4096u64);
+    let pages = (end - start) / 4096;
+
+    let virt_base = {
+        let mut next = NEXT_MMIO.lock();
+        let base = *next;
+        *next += pages * 4096;
+        base
+    };
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | extra;
+    let mut mapper = unsafe { mapper() };
+    for i in 0..pages {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt_base + i * 4096));
+        let frame = PhysFrame::containing_address(start + i * 4096);
+        unsafe { mapper.map_to(page, frame, flags, &mut KernelFrameAllocator) }
+            .map_err(|_| "Failed to map MMIO page")?
+            .flush();
+    }
+
+    Ok(VirtAddr::new(virt_base + (phys - start)))
+}
+
+/// Pages in `[start, start + size)`, as the first page and a count
+fn page_span(start: VirtAddr, size: u64) -> (VirtAddr, u64) {
+    let first = start.align_down(4096u64);
+    let end = (start + size.max(1)).align_up(4096u64);
+    (first, (end - first) / 4096)
+}
+
+/// Remove the mappings covering `[start, start + size)` on every CPU
+///
+/// The frames behind them are not freed; callers own those.
+pub fn unmap(start: VirtAddr, size: u64) -> Result<(), &'static str> {
+    let (first, pages) = page_span(start, size);
+    let mut mapper = unsafe { mapper() };
+    for i in 0..pages {
+        let page = Page::<Size4KiB>::containing_address(first + i * 4096);
+        // Flushed below, on every CPU at once
+        mapper.unmap(page).map_err(|_| "Page not mapped")?.1.ignore();
+    }
+    crate::smp::tlb::shootdown(first, pages);
+    Ok(())
+}
+
+/// Change the flags of the mappings covering `[start, start + size)` on
+/// every CPU
+pub fn protect(start: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), &'static str> {
+    let (first, pages) = page_span(start, size);
+    let mut mapper = unsafe { mapper() };
+    for i in 0..pages {
+        let page = Page::<Size4KiB>::containing_address(first + i * 4096);
+        unsafe { mapper.update_flags(page, flags) }
+            .map_err(|_| "Page not mapped")?
+            .ignore();
+    }
+    crate::smp::tlb::shootdown(first, pages);
+    Ok(())
+}
+
 /// Frame allocator that uses our physical frame allocator
 pub struct KernelFrameAllocator;
 
@@ -2,18 +2,126 @@
 //!
 //! This module provides a simple bump allocator for physical memory frames.
 //! It's much simpler than a bitmap allocator and avoids the hanging issue.
+//! Frames are only taken from regions the memory map reports as usable, so
+//! they never overlap the kernel, modules or bootloader page tables.
+//!
+//! Freed frames just below the bump pointer give it back; other freed runs
+//! are merged with their neighbours, kept in a short list and reused first.
+//! Once that list is full, further frames are chained through the HHDM into
+//! a free list of single frames.
 
-use limine::memory_map::Entry;
+use limine::memory_map::{Entry, EntryType};
 use spin::Mutex;
 
 /// Size of a physical frame (4KB)
 pub const FRAME_SIZE: usize = 4096;
 
-/// Simple bump allocator - starts at 2MB to avoid low memory
-static NEXT_FRAME: Mutex<u64> = Mutex::new(0x200000);
+/// Lowest address handed out; low memory is left to firmware structures
+const MIN_ADDR: u64 = 0x200000;
+
+/// Maximum number of usable regions tracked
+const MAX_REGIONS: usize = 64;
+
+/// Maximum number of freed runs remembered; frames freed beyond this go to
+/// the spill list
+const MAX_FREED: usize = 32;
+
+/// Usable regions from the memory map and the bump pointer within them
+struct Regions {
+    list: [(u64, u64); MAX_REGIONS],
+    count: usize,
+    /// Index of the region currently being allocated from
+    current: usize,
+    next: u64,
+    /// Freed runs as (start, end), not yet handed out again
+    freed: [(u64, u64); MAX_FREED],
+    freed_count: usize,
+    /// Frames freed while `freed` was full; each holds the physical address
+    /// of the next, read through the HHDM, and 0 ends the list
+    spilled: u64,
+    spilled_count: u64,
+}
+
+impl Regions {
+    /// Take `size` bytes from the front of a freed run that is big enough
+    fn take_freed(&mut self, size: u64) -> Option<u64> {
+        let index = self.freed[..self.freed_count].iter().position(|(start, end)| end - start >= size)?;
+        let start = self.freed[index].0;
+        self.freed[index].0 += size;
+        if self.freed[index].0 == self.freed[index].1 {
+            self.freed_count -= 1;
+            self.freed[index] = self.freed[self.freed_count];
+        }
+        Some(start)
+    }
+
+    /// Take a single frame from the spill list
+    fn take_spilled(&mut self) -> Option<u64> {
+        if self.spilled == 0 {
+            return None;
+        }
+        let addr = self.spilled;
+        let link = (super::paging::hhdm_offset().as_u64() + addr) as *const u64;
+        // The frame is free and mapped by the HHDM, and its first word was
+        // set by `release`
+        self.spilled = unsafe { link.read() };
+        self.spilled_count -= 1;
+        Some(addr)
+    }
+
+    /// Return a freed run, merging it with the runs it touches
+    fn release(&mut self, (mut start, mut end): (u64, u64)) {
+        // A merged run may touch another one, so repeat until none do
+        while let Some(index) = self.freed[..self.freed_count]
+            .iter()
+            .position(|&(s, e)| e == start || s == end)
+        {
+            let (s, e) = self.freed[index];
+            start = start.min(s);
+            end = end.max(e);
+            self.freed_count -= 1;
+            self.freed[index] = self.freed[self.freed_count];
+        }
 
-/// Maximum memory we'll allocate (limit to 64MB for safety)
-const MAX_FRAME: u64 = 0x200000 + (64 * 1024 * 1024);
+        if end == self.next {
+            self.next = start;
+            self.reclaim();
+        } else if self.freed_count < MAX_FREED {
+            self.freed[self.freed_count] = (start, end);
+            self.freed_count += 1;
+        } else {
+            let offset = super::paging::hhdm_offset().as_u64();
+            for addr in (start..end).step_by(FRAME_SIZE) {
+                // Nothing else refers to a freed frame, so its first word
+                // can hold the link
+                unsafe { ((offset + addr) as *mut u64).write(self.spilled) };
+                self.spilled = addr;
+                self.spilled_count += 1;
+            }
+        }
+    }
+
+    /// Move the bump pointer back over freed runs that end right at it
+    fn reclaim(&mut self) {
+        while let Some(index) = self.freed[..self.freed_count].iter().position(|&(_, end)| end == self.next) {
+            self.next = self.freed[index].0;
+            self.freed_count -= 1;
+            self.freed[index] = self.freed[self.freed_count];
+        }
+    }
+}
+
+/// Simple bump allocator over the usable regions of the memory map
+static REGIONS: Mutex<Regions> = Mutex::new(Regions {
+    list: [(0, 0); MAX_REGIONS],
+    count: 0,
+    current: 0,
+    next: 0,
+    freed: [(0, 0); MAX_FREED],
+    freed_count: 0,
+    spilled: 0,
+    spilled_count: 0,
+});
 
 /// Represents a physical memory frame
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -30,45 +138,89 @@ impl PhysFrame {
     }
 }
 
-/// Initialize the physical frame allocator (simple version)
-pub fn init(_entries: &[&Entry]) {
-    // For the simple allocator, we don't need to parse the memory map
-    // We just start allocating from 2MB onwards
+/// Initialize the physical frame allocator from the usable memory map entries
+pub fn init(entries: &[&Entry]) {
+    let mut regions = REGIONS.lock();
+    for entry in entries.iter().filter(|e| e.entry_type == EntryType::USABLE) {
+        let start = (entry.base.max(MIN_ADDR) + FRAME_SIZE as u64 - 1) & !(FRAME_SIZE as u64 - 1);
+        let end = (entry.base + entry.length) & !(FRAME_SIZE as u64 - 1);
+        if start >= end || regions.count == MAX_REGIONS {
+            continue;
+        }
+        let index = regions.count;
+        regions.list[index] = (start, end);
+        regions.count += 1;
+    }
+    regions.current = 0;
+    regions.next = regions.list[0].0;
+
     crate::serial::print("Simple physical allocator initialized (bump allocator)\n");
-    crate::serial::print("Allocation range: 2MB - 66MB\n");
+    crate::serial::print("Usable regions: ");
+    crate::memory::print_decimal(regions.count as u64);
+    crate::serial::print("\n");
 }
 
 /// Allocate a physical frame
 pub fn alloc_frame() -> Option<PhysFrame> {
-    let mut next = NEXT_FRAME.lock();
-    
-    if *next >= MAX_FRAME {
-        return None; // Out of memory
+    alloc_contiguous(1)
+}
+
+/// Allocate `count` physically contiguous frames, returning the first
+///
+/// Used for DMA buffers that a device accesses by physical address.
+pub fn alloc_contiguous(count: usize) -> Option<PhysFrame> {
+    let size = (count * FRAME_SIZE) as u64;
+    let mut regions = REGIONS.lock();
+    if count == 1
+        && let Some(addr) = regions.take_spilled()
+    {
+        return Some(PhysFrame::containing_address(addr));
+    }
+    if let Some(addr) = regions.take_freed(size) {
+        return Some(PhysFrame::containing_address(addr));
     }
-    
-    let frame = PhysFrame::containing_address(*next);
-    *next += FRAME_SIZE as u64;
-    
-    Some(frame)
+    while regions.current < regions.count {
+        let (_, end) = regions.list[regions.current];
+        if regions.next + size <= end {
+            let frame = PhysFrame::containing_address(regions.next);
+            regions.next += size;
+            return Some(frame);
+        }
+        // Skip the rest of this region
+        regions.current += 1;
+        if regions.current < regions.count {
+            regions.next = regions.list[regions.current].0;
+        }
+    }
+    None
+}
+
+/// Deallocate a physical frame
+pub fn dealloc_frame(frame: PhysFrame) {
+    dealloc_contiguous(frame, 1);
 }
 
-/// Deallocate a physical frame (no-op for bump allocator)
-pub fn dealloc_frame(_frame: PhysFrame) {
-    // Bump allocators don't support deallocation
-    // In a real OS, you'd want a proper allocator
+/// Deallocate `count` contiguous frames starting at `frame`
+pub fn dealloc_contiguous(frame: PhysFrame, count: usize) {
+    let run = (frame.addr, frame.addr + (count * FRAME_SIZE) as u64);
+    REGIONS.lock().release(run);
 }
 
-/// Get the amount of free memory (estimate)
+/// Get the amount of free memory
 pub fn free_memory() -> u64 {
-    let next = *NEXT_FRAME.lock();
-    if next < MAX_FRAME {
-        MAX_FRAME - next
-    } else {
-        0
+    let regions = REGIONS.lock();
+    if regions.current >= regions.count {
+        return 0;
     }
+    let rest: u64 = regions.list[regions.current + 1..regions.count]
+        .iter()
+        .chain(&regions.freed[..regions.freed_count])
+        .map(|(start, end)| end - start)
+        .sum();
+    regions.list[regions.current].1 - regions.next + rest + regions.spilled_count * FRAME_SIZE as u64
 }
 
-/// Get the number of free frames (estimate)
+/// Get the number of free frames
 pub fn free_frames() -> usize {
     (free_memory() / FRAME_SIZE as u64) as usize
 }
@@ -1,11 +1,17 @@
 use core::panic::PanicInfo;
+use x86_64::instructions::port::Port;
 
 /// Simple function to write a byte to serial port COM1 (0x3F8)
+///
+/// Talks to the UART directly rather than through the driver, whose lock
+/// the panicking code may hold.
 fn serial_write_byte(c: u8) {
     const COM1: u16 = 0x3F8;
+    let mut data = Port::<u8>::new(COM1);
+    let mut line_status = Port::<u8>::new(COM1 + 5);
     unsafe {
-        while (core::ptr::read_volatile((COM1 + 5) as *const u8) & 0x20) == 0 {} // Wait for transmit buffer empty
-        core::ptr::write_volatile(COM1 as *mut u8, c);
+        while (line_status.read() & 0x20) == 0 {} // Wait for transmit buffer empty
+        data.write(c);
     }
 }
 
@@ -31,6 +37,9 @@ pub fn hcf() -> ! {
 /// This function is called on panic.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Nothing else may run on a kernel in an unknown state
+    crate::smp::stop_others();
+
     // Print panic information to serial port for debugging
     serial_print("\n!!! KERNEL PANIC !!!\n");
     serial_print("Message: Panic occurred\n");
@@ -0,0 +1,160 @@
+//! Reboot and power-off
+//!
+//! Both try the ACPI way first, then fall back to methods that work on
+//! older or emulated hardware. The other CPUs are halted first. Callers are
+//! expected to have synced filesystems; nothing here returns.
+
+use x86_64::PhysAddr;
+use x86_64::instructions::port::Port;
+
+use crate::acpi::GenericAddress;
+
+/// PM1 control register fields
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 0x7 << PM1_SLP_TYP_SHIFT;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// Polls of SCI_EN after asking firmware to switch to ACPI mode
+const ACPI_ENABLE_TIMEOUT: u32 = 1_000_000;
+
+/// 8042 controller: status/command port and the pulse-reset-line command
+const KBC_COMMAND: u16 = 0x64;
+const KBC_STATUS_INPUT_FULL: u8 = 1 << 1;
+const KBC_RESET: u8 = 0xFE;
+
+/// PIIX/ICH reset control register
+const RESET_CONTROL: u16 = 0xCF9;
+
+/// Emulator-specific power-off ports and values
+const EMULATOR_POWER_OFF: [(u16, u16); 3] = [
+    (0x604, 0x2000),  // QEMU
+    (0xB004, 0x2000), // Bochs and older QEMU
+    (0x4004, 0x3400), // VirtualBox
+];
+
+/// Write a byte to a register described by a Generic Address Structure
+fn write_register(reg: &GenericAddress, value: u8) -> bool {
+    match reg.space {
+        GenericAddress::SPACE_IO => {
+            unsafe { Port::<u8>::new(reg.address as u16).write(value) };
+            true
+        }
+        GenericAddress::SPACE_MEMORY => match crate::memory::paging::map_mmio(PhysAddr::new(reg.address), 1) {
+            Ok(virt) => {
+                unsafe { core::ptr::write_volatile(virt.as_mut_ptr::<u8>(), value) };
+                true
+            }
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Spin for a while so a reset has time to take effect
+fn settle() {
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Restart the machine
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+    crate::serial::print("Rebooting...\n");
+    crate::smp::halt_others();
+
+    // ACPI reset register
+    if let Some(fadt) = crate::acpi::fadt()
+        && fadt.supports_reset()
+        && let Some(reset) = fadt.reset_register
+        && write_register(&reset, fadt.reset_value)
+    {
+        settle();
+    }
+
+    // Pulse the CPU reset line through the keyboard controller
+    unsafe {
+        let mut command = Port::<u8>::new(KBC_COMMAND);
+        for _ in 0..100_000 {
+            if command.read() & KBC_STATUS_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        command.write(KBC_RESET);
+    }
+    settle();
+
+    // Hard reset through the chipset's reset control register
+    unsafe {
+        let mut port = Port::<u8>::new(RESET_CONTROL);
+        port.write(0x02);
+        port.write(0x06);
+    }
+    settle();
+
+    // Triple fault: with an empty IDT the breakpoint cannot be delivered
+    crate::serial::print("Reboot: all methods failed, forcing a triple fault\n");
+    let empty = x86_64::structures::DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::new(0),
+    };
+    unsafe {
+        x86_64::instructions::tables::lidt(&empty);
+        core::arch::asm!("int3");
+    }
+    crate::panic::hcf();
+}
+
+/// Enter the S5 soft-off state through the PM1 control registers
+fn acpi_power_off() -> Result<(), &'static str> {
+    let fadt = crate::acpi::fadt().ok_or("no FADT")?;
+    let (typ_a, typ_b) = crate::acpi::s5_sleep_type().ok_or("no \\_S5 object")?;
+    if fadt.pm1a_control == 0 {
+        return Err("no PM1a control block");
+    }
+
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control as u16);
+    // Firmware may still own the fixed hardware; ask for ACPI mode
+    if unsafe { pm1a.read() } & PM1_SCI_EN == 0 && fadt.smi_command != 0 && fadt.acpi_enable != 0 {
+        unsafe { Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable) };
+        for _ in 0..ACPI_ENABLE_TIMEOUT {
+            if unsafe { pm1a.read() } & PM1_SCI_EN != 0 {
+                break;
+            }
+        }
+    }
+
+    let sleep = |port: &mut Port<u16>, typ: u8| unsafe {
+        let value = port.read() & !PM1_SLP_TYP_MASK;
+        port.write(value | (typ as u16) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN);
+    };
+    sleep(&mut pm1a, typ_a);
+    if fadt.pm1b_control != 0 {
+        sleep(&mut Port::<u16>::new(fadt.pm1b_control as u16), typ_b);
+    }
+    settle();
+    Err("machine did not power off")
+}
+
+/// Turn the machine off
+pub fn shutdown() -> ! {
+    x86_64::instructions::interrupts::disable();
+    crate::serial::print("Powering off...\n");
+    crate::smp::halt_others();
+
+    if let Err(e) = acpi_power_off() {
+        crate::serial::print("Power off: ");
+        crate::serial::print(e);
+        crate::serial::print("\n");
+    }
+
+    for (port, value) in EMULATOR_POWER_OFF {
+        unsafe { Port::<u16>::new(port).write(value) };
+    }
+    settle();
+
+    crate::serial::print("Power off failed; it is now safe to turn off the machine\n");
+    crate::console::println("It is now safe to turn off the machine.");
+    crate::panic::hcf();
+}
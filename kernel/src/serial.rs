@@ -1,44 +1,40 @@
-const COM1_PORT: u16 = 0x3F8;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
 
-/// Initializes the COM1 serial port.
-pub fn init() {
-    unsafe {
-        // Disable all interrupts from the serial port
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 1, in("al") 0x00u8);
-
-        // Enable DLAB (Divisor Latch Access Bit) to set the baud rate
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 3, in("al") 0x80u8);
+/// Whether the next byte printed starts a new log line
+static LINE_START: AtomicBool = AtomicBool::new(true);
 
-        // Set divisor to 3 (lo byte) for 38400 baud (115200 / 3)
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 0, in("al") 0x03u8);
-        // Set divisor to 0 (hi byte)
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 1, in("al") 0x00u8);
-
-        // Set line control register: 8 bits, no parity, one stop bit
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 3, in("al") 0x03u8);
+/// Initializes the COM1 serial port for kernel messages.
+pub fn init() {
+    crate::drivers::uart::init_early();
+}
 
-        // Enable FIFO, clear them, with 14-byte threshold
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 2, in("al") 0xC7u8);
+/// Formats straight to the port and the kernel log console, for use
+/// before the heap is up.
+struct Raw;
 
-        // Enable IRQs, RTS/DSR set
-        core::arch::asm!("out dx, al", in("dx") COM1_PORT + 4, in("al") 0x0Bu8);
+impl Write for Raw {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_bytes(s.as_bytes());
+        crate::console::log(s);
+        Ok(())
     }
 }
 
-/// Prints a string to the COM1 serial port.
+/// Prints a string to the COM1 serial port and the kernel log console,
+/// prefixing each new line with the time since boot.
 pub fn print(s: &str) {
-    for byte in s.bytes() {
-        unsafe {
-            // Wait until the transmit buffer is empty
-            let mut status: u8;
-            loop {
-                core::arch::asm!("in al, dx", in("dx") COM1_PORT + 5, out("al") status);
-                if (status & 0x20) != 0 {
-                    break;
-                }
-            }
-            // Write the byte to the serial port
-            core::arch::asm!("out dx, al", in("dx") COM1_PORT, in("al") byte);
+    for line in s.split_inclusive('\n') {
+        if LINE_START.load(Ordering::Relaxed) {
+            let uptime = crate::time::uptime();
+            let _ = write!(Raw, "[{:5}.{:06}] ", uptime.as_secs(), uptime.subsec_micros());
         }
+        let _ = Raw.write_str(line);
+        LINE_START.store(line.ends_with('\n'), Ordering::Relaxed);
     }
 }
+
+/// Writes raw bytes to the COM1 serial port, returning once they are sent.
+pub fn write_bytes(bytes: &[u8]) {
+    crate::drivers::uart::com1().write_sync(bytes);
+}
@@ -2,18 +2,42 @@
 
 use alloc::string::String;
 
+use crate::fs::{self, FsError};
+
 /// Help command - show available commands
 pub fn cmd_help(_args: &[String]) {
     crate::console::println("Available commands:");
-    crate::console::println("  help      - Show this help message");
-    crate::console::println("  clear     - Clear the screen");
-    crate::console::println("  echo      - Print arguments to screen");
-    crate::console::println("  mem       - Show memory information");
-    crate::console::println("  tasks     - Show task information");
-    crate::console::println("  uptime    - Show system uptime");
-    crate::console::println("  history   - Show command history");
-    crate::console::println("  reboot    - Restart the system");
-    crate::console::println("  panic     - Trigger a kernel panic (for testing)");
+    crate::console::println("  help       - Show this help message");
+    crate::console::println("  clear      - Clear the screen");
+    crate::console::println("  echo       - Print arguments to screen");
+    crate::console::println("  mem        - Show memory information");
+    crate::console::println("  tasks      - Show task information");
+    crate::console::println("  uptime     - Show system uptime");
+    crate::console::println("  date       - Show the date and time (+%s for seconds since 1970)");
+    crate::console::println("  sleep      - Wait for a number of seconds (fractions allowed)");
+    crate::console::println("  loadkeys   - Select the keyboard layout, or list layouts");
+    crate::console::println("  setfont    - Load a PSF console font, or show the current one");
+    crate::console::println("  scrollback - Show or set how many lines of console history are kept");
+    crate::console::println("  serial     - Show or set serial port settings (serial ttyS0 9600 8N1)");
+    crate::console::println("  stty       - Show or change terminal settings (raw, sane, -echo, ...)");
+    crate::console::println("  kill       - Send a signal to a task (kill -STOP 2)");
+    crate::console::println("  fg         - Continue a task and give it the terminal's signals");
+    crate::console::println("  history    - Show command history");
+    crate::console::println("  mount      - Mount a filesystem or list mounts");
+    crate::console::println("  umount     - Unmount a filesystem");
+    crate::console::println("  ls         - List a directory (-l for details)");
+    crate::console::println("  cat        - Print file contents");
+    crate::console::println("  write      - Write text to a file");
+    crate::console::println("  mkdir      - Create a directory");
+    crate::console::println("  rm         - Remove a file or empty directory");
+    crate::console::println("  ln         - Create a symbolic link (ln -s)");
+    crate::console::println("  lsblk      - List block devices and partitions");
+    crate::console::println("  lspci      - List PCI devices (-v for BARs and capabilities)");
+    crate::console::println("  acpi       - Dump ACPI tables (tables, madt, fadt, hpet, mcfg)");
+    crate::console::println("  reboot     - Restart the system");
+    crate::console::println("  shutdown   - Power off the system (-r to reboot)");
+    crate::console::println("  poweroff   - Power off the system");
+    crate::console::println("  panic      - Trigger a kernel panic (for testing)");
     crate::console::println("");
 }
 
@@ -33,61 +57,328 @@ pub fn cmd_echo(args: &[String]) {
     crate::console::println("");
 }
 
-/// Memory command - show memory information
+/// Memory command - show memory information from `/proc/meminfo`
 pub fn cmd_mem(_args: &[String]) {
-    let free_memory = crate::memory::physical::free_memory();
-    let free_frames = crate::memory::physical::free_frames();
-    
-    crate::console::println("Memory Information:");
-    crate::console::print("  Free memory: ");
-    print_size(free_memory);
-    crate::console::println("");
-    
-    crate::console::print("  Free frames: ");
-    print_decimal(free_frames as u64);
-    crate::console::println("");
-    
-    crate::console::print("  Frame size: ");
-    print_decimal(crate::memory::physical::FRAME_SIZE as u64);
-    crate::console::println(" bytes");
-    crate::console::println("");
+    match fs::file::read_to_string("/proc/meminfo") {
+        Ok(text) => {
+            crate::console::println("Memory Information:");
+            for line in text.lines() {
+                crate::console::print("  ");
+                crate::console::println(line);
+            }
+            crate::console::println("");
+        }
+        Err(e) => print_fs_error("mem", "/proc/meminfo", e),
+    }
 }
 
-/// Tasks command - show task information
+/// Tasks command - list tasks from `/proc/tasks`
 pub fn cmd_tasks(_args: &[String]) {
-    let (ready_tasks, switches) = crate::task::SCHEDULER.lock().stats();
-    
+    let entries = match fs::file::read_dir("/proc/tasks") {
+        Ok(entries) => entries,
+        Err(e) => return print_fs_error("tasks", "/proc/tasks", e),
+    };
+
     crate::console::println("Task Information:");
-    crate::console::print("  Ready tasks: ");
-    print_decimal(ready_tasks as u64);
-    crate::console::println("");
-    
-    crate::console::print("  Context switches: ");
-    print_decimal(switches);
-    crate::console::println("");
+    crate::console::println("     ID  CPU  STATE       NAME");
+    for entry in entries {
+        // A task may exit between listing the directory and reading its status
+        let Ok(status) = fs::file::read_to_string(&alloc::format!("/proc/tasks/{}/status", entry.name))
+        else {
+            continue;
+        };
+        let field = |key: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .map(str::trim)
+                .unwrap_or("?")
+        };
+        crate::console::println(&alloc::format!(
+            "  {:>5}  {:>3}  {:<10}  {}",
+            field("Id:"),
+            field("Cpu:"),
+            field("State:"),
+            field("Name:")
+        ));
+    }
     crate::console::println("");
 }
 
-/// Uptime command - show system uptime
+/// Uptime command - show system uptime from `/proc/uptime`
 pub fn cmd_uptime(_args: &[String]) {
-    let ticks = crate::drivers::timer::ticks();
-    let seconds = ticks / 100; // 100 Hz timer
+    let text = match fs::file::read_to_string("/proc/uptime") {
+        Ok(text) => text,
+        Err(e) => return print_fs_error("uptime", "/proc/uptime", e),
+    };
+    let mut fields = text.split_whitespace();
+    let seconds = fields
+        .next()
+        .and_then(|uptime| uptime.split('.').next())
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(0);
+    let ticks = fields.next().unwrap_or("0");
     let minutes = seconds / 60;
     let hours = minutes / 60;
-    
+
     crate::console::println("System Uptime:");
-    crate::console::print("  ");
-    print_decimal(hours);
-    crate::console::print("h ");
-    print_decimal(minutes % 60);
-    crate::console::print("m ");
-    print_decimal(seconds % 60);
-    crate::console::print("s (");
-    print_decimal(ticks);
-    crate::console::println(" ticks)");
+    crate::console::println(&alloc::format!(
+        "  {}h {}m {}s ({} ticks)",
+        hours,
+        minutes % 60,
+        seconds % 60,
+        ticks
+    ));
     crate::console::println("");
 }
 
+/// Date command - show the wall-clock time
+pub fn cmd_date(args: &[String]) {
+    let now = crate::time::now();
+    match args.first().map(String::as_str) {
+        None => crate::console::println(&alloc::format!("{}", crate::time::DateTime::from_unix(now))),
+        Some("+%s") => crate::console::println(&alloc::format!("{}", now)),
+        Some(_) => crate::console::println("Usage: date [+%s]"),
+    }
+}
+
+/// Loadkeys command - select the keyboard layout by name
+pub fn cmd_loadkeys(args: &[String]) {
+    use crate::drivers::keyboard::{self, layout};
+
+    let Some(name) = args.first() else {
+        let current = keyboard::layout();
+        for layout in layout::LAYOUTS {
+            let marker = if core::ptr::eq(*layout, current) { '*' } else { ' ' };
+            crate::console::println(&alloc::format!("{} {:<8} {}", marker, layout.name, layout.description));
+        }
+        return;
+    };
+    match layout::find(name) {
+        Some(layout) => {
+            keyboard::set_layout(layout);
+            crate::console::println(&alloc::format!("Keyboard layout: {}", layout.description));
+        }
+        None => crate::console::println(&alloc::format!("loadkeys: unknown layout '{}'", name)),
+    }
+}
+
+/// Setfont command - load a PSF font for the console, `default` for the
+/// built-in one
+pub fn cmd_setfont(args: &[String]) {
+    use crate::console::{self, psf};
+
+    let Some(path) = args.first() else {
+        match console::font() {
+            Some(font) => {
+                let (width, height) = font.size();
+                let table = if font.has_unicode_table() { ", Unicode table" } else { "" };
+                crate::console::println(&alloc::format!("{}x{}, {} glyphs{}", width, height, font.glyph_count(), table));
+            }
+            None => crate::console::println("setfont: no console"),
+        }
+        return;
+    };
+    let font = if path == "default" {
+        console::font::builtin()
+    } else {
+        let data = match fs::file::read(path) {
+            Ok(data) => data,
+            Err(e) => return print_fs_error("setfont", path, e),
+        };
+        match psf::parse(&data) {
+            Ok(font) => font,
+            Err(e) => {
+                crate::console::println(&alloc::format!("setfont: {}: {}", path, e.as_str()));
+                return;
+            }
+        }
+    };
+    if let Err(e) = console::set_font(font) {
+        crate::console::println(&alloc::format!("setfont: {}: {}", path, e));
+    }
+}
+
+/// Scrollback command - show or set the console's scrollback length
+pub fn cmd_scrollback(args: &[String]) {
+    let Some(lines) = args.first() else {
+        crate::console::println(&alloc::format!("{} lines", crate::console::scrollback_limit()));
+        return;
+    };
+    match lines.parse() {
+        Ok(lines) => crate::console::set_scrollback_limit(lines),
+        Err(_) => crate::console::println("Usage: scrollback [LINES]"),
+    }
+}
+
+/// Serial command - show the COM ports, or change one's baud rate and format
+pub fn cmd_serial(args: &[String]) {
+    use crate::drivers::uart;
+
+    let Some(name) = args.first() else {
+        for port in uart::PORTS.iter().filter(|port| port.is_present()) {
+            crate::console::println(&alloc::format!("{}: {}", port.name, port.config()));
+        }
+        return;
+    };
+    let Some(port) = uart::find(name) else {
+        crate::console::println(&alloc::format!("serial: no such port '{}'", name));
+        return;
+    };
+
+    let mut config = port.config();
+    if let Some(baud) = args.get(1) {
+        match baud.parse() {
+            Ok(baud) => config.baud = baud,
+            Err(_) => {
+                crate::console::println("Usage: serial [PORT [BAUD [FORMAT]]]");
+                return;
+            }
+        }
+    }
+    if let Some(format) = args.get(2)
+        && let Err(e) = config.parse_format(format)
+    {
+        crate::console::println(&alloc::format!("serial: {}", e));
+        return;
+    }
+    match port.configure(config) {
+        Ok(()) => crate::console::println(&alloc::format!("{}: {}", port.name, config)),
+        Err(e) => crate::console::println(&alloc::format!("serial: {}", e)),
+    }
+}
+
+/// Stty command - show or change the console terminal's settings
+pub fn cmd_stty(args: &[String]) {
+    use crate::tty::{self, termios::*};
+
+    let mut termios = tty::with(tty::console(), |tty| tty.termios());
+    if args.is_empty() {
+        let mut flags = String::new();
+        for (name, flag) in LocalFlags::all().iter_names() {
+            let prefix = if termios.lflag.contains(flag) { "" } else { "-" };
+            flags.push_str(&alloc::format!("{}{} ", prefix, name.to_ascii_lowercase()));
+        }
+        for (name, flag) in InputFlags::all().iter_names() {
+            let prefix = if termios.iflag.contains(flag) { "" } else { "-" };
+            flags.push_str(&alloc::format!("{}{} ", prefix, name.to_ascii_lowercase()));
+        }
+        crate::console::println(flags.trim_end());
+
+        let mut chars = String::new();
+        let names = [
+            ("intr", VINTR),
+            ("quit", VQUIT),
+            ("erase", VERASE),
+            ("kill", VKILL),
+            ("eof", VEOF),
+            ("susp", VSUSP),
+            ("werase", VWERASE),
+        ];
+        for (name, index) in names {
+            let c = termios.cc[index];
+            if c == VDISABLE {
+                chars.push_str(&alloc::format!("{} = <undef>; ", name));
+            } else {
+                chars.push_str(&alloc::format!("{} = ^{}; ", name, (c ^ 0x40) as char));
+            }
+        }
+        crate::console::println(chars.trim_end());
+        return;
+    }
+
+    for arg in args {
+        let (on, name) = match arg.strip_prefix('-') {
+            Some(name) => (false, name),
+            None => (true, arg.as_str()),
+        };
+        let upper = name.to_ascii_uppercase();
+        match name {
+            "sane" => termios = Termios::sane(),
+            "raw" if on => termios.make_raw(),
+            "raw" | "cooked" => {
+                let sane = Termios::sane();
+                termios.iflag = sane.iflag;
+                termios.lflag = sane.lflag;
+            }
+            _ => {
+                if let Some(flag) = LocalFlags::from_name(&upper) {
+                    termios.lflag.set(flag, on);
+                } else if let Some(flag) = InputFlags::from_name(&upper) {
+                    termios.iflag.set(flag, on);
+                } else {
+                    crate::console::println(&alloc::format!("stty: invalid argument '{}'", arg));
+                    return;
+                }
+            }
+        }
+    }
+    tty::with(tty::console(), |tty| tty.set_termios(termios));
+}
+
+/// Parse a task ID argument
+fn parse_task_id(text: &str) -> Option<crate::task::TaskId> {
+    text.parse().ok().map(crate::task::TaskId)
+}
+
+/// Kill command - send a signal to a task, SIGTERM by default
+pub fn cmd_kill(args: &[String]) {
+    use crate::task::signal::{self, Signal};
+
+    let (signal, id) = match args {
+        [id] => (Some(Signal::Terminate), id),
+        [signal, id] => (signal.strip_prefix('-').and_then(Signal::parse), id),
+        _ => (None, &String::new()),
+    };
+    let (Some(signal), Some(id)) = (signal, parse_task_id(id)) else {
+        crate::console::println("Usage: kill [-SIGNAL] ID");
+        return;
+    };
+    if !signal::send(id, signal) {
+        crate::console::println(&alloc::format!("kill: no task {}", id.0));
+    }
+}
+
+/// Fg command - continue a task and make it the terminal's foreground task
+pub fn cmd_fg(args: &[String]) {
+    use crate::task::signal::{self, Signal};
+    use crate::tty;
+
+    let Some(id) = args.first().and_then(|arg| parse_task_id(arg)) else {
+        crate::console::println("Usage: fg ID");
+        return;
+    };
+    if !signal::send(id, Signal::Continue) {
+        crate::console::println(&alloc::format!("fg: no task {}", id.0));
+        return;
+    }
+    tty::with(tty::console(), |tty| tty.set_foreground(Some(id)));
+}
+
+/// Parse seconds with an optional fraction, e.g. `2` or `0.25`
+fn parse_seconds(text: &str) -> Option<crate::time::Duration> {
+    let (secs, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs = if secs.is_empty() { 0 } else { secs.parse::<u64>().ok()? };
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32)
+    };
+    Some(crate::time::Duration::new(secs, nanos))
+}
+
+/// Sleep command - wait for the given number of seconds
+pub fn cmd_sleep(args: &[String]) {
+    let Some(duration) = args.first().and_then(|arg| parse_seconds(arg)) else {
+        crate::console::println("Usage: sleep SECONDS");
+        return;
+    };
+    crate::time::sleep(duration);
+}
+
 /// History command - show command history
 pub fn cmd_history(_args: &[String], history: &[String]) {
     crate::console::println("Command History:");
@@ -100,29 +391,384 @@ pub fn cmd_history(_args: &[String], history: &[String]) {
     crate::console::println("");
 }
 
-/// Reboot command - restart the system
+/// Mount command - mount a block device, or list mounts without arguments
+pub fn cmd_mount(args: &[String]) {
+    if args.is_empty() {
+        for (path, fs_type) in fs::mount::list() {
+            crate::console::print(fs_type);
+            crate::console::print(" on ");
+            crate::console::println(&path);
+        }
+        crate::console::print("Block devices:");
+        for name in crate::drivers::block::names() {
+            crate::console::print(" ");
+            crate::console::print(&name);
+        }
+        crate::console::println("");
+        return;
+    }
+
+    if args.len() < 2 {
+        crate::console::println("Usage: mount <device> <path> [type]");
+        return;
+    }
+
+    let fs_type = args.get(2).map(|s| s.as_str()).unwrap_or("ext2");
+    if let Err(e) = fs::mount_device(&args[0], &args[1], fs_type) {
+        print_fs_error("mount", &args[0], e);
+    }
+}
+
+/// Umount command - detach a mounted filesystem
+pub fn cmd_umount(args: &[String]) {
+    let Some(path) = args.first() else {
+        crate::console::println("Usage: umount <path>");
+        return;
+    };
+    if let Err(e) = fs::mount::unmount(path) {
+        print_fs_error("umount", path, e);
+    }
+}
+
+/// Lsblk command - list block devices and their partitions
+pub fn cmd_lsblk(_args: &[String]) {
+    use crate::drivers::block::DeviceKind;
+
+    crate::console::println("NAME          SIZE  TYPE       START  DESCRIPTION");
+    for info in crate::drivers::block::list() {
+        let size = format_size(info.size);
+        match info.kind {
+            DeviceKind::Disk => {
+                crate::console::println(&alloc::format!("{:<10} {:>7}  disk", info.name, size));
+            }
+            DeviceKind::Partition { start, type_name, label, .. } => {
+                let mut line = alloc::format!(
+                    "{:<10} {:>7}  part  {:>10}  {}",
+                    info.name, size, start, type_name
+                );
+                if !label.is_empty() {
+                    line.push_str(&alloc::format!(" \"{}\"", label));
+                }
+                crate::console::println(&line);
+            }
+        }
+    }
+}
+
+/// Lspci command - list PCI functions and their drivers
+pub fn cmd_lspci(args: &[String]) {
+    use crate::drivers::pci::{self, Bar};
+
+    let verbose = args.iter().any(|a| a == "-v");
+    for entry in pci::devices() {
+        let dev = entry.device;
+        let mut line = alloc::format!(
+            "{} {:02x}{:02x}: {:04x}:{:04x} {}",
+            pci::address(&dev),
+            dev.class,
+            dev.subclass,
+            dev.vendor_id,
+            dev.device_id,
+            pci::class_name(dev.class, dev.subclass)
+        );
+        if dev.revision != 0 {
+            line.push_str(&alloc::format!(" (rev {:02x})", dev.revision));
+        }
+        if let Some(driver) = entry.driver {
+            line.push_str(&alloc::format!(" [{}]", driver));
+        }
+        crate::console::println(&line);
+        if !verbose {
+            continue;
+        }
+
+        for (index, bar) in dev.bars.iter().enumerate() {
+            let line = match *bar {
+                Bar::Memory { addr, size, prefetchable, is_64 } => alloc::format!(
+                    "    BAR{}: memory at {:#x} ({}-bit, {}) [size={}]",
+                    index,
+                    addr,
+                    if is_64 { 64 } else { 32 },
+                    if prefetchable { "prefetchable" } else { "non-prefetchable" },
+                    format_size(size)
+                ),
+                Bar::Io { port, size } => alloc::format!("    BAR{}: I/O ports at {:#x} [size={}]", index, port, size),
+                Bar::None => continue,
+            };
+            crate::console::println(&line);
+        }
+        if dev.irq_line != 0xFF && dev.irq_line != 0 {
+            crate::console::println(&alloc::format!("    IRQ {}", dev.irq_line));
+        }
+        for (id, offset) in dev.capabilities() {
+            crate::console::println(&alloc::format!(
+                "    Capabilities: [{:02x}] {}",
+                offset,
+                pci::capability_name(id)
+            ));
+        }
+    }
+}
+
+/// Acpi command - dump the ACPI tables found at boot
+pub fn cmd_acpi(args: &[String]) {
+    use crate::acpi::{self, MadtEntry};
+
+    let section = args.first().map(|s| s.as_str()).unwrap_or("all");
+    let show = |name: &str| section == "all" || section == name;
+    if !["all", "tables", "madt", "fadt", "hpet", "mcfg"].contains(&section) {
+        crate::console::println("usage: acpi [tables|madt|fadt|hpet|mcfg]");
+        return;
+    }
+    let Some(rsdp) = acpi::rsdp() else {
+        crate::console::println("acpi: no ACPI tables");
+        return;
+    };
+
+    if show("tables") {
+        crate::console::println(&alloc::format!(
+            "RSDP: revision {}, OEM {}, {} at {:#x}",
+            rsdp.revision,
+            acpi::text(&rsdp.oem_id),
+            if rsdp.xsdt { "XSDT" } else { "RSDT" },
+            rsdp.root
+        ));
+        crate::console::println("SIG   ADDRESS             LENGTH  REV  OEM");
+        for table in acpi::tables() {
+            crate::console::println(&alloc::format!(
+                "{:<4}  {:#018x}  {:>6}  {:>3}  {:<6}{}",
+                acpi::text(&table.signature),
+                table.phys,
+                table.length,
+                table.revision,
+                acpi::text(&table.oem_id),
+                if table.valid { "" } else { "  (bad checksum)" }
+            ));
+        }
+    }
+
+    if show("madt") && let Some(madt) = acpi::madt() {
+        crate::console::println(&alloc::format!(
+            "MADT: local APIC at {:#x}{}",
+            madt.local_apic_address,
+            if madt.flags & acpi::madt::PCAT_COMPAT != 0 { ", 8259 PICs present" } else { "" }
+        ));
+        for entry in &madt.entries {
+            let line = match *entry {
+                MadtEntry::LocalApic { processor_id, apic_id, enabled } => alloc::format!(
+                    "  CPU {}: APIC ID {}{}",
+                    processor_id,
+                    apic_id,
+                    if enabled { "" } else { " (disabled)" }
+                ),
+                MadtEntry::LocalX2Apic { processor_uid, x2apic_id, enabled } => alloc::format!(
+                    "  CPU {}: x2APIC ID {}{}",
+                    processor_uid,
+                    x2apic_id,
+                    if enabled { "" } else { " (disabled)" }
+                ),
+                MadtEntry::IoApic { id, address, gsi_base } => {
+                    alloc::format!("  I/O APIC {} at {:#x}, GSI base {}", id, address, gsi_base)
+                }
+                MadtEntry::InterruptOverride { source, gsi, flags, .. } => {
+                    alloc::format!("  IRQ {} -> GSI {}, flags {:#x}", source, gsi, flags)
+                }
+                MadtEntry::NmiSource { gsi, flags } => alloc::format!("  NMI on GSI {}, flags {:#x}", gsi, flags),
+                MadtEntry::LocalApicNmi { processor_id, lint, .. } => {
+                    alloc::format!("  NMI on LINT{} of CPU {:#x}", lint, processor_id)
+                }
+                MadtEntry::LocalApicAddressOverride { address } => {
+                    alloc::format!("  local APIC address override {:#x}", address)
+                }
+                MadtEntry::Other { kind } => alloc::format!("  entry type {}", kind),
+            };
+            crate::console::println(&line);
+        }
+    }
+
+    if show("fadt") && let Some(fadt) = acpi::fadt() {
+        crate::console::println(&alloc::format!(
+            "FADT: revision {}, SCI IRQ {}, DSDT at {:#x}",
+            fadt.revision, fadt.sci_interrupt, fadt.dsdt
+        ));
+        crate::console::println(&alloc::format!(
+            "  PM1a control {:#x}, PM1b control {:#x}, PM timer {:#x}",
+            fadt.pm1a_control, fadt.pm1b_control, fadt.pm_timer
+        ));
+        crate::console::println(&alloc::format!(
+            "  SMI command {:#x}, enable {:#x}, century register {:#x}, boot flags {:#x}",
+            fadt.smi_command, fadt.acpi_enable, fadt.century, fadt.boot_arch
+        ));
+        match fadt.reset_register {
+            Some(reset) if fadt.supports_reset() => crate::console::println(&alloc::format!(
+                "  reset: write {:#x} to {} {:#x}",
+                fadt.reset_value,
+                reset.space_name(),
+                reset.address
+            )),
+            _ => crate::console::println("  reset register not supported"),
+        }
+    }
+
+    if show("hpet") && let Some(hpet) = acpi::hpet() {
+        crate::console::println(&alloc::format!(
+            "HPET {}: revision {}, vendor {:04x}, {} at {:#x}, {} comparators, {}-bit counter, minimum tick {}{}",
+            hpet.number,
+            hpet.hardware_revision,
+            hpet.pci_vendor_id,
+            hpet.address.space_name(),
+            hpet.address.address,
+            hpet.comparators,
+            if hpet.counter_64bit { 64 } else { 32 },
+            hpet.minimum_tick,
+            if hpet.legacy_replacement { ", legacy replacement" } else { "" }
+        ));
+    }
+
+    if show("mcfg") {
+        for entry in acpi::mcfg() {
+            crate::console::println(&alloc::format!(
+                "MCFG: segment {} buses {:02x}-{:02x} at {:#x}",
+                entry.segment, entry.start_bus, entry.end_bus, entry.base
+            ));
+        }
+    }
+}
+
+/// Ls command - list directory contents
+pub fn cmd_ls(args: &[String]) {
+    let long = args.iter().any(|a| a == "-l");
+    let path = args.iter().find(|a| !a.starts_with('-')).map(|s| s.as_str()).unwrap_or("/");
+
+    let mut entries = match fs::file::read_dir(path) {
+        Ok(entries) => entries,
+        Err(FsError::NotADirectory) => {
+            crate::console::println(path);
+            return;
+        }
+        Err(e) => {
+            print_fs_error("ls", path, e);
+            return;
+        }
+    };
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for entry in entries {
+        if !long {
+            crate::console::println(&entry.name);
+            continue;
+        }
+
+        let full_path = if path.ends_with('/') {
+            alloc::format!("{}{}", path, entry.name)
+        } else {
+            alloc::format!("{}/{}", path, entry.name)
+        };
+        match fs::file::metadata(&full_path) {
+            Ok(meta) => {
+                crate::console::print(&meta.mode_string());
+                crate::console::print(" ");
+                crate::console::print(&alloc::format!("{:>3} {:>4} {:>4} {:>8} ", meta.nlink, meta.uid, meta.gid, meta.size));
+                crate::console::print(&entry.name);
+                if let Ok(target) = fs::file::readlink(&full_path) {
+                    crate::console::print(" -> ");
+                    crate::console::print(&target);
+                }
+                crate::console::println("");
+            }
+            Err(e) => print_fs_error("ls", &full_path, e),
+        }
+    }
+}
+
+/// Cat command - print files
+pub fn cmd_cat(args: &[String]) {
+    if args.is_empty() {
+        crate::console::println("Usage: cat <file>...");
+        return;
+    }
+    for path in args {
+        match fs::file::read_to_string(path) {
+            Ok(text) => crate::console::print(&text),
+            Err(e) => print_fs_error("cat", path, e),
+        }
+    }
+}
+
+/// Write command - replace a file's contents with the given text
+pub fn cmd_write(args: &[String]) {
+    if args.len() < 2 {
+        crate::console::println("Usage: write <file> <text>...");
+        return;
+    }
+    let mut text = args[1..].join(" ");
+    text.push('\n');
+    if let Err(e) = fs::file::write(&args[0], text.as_bytes()) {
+        print_fs_error("write", &args[0], e);
+    }
+}
+
+/// Mkdir command - create directories
+pub fn cmd_mkdir(args: &[String]) {
+    if args.is_empty() {
+        crate::console::println("Usage: mkdir <path>...");
+        return;
+    }
+    for path in args {
+        if let Err(e) = fs::file::create_dir(path) {
+            print_fs_error("mkdir", path, e);
+        }
+    }
+}
+
+/// Rm command - remove files, symlinks or empty directories
+pub fn cmd_rm(args: &[String]) {
+    if args.is_empty() {
+        crate::console::println("Usage: rm <path>...");
+        return;
+    }
+    for path in args {
+        if let Err(e) = fs::file::remove(path) {
+            print_fs_error("rm", path, e);
+        }
+    }
+}
+
+/// Ln command - create a symbolic link
+pub fn cmd_ln(args: &[String]) {
+    if args.len() != 3 || args[0] != "-s" {
+        crate::console::println("Usage: ln -s <target> <link>");
+        return;
+    }
+    if let Err(e) = fs::file::symlink(&args[1], &args[2]) {
+        print_fs_error("ln", &args[2], e);
+    }
+}
+
+/// Write back every mounted filesystem before the machine goes down
+fn sync_for_power_off() {
+    crate::console::println("Syncing filesystems...");
+    if let Err(e) = fs::mount::sync_all() {
+        crate::console::print("sync: ");
+        crate::console::println(e.as_str());
+    }
+}
+
+/// Reboot command - sync and restart the system
 pub fn cmd_reboot(_args: &[String]) {
+    sync_for_power_off();
     crate::console::println("Rebooting system...");
-    crate::console::println("(Note: QEMU might not actually reboot)");
-    
-    // Try different reboot methods
-    unsafe {
-        // Method 1: Triple fault (should cause reboot)
-        core::arch::asm!("int3; int3; int3");
-        
-        // Method 2: Reset via keyboard controller
-        use x86_64::instructions::port::Port;
-        let mut port = Port::new(0x64);
-        port.write(0xFE_u8);
-        
-        // Method 3: ACPI reset (simplified)
-        let mut port = Port::new(0xCF9);
-        port.write(0x06_u8);
-    }
-    
-    // If we get here, reboot failed
-    crate::console::println("Reboot failed. System halted.");
-    crate::panic::hcf();
+    crate::power::reboot();
+}
+
+/// Shutdown command - sync and power off (`-r` reboots instead)
+pub fn cmd_shutdown(args: &[String]) {
+    if args.iter().any(|a| a == "-r") {
+        return cmd_reboot(args);
+    }
+    sync_for_power_off();
+    crate::console::println("Powering off...");
+    crate::power::shutdown();
 }
 
 /// Panic command - trigger a kernel panic for testing
@@ -131,12 +777,28 @@ pub fn cmd_panic(_args: &[String]) {
     panic!("User-requested panic from shell");
 }
 
+/// Helper function to report a filesystem error
+fn print_fs_error(cmd: &str, path: &str, error: FsError) {
+    crate::console::print(cmd);
+    crate::console::print(": ");
+    crate::console::print(path);
+    crate::console::print(": ");
+    crate::console::println(error.as_str());
+}
+
+/// Format a byte count with a binary unit suffix
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut unit = 0;
+    let mut value = bytes;
+    while value >= 1024 && unit < UNITS.len() - 1 {
+        value /= 1024;
+        unit += 1;
+    }
+    alloc::format!("{}{}", value, UNITS[unit])
+}
+
 /// Helper function to print decimal numbers
 fn print_decimal(value: u64) {
     crate::memory::print_decimal(value);
 }
-
-/// Helper function to print sizes
-fn print_size(bytes: u64) {
-    crate::memory::print_size(bytes);
-}
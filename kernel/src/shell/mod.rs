@@ -6,9 +6,13 @@ pub mod builtins;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
+use crate::console::LOG_VT;
+use crate::tty;
+
 /// Shell state
 pub struct Shell {
-    input_buffer: String,
+    /// Input read from the terminal but not yet a complete line
+    input_buffer: Vec<u8>,
     history: Vec<String>,
     prompt: &'static str,
 }
@@ -17,7 +21,7 @@ impl Shell {
     /// Create a new shell
     pub fn new() -> Self {
         Self {
-            input_buffer: String::new(),
+            input_buffer: Vec::new(),
             history: Vec::new(),
             prompt: "polyglot> ",
         }
@@ -34,41 +38,47 @@ impl Shell {
         // self.show_prompt();
     }
     
-    /// Handle a character input
-    pub fn handle_char(&mut self, c: u8) {
-        match c {
-            b'\n' | b'\r' => {
-                // Execute command
-                crate::console::print("\n");
-                let command = self.input_buffer.trim().to_string();
-                
-                if !command.is_empty() {
-                    self.history.push(command.clone());
-                    self.execute_command(&command);
-                }
-                
+    /// Handle input or a signal from the shell's terminal
+    ///
+    /// The terminal does the line editing; complete lines are run as
+    /// commands. Input is split on CR as well as NL so that commands still
+    /// run with the terminal in raw mode.
+    fn handle_event(&mut self, event: tty::Event) {
+        match event {
+            tty::Event::Signal(_) => {
+                // Ctrl+C and friends with nothing running abandon the line
                 self.input_buffer.clear();
                 self.show_prompt();
             }
-            b'\x08' => {
-                // Backspace
-                if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    crate::console::print("\x08 \x08"); // Backspace, space, backspace
+            tty::Event::Readable => loop {
+                let mut buf = [0u8; 256];
+                match tty::with(tty::console(), |tty| tty.read(&mut buf)) {
+                    None => break,
+                    Some(0) => {
+                        crate::console::println("Use \"shutdown\" to leave the shell.");
+                        self.show_prompt();
+                    }
+                    Some(len) => {
+                        self.input_buffer.extend_from_slice(&buf[..len]);
+                        while let Some(end) = self.input_buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
+                            let line: Vec<u8> = self.input_buffer.drain(..=end).collect();
+                            self.handle_line(&String::from_utf8_lossy(&line));
+                        }
+                    }
                 }
-            }
-            c if c >= 32 && c <= 126 => {
-                // Printable character
-                self.input_buffer.push(c as char);
-                let byte_array = [c];
-                let s = core::str::from_utf8(&byte_array).unwrap_or("?");
-                crate::console::print(s);
-            }
-            _ => {
-                // Ignore other characters
-            }
+            },
         }
     }
+
+    /// Run one line of input
+    fn handle_line(&mut self, line: &str) {
+        let command = line.trim().to_string();
+        if !command.is_empty() {
+            self.history.push(command.clone());
+            self.execute_command(&command);
+        }
+        self.show_prompt();
+    }
     
     /// Show the command prompt
     fn show_prompt(&self) {
@@ -93,8 +103,29 @@ impl Shell {
             "mem" => builtins::cmd_mem(cmd_args),
             "tasks" => builtins::cmd_tasks(cmd_args),
             "uptime" => builtins::cmd_uptime(cmd_args),
+            "date" => builtins::cmd_date(cmd_args),
+            "sleep" => builtins::cmd_sleep(cmd_args),
+            "loadkeys" => builtins::cmd_loadkeys(cmd_args),
+            "setfont" => builtins::cmd_setfont(cmd_args),
+            "scrollback" => builtins::cmd_scrollback(cmd_args),
+            "serial" => builtins::cmd_serial(cmd_args),
+            "stty" => builtins::cmd_stty(cmd_args),
+            "kill" => builtins::cmd_kill(cmd_args),
+            "fg" => builtins::cmd_fg(cmd_args),
             "history" => builtins::cmd_history(cmd_args, &self.history),
+            "mount" => builtins::cmd_mount(cmd_args),
+            "umount" => builtins::cmd_umount(cmd_args),
+            "ls" => builtins::cmd_ls(cmd_args),
+            "cat" => builtins::cmd_cat(cmd_args),
+            "write" => builtins::cmd_write(cmd_args),
+            "mkdir" => builtins::cmd_mkdir(cmd_args),
+            "rm" => builtins::cmd_rm(cmd_args),
+            "ln" => builtins::cmd_ln(cmd_args),
+            "lsblk" => builtins::cmd_lsblk(cmd_args),
+            "lspci" => builtins::cmd_lspci(cmd_args),
+            "acpi" => builtins::cmd_acpi(cmd_args),
             "reboot" => builtins::cmd_reboot(cmd_args),
+            "shutdown" | "poweroff" => builtins::cmd_shutdown(cmd_args),
             "panic" => builtins::cmd_panic(cmd_args),
             _ => {
                 crate::console::print("Unknown command: ");
@@ -106,24 +137,37 @@ impl Shell {
     }
 }
 
-/// Global shell instance
-static mut SHELL: Option<Shell> = None;
+/// A shell for each virtual console but the kernel log's
+static mut SHELLS: Vec<Shell> = Vec::new();
 
-/// Initialize the shell
+/// Reader of each shell's terminal, telling it which console it is on
+const READERS: [fn(tty::Event); LOG_VT] = [
+    handle_tty_event::<0>,
+    handle_tty_event::<1>,
+    handle_tty_event::<2>,
+    handle_tty_event::<3>,
+    handle_tty_event::<4>,
+];
+
+/// Start a shell as the reader of each virtual console's terminal
 pub fn init() {
-    unsafe {
-        SHELL = Some(Shell::new());
-        if let Some(ref mut shell) = SHELL {
-            shell.run();
+    for (vt, reader) in READERS.into_iter().enumerate() {
+        let mut shell = Shell::new();
+        crate::console::with_output(vt, || shell.run());
+        let shells = &raw mut SHELLS;
+        unsafe {
+            (&mut *shells).push(shell);
         }
+        tty::with(tty::vt(vt), |tty| tty.set_reader(reader));
     }
 }
 
-/// Handle keyboard input for the shell
-pub fn handle_keyboard_input(c: u8) {
-    unsafe {
-        if let Some(ref mut shell) = SHELL {
-            shell.handle_char(c);
+/// Handle input from the terminal of console `VT`, printing to it
+fn handle_tty_event<const VT: usize>(event: tty::Event) {
+    let shells = &raw mut SHELLS;
+    crate::console::with_output(VT, || unsafe {
+        if let Some(shell) = (&mut *shells).get_mut(VT) {
+            shell.handle_event(event);
         }
-    }
+    });
 }
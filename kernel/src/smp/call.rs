@@ -0,0 +1,144 @@
+//! Cross-CPU function calls
+//!
+//! A call is queued in the target CPU's mailbox and announced with an IPI
+//! on `CALL_VECTOR`; the target runs it from the interrupt handler. The
+//! mailbox is a fixed ring in the per-CPU block, so queuing never allocates
+//! and works from paths such as TLB shootdown. Callers may wait for
+//! completion. While waiting, or for room in a full mailbox, they keep
+//! running calls queued for their own CPU, so two CPUs calling each other
+//! with interrupts disabled cannot deadlock.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+
+use super::percpu::{self, PerCpu};
+use crate::interrupts::apic::{self, IpiTarget};
+
+/// A function to run on another CPU, with its two arguments
+pub type CallFn = fn(u64, u64);
+
+/// One queued call
+pub struct Call {
+    func: CallFn,
+    args: (u64, u64),
+    /// Counter the caller waits on, or null if nobody waits
+    pending: *const AtomicUsize,
+}
+
+// The counter lives on the stack of a caller that waits for it to drain
+unsafe impl Send for Call {}
+
+/// Calls a CPU's mailbox holds before callers have to wait for room
+const MAILBOX_SLOTS: usize = 16;
+
+/// Calls queued for one CPU, oldest first
+pub struct Mailbox {
+    slots: [Option<Call>; MAILBOX_SLOTS],
+    head: usize,
+    len: usize,
+}
+
+impl Mailbox {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { None }; MAILBOX_SLOTS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Queue a call, handing it back if the mailbox is full
+    fn push(&mut self, call: Call) -> Result<(), Call> {
+        if self.len == MAILBOX_SLOTS {
+            return Err(call);
+        }
+        self.slots[(self.head + self.len) % MAILBOX_SLOTS] = Some(call);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Call> {
+        let call = self.slots[self.head].take()?;
+        self.head = (self.head + 1) % MAILBOX_SLOTS;
+        self.len -= 1;
+        Some(call)
+    }
+}
+
+/// Run every call queued for the calling CPU
+fn run_pending() {
+    let cpu = percpu::current();
+    // The queue is also drained from the IPI handler on this CPU
+    while let Some(call) = interrupts::without_interrupts(|| cpu.calls.lock().pop()) {
+        (call.func)(call.args.0, call.args.1);
+        if !call.pending.is_null() {
+            unsafe { (*call.pending).fetch_sub(1, Ordering::Release) };
+        }
+    }
+}
+
+/// Called from the `CALL_VECTOR` interrupt
+pub fn handle_interrupt() {
+    run_pending();
+}
+
+fn queue(cpu: &PerCpu, func: CallFn, args: (u64, u64), pending: *const AtomicUsize) {
+    let mut call = Call { func, args, pending };
+    loop {
+        match interrupts::without_interrupts(|| cpu.calls.lock().push(call)) {
+            Ok(()) => return,
+            Err(back) => call = back,
+        }
+        // Make sure the target is draining, and drain our own mailbox in
+        // case the target is waiting for room in it
+        apic::send_ipi(IpiTarget::Cpu(cpu.apic_id), apic::CALL_VECTOR);
+        run_pending();
+        core::hint::spin_loop();
+    }
+}
+
+/// Wait for `pending` to reach zero, serving calls made to this CPU meanwhile
+fn wait(pending: &AtomicUsize) {
+    while pending.load(Ordering::Acquire) != 0 {
+        run_pending();
+        core::hint::spin_loop();
+    }
+}
+
+/// Run `func(a, b)` on the CPU with index `target`, optionally waiting for
+/// it to finish; a call to the calling CPU runs immediately
+pub fn call_on(target: usize, func: CallFn, a: u64, b: u64, wait_done: bool) {
+    if target == percpu::current().index {
+        func(a, b);
+        return;
+    }
+    let Some(cpu) = percpu::all().find(|cpu| cpu.index == target && cpu.online.load(Ordering::Acquire))
+    else {
+        return;
+    };
+
+    let pending = AtomicUsize::new(1);
+    queue(cpu, func, (a, b), if wait_done { &pending } else { core::ptr::null() });
+    apic::send_ipi(IpiTarget::Cpu(cpu.apic_id), apic::CALL_VECTOR);
+    if wait_done {
+        wait(&pending);
+    }
+}
+
+/// Run `func(a, b)` on every other online CPU and wait for all of them
+pub fn call_others(func: CallFn, a: u64, b: u64) {
+    let me = percpu::current().index;
+    let pending = AtomicUsize::new(0);
+    let mut queued = false;
+    for cpu in percpu::all().filter(|cpu| cpu.index != me && cpu.online.load(Ordering::Acquire)) {
+        // Counted before queuing, so the target never decrements first
+        pending.fetch_add(1, Ordering::Relaxed);
+        queue(cpu, func, (a, b), &pending);
+        queued = true;
+    }
+    if !queued {
+        return;
+    }
+    apic::send_ipi(IpiTarget::Others, apic::CALL_VECTOR);
+    wait(&pending);
+}
@@ -0,0 +1,138 @@
+//! Symmetric multiprocessing
+//!
+//! Limine starts every application processor (AP) in long mode on its own
+//! stack, parked until an entry point is written to its `goto_address`.
+//! Each AP then loads its own GDT and TSS and the shared IDT, enables its
+//! local APIC, installs its per-CPU block and idles in the scheduler. TSCs
+//! are assumed to be synchronized, as they are on invariant-TSC hardware.
+
+pub mod call;
+pub mod percpu;
+pub mod tlb;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use limine::mp::Cpu;
+use limine::request::MpRequest;
+
+use crate::interrupts::apic::{self, IpiTarget};
+use crate::time::{Duration, Instant};
+use percpu::PerCpu;
+
+/// Request that Limine start the application processors
+#[used]
+#[unsafe(link_section = ".limine_requests")]
+static MP_REQUEST: MpRequest = MpRequest::new();
+
+/// How long to wait for all APs to report in
+const AP_START_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for the other CPUs to stop before shutdown or reboot
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Set up the bootstrap processor's per-CPU block
+///
+/// Runs before the local APIC is mapped; without an MP response there are
+/// no other CPUs to tell apart, so the APIC ID is left at 0.
+pub fn init_bsp() {
+    let apic_id = MP_REQUEST.get_response().map_or(0, |response| response.bsp_lapic_id());
+    percpu::install(percpu::add(apic_id).expect("no per-CPU block for the bootstrap processor"));
+}
+
+/// Start every application processor and wait for them to come online
+pub fn init() {
+    let Some(response) = MP_REQUEST.get_response() else {
+        crate::serial::print("SMP: no response from bootloader, running on one CPU\n");
+        return;
+    };
+    if !crate::interrupts::irq::apic_mode() {
+        crate::serial::print("SMP: application processors need the local APIC, running on one CPU\n");
+        return;
+    }
+
+    let cpus = response.cpus();
+    let mut started = 1;
+    for cpu in cpus.iter().filter(|cpu| cpu.lapic_id != response.bsp_lapic_id()) {
+        let Some(block) = percpu::add(cpu.lapic_id) else {
+            crate::serial::print("SMP: out of per-CPU blocks, leaving the remaining CPUs parked\n");
+            break;
+        };
+        started += 1;
+        cpu.extra.store(block as *const PerCpu as u64, Ordering::Release);
+        // Publishes the store above before the AP starts running
+        cpu.goto_address.write(ap_entry);
+    }
+
+    let deadline = Instant::now() + AP_START_TIMEOUT;
+    while percpu::online_count() < started && Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+
+    crate::serial::print("SMP: ");
+    crate::memory::print_decimal(percpu::online_count() as u64);
+    crate::serial::print(" of ");
+    crate::memory::print_decimal(cpus.len() as u64);
+    crate::serial::print(" CPUs online\n");
+}
+
+/// Stop every other CPU, so nothing else runs while the machine powers off
+/// or resets
+///
+/// Each takes the request at its next interrupt window and goes offline.
+/// A CPU spinning with interrupts disabled might never do so, which is why
+/// the wait is bounded.
+pub fn halt_others() {
+    let me = percpu::current().index;
+    for cpu in percpu::all() {
+        if cpu.index != me && cpu.online.load(Ordering::Acquire) {
+            call::call_on(cpu.index, halt, 0, 0, false);
+        }
+    }
+    let deadline = Instant::now() + HALT_TIMEOUT;
+    while percpu::online_count() > 1 && Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Set once a panicking CPU has told the others to stop
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Stop every other CPU at once, for the panic handler
+///
+/// Unlike `halt_others` this takes no locks and does not wait: an NMI
+/// reaches each CPU even with interrupts disabled, and the NMI handler
+/// halts it once `stopping` is set.
+pub fn stop_others() {
+    if STOPPING.swap(true, Ordering::AcqRel) || percpu::online_count() <= 1 {
+        return;
+    }
+    apic::send_nmi(IpiTarget::Others);
+}
+
+/// Whether a panic has asked every CPU but its own to stop
+pub fn stopping() -> bool {
+    STOPPING.load(Ordering::Acquire)
+}
+
+/// Take the calling CPU offline for good
+fn halt(_: u64, _: u64) {
+    percpu::current().online.store(false, Ordering::Release);
+    crate::panic::hcf();
+}
+
+/// Entry point of each application processor
+unsafe extern "C" fn ap_entry(cpu: &Cpu) -> ! {
+    let block = unsafe { &*(cpu.extra.load(Ordering::Acquire) as *const PerCpu) };
+
+    crate::interrupts::init_ap();
+    percpu::install(block);
+    crate::time::sleep::init();
+
+    crate::serial::print("SMP: CPU ");
+    crate::memory::print_decimal(block.index as u64);
+    crate::serial::print(" online, APIC ID ");
+    crate::memory::print_decimal(block.apic_id as u64);
+    crate::serial::print("\n");
+
+    x86_64::instructions::interrupts::enable();
+    crate::task::scheduler::idle()
+}
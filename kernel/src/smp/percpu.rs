@@ -0,0 +1,95 @@
+//! Per-CPU data
+//!
+//! Each CPU's GS base points at its own `PerCpu`, whose first field points
+//! back at itself, so the current CPU's data is one `gs:`-relative load
+//! away. Blocks are allocated once and never freed, and are listed in a
+//! fixed table so finding them takes neither a lock nor an allocation.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::GsBase;
+
+use super::call::Mailbox;
+use crate::time::Instant;
+
+/// Most CPUs brought up; the rest stay parked in the bootloader
+pub const MAX_CPUS: usize = 256;
+
+/// Data owned by one CPU
+#[repr(C)]
+pub struct PerCpu {
+    /// Address of this block; must stay the first field
+    self_ptr: *const PerCpu,
+    /// Dense index, 0 for the bootstrap processor
+    pub index: usize,
+    pub apic_id: u32,
+    pub online: AtomicBool,
+    /// Deadlines of sleepers on this CPU, earliest first; they program its
+    /// local APIC timer
+    pub sleep_queue: Mutex<Vec<Instant>>,
+    /// Cross-CPU calls waiting to run here
+    pub calls: Mutex<Mailbox>,
+    /// Set when the scheduler should run here as soon as possible
+    pub need_resched: AtomicBool,
+}
+
+// `self_ptr` never changes after setup, and queued calls are only run by
+// the CPU they were queued for
+unsafe impl Sync for PerCpu {}
+
+/// Every CPU's block, in index order; the first `COUNT` are set
+static CPUS: [AtomicPtr<PerCpu>; MAX_CPUS] = [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CPUS];
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate the block for the next CPU index, if there is room for it
+///
+/// Only the bootstrap processor adds blocks, before the APs are started.
+pub fn add(apic_id: u32) -> Option<&'static PerCpu> {
+    let index = COUNT.load(Ordering::Relaxed);
+    if index == MAX_CPUS {
+        return None;
+    }
+    let block = Box::leak(Box::new(PerCpu {
+        self_ptr: core::ptr::null(),
+        index,
+        apic_id,
+        online: AtomicBool::new(false),
+        sleep_queue: Mutex::new(Vec::new()),
+        calls: Mutex::new(Mailbox::new()),
+        need_resched: AtomicBool::new(false),
+    }));
+    block.self_ptr = block;
+    CPUS[index].store(block, Ordering::Release);
+    COUNT.store(index + 1, Ordering::Release);
+    Some(block)
+}
+
+/// Point this CPU's GS base at its block
+pub fn install(block: &'static PerCpu) {
+    GsBase::write(VirtAddr::from_ptr(block));
+    block.online.store(true, Ordering::Release);
+}
+
+/// The calling CPU's block
+pub fn current() -> &'static PerCpu {
+    let ptr: *const PerCpu;
+    unsafe {
+        core::arch::asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, readonly, preserves_flags));
+        &*ptr
+    }
+}
+
+/// Every CPU's block
+pub fn all() -> impl Iterator<Item = &'static PerCpu> {
+    CPUS[..COUNT.load(Ordering::Acquire)]
+        .iter()
+        .map(|block| unsafe { &*block.load(Ordering::Acquire) })
+}
+
+/// Number of CPUs that have come online
+pub fn online_count() -> usize {
+    all().filter(|cpu| cpu.online.load(Ordering::Acquire)).count()
+}
@@ -0,0 +1,29 @@
+//! TLB shootdown
+//!
+//! Page table changes that remove or restrict a mapping must be flushed
+//! from every CPU's TLB, not just the one that made them. The initiator
+//! flushes locally, then has every other online CPU flush the same range
+//! through a cross-CPU call and waits until all have done so.
+
+use x86_64::VirtAddr;
+use x86_64::instructions::tlb;
+
+/// Ranges longer than this many pages flush the whole TLB instead
+const FULL_FLUSH_PAGES: u64 = 32;
+
+/// Flush `pages` pages starting at `start` from the calling CPU's TLB
+fn flush_local(start: u64, pages: u64) {
+    if pages > FULL_FLUSH_PAGES {
+        tlb::flush_all();
+        return;
+    }
+    for page in 0..pages {
+        tlb::flush(VirtAddr::new(start + page * 4096));
+    }
+}
+
+/// Invalidate `pages` pages starting at `start` on every CPU
+pub fn shootdown(start: VirtAddr, pages: u64) {
+    flush_local(start.as_u64(), pages);
+    super::call::call_others(flush_local, start.as_u64(), pages);
+}
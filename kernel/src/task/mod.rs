@@ -2,18 +2,19 @@
 
 pub mod task;
 pub mod scheduler;
+pub mod signal;
 pub mod switch;
 
-pub use task::Task;
-pub use scheduler::SCHEDULER;
+pub use task::{Task, TaskId};
+pub use scheduler::with_scheduler;
 
 /// Initialize the task management subsystem
 pub fn init() {
     crate::serial::print("Initializing task management...\n");
     
-    // Create the initial kernel task
+    // The initial kernel task is what the bootstrap processor is running
     let kernel_task = Task::new_kernel_task();
-    scheduler::SCHEDULER.lock().add_task(kernel_task);
+    with_scheduler(|scheduler| scheduler.set_current(kernel_task));
     
     // Create some demo tasks
     create_demo_tasks();
@@ -28,14 +29,14 @@ fn create_demo_tasks() {
         task1_main as *const () as u64,
         "Counter Task".into(),
     );
-    scheduler::SCHEDULER.lock().add_task(task1);
+    with_scheduler(|scheduler| scheduler.add_task(task1));
     
     // Task 2: Letter task  
     let task2 = Task::new(
         task2_main as *const () as u64,
         "Letter Task".into(),
     );
-    scheduler::SCHEDULER.lock().add_task(task2);
+    with_scheduler(|scheduler| scheduler.add_task(task2));
 }
 
 /// Demo task 1: Counts numbers
@@ -1,81 +1,178 @@
 //! Task scheduler implementation
 
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
 use spin::Mutex;
 use lazy_static::lazy_static;
 
-use super::task::{Task, TaskId, TaskState};
+use super::signal::{Action, Signal};
+use super::task::{Task, TaskId, TaskInfo, TaskState};
+use crate::smp::{call, percpu};
+use crate::time::{Duration, Instant};
 
 /// Global task scheduler
 lazy_static! {
-    pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+    static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
 }
 
-/// Round-robin task scheduler
-pub struct Scheduler {
+/// Run `f` with the scheduler locked
+///
+/// The timer interrupt schedules too, so interrupts stay disabled while the
+/// lock is held.
+pub fn with_scheduler<R>(f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| f(&mut SCHEDULER.lock()))
+}
+
+/// One CPU's share of the scheduler
+struct RunQueue {
     /// Queue of ready tasks
-    ready_queue: VecDeque<Task>,
-    /// Currently running task
-    current_task: Option<Task>,
+    ready: VecDeque<Task>,
+    /// Task running on this CPU
+    current: Option<Task>,
     /// Task switch counter
     switch_count: u64,
 }
 
+impl RunQueue {
+    fn new() -> Self {
+        Self {
+            ready: VecDeque::new(),
+            current: None,
+            switch_count: 0,
+        }
+    }
+}
+
+/// Round-robin task scheduler with a run queue per CPU
+///
+/// New tasks go on the shortest queue of an online CPU, which is woken
+/// with a cross-CPU call if it is not the caller. A CPU whose queue runs
+/// dry steals from the back of the longest other queue.
+pub struct Scheduler {
+    /// Run queues indexed by CPU index, grown as CPUs first schedule
+    queues: Vec<RunQueue>,
+}
+
 impl Scheduler {
     /// Create a new scheduler
     pub fn new() -> Self {
-        Self {
-            ready_queue: VecDeque::new(),
-            current_task: None,
-            switch_count: 0,
+        Self { queues: Vec::new() }
+    }
+
+    /// The run queue of CPU `cpu`
+    fn queue(&mut self, cpu: usize) -> &mut RunQueue {
+        if self.queues.len() <= cpu {
+            self.queues.resize_with(cpu + 1, RunQueue::new);
         }
+        &mut self.queues[cpu]
+    }
+
+    /// The calling CPU's run queue
+    fn local(&mut self) -> &mut RunQueue {
+        self.queue(percpu::current().index)
     }
     
-    /// Add a task to the scheduler
+    /// Add a task to the least loaded online CPU's run queue
     pub fn add_task(&mut self, mut task: Task) {
         let task_id = task.id.0;
         let task_name = task.name.clone();
+
+        let here = percpu::current().index;
+        let load = |queues: &[RunQueue], cpu: usize| {
+            queues.get(cpu).map_or(0, |q| q.ready.len() + q.current.is_some() as usize)
+        };
+        let cpu = percpu::all()
+            .filter(|cpu| cpu.online.load(Ordering::Acquire))
+            .map(|cpu| cpu.index)
+            .min_by_key(|&cpu| (load(&self.queues, cpu), cpu != here))
+            .unwrap_or(here);
         
         task.state = TaskState::Ready;
-        self.ready_queue.push_back(task);
+        self.queue(cpu).ready.push_back(task);
+        if cpu != here {
+            call::call_on(cpu, request_resched, 0, 0, false);
+        }
         
         crate::serial::print("Added task ");
         crate::memory::print_decimal(task_id);
         crate::serial::print(" (");
         crate::serial::print(&task_name);
-        crate::serial::print(")\n");
+        crate::serial::print(") on CPU ");
+        crate::memory::print_decimal(cpu as u64);
+        crate::serial::print("\n");
+    }
+
+    /// Make `task` the one running on the calling CPU, for the context
+    /// that is already executing when the scheduler starts
+    pub fn set_current(&mut self, mut task: Task) {
+        task.state = TaskState::Running;
+        self.local().current = Some(task);
     }
     
-    /// Get the currently running task
-    pub fn current_task(&self) -> Option<&Task> {
-        self.current_task.as_ref()
+    /// Get the task running on the calling CPU
+    pub fn current_task(&mut self) -> Option<&Task> {
+        self.local().current.as_ref()
     }
     
     /// Get the current task ID
-    pub fn current_task_id(&self) -> Option<TaskId> {
-        self.current_task.as_ref().map(|t| t.id)
+    pub fn current_task_id(&mut self) -> Option<TaskId> {
+        self.local().current.as_ref().map(|t| t.id)
+    }
+
+    /// Take a ready task from the back of the longest queue other than `cpu`'s
+    fn steal(&mut self, cpu: usize) -> Option<Task> {
+        let victim = (0..self.queues.len())
+            .filter(|&other| other != cpu)
+            .max_by_key(|&other| self.queues[other].ready.len())?;
+        let ready = &mut self.queues[victim].ready;
+        let index = ready.iter().rposition(|task| task.state == TaskState::Ready)?;
+        let task = ready.remove(index)?;
+
+        crate::serial::print("CPU ");
+        crate::memory::print_decimal(cpu as u64);
+        crate::serial::print(" stole task ");
+        crate::memory::print_decimal(task.id.0);
+        crate::serial::print(" from CPU ");
+        crate::memory::print_decimal(victim as u64);
+        crate::serial::print("\n");
+        Some(task)
     }
     
-    /// Schedule the next task (round-robin)
+    /// Schedule the next task on the calling CPU (round-robin)
     pub fn schedule(&mut self) -> Option<&mut Task> {
-        // Move current task back to ready queue if it's still running
-        if let Some(mut current) = self.current_task.take() {
+        let cpu = percpu::current().index;
+        percpu::current().need_resched.store(false, Ordering::Relaxed);
+        let queue = self.local();
+
+        // Move current task back to ready queue if it's still running,
+        // keeping stopped tasks queued until they are continued
+        if let Some(mut current) = queue.current.take() {
             if current.state == TaskState::Running {
                 current.state = TaskState::Ready;
-                self.ready_queue.push_back(current);
+                queue.ready.push_back(current);
+            } else if current.state == TaskState::Stopped {
+                queue.ready.push_back(current);
             }
         }
         
-        // Get next ready task
-        if let Some(mut next_task) = self.ready_queue.pop_front() {
+        // Get next ready task, stealing one if this CPU has none
+        let next = match queue.ready.iter().position(|task| task.state == TaskState::Ready) {
+            Some(index) => queue.ready.remove(index),
+            None => self.steal(cpu),
+        };
+        let queue = &mut self.queues[cpu];
+        if let Some(mut next_task) = next {
             next_task.state = TaskState::Running;
-            self.current_task = Some(next_task);
-            self.switch_count += 1;
+            queue.current = Some(next_task);
+            queue.switch_count += 1;
             
-            if let Some(ref task) = self.current_task {
-                if self.switch_count % 100 == 0 { // Log every 100 switches
-                    crate::serial::print("Switch #");
-                    crate::memory::print_decimal(self.switch_count);
+            if let Some(ref task) = queue.current {
+                if queue.switch_count % 100 == 0 { // Log every 100 switches
+                    crate::serial::print("CPU ");
+                    crate::memory::print_decimal(cpu as u64);
+                    crate::serial::print(" switch #");
+                    crate::memory::print_decimal(queue.switch_count);
                     crate::serial::print(" -> Task ");
                     crate::memory::print_decimal(task.id.0);
                     crate::serial::print("\n");
@@ -83,27 +180,82 @@ impl Scheduler {
             }
         }
         
-        self.current_task.as_mut()
+        queue.current.as_mut()
     }
     
-    /// Get scheduler statistics
+    /// Get scheduler statistics: ready tasks and switches across all CPUs
     pub fn stats(&self) -> (usize, u64) {
-        (self.ready_queue.len(), self.switch_count)
+        self.queues
+            .iter()
+            .fold((0, 0), |(ready, switches), queue| (ready + queue.ready.len(), switches + queue.switch_count))
+    }
+    
+    /// Snapshot every task known to the scheduler, ordered by ID
+    pub fn task_infos(&self) -> Vec<TaskInfo> {
+        let mut infos: Vec<TaskInfo> = self
+            .queues
+            .iter()
+            .enumerate()
+            .flat_map(|(cpu, queue)| {
+                queue
+                    .current
+                    .iter()
+                    .chain(queue.ready.iter())
+                    .map(move |task| task.info(cpu))
+            })
+            .collect();
+        infos.sort_by_key(|info| info.id.0);
+        infos
     }
     
     /// Block the current task
     pub fn block_current_task(&mut self) {
-        if let Some(ref mut task) = self.current_task {
+        if let Some(ref mut task) = self.local().current {
             task.state = TaskState::Blocked;
         }
     }
     
     /// Terminate the current task
     pub fn terminate_current_task(&mut self) {
-        if let Some(ref mut task) = self.current_task {
+        if let Some(ref mut task) = self.local().current {
             task.state = TaskState::Terminated;
         }
     }
+
+    /// Deliver a signal to the task with ID `id`, returning whether it exists
+    ///
+    /// Signals act at once: a terminated task is dropped from its run queue,
+    /// or, if it is running, when its CPU next schedules.
+    pub fn signal(&mut self, id: TaskId, signal: Signal) -> bool {
+        for queue in &mut self.queues {
+            if let Some(task) = queue.current.as_mut().filter(|task| task.id == id) {
+                apply_signal(task, signal);
+                // A continued task that never left its CPU is still running
+                if task.state == TaskState::Ready {
+                    task.state = TaskState::Running;
+                }
+                return true;
+            }
+            if let Some(index) = queue.ready.iter().position(|task| task.id == id) {
+                apply_signal(&mut queue.ready[index], signal);
+                if queue.ready[index].state == TaskState::Terminated {
+                    queue.ready.remove(index);
+                }
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Run a signal's default action
+fn apply_signal(task: &mut Task, signal: Signal) {
+    match signal.default_action() {
+        Action::Terminate => task.state = TaskState::Terminated,
+        Action::Stop => task.state = TaskState::Stopped,
+        Action::Continue if task.state == TaskState::Stopped => task.state = TaskState::Ready,
+        Action::Continue => {}
+    }
 }
 
 /// Called by timer interrupt to perform preemptive scheduling
@@ -117,11 +269,35 @@ pub fn timer_tick() {
         
         // Every 10 ticks (100ms), try to schedule
         if TICK_COUNT % 10 == 0 {
-            let mut scheduler = SCHEDULER.lock();
-            if let Some(_next_task) = scheduler.schedule() {
-                // TODO: Perform actual context switch
-                // For now, just log the scheduling decision
-            }
+            with_scheduler(|scheduler| {
+                if let Some(_next_task) = scheduler.schedule() {
+                    // TODO: Perform actual context switch
+                    // For now, just log the scheduling decision
+                }
+            });
         }
     }
 }
+
+/// How long an idle CPU sleeps between scheduling passes
+const IDLE_SLICE: Duration = Duration::from_millis(100);
+
+/// Cross-CPU call target: have the receiving CPU schedule soon
+fn request_resched(_: u64, _: u64) {
+    percpu::current().need_resched.store(true, Ordering::Relaxed);
+}
+
+/// Scheduling loop of the application processors
+///
+/// Runs a pass every `IDLE_SLICE`, or as soon as another CPU asks.
+pub fn idle() -> ! {
+    let cpu = percpu::current();
+    loop {
+        with_scheduler(|scheduler| {
+            scheduler.schedule();
+        });
+        crate::time::sleep::sleep_until(Instant::now() + IDLE_SLICE, || {
+            cpu.need_resched.load(Ordering::Relaxed)
+        });
+    }
+}
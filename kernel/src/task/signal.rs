@@ -0,0 +1,80 @@
+//! Signals
+//!
+//! A signal runs its default action on the receiving task right away.
+//! Tasks cannot catch signals: nothing would run a handler, as the
+//! scheduler does not yet switch into them.
+
+use super::TaskId;
+use super::scheduler::with_scheduler;
+
+/// Signals the kernel can deliver, numbered as on Linux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Signal {
+    /// Ctrl+C
+    Interrupt = 2,
+    /// Ctrl+\
+    Quit = 3,
+    Kill = 9,
+    Terminate = 15,
+    Continue = 18,
+    /// Ctrl+Z
+    Stop = 20,
+}
+
+/// What happens to a task that does not catch a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Terminate,
+    Stop,
+    Continue,
+}
+
+impl Signal {
+    pub const ALL: [Signal; 6] = [
+        Signal::Interrupt,
+        Signal::Quit,
+        Signal::Kill,
+        Signal::Terminate,
+        Signal::Continue,
+        Signal::Stop,
+    ];
+
+    pub fn number(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Name without the `SIG` prefix
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signal::Interrupt => "INT",
+            Signal::Quit => "QUIT",
+            Signal::Kill => "KILL",
+            Signal::Terminate => "TERM",
+            Signal::Continue => "CONT",
+            Signal::Stop => "TSTP",
+        }
+    }
+
+    /// Look up a signal by number or name, with or without `SIG`
+    pub fn parse(text: &str) -> Option<Signal> {
+        let name = text.strip_prefix("SIG").unwrap_or(text);
+        Self::ALL
+            .into_iter()
+            .find(|signal| signal.name().eq_ignore_ascii_case(name) || name.parse() == Ok(signal.number()))
+    }
+
+    pub fn default_action(&self) -> Action {
+        match self {
+            Signal::Interrupt | Signal::Quit | Signal::Kill | Signal::Terminate => Action::Terminate,
+            Signal::Continue => Action::Continue,
+            Signal::Stop => Action::Stop,
+        }
+    }
+
+}
+
+/// Send `signal` to the task with ID `id`, returning whether it exists
+pub fn send(id: TaskId, signal: Signal) -> bool {
+    with_scheduler(|scheduler| scheduler.signal(id, signal))
+}
@@ -17,9 +17,38 @@ pub enum TaskState {
     Ready,
     Running,
     Blocked,
+    /// Suspended by a stop signal until it is continued
+    Stopped,
     Terminated,
 }
 
+impl TaskState {
+    /// Name of the state for display
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Ready => "Ready",
+            TaskState::Running => "Running",
+            TaskState::Blocked => "Blocked",
+            TaskState::Stopped => "Stopped",
+            TaskState::Terminated => "Terminated",
+        }
+    }
+}
+
+/// Point-in-time description of a task, for reporting
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub state: TaskState,
+    /// Index of the CPU whose run queue holds the task
+    pub cpu: usize,
+    pub stack_base: u64,
+    pub stack_size: usize,
+    pub rsp: u64,
+    pub rip: u64,
+}
+
 /// CPU register state for context switching
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -118,6 +147,20 @@ impl Task {
         }
     }
     
+    /// Snapshot this task's reportable state
+    pub fn info(&self, cpu: usize) -> TaskInfo {
+        TaskInfo {
+            id: self.id,
+            name: self.name.clone(),
+            state: self.state,
+            cpu,
+            stack_base: self.stack_base.as_u64(),
+            stack_size: self.stack_size,
+            rsp: self.registers.rsp,
+            rip: self.registers.rip,
+        }
+    }
+    
     /// Allocate stack memory for a task
     fn allocate_stack() -> VirtAddr {
         use crate::memory::physical;
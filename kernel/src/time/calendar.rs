@@ -0,0 +1,121 @@
+//! Conversion between UNIX timestamps and calendar dates (UTC)
+
+use core::fmt;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC date and time of day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: u16, month: u8, day: u8) -> u64 {
+    // Count from March so the leap day is the last day of the year
+    let year = year as u64 - (month <= 2) as u64;
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month = month as u64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+impl DateTime {
+    /// The date at `timestamp` seconds after the epoch
+    pub fn from_unix(timestamp: u64) -> Self {
+        let days = timestamp / SECS_PER_DAY;
+        let secs = timestamp % SECS_PER_DAY;
+
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (year_of_era + era * 400 + (month <= 2) as u64) as u16;
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Seconds since the epoch; dates before 1970 are not representable
+    pub fn to_unix(self) -> Option<u64> {
+        if self.year < 1970 || !(1..=12).contains(&self.month) || self.day == 0 {
+            return None;
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        Some(days * SECS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64)
+    }
+
+    /// 0 for Sunday through 6 for Saturday
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((days_from_civil(self.year, self.month, self.day) + 4) % 7) as u8
+    }
+}
+
+/// `date`-style output, e.g. `Thu Jan  1 00:00:00 UTC 1970`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:2} {:02}:{:02}:{:02} UTC {}",
+            WEEKDAYS[self.weekday() as usize],
+            MONTHS[(self.month.clamp(1, 12) - 1) as usize],
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.year
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch() {
+        let epoch = DateTime::from_unix(0);
+        assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+        assert_eq!(epoch.weekday(), 4);
+        assert_eq!(epoch.to_unix(), Some(0));
+    }
+
+    #[test]
+    fn test_leap_day_round_trip() {
+        let date = DateTime { year: 2024, month: 2, day: 29, hour: 23, minute: 59, second: 58 };
+        let timestamp = date.to_unix().unwrap();
+        assert_eq!(timestamp, 1_709_251_198);
+        assert_eq!(DateTime::from_unix(timestamp), date);
+    }
+
+    #[test]
+    fn test_display() {
+        let date = DateTime::from_unix(1_000_000_000);
+        assert_eq!(alloc::format!("{}", date), "Sun Sep  9 01:46:40 UTC 2001");
+    }
+}
@@ -0,0 +1,100 @@
+//! Timekeeping
+//!
+//! `Instant` is a monotonic clock with nanosecond resolution read from the
+//! TSC. Wall-clock time is the RTC's reading at boot advanced by that
+//! clock. Sleeping goes through a queue of deadlines; in APIC mode the
+//! earliest one programs this CPU's local APIC timer as a one-shot, so
+//! nothing fires while nobody is waiting.
+
+pub mod calendar;
+pub mod sleep;
+pub mod tsc;
+
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub use calendar::DateTime;
+pub use core::time::Duration;
+pub use sleep::sleep;
+
+/// TSC value at calibration, the zero point of `Instant`
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// UNIX time when `Instant` was zero, 0 if the RTC could not be read
+static BOOT_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// A point on the monotonic clock, in nanoseconds since boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The current time; zero until the TSC is calibrated
+    pub fn now() -> Self {
+        if tsc::hz() == 0 {
+            return Self(0);
+        }
+        Self(tsc::to_nanos(tsc::read().saturating_sub(BOOT_TSC.load(Ordering::Relaxed))))
+    }
+
+    /// Time since `earlier`, or zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_add(rhs.as_nanos().min(u64::MAX as u128) as u64))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Time since boot
+pub fn uptime() -> Duration {
+    Duration::from_nanos(Instant::now().as_nanos())
+}
+
+/// Seconds since the UNIX epoch
+pub fn now() -> u64 {
+    BOOT_EPOCH.load(Ordering::Relaxed) + uptime().as_secs()
+}
+
+/// Calibrate the TSC, read the RTC and set up the timer for sleeping
+pub fn init() {
+    crate::serial::print("Initializing timekeeping...\n");
+    tsc::calibrate();
+    BOOT_TSC.store(tsc::read(), Ordering::Relaxed);
+
+    match crate::drivers::rtc::read().and_then(|date| Some((date, date.to_unix()?))) {
+        Some((date, timestamp)) => {
+            BOOT_EPOCH.store(timestamp, Ordering::Relaxed);
+            crate::serial::print(&alloc::format!("RTC: {}\n", date));
+        }
+        None => crate::serial::print("RTC: no valid date, wall clock starts at the epoch\n"),
+    }
+
+    sleep::init();
+}
@@ -0,0 +1,92 @@
+//! Sleep queue and tickless timer
+//!
+//! Sleepers add their deadline to their CPU's sorted queue and halt. Whenever the
+//! queue changes, or the timer fires, the local APIC timer is re-armed for
+//! the earliest deadline still in the future, or left stopped if there is
+//! none. Without an APIC timer the RTC's periodic interrupt wakes sleepers
+//! instead; with interrupts disabled, sleeping spins on the TSC.
+
+use x86_64::instructions::interrupts;
+
+use super::{Duration, Instant};
+use crate::drivers::rtc;
+use crate::interrupts::apic;
+use crate::smp::percpu;
+
+/// RTC periodic rate used to wake sleepers when there is no APIC timer
+const RTC_WAKEUP_HZ: u32 = 1024;
+
+/// Arm the timer for the earliest pending deadline, or stop it
+fn program_next(queue: &[Instant]) {
+    let now = Instant::now();
+    match queue.iter().find(|&&deadline| deadline > now) {
+        Some(&deadline) => apic::arm_timer(deadline - now),
+        None => apic::stop_timer(),
+    }
+}
+
+/// Called from the local APIC timer interrupt
+pub fn handle_interrupt() {
+    program_next(&percpu::current().sleep_queue.lock());
+}
+
+/// Block the calling CPU for at least `duration`
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration, || false);
+}
+
+/// Block the calling CPU until `deadline`, or until `wake` returns true
+/// after an interrupt
+pub fn sleep_until(deadline: Instant, wake: impl Fn() -> bool) {
+    let tickless = apic::timer_ready();
+    if !(tickless || rtc::periodic_hz() != 0) || !interrupts::are_enabled() {
+        while Instant::now() < deadline && !wake() {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+
+    if tickless {
+        interrupts::without_interrupts(|| {
+            let mut queue = percpu::current().sleep_queue.lock();
+            let index = queue.partition_point(|&d| d <= deadline);
+            queue.insert(index, deadline);
+            program_next(&queue);
+        });
+    }
+
+    loop {
+        // Check and halt with interrupts off so the wakeup cannot slip in between
+        interrupts::disable();
+        if Instant::now() >= deadline || wake() {
+            break;
+        }
+        interrupts::enable_and_hlt();
+    }
+
+    if tickless {
+        let mut queue = percpu::current().sleep_queue.lock();
+        if let Some(index) = queue.iter().position(|&d| d == deadline) {
+            queue.remove(index);
+        }
+        program_next(&queue);
+    }
+    interrupts::enable();
+}
+
+/// Set up the calling CPU's local APIC timer, or the RTC if interrupts go
+/// through the PIC
+pub fn init() {
+    if crate::interrupts::irq::apic_mode() {
+        apic::init_timer();
+        return;
+    }
+    match rtc::enable_periodic(RTC_WAKEUP_HZ) {
+        Ok(_) => crate::serial::print("Timer: no local APIC, sleepers are woken by the RTC\n"),
+        Err(e) => {
+            crate::serial::print("Timer: ");
+            crate::serial::print(e);
+            crate::serial::print(", sleeps will spin\n");
+        }
+    }
+}
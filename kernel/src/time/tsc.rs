@@ -0,0 +1,166 @@
+//! Time Stamp Counter calibration
+//!
+//! The TSC counts at a fixed rate on anything recent, but that rate has to
+//! be measured. The HPET's main counter has a known period and is used when
+//! the ACPI tables describe one; otherwise PIT channel 2 is run once as a
+//! 10 ms one-shot. Either wait gives up if the timer never seems to move,
+//! as on machines without a working PIT, and the rate CPUID reports is used
+//! instead, or failing that a guess.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::PhysAddr;
+use x86_64::instructions::port::Port;
+
+use crate::acpi::GenericAddress;
+
+/// Length of the calibration window
+const CALIBRATION_MS: u64 = 10;
+
+/// TSC ticks after which a calibration window is taken to have stalled;
+/// 10 ms at 10 GHz, faster than any TSC
+const CALIBRATION_LIMIT: u64 = 100_000_000;
+
+/// Rate assumed when nothing can measure or report it
+const DEFAULT_HZ: u64 = 1_000_000_000;
+
+/// PIT input clock and ports used to run channel 2 as a one-shot
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_GATE: u16 = 0x61;
+/// Port 0x61 bits: channel 2 gate, speaker enable, channel 2 output
+const GATE_ENABLE: u8 = 1 << 0;
+const GATE_SPEAKER: u8 = 1 << 1;
+const GATE_OUTPUT: u8 = 1 << 5;
+
+/// HPET registers: capabilities (period in femtoseconds in the top half),
+/// general configuration and the main counter
+const HPET_CAPABILITIES: usize = 0x00;
+const HPET_CONFIG: usize = 0x10;
+const HPET_COUNTER: usize = 0xF0;
+const HPET_ENABLE: u64 = 1 << 0;
+
+/// TSC ticks per second, 0 until calibrated
+static HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Nanoseconds per TSC tick as a 32.32 fixed point multiplier
+static NS_MULT: AtomicU64 = AtomicU64::new(0);
+
+/// Read the TSC
+pub fn read() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+pub fn hz() -> u64 {
+    HZ.load(Ordering::Relaxed)
+}
+
+/// Convert a TSC tick count to nanoseconds
+pub fn to_nanos(ticks: u64) -> u64 {
+    ((ticks as u128 * NS_MULT.load(Ordering::Relaxed) as u128) >> 32) as u64
+}
+
+/// Whether the TSC keeps a constant rate across P- and C-states
+pub fn invariant() -> bool {
+    let max = core::arch::x86_64::__cpuid(0x8000_0000).eax;
+    max >= 0x8000_0007 && core::arch::x86_64::__cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// TSC ticks over the calibration window, timed by the HPET
+fn calibrate_hpet() -> Option<u64> {
+    let hpet = crate::acpi::hpet()?;
+    if hpet.address.space != GenericAddress::SPACE_MEMORY {
+        return None;
+    }
+    let base = crate::memory::paging::map_mmio(PhysAddr::new(hpet.address.address), 0x100).ok()?;
+    let reg = |offset: usize| (base.as_u64() as usize + offset) as *mut u64;
+
+    unsafe {
+        let period_fs = core::ptr::read_volatile(reg(HPET_CAPABILITIES)) >> 32;
+        if period_fs == 0 || period_fs > 100_000_000 {
+            return None;
+        }
+        let config = core::ptr::read_volatile(reg(HPET_CONFIG));
+        core::ptr::write_volatile(reg(HPET_CONFIG), config | HPET_ENABLE);
+
+        let window = CALIBRATION_MS * 1_000_000_000_000 / period_fs;
+        let start = core::ptr::read_volatile(reg(HPET_COUNTER));
+        let tsc_start = read();
+        while core::ptr::read_volatile(reg(HPET_COUNTER)).wrapping_sub(start) < window {
+            if read() - tsc_start > CALIBRATION_LIMIT {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+        Some(read() - tsc_start)
+    }
+}
+
+/// TSC ticks over the calibration window, timed by PIT channel 2
+fn calibrate_pit() -> Option<u64> {
+    let count = (PIT_FREQUENCY * CALIBRATION_MS / 1000) as u16;
+    unsafe {
+        let mut gate = Port::<u8>::new(PIT_GATE);
+        let mut channel2 = Port::<u8>::new(PIT_CHANNEL2);
+
+        // Gate on, speaker off; mode 0 raises the output when the count ends
+        let value = gate.read();
+        gate.write((value & !GATE_SPEAKER) | GATE_ENABLE);
+        Port::<u8>::new(PIT_COMMAND).write(0b1011_0000);
+        channel2.write(count as u8);
+        channel2.write((count >> 8) as u8);
+
+        let tsc_start = read();
+        let mut ticks = 0;
+        while gate.read() & GATE_OUTPUT == 0 && ticks <= CALIBRATION_LIMIT {
+            core::hint::spin_loop();
+            ticks = read() - tsc_start;
+        }
+        gate.write(value);
+        (ticks <= CALIBRATION_LIMIT).then_some(read() - tsc_start)
+    }
+}
+
+/// The TSC rate the CPU reports: leaf 0x15 gives it as a ratio of the
+/// crystal clock, leaf 0x16 gives the base frequency in MHz
+fn cpuid_hz() -> Option<u64> {
+    let max = core::arch::x86_64::__cpuid(0).eax;
+    if max >= 0x15 {
+        let leaf = core::arch::x86_64::__cpuid(0x15);
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+        }
+    }
+    if max >= 0x16 {
+        let mhz = core::arch::x86_64::__cpuid(0x16).eax & 0xFFFF;
+        if mhz != 0 {
+            return Some(mhz as u64 * 1_000_000);
+        }
+    }
+    None
+}
+
+/// Measure the TSC frequency
+pub fn calibrate() {
+    let measured = |ticks: Option<u64>| ticks.map(|ticks| ticks * (1000 / CALIBRATION_MS)).filter(|&hz| hz != 0);
+    let (hz, source) = if let Some(hz) = measured(calibrate_hpet()) {
+        (hz, "calibrated against the HPET")
+    } else if let Some(hz) = measured(calibrate_pit()) {
+        (hz, "calibrated against the PIT")
+    } else if let Some(hz) = cpuid_hz().filter(|&hz| hz != 0) {
+        (hz, "as reported by CPUID")
+    } else {
+        (DEFAULT_HZ, "assumed")
+    };
+    HZ.store(hz, Ordering::Relaxed);
+    NS_MULT.store((1_000_000_000u64 << 32) / hz, Ordering::Relaxed);
+
+    crate::serial::print("TSC: ");
+    crate::memory::print_decimal(hz / 1_000_000);
+    crate::serial::print(" MHz, ");
+    crate::serial::print(source);
+    if !invariant() {
+        crate::serial::print(" (not invariant)");
+    }
+    crate::serial::print("\n");
+}
@@ -0,0 +1,542 @@
+//! Terminals
+//!
+//! A TTY sits between an input source and whatever reads it. Its line
+//! discipline applies the `Termios` settings: in canonical mode input is
+//! collected and edited a line at a time, with ERASE, WERASE and KILL, and
+//! only complete lines can be read; otherwise every character is readable
+//! as soon as it arrives. Echo goes to the terminal's output. The INTR,
+//! QUIT and SUSP characters send signals to the foreground task, or, with
+//! none, are reported to the reader, such as the shell; EOF at the start
+//! of a line makes the next read return end of file.
+//!
+//! Each virtual console has a TTY; typed input goes to the one on screen,
+//! and COM1 input, decoded from UTF-8, to the first.
+
+pub mod termios;
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::console::psf::REPLACEMENT;
+use crate::console::terminal::Utf8Decoder;
+use crate::console::{self, LOG_VT, VT_COUNT};
+use crate::drivers::device::{Device, DeviceError};
+use crate::task::TaskId;
+use crate::task::signal::{self, Signal};
+use termios::{InputFlags, LocalFlags, Termios, VEOF, VERASE, VINTR, VKILL, VQUIT, VSUSP, VWERASE};
+
+/// Longest line canonical mode will collect
+const MAX_CANON: usize = 4096;
+
+/// Input kept for readers; further input is dropped when full
+const MAX_INPUT: usize = 4096;
+
+/// Something the reader of a TTY should look at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Input or end of file can be read
+    Readable,
+    /// A signal character was typed with no foreground task to receive it
+    Signal(Signal),
+}
+
+/// A terminal and its line discipline
+pub struct Tty {
+    termios: Termios,
+    /// Line being edited in canonical mode
+    line: String,
+    /// Input ready to be read
+    ready: VecDeque<u8>,
+    /// End of file was typed and not yet read
+    eof: bool,
+    /// Task that receives signals from the keyboard
+    foreground: Option<TaskId>,
+    /// Called with events, outside the TTY lock
+    reader: Option<fn(Event)>,
+    /// Where echo is written
+    output: fn(&str),
+    /// Echo not yet written; it goes out once the TTY lock is released, as
+    /// writing it can feed input back into this TTY
+    echoed: String,
+    /// Partial UTF-8 sequence of byte input
+    utf8: Utf8Decoder,
+}
+
+impl Tty {
+    pub const fn new(output: fn(&str)) -> Self {
+        Self {
+            termios: Termios::sane(),
+            line: String::new(),
+            ready: VecDeque::new(),
+            eof: false,
+            foreground: None,
+            reader: None,
+            output,
+            echoed: String::new(),
+            utf8: Utf8Decoder::new(),
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    /// Change the settings; a partly edited line becomes readable when
+    /// canonical mode is turned off
+    pub fn set_termios(&mut self, termios: Termios) {
+        if !termios.lflag.contains(LocalFlags::ICANON) {
+            let line = core::mem::take(&mut self.line);
+            self.push_ready(line.as_bytes());
+        }
+        self.termios = termios;
+    }
+
+    /// Choose the task that keyboard signals go to, or none for the reader
+    pub fn set_foreground(&mut self, task: Option<TaskId>) {
+        self.foreground = task;
+    }
+
+    /// Register the function told about input and unclaimed signals
+    pub fn set_reader(&mut self, reader: fn(Event)) {
+        self.reader = Some(reader);
+    }
+
+    /// Read input without blocking
+    ///
+    /// Returns `None` if there is nothing to read and `Some(0)` at end of
+    /// file. In canonical mode a read returns at most one line.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.ready.is_empty() {
+            return core::mem::take(&mut self.eof).then_some(0);
+        }
+        let canonical = self.termios.lflag.contains(LocalFlags::ICANON);
+        let mut len = 0;
+        while len < buf.len()
+            && let Some(byte) = self.ready.pop_front()
+        {
+            buf[len] = byte;
+            len += 1;
+            if canonical && byte == b'\n' {
+                break;
+            }
+        }
+        Some(len)
+    }
+
+    fn echo(&mut self, s: &str) {
+        self.echoed.push_str(s);
+    }
+
+    /// Echo a typed character, showing control characters as `^X`
+    fn echo_char(&mut self, c: char) {
+        if self.termios.lflag.contains(LocalFlags::ECHOCTL) && c.is_ascii_control() && c != '\n' && c != '\t' {
+            let caret = [b'^', c as u8 ^ 0x40];
+            self.echo(core::str::from_utf8(&caret).unwrap_or("^?"));
+            return;
+        }
+        let mut utf8 = [0; 4];
+        self.echo(c.encode_utf8(&mut utf8));
+    }
+
+    /// Remove an erased character from the screen
+    fn rub_out(&mut self, c: char) {
+        if !self.termios.lflag.contains(LocalFlags::ECHO | LocalFlags::ECHOE) {
+            return;
+        }
+        let width = if c.is_ascii_control() && self.termios.lflag.contains(LocalFlags::ECHOCTL) { 2 } else { 1 };
+        for _ in 0..width {
+            self.echo("\x08 \x08");
+        }
+    }
+
+    fn push_ready(&mut self, bytes: &[u8]) {
+        let room = MAX_INPUT - self.ready.len();
+        self.ready.extend(&bytes[..bytes.len().min(room)]);
+    }
+
+    /// Move the edited line to the readable input
+    fn finish_line(&mut self) {
+        let line = core::mem::take(&mut self.line);
+        self.push_ready(line.as_bytes());
+    }
+
+    /// Send a signal to the foreground task, which loses the terminal if
+    /// the signal stops or ends it; without one the reader hears of it
+    fn send_signal(&mut self, signal: Signal) -> Option<Event> {
+        if let Some(task) = self.foreground.take()
+            && signal::send(task, signal)
+        {
+            return None;
+        }
+        Some(Event::Signal(signal))
+    }
+
+    /// Decode a byte of input into up to two characters: U+FFFD for a
+    /// sequence it cuts short, then the one it completes
+    fn decode(&mut self, byte: u8) -> [Option<char>; 2] {
+        if byte.is_ascii() {
+            [self.utf8.abandon().then_some(REPLACEMENT), Some(byte as char)]
+        } else {
+            [self.utf8.feed(byte), None]
+        }
+    }
+
+    /// Run one input character through the line discipline
+    fn receive(&mut self, mut c: char) -> Option<Event> {
+        let Termios { iflag, lflag, .. } = self.termios;
+        let echo = lflag.contains(LocalFlags::ECHO);
+
+        if c == '\r' {
+            if iflag.contains(InputFlags::IGNCR) {
+                return None;
+            }
+            if iflag.contains(InputFlags::ICRNL) {
+                c = '\n';
+            }
+        } else if c == '\n' && iflag.contains(InputFlags::INLCR) {
+            c = '\r';
+        }
+
+        if lflag.contains(LocalFlags::ISIG) {
+            let signal = [(VINTR, Signal::Interrupt), (VQUIT, Signal::Quit), (VSUSP, Signal::Stop)]
+                .into_iter()
+                .find(|&(index, _)| self.termios.is(index, c));
+            if let Some((_, signal)) = signal {
+                // Typed-ahead input is discarded
+                self.line.clear();
+                self.ready.clear();
+                if echo {
+                    self.echo_char(c);
+                    self.echo("\n");
+                }
+                return self.send_signal(signal);
+            }
+        }
+
+        if !lflag.contains(LocalFlags::ICANON) {
+            let mut utf8 = [0; 4];
+            self.push_ready(c.encode_utf8(&mut utf8).as_bytes());
+            if echo {
+                self.echo_char(c);
+            }
+            return Some(Event::Readable);
+        }
+
+        if self.termios.is(VERASE, c) {
+            if let Some(erased) = self.line.pop() {
+                self.rub_out(erased);
+            }
+            return None;
+        }
+        if self.termios.is(VWERASE, c) {
+            // Trailing blanks, then the word before them
+            let blank = [' ', '\t'];
+            while self.line.ends_with(blank)
+                && let Some(erased) = self.line.pop()
+            {
+                self.rub_out(erased);
+            }
+            while !self.line.is_empty()
+                && !self.line.ends_with(blank)
+                && let Some(erased) = self.line.pop()
+            {
+                self.rub_out(erased);
+            }
+            return None;
+        }
+        if self.termios.is(VKILL, c) {
+            if lflag.contains(LocalFlags::ECHOK) {
+                while let Some(erased) = self.line.pop() {
+                    self.rub_out(erased);
+                }
+            }
+            self.line.clear();
+            return None;
+        }
+        if self.termios.is(VEOF, c) {
+            if self.line.is_empty() {
+                self.eof = true;
+            } else {
+                self.finish_line();
+            }
+            return Some(Event::Readable);
+        }
+
+        if c == '\n' {
+            self.line.push('\n');
+            self.finish_line();
+            if echo || lflag.contains(LocalFlags::ECHONL) {
+                self.echo("\n");
+            }
+            return Some(Event::Readable);
+        }
+        if self.line.len() + c.len_utf8() < MAX_CANON {
+            self.line.push(c);
+            if echo {
+                self.echo_char(c);
+            }
+        }
+        None
+    }
+}
+
+/// The virtual console terminals
+static VTS: [Mutex<Tty>; VT_COUNT] = [
+    Mutex::new(Tty::new(console::print_vt::<0>)),
+    Mutex::new(Tty::new(console::print_vt::<1>)),
+    Mutex::new(Tty::new(console::print_vt::<2>)),
+    Mutex::new(Tty::new(console::print_vt::<3>)),
+    Mutex::new(Tty::new(console::print_vt::<4>)),
+    Mutex::new(Tty::new(console::print_vt::<5>)),
+];
+
+/// The terminal of virtual console `index`
+pub fn vt(index: usize) -> &'static Mutex<Tty> {
+    &VTS[index]
+}
+
+/// The terminal of the current console: the one whose shell is running,
+/// or else the one on screen
+pub fn console() -> &'static Mutex<Tty> {
+    vt(console::current())
+}
+
+/// The terminal typed input goes to; none while the kernel log is shown
+pub fn foreground() -> Option<&'static Mutex<Tty>> {
+    let index = console::foreground();
+    (index != LOG_VT).then(|| vt(index))
+}
+
+/// Feed a typed character to a terminal, echo it and tell its reader what
+/// changed
+pub fn input(tty: &Mutex<Tty>, c: char) {
+    let (event, reader, echoed, output) = without_interrupts(|| {
+        let mut tty = tty.lock();
+        let event = tty.receive(c);
+        (event, tty.reader, core::mem::take(&mut tty.echoed), tty.output)
+    });
+    if !echoed.is_empty() {
+        output(&echoed);
+    }
+    if let (Some(event), Some(reader)) = (event, reader) {
+        reader(event);
+    }
+}
+
+/// Feed a byte of UTF-8 input, such as from a serial line, to a terminal
+pub fn input_byte(tty: &Mutex<Tty>, byte: u8) {
+    for c in with(tty, |tty| tty.decode(byte)).into_iter().flatten() {
+        input(tty, c);
+    }
+}
+
+/// Run `f` on a terminal with its lock held
+pub fn with<R>(tty: &Mutex<Tty>, f: impl FnOnce(&mut Tty) -> R) -> R {
+    without_interrupts(|| f(&mut tty.lock()))
+}
+
+/// Register the terminal devices
+pub fn init() {
+    crate::drivers::device::register("tty", Arc::new(TtyDevice(None)));
+    for index in 0..VT_COUNT {
+        crate::drivers::device::register(&alloc::format!("tty{}", index + 1), Arc::new(TtyDevice(Some(index))));
+    }
+}
+
+/// `/dev/ttyN`: reads return input through the line discipline, writes go
+/// to the terminal's output; `/dev/tty` is the current console's terminal
+///
+/// End of file reads as zero bytes, as does no input.
+pub struct TtyDevice(Option<usize>);
+
+impl TtyDevice {
+    fn tty(&self) -> &'static Mutex<Tty> {
+        self.0.map_or_else(console, vt)
+    }
+}
+
+impl Device for TtyDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        Ok(with(self.tty(), |tty| tty.read(buf)).unwrap_or(0))
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, DeviceError> {
+        let output = with(self.tty(), |tty| tty.output);
+        output(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn tty(setup: impl FnOnce(&mut Termios)) -> Tty {
+        let mut tty = Tty::new(|_| {});
+        let mut termios = Termios::sane();
+        setup(&mut termios);
+        tty.set_termios(termios);
+        tty
+    }
+
+    /// Feed `input` and return the events it caused
+    fn feed(tty: &mut Tty, input: &str) -> Vec<Event> {
+        input.chars().filter_map(|c| tty.receive(c)).collect()
+    }
+
+    fn echoed(tty: &mut Tty) -> String {
+        core::mem::take(&mut tty.echoed)
+    }
+
+    fn read(tty: &mut Tty) -> Option<String> {
+        let mut buf = [0u8; 64];
+        let len = tty.read(&mut buf)?;
+        Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    #[test]
+    fn test_canonical_reads_whole_lines() {
+        let mut tty = tty(|_| {});
+        assert!(feed(&mut tty, "ls -l").is_empty());
+        assert_eq!(read(&mut tty), None);
+
+        // Enter sends CR, which ICRNL turns into NL
+        assert_eq!(feed(&mut tty, "\rpwd\r"), [Event::Readable, Event::Readable]);
+        assert_eq!(echoed(&mut tty), "ls -l\npwd\n");
+        assert_eq!(read(&mut tty).as_deref(), Some("ls -l\n"));
+        assert_eq!(read(&mut tty).as_deref(), Some("pwd\n"));
+        assert_eq!(read(&mut tty), None);
+    }
+
+    #[test]
+    fn test_erase_and_word_erase() {
+        let mut tty = tty(|_| {});
+        feed(&mut tty, "cat\x7f\x7fp");
+        assert_eq!(tty.line, "cp");
+        assert_eq!(echoed(&mut tty), "cat\x08 \x08\x08 \x08p");
+
+        feed(&mut tty, " foo bar  \x17");
+        assert_eq!(tty.line, "cp foo ");
+        feed(&mut tty, "\x17\x17\x17");
+        assert_eq!(tty.line, "");
+
+        // Erasing past the start of the line does nothing
+        echoed(&mut tty);
+        feed(&mut tty, "\x7f");
+        assert_eq!(echoed(&mut tty), "");
+    }
+
+    #[test]
+    fn test_erase_without_echoe() {
+        let mut tty = tty(|termios| termios.lflag.remove(LocalFlags::ECHOE));
+        feed(&mut tty, "ab\x7f\n");
+        assert_eq!(echoed(&mut tty), "ab\n");
+        assert_eq!(read(&mut tty).as_deref(), Some("a\n"));
+    }
+
+    #[test]
+    fn test_kill_line() {
+        let mut tty = tty(|_| {});
+        feed(&mut tty, "abc\x15");
+        assert_eq!(tty.line, "");
+        assert_eq!(echoed(&mut tty), "abc\x08 \x08\x08 \x08\x08 \x08");
+
+        let mut tty = self::tty(|termios| termios.lflag.remove(LocalFlags::ECHOK));
+        feed(&mut tty, "abc\x15d\n");
+        assert_eq!(echoed(&mut tty), "abcd\n");
+        assert_eq!(read(&mut tty).as_deref(), Some("d\n"));
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut tty = tty(|_| {});
+        assert_eq!(feed(&mut tty, "ab\x04"), [Event::Readable]);
+        assert_eq!(read(&mut tty).as_deref(), Some("ab"));
+        assert_eq!(read(&mut tty), None);
+
+        feed(&mut tty, "\x04");
+        assert_eq!(read(&mut tty).as_deref(), Some(""));
+        assert_eq!(read(&mut tty), None);
+    }
+
+    #[test]
+    fn test_signal_characters() {
+        let mut tty = tty(|_| {});
+        feed(&mut tty, "ab\n");
+        feed(&mut tty, "cd");
+        echoed(&mut tty);
+
+        // Without a foreground task the reader hears of the signal, and
+        // typed-ahead input is discarded
+        assert_eq!(feed(&mut tty, "\x03"), [Event::Signal(Signal::Interrupt)]);
+        assert_eq!(echoed(&mut tty), "^C\n");
+        assert_eq!(tty.line, "");
+        assert_eq!(read(&mut tty), None);
+
+        assert_eq!(feed(&mut tty, "\x1c"), [Event::Signal(Signal::Quit)]);
+        assert_eq!(feed(&mut tty, "\x1a"), [Event::Signal(Signal::Stop)]);
+    }
+
+    #[test]
+    fn test_signal_characters_without_isig() {
+        let mut tty = tty(|termios| termios.lflag.remove(LocalFlags::ISIG));
+        assert!(feed(&mut tty, "\x03").is_empty());
+        assert_eq!(echoed(&mut tty), "^C");
+        assert_eq!(tty.line, "\x03");
+    }
+
+    #[test]
+    fn test_echo_flags() {
+        let mut tty = tty(|termios| termios.lflag.remove(LocalFlags::ECHO));
+        feed(&mut tty, "secret\n");
+        assert_eq!(echoed(&mut tty), "");
+        assert_eq!(read(&mut tty).as_deref(), Some("secret\n"));
+
+        let mut tty = self::tty(|termios| {
+            termios.lflag.remove(LocalFlags::ECHO);
+            termios.lflag.insert(LocalFlags::ECHONL);
+        });
+        feed(&mut tty, "secret\n");
+        assert_eq!(echoed(&mut tty), "\n");
+
+        let mut tty = self::tty(|termios| termios.lflag.remove(LocalFlags::ECHOCTL));
+        feed(&mut tty, "\x01");
+        assert_eq!(echoed(&mut tty), "\x01");
+    }
+
+    #[test]
+    fn test_raw_mode() {
+        let mut tty = tty(Termios::make_raw);
+        assert_eq!(feed(&mut tty, "a\x7f\x03\r"), [Event::Readable; 4]);
+        assert_eq!(echoed(&mut tty), "");
+        assert_eq!(read(&mut tty).as_deref(), Some("a\x7f\x03\r"));
+    }
+
+    #[test]
+    fn test_leaving_canonical_mode_releases_the_line() {
+        let mut tty = tty(|_| {});
+        feed(&mut tty, "half");
+        let mut termios = tty.termios();
+        termios.lflag.remove(LocalFlags::ICANON);
+        tty.set_termios(termios);
+        assert_eq!(read(&mut tty).as_deref(), Some("half"));
+    }
+
+    #[test]
+    fn test_input_translations() {
+        let mut tty = tty(|termios| termios.iflag.insert(InputFlags::IGNCR));
+        feed(&mut tty, "a\rb\n");
+        assert_eq!(read(&mut tty).as_deref(), Some("ab\n"));
+
+        let mut tty = self::tty(|termios| {
+            termios.make_raw();
+            termios.iflag.insert(InputFlags::INLCR);
+        });
+        feed(&mut tty, "\n");
+        assert_eq!(read(&mut tty).as_deref(), Some("\r"));
+    }
+}
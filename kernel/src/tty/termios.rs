@@ -0,0 +1,105 @@
+//! Terminal settings
+//!
+//! A subset of POSIX termios: the input translations, the local modes
+//! that select canonical editing, echo and signal generation, and the
+//! control characters they act on. Flag names and bit values follow Linux.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Input translations
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InputFlags: u32 {
+        /// Translate NL to CR
+        const INLCR = 0o100;
+        /// Ignore CR
+        const IGNCR = 0o200;
+        /// Translate CR to NL
+        const ICRNL = 0o400;
+    }
+}
+
+bitflags! {
+    /// Local modes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LocalFlags: u32 {
+        /// Generate signals for the INTR, QUIT and SUSP characters
+        const ISIG = 0o1;
+        /// Canonical mode: input is edited and read a line at a time
+        const ICANON = 0o2;
+        /// Echo input
+        const ECHO = 0o10;
+        /// Echo ERASE and WERASE by rubbing out characters
+        const ECHOE = 0o20;
+        /// Echo KILL by rubbing out the line
+        const ECHOK = 0o40;
+        /// Echo NL even when ECHO is off
+        const ECHONL = 0o100;
+        /// Echo control characters as `^X`
+        const ECHOCTL = 0o1000;
+    }
+}
+
+/// Indices into `Termios::cc`
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VSUSP: usize = 10;
+pub const VWERASE: usize = 14;
+/// Number of control characters
+pub const NCCS: usize = 19;
+
+/// Disabled control character
+pub const VDISABLE: u8 = 0;
+
+/// Terminal settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    pub iflag: InputFlags,
+    pub lflag: LocalFlags,
+    /// Control characters, indexed by `VINTR` and friends
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// Settings a terminal starts with, as `stty sane` restores them
+    pub const fn sane() -> Self {
+        let mut cc = [VDISABLE; NCCS];
+        cc[VINTR] = 0x03; // Ctrl+C
+        cc[VQUIT] = 0x1C; // Ctrl+\
+        cc[VERASE] = 0x7F; // DEL, sent by Backspace
+        cc[VKILL] = 0x15; // Ctrl+U
+        cc[VEOF] = 0x04; // Ctrl+D
+        cc[VSUSP] = 0x1A; // Ctrl+Z
+        cc[VWERASE] = 0x17; // Ctrl+W
+        Self {
+            iflag: InputFlags::ICRNL,
+            lflag: LocalFlags::ISIG
+                .union(LocalFlags::ICANON)
+                .union(LocalFlags::ECHO)
+                .union(LocalFlags::ECHOE)
+                .union(LocalFlags::ECHOK)
+                .union(LocalFlags::ECHOCTL),
+            cc,
+        }
+    }
+
+    /// Turn off all input processing, like `cfmakeraw`
+    pub fn make_raw(&mut self) {
+        self.iflag.remove(InputFlags::INLCR | InputFlags::IGNCR | InputFlags::ICRNL);
+        self.lflag.remove(LocalFlags::ISIG | LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ECHONL);
+    }
+
+    /// Whether `c` is the control character at `index`
+    pub fn is(&self, index: usize, c: char) -> bool {
+        self.cc[index] != VDISABLE && c as u32 == self.cc[index] as u32
+    }
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        Self::sane()
+    }
+}